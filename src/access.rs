@@ -0,0 +1,442 @@
+use mongodb::bson::{doc, Document};
+use mongodb::bson::oid::ObjectId;
+use rocket::form;
+use serde::{Deserialize, Serialize};
+
+use crate::database::{get_event_by_id, get_events, get_sessions_by_ids, get_user_by_username, DatabaseResult};
+use crate::events::Event;
+use crate::security::{SecurityRole, SecurityToken};
+use crate::registration::is_admitted;
+use crate::sessions::Session;
+
+// Sichtbarkeit einer Session
+// public: alle angemeldeten User, restricted: nur die freigegebenen User, Gruppen und Rollen,
+// unlisted: alle mit dem Link, die Session taucht aber in keiner Liste auf
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionVisibility {
+    #[default]
+    Public,
+    Restricted,
+    Unlisted
+}
+
+impl SessionVisibility {
+    pub const ALL: [SessionVisibility; 3] = [SessionVisibility::Public, SessionVisibility::Restricted, SessionVisibility::Unlisted];
+
+    pub fn parse(value: &str) -> Option<SessionVisibility> {
+        match value.trim() {
+            "public" => Some(SessionVisibility::Public),
+            "restricted" => Some(SessionVisibility::Restricted),
+            "unlisted" => Some(SessionVisibility::Unlisted),
+            _ => None
+        }
+    }
+
+    pub fn value(&self) -> &'static str {
+        match self {
+            SessionVisibility::Public => "public",
+            SessionVisibility::Restricted => "restricted",
+            SessionVisibility::Unlisted => "unlisted",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SessionVisibility::Public => "Öffentlich (alle angemeldeten User)",
+            SessionVisibility::Restricted => "Eingeschränkt (nur freigegebene User, Gruppen und Rollen)",
+            SessionVisibility::Unlisted => "Nicht gelistet (nur über den Link)",
+        }
+    }
+}
+
+// Auswahl der Sichtbarkeit für die Formulare
+#[derive(Debug, Serialize)]
+pub struct VisibilityOption {
+    pub value: &'static str,
+    pub label: &'static str
+}
+
+pub fn visibility_options() -> Vec<VisibilityOption> {
+    return SessionVisibility::ALL.iter()
+        .map(|v| VisibilityOption { value: v.value(), label: v.label() })
+        .collect();
+}
+
+// Zugriffsregeln einer Session, die Listen werden nur bei restricted ausgewertet
+// roles enthält die Rollen wie am User gespeichert (ADMIN, MODERATOR, USER)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionAccess {
+    #[serde(default)]
+    pub visibility: SessionVisibility,
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<String>
+}
+
+// Zerlegt eine komma-getrennte Eingabe, leere und doppelte Einträge entfallen
+pub fn parse_list(value: &str) -> Vec<String> {
+    let mut list: Vec<String> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        if !list.iter().any(|l| l == entry) {
+            list.push(entry.to_string());
+        }
+    }
+    return list;
+}
+
+// Ohne Auswahl bleibt es beim Anlegen bei public bzw. beim Bearbeiten unverändert
+pub fn valid_visibility<'v>(value: &Option<&str>) -> form::Result<'v, ()> {
+    match value.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(v) if SessionVisibility::parse(v).is_none() => Err(form::Error::validation("Unbekannte Sichtbarkeit"))?,
+        _ => Ok(())
+    }
+}
+
+pub fn valid_roles<'v>(value: &Option<&str>) -> form::Result<'v, ()> {
+    // "-" leert die Liste beim Bearbeiten
+    let roles = parse_list(value.unwrap_or("").trim_start_matches('-'));
+    match roles.iter().find(|role| !matches!(role.as_str(), "ADMIN" | "MODERATOR" | "USER")) {
+        Some(role) => Err(form::Error::validation(format!("Unbekannte Rolle '{}' (erlaubt: ADMIN, MODERATOR, USER)", role)))?,
+        None => Ok(())
+    }
+}
+
+pub fn role_name(role: &SecurityRole) -> &'static str {
+    match role {
+        SecurityRole::ADMIN => "ADMIN",
+        SecurityRole::MODERATOR => "MODERATOR",
+        SecurityRole::USER => "USER",
+    }
+}
+
+// Der angemeldete User mit allem, was für die Zugriffsprüfung nötig ist
+#[derive(Debug, Clone)]
+pub struct Viewer {
+    pub username: String,
+    pub role: String,
+    pub groups: Vec<String>,
+    // Events, die der User nicht öffnen bzw. nicht gelistet sehen darf, ihre Sessions erben das
+    pub hidden_events: Vec<ObjectId>,
+    pub unlisted_events: Vec<ObjectId>,
+    // Konnten die Events nicht geladen werden, gelten alle Sessions in Events als gesperrt
+    pub events_locked: bool
+}
+
+impl Viewer {
+    pub fn is_admin(&self) -> bool {
+        return self.role == "ADMIN";
+    }
+
+    fn is_granted(&self, access: &SessionAccess) -> bool {
+        return access.users.iter().any(|u| u == &self.username)
+            || access.groups.iter().any(|g| self.groups.contains(g))
+            || access.roles.iter().any(|r| r == &self.role);
+    }
+
+    // Darf die Session öffnen (Seite, Chat, Anhänge, Stream)
+    pub fn can_view(&self, access: &SessionAccess) -> bool {
+        if self.is_admin() {
+            return true;
+        }
+        match access.visibility {
+            SessionVisibility::Public | SessionVisibility::Unlisted => true,
+            SessionVisibility::Restricted => self.is_granted(access)
+        }
+    }
+
+    // Bekommt die Session in Übersicht, Suche und Kalender-Feed angezeigt
+    pub fn can_list(&self, access: &SessionAccess) -> bool {
+        if self.is_admin() {
+            return true;
+        }
+        match access.visibility {
+            SessionVisibility::Public => true,
+            SessionVisibility::Restricted => self.is_granted(access),
+            SessionVisibility::Unlisted => false
+        }
+    }
+
+    // Eine Session in einem Event muss die Regeln der Session und die des Events erfüllen
+    pub fn can_view_session(&self, session: &Session) -> bool {
+        return self.can_view(&session.access)
+            && !session.event.as_ref().map_or(false, |placement| self.events_locked || self.hidden_events.contains(&placement.event_id));
+    }
+
+    pub fn can_list_session(&self, session: &Session) -> bool {
+        return self.can_list(&session.access)
+            && !session.event.as_ref().map_or(false, |placement| self.events_locked || self.unlisted_events.contains(&placement.event_id));
+    }
+
+    // Merkt sich, welche Events (und damit deren Sessions) der User nicht öffnen bzw. nicht gelistet sehen darf
+    pub fn with_events(mut self, events: &[Event]) -> Viewer {
+        self.hidden_events = events.iter().filter(|e| !self.can_view(&e.access)).map(|e| e.id).collect();
+        self.unlisted_events = events.iter().filter(|e| !self.can_list(&e.access)).map(|e| e.id).collect();
+        return self;
+    }
+
+    // MongoDB-Filter mit derselben Bedeutung wie can_list_session, damit Seitenzahl und Anzahl stimmen
+    // Sessions ohne access-Feld (vor Einführung der Regeln angelegt) gelten als public
+    pub fn listing_filter(&self) -> Option<Document> {
+        if self.is_admin() {
+            return None;
+        }
+        // Sessions ohne Event haben kein event.event_id, null trifft auch auf fehlende Felder zu
+        let events = if self.events_locked {
+            doc! {"event.event_id": null}
+        } else {
+            doc! {"event.event_id": {"$nin": &self.unlisted_events}}
+        };
+        let mut filter = doc! {
+            "$or": [
+                {"access.visibility": {"$nin": ["restricted", "unlisted"]}},
+                {"access.visibility": "restricted", "$or": [
+                    {"access.users": &self.username},
+                    {"access.groups": {"$in": &self.groups}},
+                    {"access.roles": &self.role}
+                ]}
+            ]
+        };
+        filter.extend(events);
+        return Some(filter);
+    }
+}
+
+// Gruppen stehen nicht im Auth-Token und werden deshalb aus der Datenbank geladen
+// Ein unbekannter User bekommt nur die Rechte aus dem Token
+// Ohne die Events (Datenbankfehler) gelten die Sessions darin als gesperrt, der Fehler fällt dann beim Event auf
+// Admins dürfen alles sehen und sind davon nicht betroffen
+pub async fn viewer_from_token(database: &mongodb::Database, token: &SecurityToken) -> Viewer {
+    let groups = match get_user_by_username(database, &token.username).await {
+        Ok(Some(user)) => user.groups,
+        _ => Vec::new()
+    };
+    let viewer = Viewer {
+        username: token.username.clone(),
+        role: role_name(&token.role).to_string(),
+        groups,
+        hidden_events: Vec::new(),
+        unlisted_events: Vec::new(),
+        events_locked: false
+    };
+    return match get_events(database).await {
+        Ok(events) => viewer.with_events(&events),
+        Err(e) => {
+            error!("Failed to load events for access checks: {}", e);
+            Viewer { events_locked: !viewer.is_admin(), ..viewer }
+        }
+    };
+}
+
+// Für Zugänge ohne Auth-Token (z.B. Kalender-Abo), hier kommt auch die Rolle aus der Datenbank
+pub async fn viewer_from_username(database: &mongodb::Database, username: &str) -> DatabaseResult<Option<Viewer>> {
+    let viewer = get_user_by_username(database, username).await?.map(|user| Viewer {
+        username: user.username,
+        role: user.role,
+        groups: user.groups,
+        hidden_events: Vec::new(),
+        unlisted_events: Vec::new(),
+        events_locked: false
+    });
+    return match viewer {
+        Some(viewer) => Ok(Some(viewer.with_events(&get_events(database).await?))),
+        None => Ok(None)
+    };
+}
+
+// Lädt eine Session nur, wenn sie existiert und der User sie (und ihr Event) sehen darf
+pub async fn accessible_session(database: &mongodb::Database, viewer: &Viewer, id: &ObjectId) -> DatabaseResult<Option<Session>> {
+    let session = get_sessions_by_ids(database, &[*id]).await?.into_iter().next();
+    return Ok(session.filter(|session| viewer.can_view_session(session)));
+}
+
+// Lädt ein Event nur, wenn es existiert und der User es sehen darf
+pub async fn accessible_event(database: &mongodb::Database, viewer: &Viewer, id: &ObjectId) -> DatabaseResult<Option<Event>> {
+    return Ok(get_event_by_id(database, id).await?.filter(|event| viewer.can_view(&event.access)));
+}
+
+// Wie accessible_session, verlangt die Session eine Anmeldung muss der User außerdem bestätigt sein
+// Wird für Chat, Stream und Anhänge genutzt
+pub async fn viewable_session(database: &mongodb::Database, viewer: &Viewer, id: &ObjectId) -> DatabaseResult<Option<Session>> {
+    return match accessible_session(database, viewer, id).await? {
+        Some(session) if is_admitted(database, &session, viewer).await => Ok(Some(session)),
+        _ => Ok(None)
+    };
+}
+
+// Öffentlich (ohne Anmeldung, z.B. Poster und Vorschau beim Teilen) ist eine Session nur, wenn sie und ihr Event
+// (falls vorhanden) öffentlich sind, fehlt das Event (z.B. gelöscht), gilt die Session nicht als öffentlich
+pub fn is_public_session(session: &Session, event: Option<&Event>) -> bool {
+    if session.access.visibility != SessionVisibility::Public {
+        return false;
+    }
+    return match &session.event {
+        Some(placement) => event.map_or(false, |event| event.id == placement.event_id
+            && event.access.visibility == SessionVisibility::Public),
+        None => true
+    };
+}
+
+// Wie is_public_session, das Event wird dafür geladen
+pub async fn publicly_visible(database: &mongodb::Database, session: &Session) -> DatabaseResult<bool> {
+    if session.access.visibility != SessionVisibility::Public {
+        return Ok(false);
+    }
+    let event = match &session.event {
+        Some(placement) => get_event_by_id(database, &placement.event_id).await?,
+        None => None
+    };
+    return Ok(is_public_session(session, event.as_ref()));
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use crate::events::{EventBranding, EventPlacement};
+    use mongodb::bson::Bson;
+
+    fn viewer(role: &str, groups: &[&str]) -> Viewer {
+        return Viewer {
+            username: "max".to_string(),
+            role: role.to_string(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            hidden_events: Vec::new(),
+            unlisted_events: Vec::new(),
+            events_locked: false
+        };
+    }
+
+    fn restricted(users: &[&str], groups: &[&str], roles: &[&str]) -> SessionAccess {
+        return SessionAccess {
+            visibility: SessionVisibility::Restricted,
+            users: users.iter().map(|u| u.to_string()).collect(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            roles: roles.iter().map(|r| r.to_string()).collect()
+        };
+    }
+
+    #[test]
+    fn test_public_and_unlisted() {
+        let user = viewer("USER", &[]);
+        assert!(user.can_view(&SessionAccess::default()));
+        assert!(user.can_list(&SessionAccess::default()));
+
+        let unlisted = SessionAccess { visibility: SessionVisibility::Unlisted, ..SessionAccess::default() };
+        assert!(user.can_view(&unlisted));
+        assert!(!user.can_list(&unlisted));
+        assert!(viewer("ADMIN", &[]).can_list(&unlisted));
+    }
+
+    #[test]
+    fn test_restricted() {
+        assert!(viewer("USER", &[]).can_view(&restricted(&["max"], &[], &[])));
+        assert!(viewer("USER", &["team-a"]).can_view(&restricted(&[], &["team-a", "team-b"], &[])));
+        assert!(viewer("MODERATOR", &[]).can_list(&restricted(&[], &[], &["MODERATOR"])));
+        assert!(!viewer("USER", &["team-c"]).can_view(&restricted(&["moritz"], &["team-a"], &["MODERATOR"])));
+        // Admins dürfen immer alles sehen, sonst könnten sie die Session nicht verwalten
+        assert!(viewer("ADMIN", &[]).can_view(&restricted(&[], &[], &[])));
+    }
+
+    #[test]
+    fn test_listing_filter() {
+        assert!(viewer("ADMIN", &[]).listing_filter().is_none());
+        let filter = viewer("USER", &["team-a"]).listing_filter().unwrap();
+        assert_eq!(filter.get_array("$or").unwrap().len(), 2);
+    }
+
+    fn event(access: SessionAccess) -> Event {
+        return Event {
+            id: ObjectId::new(),
+            name: "Konferenz".to_string(),
+            slug: "konferenz".to_string(),
+            description: "".to_string(),
+            start_date: NaiveDate::from_ymd(2022, 9, 1),
+            end_date: NaiveDate::from_ymd(2022, 9, 2),
+            branding: EventBranding::default(),
+            tracks: Vec::new(),
+            access,
+            created_at: Utc::now()
+        };
+    }
+
+    fn session(event: &Event, access: SessionAccess) -> Session {
+        return Session {
+            slug: "test".to_string(),
+            start: Utc.ymd(2022, 9, 1).and_hms(8, 0, 0),
+            end: Utc.ymd(2022, 9, 1).and_hms(9, 0, 0),
+            name: "Test".to_string(),
+            access,
+            event: Some(EventPlacement { event_id: event.id, track: None }),
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn test_event_inheritance() {
+        let closed = event(restricted(&[], &["team-a"], &[]));
+        let hidden = event(SessionAccess { visibility: SessionVisibility::Unlisted, ..SessionAccess::default() });
+        let open = event(SessionAccess::default());
+
+        let user = viewer("USER", &[]).with_events(&[closed.clone(), hidden.clone(), open.clone()]);
+        assert_eq!(user.hidden_events, vec![closed.id]);
+        assert_eq!(user.unlisted_events, vec![closed.id, hidden.id]);
+        assert!(viewer("USER", &["team-a"]).with_events(&[closed.clone()]).hidden_events.is_empty());
+
+        // Die Regeln der Session gelten zusätzlich zu denen des Events
+        assert!(!user.can_view_session(&session(&closed, SessionAccess::default())));
+        assert!(user.can_view_session(&session(&hidden, SessionAccess::default())));
+        assert!(!user.can_list_session(&session(&hidden, SessionAccess::default())));
+        assert!(user.can_list_session(&session(&open, SessionAccess::default())));
+        assert!(!user.can_view_session(&session(&open, restricted(&["moritz"], &[], &[]))));
+
+        let filter = user.listing_filter().unwrap();
+        assert_eq!(filter.get_document("event.event_id").unwrap().get_array("$nin").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_is_public_session() {
+        let public = event(SessionAccess::default());
+        let closed = event(restricted(&[], &["team-a"], &[]));
+        let unlisted = SessionAccess { visibility: SessionVisibility::Unlisted, ..SessionAccess::default() };
+
+        assert!(is_public_session(&Session { event: None, ..session(&public, SessionAccess::default()) }, None));
+        assert!(!is_public_session(&Session { event: None, ..session(&public, unlisted.clone()) }, None));
+
+        // Die Session erbt die Sichtbarkeit ihres Events
+        assert!(is_public_session(&session(&public, SessionAccess::default()), Some(&public)));
+        assert!(!is_public_session(&session(&closed, SessionAccess::default()), Some(&closed)));
+        assert!(!is_public_session(&session(&public, unlisted), Some(&public)));
+        assert!(!is_public_session(&session(&public, SessionAccess::default()), None));
+    }
+
+    #[test]
+    fn test_events_locked() {
+        // Ohne geladene Events sind alle Sessions in Events gesperrt, Sessions ohne Event bleiben sichtbar
+        let open = event(SessionAccess::default());
+        let user = Viewer { events_locked: true, ..viewer("USER", &[]) };
+        assert!(!user.can_view_session(&session(&open, SessionAccess::default())));
+        assert!(!user.can_list_session(&session(&open, SessionAccess::default())));
+        assert!(user.can_view_session(&Session { event: None, ..session(&open, SessionAccess::default()) }));
+
+        let filter = user.listing_filter().unwrap();
+        assert_eq!(filter.get("event.event_id"), Some(&Bson::Null));
+    }
+
+    #[test]
+    fn test_parse_list_and_validation() {
+        assert_eq!(parse_list(" anna, team-a ,,anna "), vec!["anna".to_string(), "team-a".to_string()]);
+        assert!(parse_list("").is_empty());
+        assert!(valid_visibility(&Some("unlisted")).is_ok());
+        assert!(valid_visibility(&Some("")).is_ok());
+        assert!(valid_visibility(&None).is_ok());
+        assert!(valid_visibility(&Some("secret")).is_err());
+        assert!(valid_roles(&Some("ADMIN, USER")).is_ok());
+        assert!(valid_roles(&Some("-")).is_ok());
+        assert!(valid_roles(&Some("ADMIN, GAST")).is_err());
+    }
+}
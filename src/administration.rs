@@ -1,19 +1,26 @@
 use std::collections::HashMap;
 use serde::Serialize;
-use chrono::{DateTime, NaiveDateTime, Utc};
-use rocket::form::Form;
+use chrono::NaiveDateTime;
+use rocket::form::{self, Contextual, Form};
 use rocket::fs::FileServer;
-use rocket::http::{Cookie, CookieJar};
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
 use rocket_dyn_templates::{Template, context};
-use crate::database::{add_new_session, get_client, get_session_by_name, get_standard_database, remove_session_by_name, update_session};
-use crate::ObjectId;
-use crate::sessions::{Session, SessionStream, StreamType, User};
+use crate::database::{add_session_with_unique_slug, get_client, get_session_by_key, get_standard_database, trash_session_by_id, update_session, DatabaseError};
+use crate::sessions::{create_slug, Session, SessionStream, User};
+use crate::access::{parse_list, valid_roles, valid_visibility, visibility_options, SessionAccess, SessionVisibility, VisibilityOption};
+use crate::markdown::{DescriptionFormat, MAX_DESCRIPTION_LENGTH};
+use crate::lifecycle::{refresh_session_status, Lifecycle, DEFAULT_ARCHIVE_AFTER_DAYS};
+use crate::providers::{parse_fallback_link, parse_stream_link, plattform_options, stream_type_from_plattform, valid_fallback_link, valid_stream_link, PlattformOption};
 
 
 use crate::security::validate_token;
 use crate::security::{SecurityToken, SecurityRole};
 
 use crate::sessions::FORMAT_STR;
+use crate::timezone::{local_to_utc, parse_timezone, timezone_names, user_timezone, utc_to_local, valid_timezone};
 
 
 
@@ -21,7 +28,47 @@ use crate::sessions::FORMAT_STR;
 struct AdminContext<'a> {
     jwt: &'a str,
     fullname: &'a str,
-    token: SecurityToken
+    token: SecurityToken,
+    error: Option<String>,
+    plattforms: Vec<PlattformOption>,
+    visibilities: Vec<VisibilityOption>,
+    // Vorgabe für die Zeitzone neuer Sessions und Auswahl aller Zeitzonen
+    timezone: String,
+    timezones: Vec<&'static str>,
+    form: FormFeedback
+}
+
+//Fehler je Feld und die abgeschickten Werte, damit das Formular erneut befüllt werden kann
+#[derive(Serialize, Default)]
+pub(crate) struct FormFeedback {
+    pub(crate) errors: HashMap<String, String>,
+    pub(crate) values: HashMap<String, String>
+}
+
+impl FormFeedback {
+    pub(crate) fn from_context(context: &form::Context<'_>, fields: &[&str]) -> FormFeedback {
+        let mut feedback = FormFeedback::default();
+        for field in fields {
+            if let Some(value) = context.field_value(*field) {
+                feedback.values.insert(field.to_string(), value.to_string());
+            }
+        }
+        //Pro Feld wird nur der erste Fehler angezeigt
+        for error in context.errors() {
+            if let Some(name) = &error.name {
+                feedback.errors.entry(name.to_string()).or_insert_with(|| error.to_string());
+            }
+        }
+        return feedback;
+    }
+}
+
+//Feld-Validierung für Zeitangaben im Format FORMAT_STR
+pub(crate) fn valid_date_time<'v>(value: &str) -> form::Result<'v, ()> {
+    match NaiveDateTime::parse_from_str(value, FORMAT_STR) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(form::Error::validation("Bitte im Format D.M.Y H:M:SEC angeben, z.B. 09.07.2022 18:00:00").into())
+    }
 }
 
 fn get_token_value(token: Option<Cookie>)->Cookie{
@@ -67,7 +114,13 @@ pub fn show_overview(cookies: &CookieJar<'_>) -> Template {
             return Template::render("sessions/admin", AdminContext {
                 jwt: token_value.value(),
                 fullname: fullname_value.value(),
-                token: t
+                token: t,
+                error: None,
+                plattforms: plattform_options(),
+                visibilities: visibility_options(),
+                timezone: user_timezone(cookies),
+                timezones: timezone_names(),
+                form: FormFeedback::default()
             });
         },
         None => {
@@ -78,25 +131,58 @@ pub fn show_overview(cookies: &CookieJar<'_>) -> Template {
 }
 
 //Struct mit allen Inputs für eine Session als Strings, die Inputs müssen eine minimal Länge von 1 haben
+//Der Channel wird nicht mehr eingegeben, sondern aus dem Link der gewählten Plattform abgeleitet
 #[derive(FromForm, Debug)]
 pub struct NewSession<'r> {
-    #[field(validate = len(1..))]
+    #[field(validate = valid_date_time())]
     start: &'r str, //Umformatierung in Datetime
-    #[field(validate = len(1..))]
+    #[field(validate = valid_date_time())]
     end: &'r str,
+    //IANA-Zeitzone, in der Beginn und Ende eingegeben wurden
+    #[field(validate = valid_timezone())]
+    timezone: &'r str,
     #[field(validate = len(1..))]
     name: &'r str,
-    #[field(validate = len(1..))]
+    //Markdown, wird beim Anzeigen gerendert und bereinigt
+    #[field(validate = len(1..=MAX_DESCRIPTION_LENGTH))]
     description: &'r str,
-    #[field(validate = len(1..))]
+    #[field(validate = valid_stream_link(self.plattform))]
     link: &'r str,
     #[field(validate = len(1..))]
-    channel: &'r str,
-    #[field(validate = len(1..))]
     plattform: &'r str,
+    //Optionale Ausweich-Quelle für selbst gehostete Streams
+    #[field(validate = valid_fallback_link())]
+    fallback: Option<&'r str>,
+    //Sichtbarkeit und Freigaben, die Listen sind komma-getrennt
+    #[field(validate = valid_visibility())]
+    visibility: Option<&'r str>,
+    allowed_users: Option<&'r str>,
+    allowed_groups: Option<&'r str>,
+    #[field(validate = valid_roles())]
+    allowed_roles: Option<&'r str>,
 
 }
 
+const NEW_SESSION_FIELDS: [&str; 12] = ["start", "end", "timezone", "name", "description", "link", "plattform", "fallback",
+    "visibility", "allowed_users", "allowed_groups", "allowed_roles"];
+
+//Leere Eingaben werden als "keine Ausweich-Quelle" gespeichert
+pub(crate) fn fallback_value(fallback: Option<&str>) -> Option<String> {
+    return fallback.map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(str::to_string);
+}
+
+//Zugriffsregeln aus den Formularfeldern, ohne Auswahl ist die Session public
+pub(crate) fn access_value(visibility: Option<&str>, users: Option<&str>, groups: Option<&str>, roles: Option<&str>) -> SessionAccess {
+    return SessionAccess {
+        visibility: visibility.and_then(SessionVisibility::parse).unwrap_or_default(),
+        users: parse_list(users.unwrap_or("")),
+        groups: parse_list(groups.unwrap_or("")),
+        roles: parse_list(roles.unwrap_or(""))
+    };
+}
+
 //Anzeigen des Creation-Templates für Sessions
 #[get("/session/list/create")]
 pub async fn ask_session_detail(cookies: &CookieJar<'_>) -> Template
@@ -124,7 +210,13 @@ pub async fn ask_session_detail(cookies: &CookieJar<'_>) -> Template
             return Template::render("admin/create_session", AdminContext {
                 jwt: token_value.value(),
                 fullname: fullname_value.value(),
-                token: t
+                token: t,
+                error: None,
+                plattforms: plattform_options(),
+                visibilities: visibility_options(),
+                timezone: user_timezone(cookies),
+                timezones: timezone_names(),
+                form: FormFeedback::default()
             });
 
         },
@@ -137,7 +229,7 @@ pub async fn ask_session_detail(cookies: &CookieJar<'_>) -> Template
 
 //Methode zum Erstellen von Sessions
 #[post("/admin/session/add",  data = "<newSession>")]
-pub async  fn add_session(newSession:  Form<NewSession<'_>>, cookies: &CookieJar<'_>)-> Template{
+pub async  fn add_session(newSession:  Form<Contextual<'_, NewSession<'_>>>, cookies: &CookieJar<'_>)-> Template{
     // streamie.live ist der Standardcookie für den Auth-Token
     let token: Option<Cookie> = cookies.get_private("streamie.live");
     let token_value = match token {
@@ -154,36 +246,94 @@ pub async  fn add_session(newSession:  Form<NewSession<'_>>, cookies: &CookieJar
             if t.role != SecurityRole::ADMIN {
                 return  Template::render("unauthorized", context!{});
             }
+
+            let fullname: Option<Cookie> = cookies.get_private("fullname");
+            let fullname_value = get_fullname(fullname);
+
+            //Bei ungültigen Eingaben wird das Formular mit den Fehlern je Feld erneut angezeigt
+            let new_session = match newSession.value {
+                Some(ref value) => value,
+                None => {
+                    return Template::render("admin/create_session", AdminContext {
+                        jwt: token_value.value(),
+                        fullname: fullname_value.value(),
+                        token: t,
+                        error: None,
+                        plattforms: plattform_options(),
+                        visibilities: visibility_options(),
+                        timezone: user_timezone(cookies),
+                        timezones: timezone_names(),
+                        form: FormFeedback::from_context(&newSession.context, &NEW_SESSION_FIELDS)
+                    });
+                }
+            };
+
             //Umformatierung der Daten aus Strings in die richtigen Formate , wie bsp. Datetimes
+            //Die Zeiten werden in der gewählten Zeitzone eingegeben und als UTC gespeichert
             let database = get_standard_database().await;
-            let startS = DateTime::<Utc>::from_utc(NaiveDateTime::parse_from_str(
-                newSession.start, FORMAT_STR).expect("failed to parse startDateTime"), Utc);
-            let endS = DateTime::<Utc>::from_utc(NaiveDateTime::parse_from_str(
-                newSession.end, FORMAT_STR).expect("failed to parse startDateTime"), Utc);
-            let mut stream_type_session= StreamType::Twitch;
-            //Zuweisung des Stream_types-Enums
-            if newSession.plattform.to_string().eq("Youtube") {
-                stream_type_session = StreamType::Youtube;
-            }else if newSession.plattform.to_string().eq("None") {
-                stream_type_session = StreamType::None;
-            }else {
-                stream_type_session = StreamType::Twitch;
-            }
+            let tz = parse_timezone(new_session.timezone).expect("timezone was validated");
+            let (startS, endS) = match (local_to_utc(new_session.start, tz), local_to_utc(new_session.end, tz)) {
+                (Ok(start), Ok(end)) => (start, end),
+                (start, end) => {
+                    let mut form = FormFeedback::from_context(&newSession.context, &NEW_SESSION_FIELDS);
+                    for (field, result) in [("start", start), ("end", end)] {
+                        if let Err(e) = result {
+                            form.errors.insert(field.to_string(), e);
+                        }
+                    }
+                    return Template::render("admin/create_session", AdminContext {
+                        jwt: token_value.value(),
+                        fullname: fullname_value.value(),
+                        token: t,
+                        error: None,
+                        plattforms: plattform_options(),
+                        visibilities: visibility_options(),
+                        timezone: user_timezone(cookies),
+                        timezones: timezone_names(),
+                        form
+                    });
+                }
+            };
+            //Zuweisung des Stream_types-Enums über die registrierten Provider
+            let stream_type_session = stream_type_from_plattform(new_session.plattform);
+            //Der Link wurde bereits validiert, daraus wird die kanonische ID als channel übernommen
+            let channel = parse_stream_link(&stream_type_session, new_session.link).unwrap_or_default();
             //Erstellung einer neuen Session aus den erhaltenen und Umformatierten Daten
-            let sessionD:Session = Session{
-                id: ObjectId::new(),
+            //Der slug wird beim Speichern vergeben, gleichnamige Sessions bekommen eine Nummer angehängt
+            let mut sessionD:Session = Session{
+                slug: create_slug(new_session.name),
                 start: startS,
                 end:  endS,
-                name: newSession.name.to_string(),
-                description: newSession.description.to_string(),
+                name: new_session.name.to_string(),
+                description: new_session.description.to_string(),
                 stream:SessionStream{
-                    link: newSession.link.to_string(),
-                    channel: newSession.channel.to_string(),
-                    stream_type: stream_type_session
+                    link: new_session.link.trim().to_string(),
+                    channel,
+                    stream_type: stream_type_session,
+                    fallback: fallback_value(new_session.fallback)
                 },
+                access: access_value(new_session.visibility, new_session.allowed_users,
+                                     new_session.allowed_groups, new_session.allowed_roles),
+                timezone: tz.name().to_string(),
+                description_format: DescriptionFormat::Markdown,
+                ..Default::default()
             };
             //Eingabe der Session in die DB und dortige Erstellung
-            add_new_session(&database, &sessionD).await;
+            //Schlägt das Speichern fehl, wird das Formular mit einem Hinweis erneut angezeigt
+            if let Err(e) = add_session_with_unique_slug(&database, &mut sessionD).await {
+                error!("Failed to add session: {}", e);
+                return Template::render("admin/create_session", AdminContext {
+                    jwt: token_value.value(),
+                    fullname: fullname_value.value(),
+                    token: t,
+                    error: Some("Die Session konnte nicht gespeichert werden.".to_string()),
+                    plattforms: plattform_options(),
+                    visibilities: visibility_options(),
+                    timezone: user_timezone(cookies),
+                    timezones: timezone_names(),
+                    form: FormFeedback::from_context(&newSession.context, &NEW_SESSION_FIELDS)
+                });
+            }
             return show_overview(cookies)
         },
         None => {
@@ -222,8 +372,13 @@ pub async fn ask_session_detail_update(cookies: &CookieJar<'_>) ->Template
                     jwt: token_value.value(),
                     fullname: fullname_value.value(),
 
-                    token: t
-
+                    token: t,
+                    error: None,
+                    plattforms: plattform_options(),
+                    visibilities: visibility_options(),
+                    timezone: user_timezone(cookies),
+                    timezones: timezone_names(),
+                    form: FormFeedback::default()
                 });
         },
         None => {
@@ -233,30 +388,177 @@ pub async fn ask_session_detail_update(cookies: &CookieJar<'_>) ->Template
 
 }
 
-//Struct mit den Werten die Upgedaten werden sollen, können die Länge 0 sein => Felder müssen nicht gesetzt sein, außer old_name
+//Update-Template mit dem aktuellen Stand einer Session, die per id oder slug adressiert wird
+#[derive(Serialize)]
+struct EditContext<'a> {
+    #[serde(flatten)]
+    admin: AdminContext<'a>,
+    current: SessionState
+}
+
+//Anzeigen des Update-Templates, bereits mit den Werten der Session befüllt
+#[get("/admin/session/<key>/edit")]
+pub async fn edit_session(key: &str, cookies: &CookieJar<'_>) -> Option<Template>
+{
+    // streamie.live ist der Standardcookie für den Auth-Token
+    let token: Option<Cookie> = cookies.get_private("streamie.live");
+    let token_value = get_token_value(token);
+
+    match validate_token(token_value.value().to_string()) {
+        Some(t) if t.role == SecurityRole::ADMIN => {
+            let database = get_standard_database().await;
+            let session = get_session_by_key(&database, key).await.ok()??;
+
+            let fullname: Option<Cookie> = cookies.get_private("fullname");
+            let fullname_value = get_fullname(fullname);
+
+            return Some(Template::render("admin/update_session", EditContext {
+                admin: AdminContext {
+                    jwt: token_value.value(),
+                    fullname: fullname_value.value(),
+                    token: t,
+                    error: None,
+                    plattforms: plattform_options(),
+                    visibilities: visibility_options(),
+                    timezone: user_timezone(cookies),
+                    timezones: timezone_names(),
+                    form: FormFeedback::default()
+                },
+                current: SessionState::from_session(&session)
+            }));
+        },
+        _ => {
+            return Some(Template::render("unauthorized", context!{}));
+        }
+    }
+}
+
+//Struct mit den Werten die Upgedaten werden sollen, können die Länge 0 sein => Felder müssen nicht gesetzt sein
+//Die Session selbst wird über die id oder den slug in der URL bestimmt
+//version ist der Stand der Session, auf dem die Änderung basiert (optimistische Sperre), ohne version wird mit 422 abgelehnt
 #[derive(FromForm)]
 pub struct UpSession<'r> {
     #[field(validate = len(0..))]
     start: &'r str, //Umformatierung in Datetime
     #[field(validate = len(0..))]
     end: &'r str,
+    //Leer lässt die Zeitzone der Session unverändert
+    timezone: Option<&'r str>,
     #[field(validate = len(0..))]
     name: &'r str,
-    #[field(validate = len(0..))]
+    #[field(validate = len(0..=MAX_DESCRIPTION_LENGTH))]
     description: &'r str,
     #[field(validate = len(0..))]
     link: &'r str,
     #[field(validate = len(0..))]
-    channel: &'r str,
-    #[field(validate = len(0..))]
     plattform: &'r str,
-    #[field(validate = len(1..))]
-    old_name: &'r str,
+    #[field(validate = len(0..))]
+    fallback: Option<&'r str>,
+    //Leere Eingaben lassen die Freigaben unverändert, "-" leert eine Liste
+    visibility: Option<&'r str>,
+    allowed_users: Option<&'r str>,
+    allowed_groups: Option<&'r str>,
+    allowed_roles: Option<&'r str>,
+    version: Option<i64>,
+}
+
+//Aktueller Stand einer Session in der Form des Update-Formulars, wird zum Befüllen und für den Merge-Dialog genutzt
+#[derive(Serialize, Debug)]
+pub struct SessionState {
+    pub id: String,
+    pub slug: String,
+    pub name: String,
+    pub description: String,
+    pub start: String,
+    pub end: String,
+    pub timezone: String,
+    pub link: String,
+    pub channel: String,
+    pub plattform: String,
+    pub fallback: String,
+    pub visibility: String,
+    pub allowed_users: String,
+    pub allowed_groups: String,
+    pub allowed_roles: String,
+    pub version: i64,
+}
+
+impl SessionState {
+    pub fn from_session(session: &Session) -> SessionState {
+        return SessionState {
+            id: session.id.to_hex(),
+            slug: session.slug.clone(),
+            name: session.name.clone(),
+            description: session.description.clone(),
+            start: utc_to_local(session.start, session.tz()),
+            end: utc_to_local(session.end, session.tz()),
+            timezone: session.timezone.clone(),
+            link: session.stream.link.clone(),
+            channel: session.stream.channel.clone(),
+            plattform: format!("{:?}", session.stream.stream_type),
+            fallback: session.stream.fallback.clone().unwrap_or_default(),
+            visibility: session.access.visibility.value().to_string(),
+            allowed_users: session.access.users.join(", "),
+            allowed_groups: session.access.groups.join(", "),
+            allowed_roles: session.access.roles.join(", "),
+            version: session.version,
+        };
+    }
+}
+
+//Antwort des Updates: status 0 = fehlgeschlagen, 1 = gespeichert, 2 = Konflikt
+//session enthält den aktuellen Stand auf dem Server, errors die Fehler je Feld
+#[derive(Serialize)]
+pub struct UpdateResult {
+    pub status: u8,
+    pub session: Option<SessionState>,
+    pub errors: HashMap<String, String>,
+}
+
+fn update_result(status: Status, result: u8, session: Option<SessionState>) -> status::Custom<Json<UpdateResult>> {
+    return status::Custom(status, Json(UpdateResult {
+        status: result,
+        session,
+        errors: HashMap::new()
+    }));
+}
+
+fn invalid_field(field: &str, message: String) -> status::Custom<Json<UpdateResult>> {
+    let mut errors = HashMap::new();
+    errors.insert(field.to_string(), message);
+    return status::Custom(Status::UnprocessableEntity, Json(UpdateResult {
+        status: 0,
+        session: None,
+        errors
+    }));
+}
+
+//Liefert den aktuellen Stand einer Session für das Update-Formular
+#[get("/admin/session/<key>/state")]
+pub async fn session_state(key: &str, cookies: &CookieJar<'_>) -> Option<Json<SessionState>> {
+    // streamie.live ist der Standardcookie für den Auth-Token
+    let token: Option<Cookie> = cookies.get_private("streamie.live");
+    let token_value = match token {
+        Some(jwt) => jwt,
+        None => Cookie::new("streamie.live", "None")
+    };
+
+    match validate_token(token_value.value().to_string()) {
+        Some(t) if t.role == SecurityRole::ADMIN => {
+            let database = get_standard_database().await;
+            return get_session_by_key(&database, key).await.ok()?
+                .map(|session| Json(SessionState::from_session(&session)));
+        },
+        _ => {
+            return None;
+        }
+    }
 }
 
 //Methode zum Updaten der Session mit einem Input aus Daten die in dem obigen Struct übergeben werden
-#[put("/admin/session/update",  data = "<updated_session>")]
-pub async  fn admin_update_session(updated_session:  Form<UpSession<'_>>, cookies: &CookieJar<'_>)-> () {
+//Wurde die Session seit dem Laden des Formulars geändert, wird mit 409 und dem aktuellen Stand geantwortet
+#[put("/admin/session/<key>",  data = "<updated_session>")]
+pub async  fn admin_update_session(key: &str, updated_session:  Form<UpSession<'_>>, lifecycle: &State<Lifecycle>, cookies: &CookieJar<'_>)-> status::Custom<Json<UpdateResult>> {
 
     // streamie.live ist der Standardcookie für den Auth-Token
     let token: Option<Cookie> = cookies.get_private("streamie.live");
@@ -272,20 +574,57 @@ pub async  fn admin_update_session(updated_session:  Form<UpSession<'_>>, cookie
         Some(t) => {
 
                 if t.role != SecurityRole::ADMIN {
-                    return;
+                    return update_result(Status::Ok, 0, None);
                 }
 
+                //Ohne version kann ein veralteter Stand nicht erkannt werden, die Änderung wird abgelehnt
+                let version = match updated_session.version {
+                    Some(version) => version,
+                    None => return invalid_field("version", "Der Stand der Session fehlt, bitte das Formular neu laden".to_string())
+                };
+
                 let database = get_standard_database().await;
-                let mut session: Session = get_session_by_name(&database, updated_session.old_name.to_string()).await;
+                let mut session: Session = match get_session_by_key(&database, key).await {
+                    Ok(Some(session)) => session,
+                    Ok(None) => return update_result(Status::NotFound, 0, None),
+                    Err(e) => {
+                        error!("Failed to load session: {}", e);
+                        return update_result(Status::InternalServerError, 0, None);
+                    }
+                };
+
+                //Basiert die Änderung auf einem veralteten Stand, wird sofort ein Konflikt gemeldet
+                if version != session.version {
+                    return update_result(Status::Conflict, 2, Some(SessionState::from_session(&session)));
+                }
+                //Das Update prüft beim Schreiben die version aus dem Formular, nicht den eben geladenen Stand
+                session.version = version;
+
+                //Eine neue Zeitzone gilt für die Eingaben im selben Formular,
+                //ohne neue Zeiten bleiben Beginn und Ende als Zeitpunkt unverändert
+                if let Some(timezone) = updated_session.timezone.map(str::trim).filter(|tz| !tz.is_empty()) {
+                    match parse_timezone(timezone) {
+                        Some(tz) => session.timezone = tz.name().to_string(),
+                        None => return invalid_field("timezone", format!("Unbekannte Zeitzone '{}', z.B. Europe/Berlin", timezone))
+                    }
+                }
+
+                //Zeitangaben werden vor dem Übernehmen in der Zeitzone der Session umgerechnet
+                for (field, value) in [("start", updated_session.start), ("end", updated_session.end)] {
+                    if !value.is_empty() {
+                        match local_to_utc(value, session.tz()) {
+                            Ok(date) if field == "start" => session.start = date,
+                            Ok(date) => session.end = date,
+                            Err(e) => return invalid_field(field, e)
+                        }
+                    }
+                }
 
                 //alle neuen Werte werden in einer Hash Map gespeichert
                 let mut map :HashMap<&str, &str> = HashMap::new();
                 map.insert("name", updated_session.name);
                 map.insert("description", updated_session.description);
-                map.insert("start", updated_session.start);
-                map.insert("end", updated_session.end);
                 map.insert("link", updated_session.link);
-                map.insert("channel", updated_session.channel);
                 map.insert("plattform", updated_session.plattform);
 
                 //iteration über die hash map  Überprüfung ob der neue Wert existiert
@@ -294,29 +633,83 @@ pub async  fn admin_update_session(updated_session:  Form<UpSession<'_>>, cookie
                         //wenn der Wert nicht null ist wird er neu gesetzt, sonst bleibt der alte Wert bestand
                         match key {
                             &"name" => session.name = val.to_string(),
-                            &"description"=> session.description = val.to_string(),
-                            &"start"=> session.start = DateTime::<Utc>::from_utc(NaiveDateTime::parse_from_str(
-                                val, FORMAT_STR).expect("failed to parse startDateTime"), Utc),
-                            &"end" => session.end =  DateTime::<Utc>::from_utc(NaiveDateTime::parse_from_str(
-                                val, FORMAT_STR).expect("failed to parse startDateTime"), Utc),
-                            &"channel"=> session.stream.channel = val.to_string(),
-                            &"link" => session.stream.link = val.to_string(),
-                            &"plattform"=>if updated_session.plattform.to_string().eq("Youtube") {
-                                                session.stream.stream_type = StreamType::Youtube;
-                                            }else if updated_session.plattform.to_string().eq("None") {
-                                                session.stream.stream_type = StreamType::None;
-                                            }else {
-                                                session.stream.stream_type = StreamType::Twitch;
-                                            }
+                            //Eine geänderte Beschreibung stammt aus dem Markdown-Editor, unverändert bleibt Altbestand Klartext
+                            &"description"=> if *val != session.description {
+                                session.description = val.to_string();
+                                session.description_format = DescriptionFormat::Markdown;
+                            },
+                            &"link" => session.stream.link = val.trim().to_string(),
+                            &"plattform"=> session.stream.stream_type = stream_type_from_plattform(val),
                             _ => {}
                         }
                     }
                 }
-            //session wird in der Datenbank geupdated
-                update_session(&database, &session).await;
+
+                //Die Ausweich-Quelle wird nur bei einer Eingabe geändert, "-" entfernt sie
+                match updated_session.fallback.map(str::trim) {
+                    Some("-") => session.stream.fallback = None,
+                    Some(fallback) if !fallback.is_empty() => match parse_fallback_link(fallback) {
+                        Ok(fallback) => session.stream.fallback = Some(fallback),
+                        Err(e) => return invalid_field("fallback", e.to_string())
+                    },
+                    _ => {}
+                }
+
+                //Freigaben werden wie die Ausweich-Quelle nur bei einer Eingabe geändert
+                if let Err(e) = valid_visibility(&updated_session.visibility) {
+                    return invalid_field("visibility", e.to_string());
+                }
+                if let Err(e) = valid_roles(&updated_session.allowed_roles) {
+                    return invalid_field("allowed_roles", e.to_string());
+                }
+                if let Some(visibility) = updated_session.visibility.and_then(SessionVisibility::parse) {
+                    session.access.visibility = visibility;
+                }
+                for (list, value) in [(&mut session.access.users, updated_session.allowed_users),
+                                      (&mut session.access.groups, updated_session.allowed_groups),
+                                      (&mut session.access.roles, updated_session.allowed_roles)] {
+                    match value.map(str::trim) {
+                        Some("-") => list.clear(),
+                        Some(entries) if !entries.is_empty() => *list = parse_list(entries),
+                        _ => {}
+                    }
+                }
+
+                //Bei geändertem Link oder Plattform wird der channel neu aus dem Link abgeleitet
+                if !updated_session.link.is_empty() || !updated_session.plattform.is_empty() {
+                    match parse_stream_link(&session.stream.stream_type, &session.stream.link) {
+                        Ok(channel) => session.stream.channel = channel,
+                        Err(e) => return invalid_field("link", e.to_string())
+                    }
+                }
+            //session wird in der Datenbank geupdated, die version wird dabei geprüft
+                match update_session(&database, &session).await {
+                    Ok(()) => {
+                        session.version += 1;
+                        //Geänderte Zeiten wirken sich sofort auf den Status aus
+                        refresh_session_status(&database, lifecycle, &session.id).await;
+                        return update_result(Status::Ok, 1, Some(SessionState::from_session(&session)));
+                    },
+                    Err(DatabaseError::Conflict(_)) => {
+                        //Zwischen Laden und Schreiben wurde die Session geändert -> aktuellen Stand mitliefern,
+                        //wurde sie inzwischen gelöscht, gibt es keinen Stand mehr
+                        return match get_session_by_key(&database, &session.id.to_hex()).await {
+                            Ok(Some(current)) => update_result(Status::Conflict, 2, Some(SessionState::from_session(&current))),
+                            Ok(None) => update_result(Status::NotFound, 0, None),
+                            Err(e) => {
+                                error!("Failed to reload session: {}", e);
+                                update_result(Status::InternalServerError, 0, None)
+                            }
+                        };
+                    },
+                    Err(e) => {
+                        error!("Failed to update session: {}", e);
+                        return update_result(Status::InternalServerError, 0, None);
+                    }
+                }
         },
         None => {
-
+                return update_result(Status::Ok, 0, None);
         }
     }
 }
@@ -348,8 +741,13 @@ pub async fn ask_session_detail_delete(cookies: &CookieJar<'_>) ->Template
                 return Template::render("admin/delete_session", AdminContext {
                     jwt: token_value.value(),
                     fullname: fullname_value.value(),
-                    token: t
-
+                    token: t,
+                    error: None,
+                    plattforms: plattform_options(),
+                    visibilities: visibility_options(),
+                    timezone: user_timezone(cookies),
+                    timezones: timezone_names(),
+                    form: FormFeedback::default()
                 });
         },
         None => {
@@ -359,16 +757,9 @@ pub async fn ask_session_detail_delete(cookies: &CookieJar<'_>) ->Template
 
 }
 
-//Struct mit einem String der mindestens 1 groß sein muss, wird benötigt zum löschen
-#[derive(FromForm)]
-pub struct DelSession<'r> {
-    #[field(validate = len(1..))]
-    name: &'r str,
-}
-
-//Löschen Einer Session aktuell über den Namen der Session
-#[delete("/session/delete/<stream_name>" )]
-pub async fn delete_session( stream_name: &str ,cookies: &CookieJar<'_>) -> () {
+//Löschen einer Session über ihre id oder ihren slug, die Session landet im Papierkorb
+#[delete("/admin/session/<key>")]
+pub async fn delete_session(key: &str, cookies: &CookieJar<'_>) -> Status {
     // streamie.live ist der Standardcookie für den Auth-Token
     let token: Option<Cookie> = cookies.get_private("streamie.live");
     let token_value = match token {
@@ -383,15 +774,30 @@ pub async fn delete_session( stream_name: &str ,cookies: &CookieJar<'_>) -> () {
         Some(t) => {
 
             if t.role != SecurityRole::ADMIN {
-                return;
+                return Status::Unauthorized;
             }
 
-            //Datenbank wird geholt und Session wird aus dieser gelöscht
+            //Datenbank wird geholt und Session wird in den Papierkorb verschoben
             let database = get_standard_database().await;
-            remove_session_by_name(&database, stream_name.to_string()).await;
+            let session = match get_session_by_key(&database, key).await {
+                Ok(Some(session)) => session,
+                Ok(None) => return Status::NotFound,
+                Err(e) => {
+                    error!("Failed to load session: {}", e);
+                    return Status::InternalServerError;
+                }
+            };
+            match trash_session_by_id(&database, &session.id).await {
+                Ok(true) => return Status::Ok,
+                Ok(false) => return Status::NotFound,
+                Err(e) => {
+                    warn!("Session '{}' could not be deleted: {}", key, e);
+                    return Status::InternalServerError;
+                }
+            }
         },
         None => {
-            return;
+            return Status::Unauthorized;
         }
     }
 }
@@ -405,12 +811,15 @@ fn rocket() -> _ {
         add_session,
         ask_session_detail,
         ask_session_detail_update,
+        edit_session,
+        session_state,
         admin_update_session,
         ask_session_detail_delete,
         delete_session,
 
     ])
         .attach(Template::fairing())
+        .manage(Lifecycle::new(DEFAULT_ARCHIVE_AFTER_DAYS))
 }
 
 #[cfg(test)]
@@ -418,6 +827,7 @@ mod tests {
     use cookie::Expiration::Session;
     use futures::task::Spawn;
     use mongodb::Database;
+    use mongodb::bson::oid::ObjectId;
     use super::rocket;
     use super::*;
     use rocket::http::Status;
@@ -427,8 +837,9 @@ mod tests {
     use rocket::response::Body;
     use crate::{ContentType, SessionStream};
     use crate::administration::NewSession;
-    use crate::database::{get_database_by_name, TEST_DATABASE_NAME};
+    use crate::database::{add_new_session, get_database_by_name, remove_session_by_id, TEST_DATABASE_NAME};
     use crate::sessions::StreamType;
+    use chrono::{DateTime, Utc};
 
     #[tokio::test]
     async fn test_admin_overview(){
@@ -438,16 +849,45 @@ mod tests {
     }
 
 
+    #[tokio::test]
+    async fn test_update_without_version(){
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let admin = crate::security::create_token(SecurityToken {
+            username: "admin".to_string(),
+            role: SecurityRole::ADMIN,
+            iss: "streamie.live".to_string(),
+            iat: now,
+            exp: now + 300
+        });
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+
+        //Ohne version würde eine Änderung auf veraltetem Stand unbemerkt überschreiben
+        for body in ["name=Neu&description=&start=&end=&link=&plattform=", "name=Neu&description=&start=&end=&link=&plattform=&version="] {
+            let response = client.put("/admin/session/test")
+                .header(ContentType::Form)
+                .private_cookie(Cookie::new("streamie.live", admin.clone()))
+                .body(body)
+                .dispatch().await;
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+            assert!(response.into_string().await.unwrap().contains("\"version\""));
+        }
+    }
+
     #[tokio::test]
     async fn test_admin_ask_session_detail(){
         let n1 = super::NewSession{
             start: "09.07.2022 07:48:15",
             end: "10.07.2022 07:48:15",
+            timezone: "Europe/Berlin",
             name: "Test",
             description: "ein Test zum Streamen",
             link: "https://www.twitch.tv/primeleague",
-            channel: "PrimeLeague",
-            plattform: "Twitch"
+            plattform: "Twitch",
+            fallback: None,
+            visibility: Some("restricted"),
+            allowed_users: Some("anna, max"),
+            allowed_groups: None,
+            allowed_roles: Some("MODERATOR")
         };
         let client = Client::tracked(rocket()).await.expect("valid rocket instance");
         let mut response = client.get(uri!(super::ask_session_detail)).dispatch().await;
@@ -466,8 +906,10 @@ mod tests {
     #[tokio::test]
     async fn test_admin_delete_session() {
 
+        let id = ObjectId::new();
         let sessionD = super::Session{
-            id: ObjectId::new(),
+            id,
+            slug: format!("test-{}", id.to_hex()),
             start:  DateTime::<Utc>::from_utc(NaiveDateTime::parse_from_str(
                 "09.07.2022 07:48:15", FORMAT_STR).expect("failed to parse startDateTime"), Utc),
             end:  DateTime::<Utc>::from_utc(NaiveDateTime::parse_from_str(
@@ -477,16 +919,30 @@ mod tests {
             stream:SessionStream{
                 link: "https://www.twitch.tv/primeleague".to_string(),
                 channel: "PrimeLeague".to_string(),
-                stream_type: StreamType::Twitch
+                stream_type: StreamType::Twitch,
+                fallback: None
             },
+            ..Default::default()
         };
         let database = get_standard_database().await;
         add_new_session(&database, &sessionD).await;
-        let del1 = "Test";
         let client = Client::tracked(rocket()).await.expect("valid rocket instance");
 
-        let mut response = client.delete(uri!(super::delete_session(del1))).dispatch().await;
+        //Ohne Anmeldung als Admin wird weder per slug noch per id gelöscht
+        for key in [sessionD.slug.clone(), sessionD.id.to_hex()] {
+            let response = client.delete(uri!(super::delete_session(&key))).dispatch().await;
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+
+        remove_session_by_id(&database, &sessionD.id).await;
+    }
+
+    #[tokio::test]
+    async fn test_edit_session_unauthorized(){
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get("/admin/session/62a05c8631a6964f64d829ac/edit").dispatch().await;
         assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("Keine Berechtigung"));
     }
 
     #[tokio::test]
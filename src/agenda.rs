@@ -0,0 +1,336 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use mongodb::bson::oid::ObjectId;
+use rocket::form::Form;
+use rocket::http::CookieJar;
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::database::{get_sessions_by_ids, get_speakers, get_speakers_by_ids, get_standard_database, set_session_agenda};
+use crate::security::admin_token;
+use crate::speakers::{Speaker, TeraSpeaker};
+use crate::timezone::{local_to_utc, utc_to_local};
+
+// Ein zeitlicher Abschnitt der Session (z.B. Begrüßung, Vortrag, Fragerunde)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgendaSegment {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub title: String,
+    // Verweise auf die Sprecher-Profile aus speakers.rs
+    #[serde(default)]
+    pub speakers: Vec<ObjectId>,
+    #[serde(default)]
+    pub notes: String
+}
+
+// Index des Abschnitts, der zum Zeitpunkt now läuft
+pub fn current_segment(agenda: &[AgendaSegment], now: DateTime<Utc>) -> Option<usize> {
+    return agenda.iter().position(|segment| segment.start <= now && now < segment.end);
+}
+
+// Abschnitt für die Session-Seite, die Sprecher sind bereits aufgelöst
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TeraSegment {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub title: String,
+    pub notes: String,
+    pub speakers: Vec<TeraSpeaker>,
+    pub current: bool
+}
+
+// Abschnitt für das Bearbeiten-Formular, die Zeiten im Format der übrigen Formulare in der Zeitzone der Session
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SegmentState {
+    pub start: String,
+    pub end: String,
+    pub title: String,
+    pub notes: String,
+    pub speakers: Vec<String>
+}
+
+impl SegmentState {
+    pub fn from_segment(segment: &AgendaSegment, tz: Tz) -> SegmentState {
+        return SegmentState {
+            start: utc_to_local(segment.start, tz),
+            end: utc_to_local(segment.end, tz),
+            title: segment.title.clone(),
+            notes: segment.notes.clone(),
+            speakers: segment.speakers.iter().map(|s| s.to_hex()).collect()
+        };
+    }
+}
+
+// Agenda mit den Profilen aller genannten Sprecher, unbekannte (gelöschte) Sprecher entfallen
+pub fn tera_agenda(agenda: &[AgendaSegment], speakers: &[Speaker], now: DateTime<Utc>) -> Vec<TeraSegment> {
+    let current = current_segment(agenda, now);
+    return agenda.iter().enumerate()
+        .map(|(index, segment)| TeraSegment {
+            start: segment.start,
+            end: segment.end,
+            title: segment.title.clone(),
+            notes: segment.notes.clone(),
+            speakers: segment.speakers.iter()
+                .filter_map(|id| speakers.iter().find(|s| s.id == *id))
+                .map(TeraSpeaker::from_speaker)
+                .collect(),
+            current: current == Some(index)
+        })
+        .collect();
+}
+
+// Alle Sprecher der Session in der Reihenfolge ihres ersten Auftritts, jeder nur einmal
+pub fn session_speaker_ids(agenda: &[AgendaSegment]) -> Vec<ObjectId> {
+    let mut ids: Vec<ObjectId> = Vec::new();
+    for id in agenda.iter().flat_map(|segment| segment.speakers.iter()) {
+        if !ids.contains(id) {
+            ids.push(*id);
+        }
+    }
+    return ids;
+}
+
+// Lädt die Sprecher-Profile zur Agenda, bei einem Datenbankfehler wird die Agenda ohne Sprecher gezeigt
+pub async fn agenda_speakers(database: &mongodb::Database, agenda: &[AgendaSegment]) -> Vec<Speaker> {
+    let ids = session_speaker_ids(agenda);
+    if ids.is_empty() {
+        return Vec::new();
+    }
+    let speakers = get_speakers_by_ids(database, &ids).await.unwrap_or_default();
+    // In der Reihenfolge der Agenda statt der Datenbank
+    return ids.iter()
+        .filter_map(|id| speakers.iter().find(|s| s.id == *id).cloned())
+        .collect();
+}
+
+// Eine Zeile des Formulars, die Felder werden als segments[0].title usw. übertragen
+// speakers kann mehrfach vorkommen (Mehrfachauswahl)
+#[derive(FromForm)]
+pub struct SegmentInput<'r> {
+    pub start: &'r str,
+    pub end: &'r str,
+    pub title: &'r str,
+    pub speakers: Vec<&'r str>,
+    pub notes: Option<&'r str>
+}
+
+#[derive(FromForm)]
+pub struct AgendaForm<'r> {
+    pub segments: Vec<SegmentInput<'r>>
+}
+
+// Prüft alle Abschnitte und liefert sämtliche Fehler auf einmal
+// Die Abschnitte müssen innerhalb der Session liegen und dürfen sich nicht überschneiden, gespeichert wird nach Beginn sortiert
+// Die Zeiten werden wie Beginn und Ende der Session in deren Zeitzone tz eingegeben
+pub fn parse_agenda(inputs: &[SegmentInput<'_>], session_start: DateTime<Utc>, session_end: DateTime<Utc>,
+                    tz: Tz, known_speakers: &[ObjectId]) -> Result<Vec<AgendaSegment>, Vec<String>> {
+    let mut errors: Vec<String> = Vec::new();
+    let mut agenda: Vec<AgendaSegment> = Vec::new();
+
+    for (index, input) in inputs.iter().enumerate() {
+        let row = index + 1;
+        let title = input.title.trim();
+        if title.is_empty() || title.chars().count() > 120 {
+            errors.push(format!("Abschnitt {}: Der Titel muss 1 bis 120 Zeichen lang sein", row));
+        }
+
+        let notes = input.notes.unwrap_or("").trim();
+        if notes.chars().count() > 2000 {
+            errors.push(format!("Abschnitt {}: Die Notizen dürfen höchstens 2000 Zeichen lang sein", row));
+        }
+
+        let mut speakers: Vec<ObjectId> = Vec::new();
+        for speaker in input.speakers.iter().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match ObjectId::parse_str(speaker).ok().filter(|id| known_speakers.contains(id)) {
+                Some(id) if !speakers.contains(&id) => speakers.push(id),
+                Some(_) => {},
+                None => errors.push(format!("Abschnitt {}: Unbekannter Sprecher", row))
+            }
+        }
+
+        let (start, end) = match (local_to_utc(input.start, tz), local_to_utc(input.end, tz)) {
+            (Ok(start), Ok(end)) => (start, end),
+            (Err(e), _) | (_, Err(e)) => {
+                errors.push(format!("Abschnitt {}: {}", row, e));
+                continue;
+            }
+        };
+        if start >= end {
+            errors.push(format!("Abschnitt {}: Das Ende muss nach dem Beginn liegen", row));
+        } else if start < session_start || end > session_end {
+            errors.push(format!("Abschnitt {}: Der Abschnitt muss innerhalb der Session liegen", row));
+        }
+
+        agenda.push(AgendaSegment {
+            start,
+            end,
+            title: title.to_string(),
+            speakers,
+            notes: notes.to_string()
+        });
+    }
+
+    agenda.sort_by_key(|segment| segment.start);
+    for pair in agenda.windows(2) {
+        if pair[1].start < pair[0].end {
+            errors.push(format!("'{}' überschneidet sich mit '{}'", pair[1].title, pair[0].title));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(agenda);
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AgendaResult {
+    pub status: u8,
+    pub errors: Vec<String>
+}
+
+impl AgendaResult {
+    fn failed(errors: Vec<String>) -> Json<AgendaResult> {
+        return Json(AgendaResult { status: 0, errors });
+    }
+}
+
+// Ersetzt die Agenda der Session, eine leere Liste entfernt sie
+#[post("/admin/session/<id>/agenda", data = "<form>")]
+pub async fn update_agenda(id: &str, form: Form<AgendaForm<'_>>, cookies: &CookieJar<'_>) -> Json<AgendaResult> {
+    if admin_token(cookies).is_none() {
+        return AgendaResult::failed(vec!["Keine Berechtigung".to_string()]);
+    }
+
+    let session_id = match ObjectId::parse_str(id) {
+        Ok(session_id) => session_id,
+        Err(_) => return AgendaResult::failed(vec!["Unbekannte Session".to_string()])
+    };
+
+    let database = get_standard_database().await;
+    let session = match get_sessions_by_ids(&database, &[session_id]).await {
+        Ok(sessions) => match sessions.into_iter().next() {
+            Some(session) => session,
+            None => return AgendaResult::failed(vec!["Unbekannte Session".to_string()])
+        },
+        Err(e) => return AgendaResult::failed(vec![e.to_string()])
+    };
+    let known_speakers: Vec<ObjectId> = match get_speakers(&database).await {
+        Ok(speakers) => speakers.iter().map(|s| s.id).collect(),
+        Err(e) => return AgendaResult::failed(vec![e.to_string()])
+    };
+
+    let agenda = match parse_agenda(&form.segments, session.start, session.end, session.tz(), &known_speakers) {
+        Ok(agenda) => agenda,
+        Err(errors) => return AgendaResult::failed(errors)
+    };
+
+    match set_session_agenda(&database, &session_id, &agenda).await {
+        Ok(true) => return Json(AgendaResult { status: 1, errors: Vec::new() }),
+        Ok(false) => return AgendaResult::failed(vec!["Unbekannte Session".to_string()]),
+        Err(e) => {
+            error!("Failed to update agenda: {}", e);
+            return AgendaResult::failed(vec!["Die Agenda konnte nicht gespeichert werden".to_string()]);
+        }
+    }
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            update_agenda
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::TimeZone;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+
+    fn input<'r>(start: &'r str, end: &'r str, title: &'r str, speakers: Vec<&'r str>) -> SegmentInput<'r> {
+        return SegmentInput { start, end, title, speakers, notes: None };
+    }
+
+    #[test]
+    fn test_current_segment() {
+        let segment = |from: u32, to: u32| AgendaSegment {
+            start: Utc.ymd(2022, 7, 9).and_hms(from, 0, 0),
+            end: Utc.ymd(2022, 7, 9).and_hms(to, 0, 0),
+            title: format!("{}-{}", from, to),
+            speakers: Vec::new(),
+            notes: String::new()
+        };
+        let agenda = vec![segment(18, 19), segment(19, 20)];
+        assert_eq!(current_segment(&agenda, Utc.ymd(2022, 7, 9).and_hms(17, 59, 59)), None);
+        assert_eq!(current_segment(&agenda, Utc.ymd(2022, 7, 9).and_hms(18, 0, 0)), Some(0));
+        // Das Ende gehört schon zum nächsten Abschnitt
+        assert_eq!(current_segment(&agenda, Utc.ymd(2022, 7, 9).and_hms(19, 0, 0)), Some(1));
+        assert_eq!(current_segment(&agenda, Utc.ymd(2022, 7, 9).and_hms(20, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_parse_agenda() {
+        let start = Utc.ymd(2022, 7, 9).and_hms(18, 0, 0);
+        let end = Utc.ymd(2022, 7, 9).and_hms(20, 0, 0);
+        let speaker = ObjectId::new();
+        let speaker_hex = speaker.to_hex();
+
+        let agenda = parse_agenda(&[
+            input("09.07.2022 19:00:00", "09.07.2022 20:00:00", "Fragerunde", vec![]),
+            input("09.07.2022 18:00:00", "09.07.2022 19:00:00", "Vortrag", vec![&speaker_hex, &speaker_hex])
+        ], start, end, Tz::UTC, &[speaker]).unwrap();
+        assert_eq!(agenda[0].title, "Vortrag");
+        assert_eq!(agenda[0].speakers, vec![speaker]);
+        assert_eq!(session_speaker_ids(&agenda), vec![speaker]);
+
+        let errors = parse_agenda(&[
+            input("09.07.2022 18:00:00", "09.07.2022 19:30:00", "Vortrag", vec!["62a05c8631a6964f64d829ac"]),
+            input("09.07.2022 19:00:00", "09.07.2022 21:00:00", "", vec![]),
+            input("gestern", "heute", "Pause", vec![])
+        ], start, end, Tz::UTC, &[speaker]).unwrap_err();
+        assert_eq!(errors.len(), 5);
+        assert!(errors.iter().any(|e| e.contains("überschneidet")));
+
+        assert!(parse_agenda(&[], start, end, Tz::UTC, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_agenda_timezone() {
+        // 18:00 bis 20:00 UTC sind im Juli 20:00 bis 22:00 in Berlin
+        let start = Utc.ymd(2022, 7, 9).and_hms(18, 0, 0);
+        let end = Utc.ymd(2022, 7, 9).and_hms(20, 0, 0);
+        let berlin = chrono_tz::Europe::Berlin;
+
+        let agenda = parse_agenda(&[
+            input("09.07.2022 20:00:00", "09.07.2022 21:00:00", "Vortrag", vec![])
+        ], start, end, berlin, &[]).unwrap();
+        assert_eq!(agenda[0].start, start);
+        assert_eq!(SegmentState::from_segment(&agenda[0], berlin).end, "09.07.2022 21:00:00");
+
+        let errors = parse_agenda(&[
+            input("09.07.2022 18:00:00", "09.07.2022 19:00:00", "Vortrag", vec![])
+        ], start, end, berlin, &[]).unwrap_err();
+        assert!(errors[0].contains("innerhalb der Session"));
+    }
+
+    #[tokio::test]
+    async fn test_agenda_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post("/admin/session/62a05c8631a6964f64d829ac/agenda")
+            .header(ContentType::Form)
+            .body("segments[0].start=09.07.2022 18:00:00&segments[0].end=09.07.2022 19:00:00&segments[0].title=Vortrag")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+    }
+}
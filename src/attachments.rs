@@ -0,0 +1,464 @@
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use multer::{Constraints, Multipart, SizeLimit};
+use rocket::data::{ByteUnit, Data, ToByteUnit};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, CookieJar, Header, Status};
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::database::{add_attachment, get_attachment, get_session_attachments, get_sessions_by_ids, get_standard_database, remove_attachment};
+use crate::security::{admin_token, user_token, SecurityToken};
+use crate::access::{viewable_session, viewer_from_token};
+
+// Maximal so viele Dateien werden mit einem Upload angenommen
+const MAX_FILES_PER_UPLOAD: u64 = 10;
+
+// Dateiname, falls der Browser keinen oder nur einen ungültigen mitschickt
+const FALLBACK_FILE_NAME: &str = "anhang";
+
+// Konfiguration der Anhänge in der Rocket.toml
+// attachments_dir: Ordner, in dem die Dateien je session abgelegt werden
+// attachment_max_size: Obergrenze je Datei, z.B. "20 MiB"
+// attachment_extensions: erlaubte Dateiendungen, der Content-Type wird daraus abgeleitet
+#[derive(Debug, Deserialize)]
+pub struct AttachmentConfig {
+    #[serde(default = "default_attachments_dir")]
+    pub attachments_dir: String,
+    #[serde(default = "default_attachment_max_size")]
+    pub attachment_max_size: ByteUnit,
+    #[serde(default = "default_attachment_extensions")]
+    pub attachment_extensions: Vec<String>,
+}
+
+fn default_attachments_dir() -> String {
+    return "attachments".to_string();
+}
+
+fn default_attachment_max_size() -> ByteUnit {
+    return 20.mebibytes();
+}
+
+fn default_attachment_extensions() -> Vec<String> {
+    return ["pdf", "ppt", "pptx", "key", "odp", "doc", "docx", "odt", "txt", "md", "zip",
+        "png", "jpg", "jpeg", "gif", "webp", "mp4", "webm", "mp3", "m4a"]
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> AttachmentConfig {
+        return AttachmentConfig {
+            attachments_dir: default_attachments_dir(),
+            attachment_max_size: default_attachment_max_size(),
+            attachment_extensions: default_attachment_extensions(),
+        };
+    }
+}
+
+pub fn attachment_config() -> AdHoc {
+    return AdHoc::config::<AttachmentConfig>();
+}
+
+// Metadaten eines Anhangs, die Datei selbst liegt unter path
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub session_id: ObjectId,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: u64,
+    // SHA-256 als Hex, dient auch als ETag beim Download
+    pub sha256: String,
+    pub path: String,
+    pub uploaded_at: DateTime<Utc>,
+    pub uploaded_by: String,
+}
+
+// Anhang für die Tera Templates und die JSON-Liste
+#[derive(Serialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct TeraAttachment {
+    pub id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: u64,
+    pub sha256: String,
+    pub uploaded_at: DateTime<Utc>,
+    pub url: String,
+}
+
+impl TeraAttachment {
+    pub fn from_attachment(attachment: &Attachment) -> TeraAttachment {
+        return TeraAttachment {
+            id: attachment.id.to_hex(),
+            file_name: attachment.file_name.clone(),
+            content_type: attachment.content_type.clone(),
+            size: attachment.size,
+            sha256: attachment.sha256.clone(),
+            uploaded_at: attachment.uploaded_at,
+            url: format!("/session/{}/attachments/{}", attachment.session_id.to_hex(), attachment.id.to_hex()),
+        };
+    }
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich, 2 = teilweise erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AttachmentResult {
+    pub status: u8,
+    pub attachments: Vec<TeraAttachment>,
+    pub errors: Vec<String>
+}
+
+impl AttachmentResult {
+    fn failed(error: &str) -> Json<AttachmentResult> {
+        return Json(AttachmentResult { status: 0, attachments: vec![], errors: vec![error.to_string()] });
+    }
+}
+
+// Download mit festem Content-Type, die Dateien werden nie vom Browser interpretiert
+#[derive(Responder)]
+pub struct AttachmentDownload {
+    file: (ContentType, File),
+    disposition: Header<'static>,
+    nosniff: Header<'static>,
+    etag: Header<'static>,
+}
+
+// Entfernt Pfadangaben und Steuerzeichen, der Name wird nur zur Anzeige und beim Download verwendet
+pub fn sanitize_file_name(file_name: &str) -> String {
+    let base = file_name.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+    let cleaned: String = base.chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .take(200)
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').to_string();
+
+    if cleaned.is_empty() {
+        return FALLBACK_FILE_NAME.to_string();
+    }
+    return cleaned;
+}
+
+// Liefert die Endung in Kleinbuchstaben, aber nur wenn sie erlaubt ist
+pub fn allowed_extension(file_name: &str, allowed: &[String]) -> Option<String> {
+    let (_, extension) = file_name.rsplit_once('.')?;
+    let extension = extension.to_lowercase();
+    if allowed.iter().any(|a| a.eq_ignore_ascii_case(&extension)) {
+        return Some(extension);
+    }
+    return None;
+}
+
+// Content-Disposition nach RFC 6266: ASCII-Ersatzname plus filename* für Umlaute (RFC 5987)
+pub fn content_disposition(file_name: &str) -> String {
+    let ascii: String = file_name.chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+
+    let mut encoded = String::with_capacity(file_name.len() * 3);
+    for byte in file_name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.'
+            | b'^' | b'_' | b'`' | b'|' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+
+    return format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii, encoded);
+}
+
+// Anhänge gibt es nur für sessions, die existieren und nicht im Papierkorb liegen
+async fn session_exists(database: &mongodb::Database, id: &ObjectId) -> Result<bool, Status> {
+    let sessions = get_sessions_by_ids(database, &[*id]).await
+        .map_err(|_| Status::InternalServerError)?;
+    return Ok(!sessions.is_empty());
+}
+
+// Anhänge eingeschränkter Sessions bekommen nur freigegebene User, für alle anderen existieren sie nicht
+async fn may_view_session(database: &mongodb::Database, token: &SecurityToken, id: &ObjectId) -> Result<bool, Status> {
+    let viewer = viewer_from_token(database, token).await;
+    let session = viewable_session(database, &viewer, id).await
+        .map_err(|_| Status::InternalServerError)?;
+    return Ok(session.is_some());
+}
+
+// Schreibt ein Feld des Uploads in die Datei und berechnet dabei Größe und Prüfsumme
+async fn store_field(field: &mut multer::Field<'_>, path: &Path) -> Result<(u64, String), String> {
+    let mut file = File::create(path).await
+        .map_err(|_| "Die Datei konnte nicht gespeichert werden".to_string())?;
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+
+    loop {
+        match field.chunk().await {
+            Ok(Some(chunk)) => {
+                size += chunk.len() as u64;
+                hasher.update(&chunk);
+                file.write_all(&chunk).await
+                    .map_err(|_| "Die Datei konnte nicht gespeichert werden".to_string())?;
+            },
+            Ok(None) => break,
+            Err(multer::Error::FieldSizeExceeded { limit, .. }) => {
+                return Err(format!("Die Datei ist größer als {}", ByteUnit::from(limit)));
+            },
+            Err(e) => return Err(format!("Der Upload ist fehlgeschlagen: {}", e))
+        }
+    }
+    file.flush().await.map_err(|_| "Die Datei konnte nicht gespeichert werden".to_string())?;
+
+    return Ok((size, hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()));
+}
+
+// Upload eines oder mehrerer Anhänge (multipart/form-data, Feldname "file")
+#[post("/admin/session/<id>/attachments", data = "<data>")]
+pub async fn upload_attachments(id: &str, data: Data<'_>, content_type: &ContentType, config: &State<AttachmentConfig>,
+                                cookies: &CookieJar<'_>) -> Json<AttachmentResult> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return AttachmentResult::failed("Nur Admins dürfen Anhänge hochladen")
+    };
+    let session_id = match ObjectId::parse_str(id) {
+        Ok(session_id) => session_id,
+        Err(_) => return AttachmentResult::failed("Die Session wurde nicht gefunden")
+    };
+    let boundary = match content_type.params().find(|(key, _)| key == "boundary") {
+        Some((_, boundary)) if content_type.is_form_data() => boundary.to_string(),
+        _ => return AttachmentResult::failed("Erwartet wird multipart/form-data")
+    };
+
+    let database = get_standard_database().await;
+    if !matches!(session_exists(&database, &session_id).await, Ok(true)) {
+        return AttachmentResult::failed("Die Session wurde nicht gefunden");
+    }
+
+    let directory = PathBuf::from(&config.attachments_dir).join(session_id.to_hex());
+    if tokio::fs::create_dir_all(&directory).await.is_err() {
+        return AttachmentResult::failed("Der Ordner für Anhänge konnte nicht angelegt werden");
+    }
+
+    // Etwas Platz für die Multipart-Header, damit das Limit je Datei greift und nicht das des ganzen Uploads
+    let max_size = config.attachment_max_size.as_u64();
+    let stream_limit = max_size * MAX_FILES_PER_UPLOAD + 64 * 1024;
+    let constraints = Constraints::new()
+        .allowed_fields(vec!["file"])
+        .size_limit(SizeLimit::new().per_field(max_size).whole_stream(stream_limit));
+    let reader = data.open(stream_limit.bytes());
+    let mut multipart = Multipart::with_reader_with_constraints(reader, boundary, constraints);
+
+    let mut attachments = vec![];
+    let mut errors = vec![];
+    let mut files = 0;
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                errors.push(format!("Der Upload ist fehlgeschlagen: {}", e));
+                break;
+            }
+        };
+
+        let file_name = sanitize_file_name(field.file_name().unwrap_or(""));
+        files += 1;
+        if files > MAX_FILES_PER_UPLOAD {
+            errors.push(format!("{}: Es können höchstens {} Dateien auf einmal hochgeladen werden", file_name, MAX_FILES_PER_UPLOAD));
+            continue;
+        }
+        let extension = match allowed_extension(&file_name, &config.attachment_extensions) {
+            Some(extension) => extension,
+            None => {
+                errors.push(format!("{}: Dieser Dateityp ist nicht erlaubt", file_name));
+                continue;
+            }
+        };
+
+        let attachment_id = ObjectId::new();
+        let path = directory.join(attachment_id.to_hex());
+        let (size, sha256) = match store_field(&mut field, &path).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                errors.push(format!("{}: {}", file_name, e));
+                // Nach einem Fehler im Stream sind die restlichen Felder nicht mehr lesbar
+                break;
+            }
+        };
+        if size == 0 {
+            let _ = tokio::fs::remove_file(&path).await;
+            errors.push(format!("{}: Die Datei ist leer", file_name));
+            continue;
+        }
+
+        let attachment = Attachment {
+            id: attachment_id,
+            session_id,
+            file_name,
+            content_type: ContentType::from_extension(&extension).unwrap_or(ContentType::Binary).to_string(),
+            size,
+            sha256,
+            path: path.to_string_lossy().to_string(),
+            uploaded_at: Utc::now(),
+            uploaded_by: token.username.clone(),
+        };
+        match add_attachment(&database, &attachment).await {
+            Ok(()) => attachments.push(TeraAttachment::from_attachment(&attachment)),
+            Err(_) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                errors.push(format!("{}: Die Datei konnte nicht gespeichert werden", attachment.file_name));
+            }
+        }
+    }
+
+    let status = match (attachments.is_empty(), errors.is_empty()) {
+        (false, true) => 1,
+        (false, false) => 2,
+        (true, _) => 0
+    };
+    if attachments.is_empty() && errors.is_empty() {
+        errors.push("Es wurde keine Datei ausgewählt".to_string());
+    }
+
+    return Json(AttachmentResult { status, attachments, errors });
+}
+
+// Liste der Anhänge einer session für alle angemeldeten User
+#[get("/session/<id>/attachments")]
+pub async fn list_attachments(id: &str, cookies: &CookieJar<'_>) -> Result<Json<Vec<TeraAttachment>>, Status> {
+    let token = user_token(cookies).ok_or(Status::Unauthorized)?;
+
+    let session_id = ObjectId::parse_str(id).map_err(|_| Status::NotFound)?;
+    let database = get_standard_database().await;
+    if !may_view_session(&database, &token, &session_id).await? {
+        return Err(Status::NotFound);
+    }
+
+    let attachments = get_session_attachments(&database, &session_id).await
+        .map_err(|_| Status::InternalServerError)?;
+    return Ok(Json(attachments.iter().map(TeraAttachment::from_attachment).collect()));
+}
+
+// Download eines Anhangs, nur für angemeldete User
+#[get("/session/<id>/attachments/<attachment_id>")]
+pub async fn download_attachment(id: &str, attachment_id: &str, cookies: &CookieJar<'_>) -> Result<AttachmentDownload, Status> {
+    let token = user_token(cookies).ok_or(Status::Unauthorized)?;
+
+    let session_id = ObjectId::parse_str(id).map_err(|_| Status::NotFound)?;
+    let attachment_id = ObjectId::parse_str(attachment_id).map_err(|_| Status::NotFound)?;
+    let database = get_standard_database().await;
+    if !may_view_session(&database, &token, &session_id).await? {
+        return Err(Status::NotFound);
+    }
+
+    let attachment = get_attachment(&database, &session_id, &attachment_id).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+    let file = File::open(&attachment.path).await.map_err(|_| Status::NotFound)?;
+    let content_type = ContentType::parse_flexible(&attachment.content_type).unwrap_or(ContentType::Binary);
+
+    return Ok(AttachmentDownload {
+        file: (content_type, file),
+        disposition: Header::new("Content-Disposition", content_disposition(&attachment.file_name)),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+        etag: Header::new("ETag", format!("\"{}\"", attachment.sha256)),
+    });
+}
+
+// Löschen eines Anhangs samt Datei
+#[delete("/admin/session/<id>/attachments/<attachment_id>")]
+pub async fn delete_attachment(id: &str, attachment_id: &str, cookies: &CookieJar<'_>) -> Json<AttachmentResult> {
+    if admin_token(cookies).is_none() {
+        return AttachmentResult::failed("Nur Admins dürfen Anhänge löschen");
+    }
+
+    let (session_id, attachment_id) = match (ObjectId::parse_str(id), ObjectId::parse_str(attachment_id)) {
+        (Ok(session_id), Ok(attachment_id)) => (session_id, attachment_id),
+        _ => return AttachmentResult::failed("Der Anhang wurde nicht gefunden")
+    };
+
+    let database = get_standard_database().await;
+    return match remove_attachment(&database, &session_id, &attachment_id).await {
+        Ok(Some(attachment)) => {
+            let _ = tokio::fs::remove_file(&attachment.path).await;
+            Json(AttachmentResult { status: 1, attachments: vec![], errors: vec![] })
+        },
+        Ok(None) => AttachmentResult::failed("Der Anhang wurde nicht gefunden"),
+        Err(_) => AttachmentResult::failed("Der Anhang konnte nicht gelöscht werden")
+    };
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .attach(attachment_config())
+        .mount("/", routes![
+            upload_attachments,
+            list_attachments,
+            download_attachment,
+            delete_attachment
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("Folien.pdf"), "Folien.pdf");
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("C:\\Users\\max\\Präsentation \"final\".pptx"), "Präsentation final.pptx");
+        assert_eq!(sanitize_file_name(".htaccess"), "htaccess");
+        assert_eq!(sanitize_file_name("a\nb\r.txt"), "ab.txt");
+        assert_eq!(sanitize_file_name("  "), FALLBACK_FILE_NAME);
+    }
+
+    #[test]
+    fn test_allowed_extension() {
+        let allowed = default_attachment_extensions();
+        assert_eq!(allowed_extension("Folien.PDF", &allowed), Some("pdf".to_string()));
+        assert_eq!(allowed_extension("code.tar.zip", &allowed), Some("zip".to_string()));
+        assert_eq!(allowed_extension("setup.exe", &allowed), None);
+        assert_eq!(allowed_extension("index.html", &allowed), None);
+        assert_eq!(allowed_extension("pdf", &allowed), None);
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(content_disposition("Folien.pdf"),
+                   "attachment; filename=\"Folien.pdf\"; filename*=UTF-8''Folien.pdf");
+        assert_eq!(content_disposition("Übung 1.pdf"),
+                   "attachment; filename=\"_bung 1.pdf\"; filename*=UTF-8''%C3%9Cbung%201.pdf");
+    }
+
+    #[tokio::test]
+    async fn test_attachments_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get("/session/62a05c8631a6964f64d829ac/attachments").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.get("/session/62a05c8631a6964f64d829ac/attachments/62a05c8631a6964f64d829ad").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.post("/admin/session/62a05c8631a6964f64d829ac/attachments")
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", "X")))
+            .body("--X--\r\n")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+
+        let response = client.delete("/admin/session/62a05c8631a6964f64d829ac/attachments/62a05c8631a6964f64d829ad").dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+    }
+}
@@ -0,0 +1,325 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket::http::{ContentType, CookieJar, Header, Status};
+use rocket::http::uri::Host;
+use rocket::serde::json::Json;
+use serde::Serialize;
+
+use crate::access::{accessible_session, viewer_from_username, Viewer};
+use crate::database::{get_standard_database, get_upcoming_sessions, get_user_by_calendar_token, get_user_by_username,
+                      set_user_calendar_token, DatabaseError, DatabaseResult};
+use crate::security::{create_calendar_token, is_calendar_token, user_token};
+use crate::sessions::{Session, StreamType};
+
+// Kennung der Anwendung im PRODID und im Suffix der UIDs
+const CALENDAR_DOMAIN: &str = "streamie.live";
+
+// Kalender-Datei inkl. Dateiname für den Download
+#[derive(Responder)]
+pub struct CalendarResponse {
+    body: (ContentType, String),
+    disposition: Header<'static>,
+}
+
+impl CalendarResponse {
+    fn new(file_name: &str, body: String) -> CalendarResponse {
+        return CalendarResponse {
+            body: (ContentType::Calendar, body),
+            disposition: Header::new("Content-Disposition", format!("inline; filename=\"{}\"", file_name)),
+        };
+    }
+}
+
+// Text-Werte müssen nach RFC 5545 (3.3.11) escaped werden
+pub fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {},
+            _ => escaped.push(c)
+        }
+    }
+    return escaped;
+}
+
+// Zeilen dürfen höchstens 75 Oktette lang sein, Fortsetzungszeilen beginnen mit einem Leerzeichen (RFC 5545 3.1)
+// Umgebrochen wird nur an Zeichengrenzen, damit Umlaute nicht zerrissen werden
+pub fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    return folded;
+}
+
+// Zeiten werden immer in UTC ausgegeben, die Kalender-Programme rechnen in die lokale Zeitzone um
+pub fn format_utc(date: &DateTime<Utc>) -> String {
+    return date.format("%Y%m%dT%H%M%SZ").to_string();
+}
+
+// Die UID bleibt über alle Änderungen gleich, damit Kalender den Termin aktualisieren statt ihn doppelt anzulegen
+pub fn event_uid(id: &ObjectId) -> String {
+    return format!("{}@{}", id.to_hex(), CALENDAR_DOMAIN);
+}
+
+// Gegenstück zu event_uid, z.B. um einen exportierten Termin beim Import wiederzuerkennen
+pub fn session_id_from_uid(uid: &str) -> Option<ObjectId> {
+    let id = uid.strip_suffix(&format!("@{}", CALENDAR_DOMAIN))?;
+    return ObjectId::parse_str(id).ok();
+}
+
+fn event_lines(session: &Session, base_url: &str, now: &DateTime<Utc>) -> Vec<String> {
+    let url = format!("{}/session/{}", base_url, session.id.to_hex());
+    let description = if session.description.is_empty() {
+        url.clone()
+    } else {
+        format!("{}\n\n{}", session.description, url)
+    };
+
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}", event_uid(&session.id)),
+        format!("DTSTAMP:{}", format_utc(now)),
+        format!("DTSTART:{}", format_utc(&session.start)),
+        format!("DTEND:{}", format_utc(&session.end)),
+        // SEQUENCE steigt mit jeder Änderung der Session, so übernehmen Kalender geänderte Zeiten
+        format!("SEQUENCE:{}", session.version),
+        format!("SUMMARY:{}", escape_text(&session.name)),
+        format!("DESCRIPTION:{}", escape_text(&description)),
+        format!("URL:{}", url),
+        format!("LOCATION:{}", escape_text(&url)),
+        "STATUS:CONFIRMED".to_string(),
+    ];
+    // Eigene Properties, damit ein exportierter Kalender samt Stream wieder importiert werden kann
+    if session.stream.stream_type != StreamType::None {
+        lines.push(format!("X-STREAMIE-PLATTFORM:{:?}", session.stream.stream_type));
+        lines.push(format!("X-STREAMIE-LINK:{}", escape_text(&session.stream.link)));
+    }
+    lines.push("END:VEVENT".to_string());
+
+    return lines;
+}
+
+// Erzeugt einen vollständigen VCALENDAR mit einem VEVENT je Session
+pub fn render_calendar(name: &str, sessions: &[Session], base_url: &str, now: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//{}//Sessions//DE", CALENDAR_DOMAIN),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        format!("X-WR-CALNAME:{}", escape_text(name)),
+        // Kalender-Programme fragen den Feed spätestens stündlich neu ab
+        "REFRESH-INTERVAL;VALUE=DURATION:PT1H".to_string(),
+        "X-PUBLISHED-TTL:PT1H".to_string(),
+    ];
+    for session in sessions {
+        lines.extend(event_lines(session, base_url, &now));
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    return lines.iter().map(|line| fold_line(line)).collect();
+}
+
+pub(crate) fn base_url(host: Option<&Host<'_>>) -> String {
+    return format!("https://{}", host.map(|h| h.to_string()).unwrap_or_else(|| CALENDAR_DOMAIN.to_string()));
+}
+
+// Erzeugt einen neuen Kalender-Token für den User, der bisherige Link wird damit ungültig
+// Liefert None, wenn es den User nicht (mehr) gibt
+pub async fn rotate_calendar_token(database: &mongodb::Database, username: &str) -> DatabaseResult<Option<String>> {
+    // Bei einer (sehr unwahrscheinlichen) Kollision wird ein weiterer Token erzeugt
+    let mut attempt = 1;
+    loop {
+        let token = create_calendar_token();
+        match set_user_calendar_token(database, username, &token).await {
+            Ok(found) => return Ok(Some(token).filter(|_| found)),
+            Err(DatabaseError::Conflict(_)) if attempt < 3 => attempt += 1,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+// Token für den Abo-Link in der Übersicht, beim ersten Aufruf wird er erzeugt
+pub async fn calendar_token(database: &mongodb::Database, username: &str) -> DatabaseResult<Option<String>> {
+    return match get_user_by_username(database, username).await? {
+        Some(user) if user.calendar_token.is_some() => Ok(user.calendar_token),
+        Some(_) => rotate_calendar_token(database, username).await,
+        None => Ok(None)
+    };
+}
+
+// Angemeldet ist, wer einen gültigen Auth-Cookie oder einen gespeicherten Kalender-Token hat
+// Liefert den username, die Freigaben werden anschließend aus der Datenbank geladen
+async fn authorized_username(database: &mongodb::Database, token: Option<&str>, cookies: &CookieJar<'_>) -> Result<String, Status> {
+    if let Some(token) = token {
+        if !is_calendar_token(token) {
+            return Err(Status::Unauthorized);
+        }
+        return get_user_by_calendar_token(database, token).await
+            .map_err(|_| Status::InternalServerError)?
+            .map(|user| user.username)
+            .ok_or(Status::Unauthorized);
+    }
+
+    return user_token(cookies).map(|t| t.username).ok_or(Status::Unauthorized);
+}
+
+async fn authorized_viewer(database: &mongodb::Database, token: Option<&str>, cookies: &CookieJar<'_>) -> Result<Viewer, Status> {
+    let username = authorized_username(database, token, cookies).await?;
+    // Gelöschte User verlieren mit ihrem Account auch das Kalender-Abo
+    return viewer_from_username(database, &username).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::Unauthorized);
+}
+
+// Kalender-Eintrag einer einzelnen Session
+#[get("/session/<id>/calendar.ics?<token>")]
+pub async fn session_calendar(id: &str, token: Option<&str>, host: Option<&Host<'_>>,
+                              cookies: &CookieJar<'_>) -> Result<CalendarResponse, Status> {
+    let session_id = ObjectId::parse_str(id).map_err(|_| Status::NotFound)?;
+    let database = get_standard_database().await;
+    let viewer = authorized_viewer(&database, token, cookies).await?;
+    let session = accessible_session(&database, &viewer, &session_id).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let body = render_calendar(&session.name, std::slice::from_ref(&session), &base_url(host), Utc::now());
+    return Ok(CalendarResponse::new(&format!("{}.ics", session.slug), body));
+}
+
+// Abonnierbarer Feed aller kommenden Sessions, der Kalender-Token steht in der URL
+#[get("/calendar.ics?<token>")]
+pub async fn calendar_feed(token: Option<&str>, host: Option<&Host<'_>>,
+                           cookies: &CookieJar<'_>) -> Result<CalendarResponse, Status> {
+    let database = get_standard_database().await;
+    let viewer = authorized_viewer(&database, token, cookies).await?;
+    let now = Utc::now();
+    // Der Feed enthält wie die Übersicht nur gelistete Sessions
+    let sessions: Vec<Session> = get_upcoming_sessions(&database, now).await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .filter(|session| viewer.can_list_session(session))
+        .collect();
+
+    let body = render_calendar("streamie.live Sessions", &sessions, &base_url(host), now);
+    return Ok(CalendarResponse::new("streamie.ics", body));
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CalendarTokenResult {
+    pub status: u8,
+    pub token: Option<String>
+}
+
+// Ersetzt den Token des angemeldeten Users, z.B. wenn der Abo-Link weitergegeben wurde
+#[post("/calendar/token")]
+pub async fn regenerate_calendar_token(cookies: &CookieJar<'_>) -> Json<CalendarTokenResult> {
+    let token = match user_token(cookies) {
+        Some(token) => token,
+        None => return Json(CalendarTokenResult { status: 0, token: None })
+    };
+
+    let database = get_standard_database().await;
+    return match rotate_calendar_token(&database, &token.username).await {
+        Ok(Some(token)) => Json(CalendarTokenResult { status: 1, token: Some(token) }),
+        Ok(None) => Json(CalendarTokenResult { status: 0, token: None }),
+        Err(e) => {
+            error!("Failed to regenerate calendar token: {}", e);
+            Json(CalendarTokenResult { status: 0, token: None })
+        }
+    };
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            session_calendar,
+            calendar_feed,
+            regenerate_calendar_token
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::TimeZone;
+    use rocket::local::asynchronous::Client;
+
+    fn session() -> Session {
+        return Session {
+            id: ObjectId::parse_str("62a05c8631a6964f64d829ac").unwrap(),
+            slug: "rust-meetup".to_string(),
+            start: Utc.ymd(2022, 10, 30).and_hms(0, 30, 0),
+            end: Utc.ymd(2022, 10, 30).and_hms(2, 0, 0),
+            name: "Rust Meetup; Teil 1, Ownership".to_string(),
+            description: "Zeile 1\nZeile 2".to_string(),
+            version: 3,
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn test_escape_and_fold() {
+        assert_eq!(escape_text("a;b,c\\d\ne"), "a\\;b\\,c\\\\d\\ne");
+
+        let line = format!("SUMMARY:{}", "ä".repeat(50));
+        let folded = fold_line(&line);
+        assert!(folded.ends_with("\r\n"));
+        for part in folded.trim_end_matches("\r\n").split("\r\n") {
+            assert!(part.len() <= 75);
+        }
+        assert_eq!(folded.replace("\r\n ", "").trim_end(), line);
+    }
+
+    #[test]
+    fn test_render_calendar() {
+        let now = Utc.ymd(2022, 10, 1).and_hms(12, 0, 0);
+        let calendar = render_calendar("Test", &[session()], "https://streamie.live", now);
+
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+        assert!(calendar.contains("\r\nUID:62a05c8631a6964f64d829ac@streamie.live\r\n"));
+        assert!(calendar.contains("\r\nDTSTAMP:20221001T120000Z\r\n"));
+        // Sommerzeit-Ende in Europa: die UTC-Zeit bleibt unverändert
+        assert!(calendar.contains("\r\nDTSTART:20221030T003000Z\r\n"));
+        assert!(calendar.contains("\r\nDTEND:20221030T020000Z\r\n"));
+        assert!(calendar.contains("\r\nSEQUENCE:3\r\n"));
+        assert!(calendar.contains("\r\nSUMMARY:Rust Meetup\\; Teil 1\\, Ownership\r\n"));
+        assert!(calendar.contains("\r\nURL:https://streamie.live/session/62a05c8631a6964f64d829ac\r\n"));
+        assert_eq!(session_id_from_uid("62a05c8631a6964f64d829ac@streamie.live"), Some(session().id));
+        assert_eq!(session_id_from_uid("62a05c8631a6964f64d829ac@example.com"), None);
+    }
+
+    #[tokio::test]
+    async fn test_calendar_feed_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get("/calendar.ics").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.get("/calendar.ics?token=invalid").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.get("/session/62a05c8631a6964f64d829ac/calendar.ics").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.post("/calendar/token").dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "{\"status\":0,\"token\":null}");
+    }
+}
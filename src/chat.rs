@@ -5,8 +5,13 @@ use rocket::serde::{Serialize, Deserialize};
 use rocket::tokio::sync::broadcast::{Sender, error::RecvError};
 use rocket::tokio::select;
 use rocket::http::{Cookie, CookieJar};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
 
 use crate::security::{SecurityRole, validate_token};
+use crate::database::{add_chat_message, get_standard_database};
+use crate::access::{accessible_event, viewable_session, viewer_from_token, Viewer};
+use std::collections::HashMap;
 // FormGuard und Basis-Struct für eine neue Nachricht
 #[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
 #[cfg_attr(test, derive(PartialEq, UriDisplayQuery))]
@@ -28,6 +33,43 @@ pub struct ChatMessage {
     pub chat_type: String // Type ist hier die Rolle zur farblichen Markierung bestimmter User
 }
 
+// Gespeicherte Chatnachricht, room ist die id der Session
+// Wird für den Verlauf und die Volltextsuche benötigt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChatHistoryEntry {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub room: String,
+    pub username: String,
+    pub message: String,
+    pub chat_type: String,
+    pub created_at: DateTime<Utc>
+}
+
+impl ChatHistoryEntry {
+    pub fn from_message(message: &ChatMessage) -> ChatHistoryEntry {
+        return ChatHistoryEntry {
+            id: ObjectId::new(),
+            room: message.room.clone(),
+            username: message.username.clone(),
+            message: message.message.clone(),
+            chat_type: message.chat_type.clone(),
+            created_at: Utc::now()
+        };
+    }
+}
+
+// Ein Raum ist die id einer Session oder eines Events (Lobby), mitlesen und schreiben darf nur, wer diese öffnen darf
+async fn room_allowed(database: &mongodb::Database, viewer: &Viewer, room: &str) -> bool {
+    let id = match ObjectId::parse_str(room) {
+        Ok(id) => id,
+        Err(_) => return false
+    };
+    return matches!(viewable_session(database, viewer, &id).await, Ok(Some(_)))
+        || matches!(accessible_event(database, viewer, &id).await, Ok(Some(_)));
+}
+
 // Abboniere einen Channel
 #[get("/chat")]
 pub async fn retrieve_chat(queue: &State<Sender<ChatMessage>>, mut end: Shutdown, cookies: &CookieJar<'_>) -> EventStream![] {
@@ -43,9 +85,13 @@ pub async fn retrieve_chat(queue: &State<Sender<ChatMessage>>, mut end: Shutdown
     // Falls Token abgelaufen oder ungültig -> render unauthorized template
     match valuated_token {
         Some(t) => {
+            let database = get_standard_database().await;
+            let viewer = viewer_from_token(&database, &t).await;
             let mut rx = queue.subscribe();
             // Für die Server-Send-Events wurde die Rocket.rs Klasse EventStream genutzt
             EventStream! {
+                // Ob ein Raum (Session) mitgelesen werden darf, wird je Verbindung nur einmal geprüft
+                let mut rooms: HashMap<String, bool> = HashMap::new();
                 loop {
                     let msg = select! {
                         msg = rx.recv() => match msg {
@@ -56,7 +102,13 @@ pub async fn retrieve_chat(queue: &State<Sender<ChatMessage>>, mut end: Shutdown
                         _ = &mut end => break,
                     };
 
-                    yield Event::json(&msg);
+                    if !rooms.contains_key(&msg.room) {
+                        let allowed = room_allowed(&database, &viewer, &msg.room).await;
+                        rooms.insert(msg.room.clone(), allowed);
+                    }
+                    if rooms[&msg.room] {
+                        yield Event::json(&msg);
+                    }
                 }
             }
         },
@@ -70,7 +122,7 @@ pub async fn retrieve_chat(queue: &State<Sender<ChatMessage>>, mut end: Shutdown
 
 // End-Knoten für das Absetzen einer neuen Nachricht in einem Channel
 #[post("/message", data = "<form>")]
-pub fn retrieve_message(form: Form<Message>, queue: &State<Sender<ChatMessage>>, cookies: &CookieJar<'_>) {
+pub async fn retrieve_message(form: Form<Message>, queue: &State<Sender<ChatMessage>>, cookies: &CookieJar<'_>) {
     
     // streamie.live ist der Standardcookie für den Auth-Token
     let token: Option<Cookie> = cookies.get_private("streamie.live");
@@ -85,6 +137,14 @@ pub fn retrieve_message(form: Form<Message>, queue: &State<Sender<ChatMessage>>,
     match valuated_token {
         Some(t) => {
             let form = form.into_inner();
+
+            // In eingeschränkten Sessions dürfen nur freigegebene User schreiben
+            let database = get_standard_database().await;
+            let viewer = viewer_from_token(&database, &t).await;
+            if !room_allowed(&database, &viewer, &form.room).await {
+                return;
+            }
+
             let chat_message = ChatMessage {
                 room: form.room,
                 username: t.username,
@@ -97,6 +157,12 @@ pub fn retrieve_message(form: Form<Message>, queue: &State<Sender<ChatMessage>>,
                     "user".to_string()
                 }
             };
+
+            // Nachricht wird für Verlauf und Suche gespeichert, ein Fehler soll den Chat aber nicht blockieren
+            if let Err(e) = add_chat_message(&database, &ChatHistoryEntry::from_message(&chat_message)).await {
+                error!("Failed to store chat message: {}", e);
+            }
+
             let _res = queue.send(chat_message);
         },
         None => {
@@ -1,19 +1,232 @@
+use std::fmt;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use futures::{StreamExt, TryFutureExt, TryStreamExt};
-use mongodb::bson::{Bson, doc, Document, to_bson};
+use mongodb::bson::{Bson, doc, Document, from_document, to_bson};
 use mongodb::bson::oid::ObjectId;
-use mongodb::Client;
-use mongodb::options::{ClientOptions, DatabaseOptions};
+use mongodb::{Client, IndexModel};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{ClientOptions, DatabaseOptions, FindOneOptions, FindOptions, IndexOptions, ReplaceOptions};
 use rocket::http::ext::IntoCollection;
 use sha2::{Sha256, Digest};
+use crate::listing::{Page, SessionFilter, UserFilter};
 use crate::security::SecurityRole;
-use crate::sessions::{Session, SessionStream, StreamType, User};
+use crate::chat::ChatHistoryEntry;
+use crate::sessions::{create_slug, Session, SessionStatus, SessionStream, StreamType, User};
+use crate::series::SessionSeries;
+use crate::attachments::Attachment;
+use crate::posters::{remove_poster_files, Poster};
+use crate::waiting_room::WaitingRoom;
+use crate::access::Viewer;
+use crate::registration::{Registration, RegistrationSettings, RegistrationStatus};
+use crate::tracks::{SessionTrack, TrackChoice};
+use crate::agenda::AgendaSegment;
+use crate::speakers::Speaker;
+use crate::events::{Event, EventPlacement};
 
 pub const DATABASE_NAME: &str = "Streamie";
 pub const TEST_DATABASE_NAME: &str = "Test";
 
 pub const USERS_COLLECTION: &str = "users";
 pub const SESSIONS_COLLECTION: &str = "sessions";
+pub const CHAT_COLLECTION: &str = "chat_messages";
+pub const SERIES_COLLECTION: &str = "series";
+pub const ATTACHMENTS_COLLECTION: &str = "attachments";
+pub const REGISTRATIONS_COLLECTION: &str = "registrations";
+pub const TRACK_CHOICES_COLLECTION: &str = "track_choices";
+pub const SPEAKERS_COLLECTION: &str = "speakers";
+pub const EVENTS_COLLECTION: &str = "events";
+
+// MongoDB Fehlercode für die Verletzung eines unique Index
+const DUPLICATE_KEY_CODE: i32 = 11000;
+
+// Fehler beim Zugriff auf die Datenbank
+// Conflict wird geliefert, wenn ein unique Index verletzt wurde (z.B. doppelter username)
+#[derive(Debug)]
+pub enum DatabaseError {
+    Conflict(String),
+    Mongo(mongodb::error::Error),
+}
+
+pub type DatabaseResult<T> = Result<T, DatabaseError>;
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Conflict(message) => write!(f, "Conflict: {}", message),
+            DatabaseError::Mongo(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+// Duplicate-Key Fehler von MongoDB werden in einen Conflict überführt, alles andere bleibt ein Mongo-Fehler
+impl From<mongodb::error::Error> for DatabaseError {
+    fn from(e: mongodb::error::Error) -> Self {
+        let duplicate_message = match e.kind.as_ref() {
+            ErrorKind::Write(WriteFailure::WriteError(write_error))
+                if write_error.code == DUPLICATE_KEY_CODE => Some(write_error.message.clone()),
+            ErrorKind::BulkWrite(failure) => failure.write_errors.as_ref()
+                .and_then(|errors| errors.iter().find(|we| we.code == DUPLICATE_KEY_CODE))
+                .map(|we| we.message.clone()),
+            ErrorKind::Command(command_error)
+                if command_error.code == DUPLICATE_KEY_CODE => Some(command_error.message.clone()),
+            _ => None
+        };
+
+        match duplicate_message {
+            Some(message) => DatabaseError::Conflict(message),
+            None => DatabaseError::Mongo(e)
+        }
+    }
+}
+
+// Alle Indizes die beim Start angelegt bzw. überprüft werden, pro Collection
+// Die Namen sind fest vergeben, damit ein geänderter Index erkannt und neu angelegt werden kann
+fn declared_indexes() -> Vec<(&'static str, IndexModel)> {
+    return vec![
+        (USERS_COLLECTION, index_model("username_unique", doc! {"username": 1}, true)),
+        (USERS_COLLECTION, partial_unique_index_model("calendar_token_unique", doc! {"calendar_token": 1},
+                                                      doc! {"calendar_token": {"$type": "string"}})),
+        (SESSIONS_COLLECTION, index_model("slug_unique", doc! {"slug": 1}, true)),
+        (SESSIONS_COLLECTION, partial_unique_index_model("stream_key_unique", doc! {"stream_key": 1},
+                                                         doc! {"stream_key": {"$type": "string"}})),
+        (SESSIONS_COLLECTION, partial_unique_index_model("import_uid_unique", doc! {"import_uid": 1},
+                                                         doc! {"import_uid": {"$type": "string"}})),
+        // Pro Serie gibt es jeden Termin nur einmal, Termine ohne occurrence (z.B. beim Umhängen) zählen nicht
+        (SESSIONS_COLLECTION, partial_unique_index_model("series_occurrence_unique", doc! {"series_id": 1, "occurrence": 1},
+                                                         doc! {"occurrence": {"$type": "string"}})),
+        (SESSIONS_COLLECTION, index_model("start_end", doc! {"start": 1, "end": 1}, false)),
+        (SESSIONS_COLLECTION, index_model("end", doc! {"end": 1}, false)),
+        (SESSIONS_COLLECTION, index_model("status_start", doc! {"status": 1, "start": 1}, false)),
+        (SESSIONS_COLLECTION, text_index_model("name_description_text", doc! {"name": 10, "description": 1})),
+        (SESSIONS_COLLECTION, index_model("event_start", doc! {"event.event_id": 1, "start": 1}, false)),
+        (REGISTRATIONS_COLLECTION, index_model("session_id_username_unique", doc! {"session_id": 1, "username": 1}, true)),
+        (REGISTRATIONS_COLLECTION, index_model("session_id_registered_at", doc! {"session_id": 1, "registered_at": 1}, false)),
+        (ATTACHMENTS_COLLECTION, index_model("session_id_uploaded_at", doc! {"session_id": 1, "uploaded_at": 1}, false)),
+        (TRACK_CHOICES_COLLECTION, index_model("username_session_id_unique", doc! {"username": 1, "session_id": 1}, true)),
+        (TRACK_CHOICES_COLLECTION, index_model("username_chosen_at", doc! {"username": 1, "chosen_at": -1}, false)),
+        (SPEAKERS_COLLECTION, index_model("name", doc! {"name": 1}, false)),
+        (EVENTS_COLLECTION, index_model("slug_unique", doc! {"slug": 1}, true)),
+        (CHAT_COLLECTION, index_model("room_created_at", doc! {"room": 1, "created_at": 1}, false)),
+        (CHAT_COLLECTION, text_index_model("message_text", doc! {"message": 1})),
+    ];
+}
+
+// Volltext-Index, die Gewichtung bestimmt das Ranking der Suchergebnisse
+fn text_index_model(name: &str, weights: Document) -> IndexModel {
+    let mut keys = Document::new();
+    for (field, _) in weights.iter() {
+        keys.insert(field, "text");
+    }
+
+    let options = IndexOptions::builder()
+        .name(name.to_string())
+        .weights(weights)
+        .default_language("german".to_string())
+        .build();
+
+    return IndexModel::builder()
+        .keys(keys)
+        .options(options)
+        .build();
+}
+
+fn index_model(name: &str, keys: Document, unique: bool) -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(name.to_string())
+        .unique(if unique { Some(true) } else { None })
+        .build();
+
+    return IndexModel::builder()
+        .keys(keys)
+        .options(options)
+        .build();
+}
+
+// unique Index, der nur für Dokumente gilt, die dem Filter entsprechen (z.B. nur gesetzte Werte)
+fn partial_unique_index_model(name: &str, keys: Document, partial_filter: Document) -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(name.to_string())
+        .unique(true)
+        .partial_filter_expression(partial_filter)
+        .build();
+
+    return IndexModel::builder()
+        .keys(keys)
+        .options(options)
+        .build();
+}
+
+// Zwei Indizes gelten als gleich, wenn Felder und unique-Eigenschaft übereinstimmen
+// Volltext-Indizes liefert MongoDB als {_fts: "text"} zurück, dort werden die gewichteten Felder verglichen
+fn index_matches(existing: &IndexModel, declared: &IndexModel) -> bool {
+    let unique = |index: &IndexModel| index.options.as_ref()
+        .and_then(|o| o.unique)
+        .unwrap_or(false);
+    let weighted_fields = |index: &IndexModel| index.options.as_ref()
+        .and_then(|o| o.weights.as_ref())
+        .map(|w| w.keys().cloned().collect::<Vec<String>>());
+
+    let keys_match = if existing.keys.contains_key("_fts") {
+        weighted_fields(existing) == weighted_fields(declared)
+    } else {
+        existing.keys == declared.keys
+    };
+
+    return keys_match && unique(existing) == unique(declared);
+}
+
+// Legt alle deklarierten Indizes an, bzw. prüft ob die vorhandenen noch passen
+// Wird beim Start der Anwendung aufgerufen
+pub async fn ensure_indexes(database: &mongodb::Database) -> DatabaseResult<()> {
+    // Sessions ohne slug (Altbestand) bekommen einen, bevor der unique Index angelegt wird
+    backfill_session_slugs(database).await?;
+
+    for (collection_name, declared) in declared_indexes() {
+        let collection = database.collection::<Document>(collection_name);
+        let name = declared.options.as_ref().and_then(|o| o.name.clone()).unwrap();
+
+        let existing: Vec<IndexModel> = collection.list_indexes(None).await?
+            .try_collect()
+            .await?;
+
+        let found = existing.iter()
+            .find(|index| index.options.as_ref().and_then(|o| o.name.as_ref()) == Some(&name));
+
+        match found {
+            Some(index) if index_matches(index, &declared) => continue,
+            Some(_) => {
+                // Index mit gleichem Namen aber anderer Definition -> neu anlegen
+                collection.drop_index(&name, None).await?;
+            },
+            None => {}
+        }
+
+        collection.create_index(declared, None).await?;
+    }
+
+    Ok(())
+}
+
+// Vergibt allen Sessions ohne slug einen eindeutigen slug auf Basis des Namens
+async fn backfill_session_slugs(database: &mongodb::Database) -> DatabaseResult<()> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+
+    let filter = doc! {"$or": [{"slug": {"$exists": false}}, {"slug": ""}]};
+    let without_slug: Vec<Document> = collection.find(filter, None).await?
+        .try_collect()
+        .await?;
+
+    for session in without_slug {
+        let id = session.get_object_id("_id").unwrap();
+        let slug = unique_session_slug(database, session.get_str("name").unwrap_or("")).await?;
+
+        collection.update_one(doc! {"_id": id}, doc! {"$set": {"slug": &slug}}, None).await?;
+    }
+
+    Ok(())
+}
 
 // Holt sich einen mongodb client
 pub async fn get_client() -> mongodb::Client {
@@ -39,27 +252,51 @@ pub async fn get_database_by_name(name: &str) -> mongodb::Database {
 }
 
 // hinzufügen eines neuen Users
-pub async fn add_new_user(database: &mongodb::Database, user: &User) -> mongodb::error::Result<()> {
+// der username ist unique, ein doppelter username wird über den Index als Conflict gemeldet
+pub async fn add_new_user(database: &mongodb::Database, user: &User) -> DatabaseResult<()> {
     let collection = database.collection::<User>(&USERS_COLLECTION);
 
-    // Filter nach dem username
-    let filter = doc! {"username": &user.username};
-    let opt_user = collection.find_one(filter, None)
-        .await
-        .expect("error while find");
+    collection.insert_one(user, None).await?;
+
+    Ok(())
+}
 
-    // der username ist unique, wenn er schon vorhanden ist soll gepanict werden
-    if opt_user.is_some() {
-        panic!("Found Users with given username while adding, but has to be unique.");
+// Freier slug für einen Sessionnamen: der Name selbst oder mit angehängter Nummer (z.B. meetup-2)
+// Sessions im Papierkorb behalten ihren slug, damit sie unverändert wiederhergestellt werden können
+pub async fn unique_session_slug(database: &mongodb::Database, name: &str) -> DatabaseResult<String> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+    let base = create_slug(name);
+
+    let mut slug = base.clone();
+    let mut counter = 2;
+    // Ein slug im Format einer ObjectId wäre in get_session_by_key nicht von einer id zu unterscheiden
+    while ObjectId::parse_str(&slug).is_ok() || collection.find_one(doc! {"slug": &slug}, None).await?.is_some() {
+        slug = format!("{}-{}", base, counter);
+        counter += 1;
     }
 
-    collection.insert_one(user, None).await?;
+    Ok(slug)
+}
 
-    Ok(())
+// Anzahl der Versuche, falls zwischen Suche und Einfügen ein anderer den slug belegt
+const SLUG_ATTEMPTS: usize = 5;
+
+// hinzufügen einer neuen session mit einem freien slug auf Basis des Namens, der vergebene slug steht danach in session
+// Gleichnamige Sessions sind erlaubt, nur andere Konflikte (z.B. import_uid) werden gemeldet
+pub async fn add_session_with_unique_slug(database: &mongodb::Database, session: &mut Session) -> DatabaseResult<()> {
+    let mut attempt = 1;
+    loop {
+        session.slug = unique_session_slug(database, &session.name).await?;
+        match add_new_session(database, session).await {
+            Err(DatabaseError::Conflict(message)) if message.contains("slug_unique") && attempt < SLUG_ATTEMPTS => attempt += 1,
+            result => return result
+        }
+    }
 }
 
 // hinzufügen einer neuer session
-pub async fn add_new_session(database: &mongodb::Database, session: &Session) -> mongodb::error::Result<()> {
+// der slug ist unique, ein doppelter slug wird über den Index als Conflict gemeldet
+pub async fn add_new_session(database: &mongodb::Database, session: &Session) -> DatabaseResult<()> {
     let collection = database.collection::<Session>(&SESSIONS_COLLECTION);
 
     collection.insert_one(session, None).await?;
@@ -68,40 +305,85 @@ pub async fn add_new_session(database: &mongodb::Database, session: &Session) ->
 }
 
 // updaten einer session
-pub async fn update_session(database: &mongodb::Database, session: &Session) -> mongodb::error::Result<()> {
+// Optimistische Sperre: geschrieben wird nur, wenn die version in der Datenbank noch der version der
+// übergebenen session entspricht. Danach ist die gespeicherte version um eins erhöht.
+// Wurde die session zwischenzeitlich geändert, wird ein Conflict geliefert
+pub async fn update_session(database: &mongodb::Database, session: &Session) -> DatabaseResult<()> {
     let collection = database.collection::<Session>(&SESSIONS_COLLECTION);
 
-    let filter = doc! {"_id": &session.id};
+    let mut filter = not_deleted();
+    filter.insert("_id", &session.id);
+    // Altbestand ohne version wird wie version 0 behandelt
+    if session.version == 0 {
+        filter.insert("version", doc! {"$in": [0_i64, Bson::Null]});
+    } else {
+        filter.insert("version", session.version);
+    }
+
+    let result = collection.update_one(filter, construct_session_update_doc(&session), None).await?;
 
-    collection.update_one(filter, construct_session_update_doc(&session), None).await?;
+    if result.matched_count == 0 {
+        return Err(DatabaseError::Conflict(format!("Session {} was modified concurrently", session.id)));
+    }
 
     Ok(())
 }
 
 // Wir benutzt um ein session document zu bauen mitdem dann das bestehende mongodb dokukment
 // aktualisiert wird
+// start und end werden genauso wie beim insert serialisiert, damit Zeitabfragen über den Index funktionieren
 fn construct_session_update_doc(session: &Session) -> Document {
     return doc!{"$set": {
-            "start": session.start.to_string(),
-            "end": session.end.to_string(),
+            "start": to_bson(&session.start).unwrap(),
+            "end": to_bson(&session.end).unwrap(),
             "name": &session.name,
             "description": &session.description,
-            "stream": to_bson(&session.stream).unwrap()
+            "description_format": to_bson(&session.description_format).unwrap(),
+            "stream": to_bson(&session.stream).unwrap(),
+            "timezone": &session.timezone,
+            "access": to_bson(&session.access).unwrap(),
+            "version": session.version + 1
             }
     };
 }
 
-// löschen einer session per id
+// endgültiges löschen einer session per id, der Chatverlauf der Session wird mit gelöscht
 pub async fn remove_session_by_id(database: &mongodb::Database, id: &ObjectId) -> mongodb::error::Result<()> {
     let collection = database.collection::<Session>(&SESSIONS_COLLECTION);
 
     let filter = doc! {"_id": &id};
+    // Die Poster-Dateien liegen im Ordner der Anhänge und werden vor dem Aufräumen des Ordners entfernt
+    if let Some(poster) = collection.find_one(filter.clone(), None).await?.and_then(|session| session.poster) {
+        remove_poster_files(&poster).await;
+    }
     collection.delete_one(filter, None).await?;
 
+    let chat_collection = database.collection::<ChatHistoryEntry>(CHAT_COLLECTION);
+    chat_collection.delete_many(doc! {"room": id.to_hex()}, None).await?;
+
+    // Anhänge liegen im Dateisystem, die Metadaten kennen den Pfad
+    let attachment_collection = database.collection::<Attachment>(ATTACHMENTS_COLLECTION);
+    let attachments: Vec<Attachment> = attachment_collection.find(doc! {"session_id": id}, None).await?
+        .try_collect().await?;
+    for attachment in &attachments {
+        let _ = tokio::fs::remove_file(&attachment.path).await;
+        if let Some(directory) = std::path::Path::new(&attachment.path).parent() {
+            // schlägt fehl solange noch Dateien im Ordner liegen, das ist gewollt
+            let _ = tokio::fs::remove_dir(directory).await;
+        }
+    }
+    attachment_collection.delete_many(doc! {"session_id": id}, None).await?;
+
+    let registration_collection = database.collection::<Registration>(REGISTRATIONS_COLLECTION);
+    registration_collection.delete_many(doc! {"session_id": id}, None).await?;
+
+    let choice_collection = database.collection::<TrackChoice>(TRACK_CHOICES_COLLECTION);
+    choice_collection.delete_many(doc! {"session_id": id}, None).await?;
+
     Ok(())
 }
 
-// löschen eines users per id
+// endgültiges löschen eines users per id
 pub async fn remove_user_by_id(database: &mongodb::Database, id: &ObjectId) -> mongodb::error::Result<()> {
     let collection = database.collection::<User>(&USERS_COLLECTION);
 
@@ -111,58 +393,693 @@ pub async fn remove_user_by_id(database: &mongodb::Database, id: &ObjectId) -> m
     Ok(())
 }
 
-pub async fn remove_session_by_name(database: &mongodb::Database, name: String) -> mongodb::error::Result<()> {
-    let collection = database.collection::<Session>(&SESSIONS_COLLECTION);
+// Filter für alle Einträge, die nicht im Papierkorb liegen (deleted_at fehlt oder ist null)
+pub fn not_deleted() -> Document {
+    return doc! {"deleted_at": Bson::Null};
+}
 
-    let filter = doc! {"name": &name};
-    collection.delete_one(filter, None).await?;
+// Verschiebt ein Dokument in den Papierkorb, indem deleted_at gesetzt wird
+async fn set_deleted_at(collection: &mongodb::Collection<Document>, filter: Document,
+                        deleted_at: Bson) -> DatabaseResult<bool> {
+    let result = collection.update_one(filter, doc! {"$set": {"deleted_at": deleted_at}}, None).await?;
+    Ok(result.matched_count > 0)
+}
+
+// Verschiebt eine session in den Papierkorb
+pub async fn trash_session_by_id(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<bool> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    return set_deleted_at(&collection, filter, to_bson(&Utc::now()).unwrap()).await;
+}
+
+// Holt eine session aus dem Papierkorb zurück
+pub async fn restore_session_by_id(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<bool> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+    return set_deleted_at(&collection, doc! {"_id": id}, Bson::Null).await;
+}
+
+// Verschiebt einen user in den Papierkorb, ein Login ist danach nicht mehr möglich
+pub async fn trash_user_by_id(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<bool> {
+    let collection = database.collection::<Document>(USERS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    return set_deleted_at(&collection, filter, to_bson(&Utc::now()).unwrap()).await;
+}
+
+// Holt einen user aus dem Papierkorb zurück
+pub async fn restore_user_by_id(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<bool> {
+    let collection = database.collection::<Document>(USERS_COLLECTION);
+    return set_deleted_at(&collection, doc! {"_id": id}, Bson::Null).await;
+}
+
+// Setzt bzw. entfernt (None) den Stream-Key einer session
+// Ist der Key bereits vergeben, wird ein Conflict geliefert
+pub async fn set_stream_key(database: &mongodb::Database, id: &ObjectId, stream_key: Option<&str>) -> DatabaseResult<bool> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+
+    let result = collection.update_one(filter, doc! {"$set": {"stream_key": stream_key}}, None).await?;
+    Ok(result.matched_count > 0)
+}
+
+// Sucht die session zu einem Stream-Key, sessions im Papierkorb dürfen nicht mehr streamen
+pub async fn get_session_by_stream_key(database: &mongodb::Database, stream_key: &str) -> DatabaseResult<Option<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("stream_key", stream_key);
+
+    Ok(collection.find_one(filter, None).await?)
+}
+
+// Alle sessions, für die noch gestreamt werden kann (nicht beendet oder gerade live), nach Start sortiert
+pub async fn get_ingest_sessions(database: &mongodb::Database, now: DateTime<Utc>) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("$or", vec![
+        doc! {"end": {"$gt": to_bson(&now).unwrap()}},
+        doc! {"live": true},
+    ]);
+    let options = FindOptions::builder().sort(doc! {"start": 1}).build();
+
+    Ok(collection.find(filter, options).await?.try_collect().await?)
+}
+
+// Sucht die session, die aus einer Import-Datei mit dieser UID angelegt wurde
+pub async fn get_session_by_import_uid(database: &mongodb::Database, import_uid: &str) -> DatabaseResult<Option<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("import_uid", import_uid);
+
+    Ok(collection.find_one(filter, None).await?)
+}
+
+// hinzufügen einer neuen Serie
+pub async fn add_series(database: &mongodb::Database, series: &SessionSeries) -> DatabaseResult<()> {
+    let collection = database.collection::<SessionSeries>(SERIES_COLLECTION);
+
+    collection.insert_one(series, None).await?;
 
     Ok(())
 }
 
-// Sammeln aller user in der Datenbank
-pub async fn get_all_users(database: &mongodb::Database) -> Vec<User> {
-    let collection = database.collection::<User>(&USERS_COLLECTION);
+// updaten einer Serie, wie bei update_session mit optimistischer Sperre über die version
+pub async fn update_series(database: &mongodb::Database, series: &SessionSeries) -> DatabaseResult<()> {
+    let collection = database.collection::<SessionSeries>(SERIES_COLLECTION);
 
-    let mut cursor = collection.find(None, None)
-        .await
-        .expect("Error while find");
+    let filter = doc! {"_id": &series.id, "version": series.version};
+    let mut updated = series.clone();
+    updated.version += 1;
+    let result = collection.replace_one(filter, updated, None).await?;
 
-    let mut users: Vec<User> = vec![];
+    if result.matched_count == 0 {
+        return Err(DatabaseError::Conflict(format!("Series {} was modified concurrently", series.id)));
+    }
 
-    while let Some(user) = cursor.try_next()
-        .await
-        .expect("Error while processing Cursor") {
-        users.push(user);
+    Ok(())
+}
+
+pub async fn get_series_by_id(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<Option<SessionSeries>> {
+    let collection = database.collection::<SessionSeries>(SERIES_COLLECTION);
+
+    Ok(collection.find_one(doc! {"_id": id}, None).await?)
+}
+
+pub async fn get_all_series(database: &mongodb::Database) -> DatabaseResult<Vec<SessionSeries>> {
+    let collection = database.collection::<SessionSeries>(SERIES_COLLECTION);
+    let options = FindOptions::builder().sort(doc! {"start": 1}).build();
+
+    Ok(collection.find(None, options).await?.try_collect().await?)
+}
+
+// Alle Termine einer Serie nach ursprünglichem Beginn sortiert
+// Mit include_deleted auch die Termine im Papierkorb, damit diese nicht erneut angelegt werden
+pub async fn get_series_sessions(database: &mongodb::Database, series_id: &ObjectId, include_deleted: bool) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = if include_deleted { Document::new() } else { not_deleted() };
+    filter.insert("series_id", series_id);
+    let options = FindOptions::builder().sort(doc! {"occurrence": 1}).build();
+
+    Ok(collection.find(filter, options).await?.try_collect().await?)
+}
+
+// hinzufügen eines Events, ein doppelter slug wird über den Index als Conflict gemeldet
+pub async fn add_event(database: &mongodb::Database, event: &Event) -> DatabaseResult<()> {
+    let collection = database.collection::<Event>(EVENTS_COLLECTION);
+
+    collection.insert_one(event, None).await?;
+
+    Ok(())
+}
+
+pub async fn update_event(database: &mongodb::Database, event: &Event) -> DatabaseResult<bool> {
+    let collection = database.collection::<Event>(EVENTS_COLLECTION);
+
+    let result = collection.replace_one(doc! {"_id": &event.id}, event, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+pub async fn get_event_by_id(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<Option<Event>> {
+    let collection = database.collection::<Event>(EVENTS_COLLECTION);
+
+    Ok(collection.find_one(doc! {"_id": id}, None).await?)
+}
+
+pub async fn get_event_by_slug(database: &mongodb::Database, slug: &str) -> DatabaseResult<Option<Event>> {
+    let collection = database.collection::<Event>(EVENTS_COLLECTION);
+
+    Ok(collection.find_one(doc! {"slug": slug}, None).await?)
+}
+
+// Alle Events nach Beginn sortiert, werden auch für die Zugriffsprüfung der Sessions gebraucht
+pub async fn get_events(database: &mongodb::Database) -> DatabaseResult<Vec<Event>> {
+    let collection = database.collection::<Event>(EVENTS_COLLECTION);
+    let options = FindOptions::builder().sort(doc! {"start_date": 1, "name": 1}).build();
+
+    Ok(collection.find(None, options).await?.try_collect().await?)
+}
+
+// Löscht ein Event, die Sessions bleiben erhalten und gehören danach zu keinem Event mehr
+pub async fn remove_event(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<bool> {
+    let collection = database.collection::<Event>(EVENTS_COLLECTION);
+
+    let result = collection.delete_one(doc! {"_id": id}, None).await?;
+    database.collection::<Session>(SESSIONS_COLLECTION)
+        .update_many(doc! {"event.event_id": id}, doc! {"$set": {"event": Bson::Null}, "$inc": {"version": 1_i64}}, None).await?;
+
+    Ok(result.deleted_count > 0)
+}
+
+// Alle Sessions eines Events nach Beginn sortiert
+pub async fn get_event_sessions(database: &mongodb::Database, event_id: &ObjectId) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("event.event_id", event_id);
+    let options = FindOptions::builder().sort(doc! {"start": 1}).build();
+
+    Ok(collection.find(filter, options).await?.try_collect().await?)
+}
+
+// Sessions, die noch keinem Event zugeordnet sind (Auswahl beim Zuordnen)
+pub async fn get_sessions_without_event(database: &mongodb::Database) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("event", Bson::Null);
+    let options = FindOptions::builder().sort(doc! {"start": 1}).build();
+
+    Ok(collection.find(filter, options).await?.try_collect().await?)
+}
+
+// Ordnet eine Session einem Event zu bzw. entfernt sie mit None aus ihrem Event
+pub async fn set_session_event(database: &mongodb::Database, id: &ObjectId, placement: Option<&EventPlacement>) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    let update = doc! {"$set": {"event": to_bson(&placement).unwrap()}, "$inc": {"version": 1_i64}};
+    let result = collection.update_one(filter, update, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// hinzufügen einer Anmeldung, eine zweite Anmeldung desselben users wird über den Index als Conflict gemeldet
+pub async fn add_registration(database: &mongodb::Database, registration: &Registration) -> DatabaseResult<()> {
+    let collection = database.collection::<Registration>(REGISTRATIONS_COLLECTION);
+
+    collection.insert_one(registration, None).await?;
+
+    Ok(())
+}
+
+// alle Anmeldungen einer session in der Reihenfolge der Anmeldung, diese bestimmt auch die Warteliste
+pub async fn get_session_registrations(database: &mongodb::Database, session_id: &ObjectId) -> DatabaseResult<Vec<Registration>> {
+    let collection = database.collection::<Registration>(REGISTRATIONS_COLLECTION);
+    let options = FindOptions::builder().sort(doc! {"registered_at": 1, "_id": 1}).build();
+
+    Ok(collection.find(doc! {"session_id": session_id}, options).await?.try_collect().await?)
+}
+
+pub async fn get_registration(database: &mongodb::Database, session_id: &ObjectId, username: &str) -> DatabaseResult<Option<Registration>> {
+    let collection = database.collection::<Registration>(REGISTRATIONS_COLLECTION);
+
+    Ok(collection.find_one(doc! {"session_id": session_id, "username": username}, None).await?)
+}
+
+// entfernt eine Anmeldung und liefert sie zurück, damit ein frei gewordener Platz nachbesetzt werden kann
+pub async fn remove_registration(database: &mongodb::Database, session_id: &ObjectId, username: &str) -> DatabaseResult<Option<Registration>> {
+    let collection = database.collection::<Registration>(REGISTRATIONS_COLLECTION);
+
+    Ok(collection.find_one_and_delete(doc! {"session_id": session_id, "username": username}, None).await?)
+}
+
+pub async fn set_registration_status(database: &mongodb::Database, id: &ObjectId, status: RegistrationStatus,
+                                     promoted_at: Option<DateTime<Utc>>) -> DatabaseResult<bool> {
+    let collection = database.collection::<Registration>(REGISTRATIONS_COLLECTION);
+    let update = doc! {"$set": {
+        "status": to_bson(&status).unwrap(),
+        "promoted_at": to_bson(&promoted_at).unwrap()
+    }};
+    let result = collection.update_one(doc! {"_id": id}, update, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// setzt die Einstellungen der Anmeldung einer session
+// die version wird erhöht, damit ein offenes Bearbeiten-Formular den neuen Stand bemerkt
+pub async fn set_registration_settings(database: &mongodb::Database, id: &ObjectId, settings: &RegistrationSettings) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    let update = doc! {"$set": {"registration": to_bson(settings).unwrap()}, "$inc": {"version": 1_i64}};
+    let result = collection.update_one(filter, update, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// setzt die Streams einer session, der erste wird gleichzeitig zum Haupt-Stream
+// die version wird erhöht, damit ein offenes Bearbeiten-Formular den neuen Stand bemerkt
+pub async fn set_session_tracks(database: &mongodb::Database, id: &ObjectId, tracks: &[SessionTrack]) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+
+    let mut update = doc! {"tracks": to_bson(tracks).unwrap()};
+    if let Some(first) = tracks.first() {
+        update.insert("stream", to_bson(&first.stream).unwrap());
     }
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    let result = collection.update_one(filter, doc! {"$set": update, "$inc": {"version": 1_i64}}, None).await?;
 
-    return users;
+    Ok(result.matched_count > 0)
 }
 
-// Sammeln aller sessions in der Datenbank
-pub async fn get_all_sessions(database: &mongodb::Database) -> Vec<Session> {
-    let collection = database.collection::<Session>(&SESSIONS_COLLECTION);
+// ersetzt den Stream an Position index, der erste Stream steht immer in stream (siehe session_tracks)
+pub async fn set_track_stream(database: &mongodb::Database, id: &ObjectId, index: usize, stream: &SessionStream) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
 
-    let mut cursor = collection.find(None, None)
-        .await
-        .expect("Error while find");
+    let field = if index == 0 { "stream".to_string() } else { format!("tracks.{}.stream", index) };
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    let update = doc! {"$set": {field: to_bson(stream).unwrap()}, "$inc": {"version": 1_i64}};
+    let result = collection.update_one(filter, update, None).await?;
 
-    let mut sessions: Vec<Session> = vec![];
+    Ok(result.matched_count > 0)
+}
 
-    while let Some(session) = cursor.try_next()
-        .await
-        .expect("Error while processing Cursor") {
-        sessions.push(session);
+// ersetzt die Agenda einer session
+pub async fn set_session_agenda(database: &mongodb::Database, id: &ObjectId, agenda: &[AgendaSegment]) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    let update = doc! {"$set": {"agenda": to_bson(agenda).unwrap()}, "$inc": {"version": 1_i64}};
+    let result = collection.update_one(filter, update, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// setzt oder entfernt das Poster einer Session, die Dateien verwaltet posters.rs
+pub async fn set_session_poster(database: &mongodb::Database, id: &ObjectId, poster: Option<&Poster>) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    let update = doc! {"$set": {"poster": to_bson(&poster).unwrap()}, "$inc": {"version": 1_i64}};
+    let result = collection.update_one(filter, update, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// setzt Halte-Medium und Hinweis für den Warteraum einer Session
+pub async fn set_session_waiting_room(database: &mongodb::Database, id: &ObjectId, waiting_room: &WaitingRoom) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+    let update = doc! {"$set": {"waiting_room": to_bson(waiting_room).unwrap()}, "$inc": {"version": 1_i64}};
+    let result = collection.update_one(filter, update, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// hinzufügen eines Sprecher-Profils
+pub async fn add_speaker(database: &mongodb::Database, speaker: &Speaker) -> DatabaseResult<()> {
+    let collection = database.collection::<Speaker>(SPEAKERS_COLLECTION);
+
+    collection.insert_one(speaker, None).await?;
+
+    Ok(())
+}
+
+pub async fn update_speaker(database: &mongodb::Database, speaker: &Speaker) -> DatabaseResult<()> {
+    let collection = database.collection::<Speaker>(SPEAKERS_COLLECTION);
+
+    collection.replace_one(doc! {"_id": &speaker.id}, speaker, None).await?;
+
+    Ok(())
+}
+
+pub async fn get_speaker(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<Option<Speaker>> {
+    let collection = database.collection::<Speaker>(SPEAKERS_COLLECTION);
+
+    Ok(collection.find_one(doc! {"_id": id}, None).await?)
+}
+
+// alle Sprecher alphabetisch, z.B. für die Auswahl in der Agenda
+pub async fn get_speakers(database: &mongodb::Database) -> DatabaseResult<Vec<Speaker>> {
+    let collection = database.collection::<Speaker>(SPEAKERS_COLLECTION);
+    let options = FindOptions::builder().sort(doc! {"name": 1}).build();
+
+    Ok(collection.find(None, options).await?.try_collect().await?)
+}
+
+pub async fn get_speakers_by_ids(database: &mongodb::Database, ids: &[ObjectId]) -> DatabaseResult<Vec<Speaker>> {
+    let collection = database.collection::<Speaker>(SPEAKERS_COLLECTION);
+
+    Ok(collection.find(doc! {"_id": {"$in": ids}}, None).await?.try_collect().await?)
+}
+
+// entfernt einen Sprecher und liefert ihn zurück (für das Foto), in den Agenden wird er ausgetragen
+pub async fn remove_speaker(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<Option<Speaker>> {
+    let collection = database.collection::<Speaker>(SPEAKERS_COLLECTION);
+
+    let speaker = collection.find_one_and_delete(doc! {"_id": id}, None).await?;
+    if speaker.is_some() {
+        database.collection::<Document>(SESSIONS_COLLECTION)
+            .update_many(doc! {"agenda.speakers": id}, doc! {"$pull": {"agenda.$[].speakers": id}}, None).await?;
     }
 
-    return sessions;
+    Ok(speaker)
+}
+
+// merkt sich den gewählten Stream, eine frühere Auswahl für dieselbe session wird ersetzt
+pub async fn set_track_choice(database: &mongodb::Database, choice: &TrackChoice) -> DatabaseResult<()> {
+    let collection = database.collection::<TrackChoice>(TRACK_CHOICES_COLLECTION);
+    let options = ReplaceOptions::builder().upsert(true).build();
+
+    collection.replace_one(doc! {"username": &choice.username, "session_id": &choice.session_id}, choice, options).await?;
+
+    Ok(())
+}
+
+pub async fn get_track_choice(database: &mongodb::Database, username: &str, session_id: &ObjectId) -> DatabaseResult<Option<TrackChoice>> {
+    let collection = database.collection::<TrackChoice>(TRACK_CHOICES_COLLECTION);
+
+    Ok(collection.find_one(doc! {"username": username, "session_id": session_id}, None).await?)
+}
+
+// die zuletzt getroffene Auswahl eines users, egal für welche session
+pub async fn get_latest_track_choice(database: &mongodb::Database, username: &str) -> DatabaseResult<Option<TrackChoice>> {
+    let collection = database.collection::<TrackChoice>(TRACK_CHOICES_COLLECTION);
+    let options = FindOneOptions::builder().sort(doc! {"chosen_at": -1}).build();
+
+    Ok(collection.find_one(doc! {"username": username}, options).await?)
 }
 
-// holt sich die session per id
+// hinzufügen der Metadaten eines hochgeladenen Anhangs
+pub async fn add_attachment(database: &mongodb::Database, attachment: &Attachment) -> DatabaseResult<()> {
+    let collection = database.collection::<Attachment>(ATTACHMENTS_COLLECTION);
+
+    collection.insert_one(attachment, None).await?;
+
+    Ok(())
+}
+
+// alle Anhänge einer session in der Reihenfolge des Hochladens
+pub async fn get_session_attachments(database: &mongodb::Database, session_id: &ObjectId) -> DatabaseResult<Vec<Attachment>> {
+    let collection = database.collection::<Attachment>(ATTACHMENTS_COLLECTION);
+    let options = FindOptions::builder().sort(doc! {"uploaded_at": 1}).build();
+
+    Ok(collection.find(doc! {"session_id": session_id}, options).await?.try_collect().await?)
+}
+
+// ein Anhang wird immer zusammen mit seiner session gesucht, damit keine fremden Dateien abrufbar sind
+pub async fn get_attachment(database: &mongodb::Database, session_id: &ObjectId, id: &ObjectId) -> DatabaseResult<Option<Attachment>> {
+    let collection = database.collection::<Attachment>(ATTACHMENTS_COLLECTION);
+
+    Ok(collection.find_one(doc! {"_id": id, "session_id": session_id}, None).await?)
+}
+
+// entfernt die Metadaten eines Anhangs und liefert sie zurück, damit die Datei gelöscht werden kann
+pub async fn remove_attachment(database: &mongodb::Database, session_id: &ObjectId, id: &ObjectId) -> DatabaseResult<Option<Attachment>> {
+    let collection = database.collection::<Attachment>(ATTACHMENTS_COLLECTION);
+
+    Ok(collection.find_one_and_delete(doc! {"_id": id, "session_id": session_id}, None).await?)
+}
+
+// Hängt eine session an einen Termin einer (anderen) Serie, ohne occurrence wird sie nur vom Termin gelöst
+pub async fn set_session_series(database: &mongodb::Database, id: &ObjectId, series_id: &ObjectId,
+                                occurrence: Option<DateTime<Utc>>) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let update = match occurrence {
+        Some(occurrence) => doc! {"$set": {"series_id": series_id, "occurrence": to_bson(&occurrence).unwrap()}},
+        None => doc! {"$set": {"series_id": series_id}, "$unset": {"occurrence": ""}}
+    };
+    let result = collection.update_one(doc! {"_id": id}, update, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// Alle sessions, die noch nicht beendet sind, nach Start sortiert (z.B. für den Kalender-Feed)
+pub async fn get_upcoming_sessions(database: &mongodb::Database, now: DateTime<Utc>) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("end", doc! {"$gt": to_bson(&now).unwrap()});
+    let options = FindOptions::builder().sort(doc! {"start": 1}).build();
+
+    Ok(collection.find(filter, options).await?.try_collect().await?)
+}
+
+// Markiert eine session als live bzw. offline, live_since hält den Beginn der Übertragung fest
+pub async fn set_session_live(database: &mongodb::Database, id: &ObjectId, live: bool) -> DatabaseResult<bool> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+    let live_since = if live { to_bson(&Utc::now()).unwrap() } else { Bson::Null };
+
+    let result = collection.update_one(doc! {"_id": id},
+                                       doc! {"$set": {"live": live, "live_since": live_since}}, None).await?;
+    Ok(result.matched_count > 0)
+}
+
+// Alle sessions, deren Status sich noch ändern kann (nicht archiviert und nicht im Papierkorb)
+pub async fn get_active_sessions(database: &mongodb::Database) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("status", doc! {"$ne": to_bson(&SessionStatus::Archived).unwrap()});
+
+    Ok(collection.find(filter, None).await?.try_collect().await?)
+}
+
+// Schreibt den vom Scheduler berechneten Status einer session fort
+pub async fn set_session_status(database: &mongodb::Database, id: &ObjectId, status: SessionStatus) -> DatabaseResult<bool> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+    let result = collection.update_one(doc! {"_id": id},
+                                       doc! {"$set": {"status": to_bson(&status).unwrap()}}, None).await?;
+    Ok(result.matched_count > 0)
+}
+
+// Verschiebt Beginn und Ende einer session (z.B. "jetzt live gehen"), die version wird dabei erhöht
+pub async fn set_session_times(database: &mongodb::Database, id: &ObjectId, start: DateTime<Utc>, end: DateTime<Utc>) -> DatabaseResult<bool> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
+
+    let update = doc! {
+        "$set": {"start": to_bson(&start).unwrap(), "end": to_bson(&end).unwrap()},
+        "$inc": {"version": 1_i64}
+    };
+    let result = collection.update_one(filter, update, None).await?;
+    Ok(result.matched_count > 0)
+}
+
+// Alle sessions im Papierkorb, zuletzt gelöschte zuerst
+pub async fn get_trashed_sessions(database: &mongodb::Database) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let options = FindOptions::builder().sort(doc! {"deleted_at": -1}).build();
+
+    let sessions: Vec<Session> = collection.find(doc! {"deleted_at": {"$ne": Bson::Null}}, options).await?
+        .try_collect()
+        .await?;
+
+    Ok(sessions)
+}
+
+// Alle user im Papierkorb, zuletzt gelöschte zuerst
+pub async fn get_trashed_users(database: &mongodb::Database) -> DatabaseResult<Vec<User>> {
+    let collection = database.collection::<User>(USERS_COLLECTION);
+    let options = FindOptions::builder().sort(doc! {"deleted_at": -1}).build();
+
+    let users: Vec<User> = collection.find(doc! {"deleted_at": {"$ne": Bson::Null}}, options).await?
+        .try_collect()
+        .await?;
+
+    Ok(users)
+}
+
+// Löscht eine session endgültig, aber nur wenn sie im Papierkorb liegt
+pub async fn purge_trashed_session(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<bool> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+
+    let filter = doc! {"_id": id, "deleted_at": {"$ne": Bson::Null}};
+    if collection.find_one(filter, None).await?.is_none() {
+        return Ok(false);
+    }
+
+    remove_session_by_id(database, id).await?;
+    Ok(true)
+}
+
+// Löscht einen user endgültig, aber nur wenn er im Papierkorb liegt
+pub async fn purge_trashed_user(database: &mongodb::Database, id: &ObjectId) -> DatabaseResult<bool> {
+    let collection = database.collection::<User>(USERS_COLLECTION);
+
+    let filter = doc! {"_id": id, "deleted_at": {"$ne": Bson::Null}};
+    let result = collection.delete_one(filter, None).await?;
+
+    Ok(result.deleted_count > 0)
+}
+
+// Löscht alle Einträge endgültig, die vor dem angegebenen Zeitpunkt in den Papierkorb verschoben wurden
+// Liefert die Anzahl der gelöschten sessions und user
+pub async fn purge_trash(database: &mongodb::Database, before: DateTime<Utc>) -> DatabaseResult<(u64, u64)> {
+    let filter = doc! {"deleted_at": {"$ne": Bson::Null, "$lt": to_bson(&before).unwrap()}};
+
+    let sessions: Vec<Session> = database.collection::<Session>(SESSIONS_COLLECTION)
+        .find(filter.clone(), None).await?
+        .try_collect()
+        .await?;
+    for session in &sessions {
+        remove_session_by_id(database, &session.id).await?;
+    }
+
+    let users = database.collection::<User>(USERS_COLLECTION)
+        .delete_many(filter, None).await?;
+
+    Ok((sessions.len() as u64, users.deleted_count))
+}
+
+// Sammeln einer Seite von sessions, gefiltert und nach Startzeit sortiert
+// Es werden nur sessions geliefert, die der viewer gelistet sehen darf
+pub async fn find_sessions(database: &mongodb::Database, filter: &SessionFilter, viewer: &Viewer) -> DatabaseResult<Page<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let (page, per_page) = filter.page_and_size();
+    let document = match viewer.listing_filter() {
+        Some(access) => doc! {"$and": [filter.to_document(Utc::now()), access]},
+        None => filter.to_document(Utc::now())
+    };
+
+    let total = collection.count_documents(document.clone(), None).await?;
+    let sessions: Vec<Session> = collection.find(document, filter.find_options()).await?
+        .try_collect()
+        .await?;
+
+    Ok(Page::new(sessions, page, per_page, total))
+}
+
+// Sammeln einer Seite von usern, gefiltert nach Rolle und Suchbegriff
+pub async fn find_users(database: &mongodb::Database, filter: &UserFilter) -> DatabaseResult<Page<User>> {
+    let collection = database.collection::<User>(USERS_COLLECTION);
+    let (page, per_page) = filter.page_and_size();
+    let document = filter.to_document();
+
+    let total = collection.count_documents(document.clone(), None).await?;
+    let users: Vec<User> = collection.find(document, filter.find_options()).await?
+        .try_collect()
+        .await?;
+
+    Ok(Page::new(users, page, per_page, total))
+}
+
+// Volltextsuche über Name und Beschreibung der Sessions, sortiert nach Relevanz
+// Wie in der Übersicht werden nur sessions gefunden, die der viewer gelistet sehen darf
+pub async fn search_sessions(database: &mongodb::Database, query: &str, viewer: &Viewer, limit: i64) -> DatabaseResult<Vec<(Session, f64)>> {
+    let collection = database.collection::<Document>(SESSIONS_COLLECTION);
+    return search_text(&collection, query, viewer.listing_filter(), limit).await;
+}
+
+// Alle sessions, die der viewer gelistet sehen darf, ihre IDs sind die Raumnamen des Chats
+// Damit wird die Chatsuche vor dem Limit auf diese Räume eingeschränkt
+pub async fn get_listed_sessions(database: &mongodb::Database, viewer: &Viewer) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+    let mut filter = not_deleted();
+    if let Some(access) = viewer.listing_filter() {
+        filter.extend(access);
+    }
+
+    let sessions: Vec<Session> = collection.find(filter, None).await?
+        .try_collect()
+        .await?;
+    Ok(sessions)
+}
+
+// Volltextsuche im gespeicherten Chatverlauf, optional eingeschränkt auf bestimmte Räume (Sessions)
+pub async fn search_chat_messages(database: &mongodb::Database, query: &str, rooms: Option<&[String]>,
+                                  limit: i64) -> DatabaseResult<Vec<(ChatHistoryEntry, f64)>> {
+    let collection = database.collection::<Document>(CHAT_COLLECTION);
+    let filter = rooms.map(|rooms| doc! {"room": {"$in": rooms}});
+    return search_text(&collection, query, filter, limit).await;
+}
+
+// Führt eine $text Suche aus und liefert die Dokumente zusammen mit dem textScore
+async fn search_text<T: serde::de::DeserializeOwned>(collection: &mongodb::Collection<Document>, query: &str,
+                                                      filter: Option<Document>, limit: i64) -> DatabaseResult<Vec<(T, f64)>> {
+    let mut text_filter = doc! {"$text": {"$search": query}};
+    text_filter.extend(not_deleted());
+    if let Some(filter) = filter {
+        text_filter.extend(filter);
+    }
+
+    let options = FindOptions::builder()
+        .projection(doc! {"score": {"$meta": "textScore"}})
+        .sort(doc! {"score": {"$meta": "textScore"}})
+        .limit(limit)
+        .build();
+
+    let documents: Vec<Document> = collection.find(text_filter, options).await?
+        .try_collect()
+        .await?;
+
+    let mut results = Vec::new();
+    for document in documents {
+        let score = document.get_f64("score").unwrap_or(0.0);
+        let item: T = from_document(document)
+            .map_err(|e| DatabaseError::Mongo(e.into()))?;
+        results.push((item, score));
+    }
+
+    Ok(results)
+}
+
+// Speichern einer Chatnachricht für den Verlauf und die Suche
+pub async fn add_chat_message(database: &mongodb::Database, entry: &ChatHistoryEntry) -> DatabaseResult<()> {
+    let collection = database.collection::<ChatHistoryEntry>(CHAT_COLLECTION);
+
+    collection.insert_one(entry, None).await?;
+
+    Ok(())
+}
+
+// Holt mehrere sessions anhand ihrer ids, z.B. um Suchtreffer im Chat einer Session zuzuordnen
+pub async fn get_sessions_by_ids(database: &mongodb::Database, ids: &[ObjectId]) -> DatabaseResult<Vec<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("_id", doc! {"$in": ids});
+    let sessions: Vec<Session> = collection.find(filter, None).await?
+        .try_collect()
+        .await?;
+
+    Ok(sessions)
+}
+
+// holt sich die session per id, nur noch für Tests, die Routen nutzen get_session_by_key
+#[cfg(test)]
 pub async fn get_session_by_id(database: &mongodb::Database, id: &ObjectId) -> Session {
     let collection = database.collection::<Session>(&SESSIONS_COLLECTION);
 
-    let filter = doc! {"_id": id};
+    let mut filter = not_deleted();
+    filter.insert("_id", id);
     let mut cursor = collection.find_one(filter, None)
         .await
         .expect("Error while find");
@@ -170,15 +1087,68 @@ pub async fn get_session_by_id(database: &mongodb::Database, id: &ObjectId) -> S
     return cursor.unwrap();
 }
 
-pub async fn get_session_by_name(database: &mongodb::Database, name: String) -> Session {
-    let collection = database.collection::<Session>(&SESSIONS_COLLECTION);
+// holt sich die session per id oder slug, damit Links und Admin-Routen beides annehmen
+// Ein key im Format einer ObjectId wird als id gesucht, alles andere als slug
+pub async fn get_session_by_key(database: &mongodb::Database, key: &str) -> DatabaseResult<Option<Session>> {
+    let collection = database.collection::<Session>(SESSIONS_COLLECTION);
 
-    let filter = doc! {"name": name};
-    let mut cursor = collection.find_one(filter, None)
-        .await
-        .expect("Error while find");
+    let mut filter = not_deleted();
+    match ObjectId::parse_str(key) {
+        Ok(id) => filter.insert("_id", id),
+        Err(_) => filter.insert("slug", key)
+    };
 
-    return cursor.unwrap();
+    Ok(collection.find_one(filter, None).await?)
+}
+
+// holt einen user per username, z.B. um seine Gruppen für die Zugriffsprüfung zu laden
+pub async fn get_user_by_username(database: &mongodb::Database, username: &str) -> DatabaseResult<Option<User>> {
+    let collection = database.collection::<User>(USERS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("username", username);
+
+    Ok(collection.find_one(filter, None).await?)
+}
+
+// setzt die Gruppen eines users
+pub async fn set_user_groups(database: &mongodb::Database, id: &ObjectId, groups: &[String]) -> DatabaseResult<bool> {
+    let collection = database.collection::<User>(USERS_COLLECTION);
+
+    let result = collection.update_one(doc! {"_id": id}, doc! {"$set": {"groups": groups}}, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// Setzt die Zeitzone, in der dem user Zeiten angezeigt werden
+pub async fn set_user_timezone(database: &mongodb::Database, id: &ObjectId, timezone: &str) -> DatabaseResult<bool> {
+    let collection = database.collection::<User>(USERS_COLLECTION);
+
+    let result = collection.update_one(doc! {"_id": id}, doc! {"$set": {"timezone": timezone}}, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// Setzt den Token für das Kalender-Abo, der bisherige Link wird damit ungültig
+// user im Papierkorb bekommen keinen neuen Token
+pub async fn set_user_calendar_token(database: &mongodb::Database, username: &str, token: &str) -> DatabaseResult<bool> {
+    let collection = database.collection::<User>(USERS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("username", username);
+    let result = collection.update_one(filter, doc! {"$set": {"calendar_token": token}}, None).await?;
+
+    Ok(result.matched_count > 0)
+}
+
+// Sucht den user zum Token aus einem Kalender-Abo-Link
+pub async fn get_user_by_calendar_token(database: &mongodb::Database, token: &str) -> DatabaseResult<Option<User>> {
+    let collection = database.collection::<User>(USERS_COLLECTION);
+
+    let mut filter = not_deleted();
+    filter.insert("calendar_token", token);
+
+    Ok(collection.find_one(filter, None).await?)
 }
 
 // verifizierungs methode
@@ -187,13 +1157,17 @@ pub async fn get_user_by_username_and_password(database: &mongodb::Database, use
 
     let collection = database.collection::<User>(&USERS_COLLECTION);
 
-    let filter = doc! {"username": username};
+    // user im Papierkorb können sich nicht mehr einloggen
+    let mut filter = not_deleted();
+    filter.insert("username", username);
     let mut cursor = collection.find_one(filter, None)
         .await
         .expect("Error while find");
 
-
-    let user = cursor.unwrap();
+    let user = match cursor {
+        Some(user) => user,
+        None => return None
+    };
 
 
     let pw_hash = &user.hash;
@@ -219,6 +1193,7 @@ pub fn create_hash(value: &String) -> String {
 mod tests {
     use crate::add_session;
     use crate::usermanagement::create_salt;
+    use crate::access::{SessionAccess, SessionVisibility};
     use super::*;
 
     pub const FORMAT_STR: &str = "%d.%m.%Y %H:%M:%S";
@@ -235,6 +1210,42 @@ mod tests {
         remove_user_by_id(&database, &test_user.id).await;
     }
 
+    #[tokio::test]
+    async fn test_add_user_conflict() {
+        let database = get_database_by_name(TEST_DATABASE_NAME).await;
+        ensure_indexes(&database).await.expect("failed to create indexes");
+
+        let test_user = get_test_user("test_add_user_conflict_name".to_string());
+        let duplicate_user = get_test_user("test_add_user_conflict_name".to_string());
+
+        add_new_user(&database, &test_user).await.expect("failed to add user");
+
+        let result = add_new_user(&database, &duplicate_user).await;
+        assert!(matches!(result, Err(DatabaseError::Conflict(_))));
+
+        remove_user_by_id(&database, &test_user.id).await;
+    }
+
+    #[tokio::test]
+    async fn test_user_calendar_token() {
+        let database = get_database_by_name(TEST_DATABASE_NAME).await;
+
+        let test_user = get_test_user("test_calendar_token_name".to_string());
+        add_new_user(&database, &test_user).await.expect("failed to add user");
+
+        assert!(set_user_calendar_token(&database, &test_user.username, "first-token").await.unwrap());
+        let user = get_user_by_calendar_token(&database, "first-token").await.unwrap();
+        assert_eq!(user.map(|user| user.id), Some(test_user.id));
+
+        // Der neue Token ersetzt den alten
+        assert!(set_user_calendar_token(&database, &test_user.username, "second-token").await.unwrap());
+        assert!(get_user_by_calendar_token(&database, "first-token").await.unwrap().is_none());
+        assert!(get_user_by_calendar_token(&database, "second-token").await.unwrap().is_some());
+        assert!(!set_user_calendar_token(&database, "unknown_calendar_user", "third-token").await.unwrap());
+
+        remove_user_by_id(&database, &test_user.id).await;
+    }
+
     #[tokio::test]
     async fn test_add_session() {
         let database = get_database_by_name(TEST_DATABASE_NAME).await;
@@ -273,6 +1284,46 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_update_session_version_conflict() {
+        let database = get_database_by_name(TEST_DATABASE_NAME).await;
+
+        let mut test_session = get_test_session();
+        add_new_session(&database, &test_session).await;
+
+        test_session.name = "first_edit".to_string();
+        assert!(update_session(&database, &test_session).await.is_ok());
+
+        // zweite Änderung auf Basis der veralteten version
+        test_session.name = "second_edit".to_string();
+        let result = update_session(&database, &test_session).await;
+        assert!(matches!(result, Err(DatabaseError::Conflict(_))));
+
+        let current = get_session_by_id(&database, &test_session.id).await;
+        assert_eq!(current.name, "first_edit".to_string());
+        assert_eq!(current.version, 1);
+
+        remove_session_by_id(&database, &test_session.id).await;
+    }
+
+    #[tokio::test]
+    async fn test_trash_and_restore_session() {
+        let database = get_database_by_name(TEST_DATABASE_NAME).await;
+
+        let test_session = get_test_session();
+        add_new_session(&database, &test_session).await;
+
+        assert!(trash_session_by_id(&database, &test_session.id).await.unwrap());
+        let trashed = get_trashed_sessions(&database).await.unwrap();
+        assert!(trashed.iter().any(|s| s.id == test_session.id));
+        assert!(get_sessions_by_ids(&database, &[test_session.id]).await.unwrap().is_empty());
+
+        assert!(restore_session_by_id(&database, &test_session.id).await.unwrap());
+        assert_eq!(get_sessions_by_ids(&database, &[test_session.id]).await.unwrap().len(), 1);
+
+        remove_session_by_id(&database, &test_session.id).await;
+    }
+
     #[tokio::test]
     async fn test_get_user_by_username_and_password() {
         let database = get_database_by_name(TEST_DATABASE_NAME).await;
@@ -287,7 +1338,11 @@ mod tests {
             hash: hash,
             salt: salt,
             role: "USER".to_string(),
-            fullname: "fullname_test".to_string()
+            fullname: "fullname_test".to_string(),
+            deleted_at: None,
+            groups: Vec::new(),
+            timezone: None,
+            calendar_token: None,
         };
 
         add_new_user(&database, &test_user).await;
@@ -327,6 +1382,37 @@ mod tests {
         remove_session_by_id(&database, &test_session.id).await;
     }
 
+    #[tokio::test]
+    async fn test_unique_session_slug_and_key() {
+        let database = get_database_by_name(TEST_DATABASE_NAME).await;
+
+        // Gleichnamige Sessions bekommen einen slug mit angehängter Nummer
+        let name = format!("Slug Test {}", ObjectId::new().to_hex());
+        let mut first = get_test_session();
+        first.name = name.clone();
+        let mut second = get_test_session();
+        second.name = name.clone();
+        add_session_with_unique_slug(&database, &mut first).await.unwrap();
+        add_session_with_unique_slug(&database, &mut second).await.unwrap();
+        assert_eq!(first.slug, create_slug(&name));
+        assert_eq!(second.slug, format!("{}-2", create_slug(&name)));
+
+        // Auch im Papierkorb bleibt der slug belegt
+        trash_session_by_id(&database, &first.id).await.unwrap();
+        assert_eq!(unique_session_slug(&database, &name).await.unwrap(), format!("{}-3", create_slug(&name)));
+
+        assert_eq!(get_session_by_key(&database, &second.slug).await.unwrap().unwrap().id, second.id);
+        assert_eq!(get_session_by_key(&database, &second.id.to_hex()).await.unwrap().unwrap().id, second.id);
+        assert!(get_session_by_key(&database, &first.slug).await.unwrap().is_none());
+        assert!(get_session_by_key(&database, "gibt-es-nicht").await.unwrap().is_none());
+
+        // Ein Name im Format einer ObjectId ergibt keinen slug, der wie eine id aussieht
+        assert_eq!(unique_session_slug(&database, &first.id.to_hex()).await.unwrap(), format!("{}-2", first.id.to_hex()));
+
+        remove_session_by_id(&database, &first.id).await;
+        remove_session_by_id(&database, &second.id).await;
+    }
+
     #[tokio::test]
     async fn test_update_session() {
         let database = get_database_by_name(TEST_DATABASE_NAME).await;
@@ -349,15 +1435,91 @@ mod tests {
         remove_session_by_id(&database, &test_session.id).await;
     }
 
+    #[tokio::test]
+    async fn test_update_session_access() {
+        let database = get_database_by_name(TEST_DATABASE_NAME).await;
+
+        let mut test_session = get_test_session();
+        add_new_session(&database, &test_session).await;
+
+        // Sichtbarkeit und Freigaben werden im Bearbeiten-Formular geändert und müssen gespeichert werden
+        test_session.access = SessionAccess {
+            visibility: SessionVisibility::Restricted,
+            users: vec!["alice".to_string()],
+            groups: vec!["orga".to_string()],
+            roles: vec!["MODERATOR".to_string()]
+        };
+        assert!(update_session(&database, &test_session).await.is_ok());
+
+        let new_session = get_session_by_id(&database, &test_session.id).await;
+        assert_eq!(new_session.access, test_session.access);
+        assert_eq!(new_session.version, test_session.version + 1);
+
+        remove_session_by_id(&database, &test_session.id).await;
+    }
+
+    #[tokio::test]
+    async fn test_set_registration_settings() {
+        let database = get_database_by_name(TEST_DATABASE_NAME).await;
+
+        let test_session = get_test_session();
+        add_new_session(&database, &test_session).await;
+
+        let settings = RegistrationSettings { enabled: true, capacity: Some(20), registered_only: true };
+        assert!(set_registration_settings(&database, &test_session.id, &settings).await.unwrap());
+        let new_session = get_session_by_id(&database, &test_session.id).await;
+        assert_eq!(new_session.registration, settings);
+        assert_eq!(new_session.version, test_session.version + 1);
+
+        // Sessions im Papierkorb werden nicht mehr geändert
+        assert!(trash_session_by_id(&database, &test_session.id).await.unwrap());
+        assert!(!set_registration_settings(&database, &test_session.id, &settings).await.unwrap());
+
+        remove_session_by_id(&database, &test_session.id).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_listed_sessions() {
+        let database = get_database_by_name(TEST_DATABASE_NAME).await;
+
+        let public_session = get_test_session();
+        let mut restricted_session = get_test_session();
+        restricted_session.access.visibility = SessionVisibility::Restricted;
+        add_new_session(&database, &public_session).await;
+        add_new_session(&database, &restricted_session).await;
+
+        let mut viewer = Viewer {
+            username: "bob".to_string(),
+            role: "USER".to_string(),
+            groups: Vec::new(),
+            hidden_events: Vec::new(),
+            unlisted_events: Vec::new(),
+            events_locked: false
+        };
+        let ids: Vec<ObjectId> = get_listed_sessions(&database, &viewer).await.unwrap().iter().map(|session| session.id).collect();
+        assert!(ids.contains(&public_session.id));
+        assert!(!ids.contains(&restricted_session.id));
+
+        viewer.role = "ADMIN".to_string();
+        let ids: Vec<ObjectId> = get_listed_sessions(&database, &viewer).await.unwrap().iter().map(|session| session.id).collect();
+        assert!(ids.contains(&restricted_session.id));
+
+        remove_session_by_id(&database, &public_session.id).await;
+        remove_session_by_id(&database, &restricted_session.id).await;
+    }
+
     fn get_test_session() -> Session {
         let test_stream = SessionStream {
             link: "".to_string(),
             channel: "".to_string(),
-            stream_type: StreamType::Twitch
+            stream_type: StreamType::Twitch,
+            fallback: None
         };
 
+        let id = ObjectId::new();
         let test_session = Session {
-            id: ObjectId::new(),
+            id,
+            slug: format!("test-{}", id.to_hex()),
             start: DateTime::<Utc>::from_utc(NaiveDateTime::parse_from_str(
                 "09.07.2022 07:48:15", FORMAT_STR).expect("failed to parse startDateTime"),
                                              Utc),
@@ -365,8 +1527,8 @@ mod tests {
                 "09.07.2022 08:48:15", FORMAT_STR).expect("failed to parse startDateTime"),
                                            Utc),
             name: "".to_string(),
-            description: "".to_string(),
-            stream: test_stream
+            stream: test_stream,
+            ..Default::default()
         };
         test_session
     }
@@ -378,7 +1540,11 @@ mod tests {
             hash: "test_hash".to_string(),
             salt: "test_salt".to_string(),
             role: "USER".to_string(),
-            fullname: "fullname_test".to_string()
+            fullname: "fullname_test".to_string(),
+            deleted_at: None,
+            groups: Vec::new(),
+            timezone: None,
+            calendar_token: None,
         };
         test_user
     }
@@ -0,0 +1,682 @@
+use std::collections::HashMap;
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use mongodb::bson::oid::ObjectId;
+use rocket::form::{self, Contextual, Form};
+use rocket::http::{Cookie, CookieJar};
+use rocket_dyn_templates::{context, Template};
+use serde::{Deserialize, Serialize};
+
+use crate::access::{accessible_event, parse_list, valid_roles, valid_visibility, viewer_from_token, visibility_options,
+                    SessionAccess, SessionVisibility, VisibilityOption};
+use crate::administration::{access_value, FormFeedback};
+use crate::database::{add_event, get_event_by_id, get_event_by_slug, get_event_sessions, get_events,
+                      get_sessions_by_ids, get_sessions_without_event, get_standard_database, remove_event,
+                      set_session_event, update_event, DatabaseError};
+use crate::security::{admin_token, user_token, SecurityToken};
+use crate::sessions::{create_slug, Session, SessionStatus};
+use crate::timezone::{user_timezone, user_tz};
+use crate::markdown::description_text;
+
+// Datumsangaben des Events, wie bei den Zeiten der Sessions Tag.Monat.Jahr
+pub const DATE_FORMAT: &str = "%d.%m.%Y";
+
+// Akzentfarbe ohne eigenes Branding (blau wie die Semantic UI Buttons)
+pub const DEFAULT_EVENT_COLOR: &str = "#2185d0";
+
+// Sessions ohne (bekannten) Track werden auf der Event-Seite hier gesammelt
+pub const GENERAL_TRACK: &str = "Allgemein";
+
+const MAX_EVENT_TRACKS: usize = 12;
+
+// Ein Event (z.B. eine mehrtägige Konferenz) fasst viele Sessions zusammen
+// Die Zugriffsregeln gelten zusätzlich für alle Sessions des Events (siehe Viewer::can_view_session)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub slug: String,
+    #[serde(default)]
+    pub description: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    #[serde(default)]
+    pub branding: EventBranding,
+    // Tracks (z.B. Räume oder Themen) in der Reihenfolge, in der sie angezeigt werden
+    #[serde(default)]
+    pub tracks: Vec<String>,
+    #[serde(default)]
+    pub access: SessionAccess,
+    pub created_at: DateTime<Utc>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventBranding {
+    // Akzentfarbe als #rrggbb
+    pub color: String,
+    #[serde(default)]
+    pub logo_url: Option<String>
+}
+
+impl Default for EventBranding {
+    fn default() -> Self {
+        return EventBranding { color: DEFAULT_EVENT_COLOR.to_string(), logo_url: None };
+    }
+}
+
+// Zuordnung einer Session zu einem Event, wird an der Session gespeichert
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventPlacement {
+    pub event_id: ObjectId,
+    #[serde(default)]
+    pub track: Option<String>
+}
+
+// Event für die Templates, die ObjectId kann von Tera nicht verarbeitet werden
+#[derive(Debug, Serialize)]
+pub struct TeraEvent {
+    pub id: String,
+    pub name: String,
+    pub slug: String,
+    pub description: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub color: String,
+    pub logo_url: Option<String>,
+    pub tracks: Vec<String>,
+    pub visibility: SessionVisibility
+}
+
+impl TeraEvent {
+    pub fn from_event(event: &Event) -> TeraEvent {
+        return TeraEvent {
+            id: event.id.to_hex(),
+            name: event.name.clone(),
+            slug: event.slug.clone(),
+            description: event.description.clone(),
+            start_date: event.start_date,
+            end_date: event.end_date,
+            color: event.branding.color.clone(),
+            logo_url: event.branding.logo_url.clone(),
+            tracks: event.tracks.clone(),
+            visibility: event.access.visibility
+        };
+    }
+}
+
+// Session auf der Event-Seite bzw. in der Verwaltung des Events
+#[derive(Debug, Serialize)]
+pub struct EventSession {
+    pub id: String,
+    pub slug: String,
+    pub name: String,
+    pub description: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub status: SessionStatus,
+    pub live: bool,
+    pub track: Option<String>
+}
+
+impl EventSession {
+    fn from_session(session: &Session) -> EventSession {
+        return EventSession {
+            id: session.id.to_hex(),
+            slug: session.slug.clone(),
+            name: session.name.clone(),
+            //Auf der Event-Seite wird die Beschreibung ohne Markdown-Formatierung angezeigt
+            description: description_text(&session.description, session.description_format),
+            start: session.start,
+            end: session.end,
+            status: session.status,
+            live: session.live,
+            track: session.event.as_ref().and_then(|placement| placement.track.clone())
+        };
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventTrack {
+    pub name: String,
+    pub sessions: Vec<EventSession>
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventDay {
+    pub date: NaiveDate,
+    pub tracks: Vec<EventTrack>
+}
+
+// Name des Tracks, unter dem die Session angezeigt wird
+fn track_name(event: &Event, session: &Session) -> String {
+    return session.event.as_ref()
+        .and_then(|placement| placement.track.as_ref())
+        .filter(|track| event.tracks.contains(track))
+        .cloned()
+        .unwrap_or_else(|| GENERAL_TRACK.to_string());
+}
+
+// Gruppiert die Sessions nach Tag (in der Zeitzone tz) und innerhalb des Tages nach Track
+// Die Tracks stehen in der Reihenfolge des Events, Sessions ohne Track davor
+pub fn group_sessions(event: &Event, sessions: &[Session], tz: Tz) -> Vec<EventDay> {
+    let mut sorted: Vec<&Session> = sessions.iter().collect();
+    sorted.sort_by_key(|session| session.start);
+
+    let mut days: Vec<EventDay> = Vec::new();
+    for session in sorted {
+        let date = session.start.with_timezone(&tz).date().naive_local();
+        if days.last().map_or(true, |day| day.date != date) {
+            days.push(EventDay { date, tracks: Vec::new() });
+        }
+        let day = days.last_mut().unwrap();
+
+        let name = track_name(event, session);
+        match day.tracks.iter_mut().find(|track| track.name == name) {
+            Some(track) => track.sessions.push(EventSession::from_session(session)),
+            None => day.tracks.push(EventTrack { name, sessions: vec![EventSession::from_session(session)] })
+        }
+    }
+
+    for day in days.iter_mut() {
+        day.tracks.sort_by_key(|track| event.tracks.iter().position(|t| *t == track.name).map_or(0, |p| p + 1));
+    }
+    return days;
+}
+
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    return NaiveDate::parse_from_str(value.trim(), DATE_FORMAT).ok();
+}
+
+fn valid_date<'v>(value: &str) -> form::Result<'v, ()> {
+    match parse_date(value) {
+        Some(_) => Ok(()),
+        None => Err(form::Error::validation("Bitte im Format D.M.Y angeben, z.B. 09.07.2022").into())
+    }
+}
+
+fn valid_end_date<'v>(end: &str, start: &str) -> form::Result<'v, ()> {
+    valid_date(end)?;
+    if let (Some(start), Some(end)) = (parse_date(start), parse_date(end)) {
+        if end < start {
+            return Err(form::Error::validation("Das Ende darf nicht vor dem Beginn liegen.").into());
+        }
+    }
+    Ok(())
+}
+
+pub fn valid_color<'v>(value: &str) -> form::Result<'v, ()> {
+    let value = value.trim();
+    match value.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(()),
+        _ => Err(form::Error::validation("Bitte als Hex-Farbe angeben, z.B. #2185d0").into())
+    }
+}
+
+// Das Logo wird nur verlinkt, erlaubt sind deshalb nur http(s)-Adressen
+fn valid_logo_url<'v>(value: &Option<&str>) -> form::Result<'v, ()> {
+    match value.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(url) if !(url.starts_with("https://") || url.starts_with("http://")) || url.contains(char::is_whitespace) =>
+            Err(form::Error::validation("Das Logo muss eine http(s)-Adresse sein"))?,
+        _ => Ok(())
+    }
+}
+
+fn valid_tracks<'v>(value: &Option<&str>) -> form::Result<'v, ()> {
+    let tracks = parse_list(value.unwrap_or(""));
+    if tracks.len() > MAX_EVENT_TRACKS {
+        return Err(form::Error::validation(format!("Höchstens {} Tracks", MAX_EVENT_TRACKS)))?;
+    }
+    match tracks.iter().find(|track| track.chars().count() > 40) {
+        Some(track) => Err(form::Error::validation(format!("Der Track '{}' ist länger als 40 Zeichen", track)))?,
+        None => Ok(())
+    }
+}
+
+//Inputs für ein Event, Tracks und Freigaben sind komma-getrennt
+#[derive(FromForm, Debug)]
+pub struct EventForm<'r> {
+    #[field(validate = len(1..=80))]
+    name: &'r str,
+    #[field(validate = len(..=2000))]
+    description: &'r str,
+    #[field(validate = valid_date())]
+    start_date: &'r str,
+    #[field(validate = valid_end_date(self.start_date))]
+    end_date: &'r str,
+    #[field(validate = valid_color())]
+    color: &'r str,
+    #[field(validate = valid_logo_url())]
+    logo_url: Option<&'r str>,
+    #[field(validate = valid_tracks())]
+    tracks: Option<&'r str>,
+    #[field(validate = valid_visibility())]
+    visibility: Option<&'r str>,
+    allowed_users: Option<&'r str>,
+    allowed_groups: Option<&'r str>,
+    #[field(validate = valid_roles())]
+    allowed_roles: Option<&'r str>,
+}
+
+const EVENT_FIELDS: [&str; 11] = ["name", "description", "start_date", "end_date", "color", "logo_url", "tracks",
+    "visibility", "allowed_users", "allowed_groups", "allowed_roles"];
+
+// Übernimmt die Eingaben, id, slug und Anlagezeitpunkt bleiben beim Bearbeiten erhalten
+fn form_event(input: &EventForm<'_>, existing: Option<&Event>) -> Event {
+    let name = input.name.trim().to_string();
+    return Event {
+        id: existing.map_or_else(ObjectId::new, |event| event.id),
+        slug: existing.map_or_else(|| create_slug(&name), |event| event.slug.clone()),
+        name,
+        description: input.description.trim().to_string(),
+        start_date: parse_date(input.start_date).unwrap_or_else(|| Utc::now().date().naive_utc()),
+        end_date: parse_date(input.end_date).unwrap_or_else(|| Utc::now().date().naive_utc()),
+        branding: EventBranding {
+            color: input.color.trim().to_lowercase(),
+            logo_url: input.logo_url.map(str::trim).filter(|url| !url.is_empty()).map(str::to_string)
+        },
+        tracks: parse_list(input.tracks.unwrap_or("")),
+        access: access_value(input.visibility, input.allowed_users, input.allowed_groups, input.allowed_roles),
+        created_at: existing.map_or_else(Utc::now, |event| event.created_at)
+    };
+}
+
+// Formularwerte eines bestehenden Events zum Bearbeiten
+fn event_feedback(event: &Event) -> FormFeedback {
+    let values: HashMap<String, String> = [
+        ("name", event.name.clone()),
+        ("description", event.description.clone()),
+        ("start_date", event.start_date.format(DATE_FORMAT).to_string()),
+        ("end_date", event.end_date.format(DATE_FORMAT).to_string()),
+        ("color", event.branding.color.clone()),
+        ("logo_url", event.branding.logo_url.clone().unwrap_or_default()),
+        ("tracks", event.tracks.join(", ")),
+        ("visibility", event.access.visibility.value().to_string()),
+        ("allowed_users", event.access.users.join(", ")),
+        ("allowed_groups", event.access.groups.join(", ")),
+        ("allowed_roles", event.access.roles.join(", "))
+    ].into_iter().map(|(field, value)| (field.to_string(), value)).collect();
+    return FormFeedback { errors: HashMap::new(), values };
+}
+
+fn fullname(cookies: &CookieJar<'_>) -> String {
+    let fullname: Option<Cookie> = cookies.get_private("fullname");
+    return fullname.map(|f| f.value().to_string()).unwrap_or_else(|| "Unknown User".to_string());
+}
+
+fn jwt(cookies: &CookieJar<'_>) -> String {
+    return cookies.get_private("streamie.live").map(|c| c.value().to_string()).unwrap_or_default();
+}
+
+#[derive(Serialize)]
+struct EventsContext<'a> {
+    jwt: &'a str,
+    fullname: &'a str,
+    token: SecurityToken,
+    events: Vec<TeraEvent>,
+    // Event, das gerade bearbeitet wird, mit seinen Sessions und den noch freien Sessions
+    current: Option<TeraEvent>,
+    sessions: Vec<EventSession>,
+    available: Vec<EventSession>,
+    visibilities: Vec<VisibilityOption>,
+    error: Option<String>,
+    form: FormFeedback,
+    timezone: String
+}
+
+async fn render_events(cookies: &CookieJar<'_>, token: SecurityToken, current: Option<&Event>,
+                       error: Option<String>, form: FormFeedback) -> Template {
+    let database = get_standard_database().await;
+    let events = get_events(&database).await
+        .expect("Error while find")
+        .iter()
+        .map(TeraEvent::from_event)
+        .collect();
+
+    let (sessions, available) = match current {
+        Some(event) => (
+            get_event_sessions(&database, &event.id).await.expect("Error while find")
+                .iter().map(EventSession::from_session).collect(),
+            get_sessions_without_event(&database).await.expect("Error while find")
+                .iter().map(EventSession::from_session).collect()
+        ),
+        None => (Vec::new(), Vec::new())
+    };
+
+    return Template::render("admin/events", EventsContext {
+        jwt: &jwt(cookies),
+        fullname: &fullname(cookies),
+        token,
+        events,
+        current: current.map(TeraEvent::from_event),
+        sessions,
+        available,
+        visibilities: visibility_options(),
+        error,
+        form,
+        timezone: user_timezone(cookies)
+    });
+}
+
+// Übersicht aller Events mit Formular für ein neues Event
+#[get("/admin/events")]
+pub async fn show_events(cookies: &CookieJar<'_>) -> Template {
+    match admin_token(cookies) {
+        Some(t) => return render_events(cookies, t, None, None, FormFeedback::default()).await,
+        None => return Template::render("unauthorized", context!{})
+    }
+}
+
+async fn load_event(id: &str) -> Option<Event> {
+    let database = get_standard_database().await;
+    return get_event_by_id(&database, &ObjectId::parse_str(id).ok()?).await.ok()?;
+}
+
+#[get("/admin/events/<id>")]
+pub async fn edit_event(id: &str, cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let event = load_event(id).await?;
+    let feedback = event_feedback(&event);
+    return Some(render_events(cookies, token, Some(&event), None, feedback).await);
+}
+
+#[post("/admin/events", data = "<form>")]
+pub async fn add_new_event(form: Form<Contextual<'_, EventForm<'_>>>, cookies: &CookieJar<'_>) -> Template {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Template::render("unauthorized", context!{})
+    };
+
+    let feedback = FormFeedback::from_context(&form.context, &EVENT_FIELDS);
+    let input = match form.value {
+        Some(ref input) => input,
+        None => return render_events(cookies, token, None, None, feedback).await
+    };
+
+    let event = form_event(input, None);
+    let database = get_standard_database().await;
+    match add_event(&database, &event).await {
+        Ok(()) => return render_events(cookies, token, Some(&event), None, event_feedback(&event)).await,
+        Err(DatabaseError::Conflict(_)) => {
+            let error = format!("Es gibt bereits ein Event mit der Adresse /event/{}", event.slug);
+            return render_events(cookies, token, None, Some(error), feedback).await;
+        },
+        Err(e) => return render_events(cookies, token, None, Some(e.to_string()), feedback).await
+    }
+}
+
+#[post("/admin/events/<id>", data = "<form>")]
+pub async fn update_existing_event(id: &str, form: Form<Contextual<'_, EventForm<'_>>>, cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let existing = load_event(id).await?;
+    let feedback = FormFeedback::from_context(&form.context, &EVENT_FIELDS);
+    let input = match form.value {
+        Some(ref input) => input,
+        None => return Some(render_events(cookies, token, Some(&existing), None, feedback).await)
+    };
+
+    let event = form_event(input, Some(&existing));
+    let database = get_standard_database().await;
+    if let Err(e) = update_event(&database, &event).await {
+        return Some(render_events(cookies, token, Some(&existing), Some(e.to_string()), feedback).await);
+    }
+    return Some(render_events(cookies, token, Some(&event), None, event_feedback(&event)).await);
+}
+
+// Löscht das Event, die Sessions bleiben ohne Event erhalten
+#[post("/admin/events/<id>/delete")]
+pub async fn delete_event(id: &str, cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let database = get_standard_database().await;
+    match remove_event(&database, &ObjectId::parse_str(id).ok()?).await {
+        Ok(true) => return Some(render_events(cookies, token, None, None, FormFeedback::default()).await),
+        Ok(false) => return None,
+        Err(e) => return Some(render_events(cookies, token, None, Some(e.to_string()), FormFeedback::default()).await)
+    }
+}
+
+#[derive(FromForm)]
+pub struct SessionAssignment<'r> {
+    session: &'r str,
+    track: Option<&'r str>
+}
+
+// Ordnet eine Session dem Event zu bzw. ändert ihren Track, eine Session gehört immer nur zu einem Event
+#[post("/admin/events/<id>/sessions", data = "<form>")]
+pub async fn assign_session(id: &str, form: Form<SessionAssignment<'_>>, cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let event = load_event(id).await?;
+    let track = form.track.map(str::trim).filter(|track| !track.is_empty());
+    if let Some(track) = track {
+        if !event.tracks.iter().any(|t| t == track) {
+            let error = format!("Unbekannter Track '{}'", track);
+            return Some(render_events(cookies, token, Some(&event), Some(error), event_feedback(&event)).await);
+        }
+    }
+
+    let database = get_standard_database().await;
+    let placement = EventPlacement { event_id: event.id, track: track.map(str::to_string) };
+    let error = match ObjectId::parse_str(form.session) {
+        Ok(session_id) => match set_session_event(&database, &session_id, Some(&placement)).await {
+            Ok(true) => None,
+            Ok(false) => Some("Unbekannte Session".to_string()),
+            Err(e) => Some(e.to_string())
+        },
+        Err(_) => Some("Unbekannte Session".to_string())
+    };
+    return Some(render_events(cookies, token, Some(&event), error, event_feedback(&event)).await);
+}
+
+#[post("/admin/events/<id>/sessions/<session_id>/remove")]
+pub async fn unassign_session(id: &str, session_id: &str, cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let event = load_event(id).await?;
+    let database = get_standard_database().await;
+    let session_id = ObjectId::parse_str(session_id).ok()?;
+    // Nur Sessions dieses Events, eine inzwischen umgehängte Session bleibt, wo sie ist
+    let session = get_sessions_by_ids(&database, &[session_id]).await.ok()?.into_iter().next()?;
+    let error = if session.event.as_ref().map_or(false, |placement| placement.event_id == event.id) {
+        set_session_event(&database, &session_id, None).await.err().map(|e| e.to_string())
+    } else {
+        None
+    };
+    return Some(render_events(cookies, token, Some(&event), error, event_feedback(&event)).await);
+}
+
+// Alle Events, die der User gelistet sehen darf
+#[get("/events")]
+pub async fn list_events(cookies: &CookieJar<'_>) -> Template {
+    let token = match user_token(cookies) {
+        Some(t) => t,
+        None => return Template::render("unauthorized", context!{})
+    };
+
+    let database = get_standard_database().await;
+    let viewer = viewer_from_token(&database, &token).await;
+    let events: Vec<TeraEvent> = get_events(&database).await
+        .expect("Error while find")
+        .iter()
+        .filter(|event| viewer.can_list(&event.access))
+        .map(TeraEvent::from_event)
+        .collect();
+
+    return Template::render("events/list", context! {
+        jwt: jwt(cookies),
+        fullname: fullname(cookies),
+        token,
+        events
+    });
+}
+
+// Startseite eines Events mit den Sessions nach Tag und Track und der Chat-Lobby des Events
+// Die Lobby ist ein eigener Chat-Raum mit der id des Events
+#[get("/event/<slug>")]
+pub async fn event_hub(slug: &str, cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match user_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let database = get_standard_database().await;
+    let event = get_event_by_slug(&database, slug).await.ok()??;
+    let viewer = viewer_from_token(&database, &token).await;
+    if !matches!(accessible_event(&database, &viewer, &event.id).await, Ok(Some(_))) {
+        return Some(Template::render("unauthorized", context!{}));
+    }
+
+    // Die Regeln des Events sind erfüllt, es gelten noch die der einzelnen Sessions
+    let sessions: Vec<Session> = get_event_sessions(&database, &event.id).await
+        .expect("Error while find")
+        .into_iter()
+        .filter(|session| viewer.can_list(&session.access))
+        .collect();
+
+    // Die Tage werden wie die Zeiten in der Zeitzone des Users gebildet
+    return Some(Template::render("events/hub", context! {
+        jwt: jwt(cookies),
+        fullname: fullname(cookies),
+        token,
+        timezone: user_timezone(cookies),
+        days: group_sessions(&event, &sessions, user_tz(cookies)),
+        event: TeraEvent::from_event(&event)
+    }));
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            show_events,
+            edit_event,
+            add_new_event,
+            update_existing_event,
+            delete_event,
+            assign_session,
+            unassign_session,
+            list_events,
+            event_hub
+    ]).attach(Template::fairing())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::TimeZone;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+    use crate::timezone::parse_timezone;
+
+    fn event(tracks: &[&str]) -> Event {
+        return Event {
+            id: ObjectId::new(),
+            name: "Konferenz 2022".to_string(),
+            slug: "konferenz-2022".to_string(),
+            description: "".to_string(),
+            start_date: NaiveDate::from_ymd(2022, 9, 1),
+            end_date: NaiveDate::from_ymd(2022, 9, 2),
+            branding: EventBranding::default(),
+            tracks: tracks.iter().map(|t| t.to_string()).collect(),
+            access: SessionAccess::default(),
+            created_at: Utc::now()
+        };
+    }
+
+    fn session(event: &Event, name: &str, start: DateTime<Utc>, track: Option<&str>) -> Session {
+        return Session {
+            slug: create_slug(name),
+            start,
+            end: start + chrono::Duration::hours(1),
+            name: name.to_string(),
+            event: Some(EventPlacement { event_id: event.id, track: track.map(str::to_string) }),
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn test_group_sessions() {
+        let event = event(&["Saal 1", "Saal 2"]);
+        let sessions = vec![
+            session(&event, "Workshop", Utc.ymd(2022, 9, 1).and_hms(12, 0, 0), Some("Saal 2")),
+            session(&event, "Keynote", Utc.ymd(2022, 9, 1).and_hms(8, 0, 0), Some("Saal 1")),
+            session(&event, "Begrüßung", Utc.ymd(2022, 9, 1).and_hms(7, 0, 0), None),
+            // 23:30 Uhr UTC ist in Berlin schon der nächste Tag
+            session(&event, "Frühstück", Utc.ymd(2022, 9, 1).and_hms(23, 30, 0), Some("Foyer")),
+            session(&event, "Abschluss", Utc.ymd(2022, 9, 2).and_hms(15, 0, 0), Some("Saal 1"))
+        ];
+
+        let days = group_sessions(&event, &sessions, chrono_tz::Europe::Berlin);
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].date, NaiveDate::from_ymd(2022, 9, 1));
+        let names: Vec<&str> = days[0].tracks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec![GENERAL_TRACK, "Saal 1", "Saal 2"]);
+        assert_eq!(days[0].tracks[0].sessions[0].name, "Begrüßung");
+
+        // Ein unbekannter Track landet unter Allgemein
+        assert_eq!(days[1].date, NaiveDate::from_ymd(2022, 9, 2));
+        let names: Vec<&str> = days[1].tracks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec![GENERAL_TRACK, "Saal 1"]);
+        assert_eq!(days[1].tracks[0].sessions[0].name, "Frühstück");
+
+        assert!(group_sessions(&event, &[], chrono_tz::Europe::Berlin).is_empty());
+
+        // In New York liegt 23:30 Uhr UTC noch am selben Tag
+        let days = group_sessions(&event, &sessions, parse_timezone("America/New_York").unwrap());
+        assert_eq!(days.len(), 2);
+        assert!(days[0].tracks.iter().any(|t| t.sessions.iter().any(|s| s.name == "Frühstück")));
+        assert_eq!(days[1].tracks[0].sessions[0].name, "Abschluss");
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(parse_date("01.09.2022"), Some(NaiveDate::from_ymd(2022, 9, 1)));
+        assert_eq!(parse_date("2022-09-01"), None);
+        assert!(valid_end_date("01.09.2022", "01.09.2022").is_ok());
+        assert!(valid_end_date("31.08.2022", "01.09.2022").is_err());
+        assert!(valid_color("#2185D0").is_ok());
+        assert!(valid_color("2185d0").is_err());
+        assert!(valid_color("#21d").is_err());
+        assert!(valid_logo_url(&Some("https://example.org/logo.png")).is_ok());
+        assert!(valid_logo_url(&Some("")).is_ok());
+        assert!(valid_logo_url(&Some("javascript:alert(1)")).is_err());
+        assert!(valid_tracks(&Some("Saal 1, Saal 2")).is_ok());
+        assert!(valid_tracks(&Some(&"x".repeat(41))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_events_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        for uri in ["/admin/events", "/events", "/event/konferenz-2022"] {
+            let response = client.get(uri).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert!(response.into_string().await.unwrap().contains("Keine Berechtigung"));
+        }
+
+        let response = client.post("/admin/events/62a05c8631a6964f64d829ac/sessions")
+            .header(ContentType::Form)
+            .body("session=62a05c8631a6964f64d829ac")
+            .dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("Keine Berechtigung"));
+    }
+}
@@ -0,0 +1,767 @@
+use std::collections::HashSet;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use mongodb::bson::oid::ObjectId;
+use rocket::form::Form;
+use rocket::http::{Cookie, CookieJar};
+use rocket::State;
+use rocket_dyn_templates::{Template, context};
+use serde::Serialize;
+
+use crate::calendar::session_id_from_uid;
+use crate::database::{add_session_with_unique_slug, get_session_by_import_uid, get_sessions_by_ids, get_standard_database,
+                      update_session};
+use crate::lifecycle::{refresh_session_status, Lifecycle, DEFAULT_ARCHIVE_AFTER_DAYS};
+use crate::providers::{detect_stream_type, parse_fallback_link, parse_stream_link, stream_type_from_plattform};
+use crate::security::{admin_token, SecurityToken};
+use crate::sessions::{create_slug, Session, SessionStream, StreamType, FORMAT_STR};
+use crate::timezone::{naive_to_utc, user_timezone, user_tz};
+
+// Ein Eintrag aus der Import-Datei, so wie er gelesen wurde
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportRecord {
+    // Zeile (CSV) bzw. Nummer des Termins (iCalendar) für die Fehlermeldungen
+    pub position: usize,
+    pub uid: Option<String>,
+    pub name: String,
+    pub description: String,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub link: String,
+    pub plattform: String,
+    pub fallback: String,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportAction {
+    Create,
+    Update,
+}
+
+// Geprüfter Eintrag für die Vorschau, Einträge mit Fehlern werden nicht übernommen
+#[derive(Debug, Serialize)]
+pub struct ImportRow {
+    pub position: usize,
+    // Darüber wird beim erneuten Import die bestehende Session gefunden
+    pub key: String,
+    pub name: String,
+    pub description: String,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub link: String,
+    pub channel: String,
+    pub stream_type: StreamType,
+    pub fallback: Option<String>,
+    pub action: ImportAction,
+    pub existing_id: Option<String>,
+    pub errors: Vec<String>,
+}
+
+impl ImportRow {
+    pub fn is_valid(&self) -> bool {
+        return self.errors.is_empty();
+    }
+}
+
+// Ergebnis nach dem Übernehmen des Imports
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub errors: Vec<String>,
+}
+
+/**
+ * iCalendar (RFC 5545)
+ */
+
+// Fortsetzungszeilen beginnen mit einem Leerzeichen oder Tab und werden an die vorherige Zeile angehängt
+pub fn unfold_lines(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string())
+        }
+    }
+    return lines;
+}
+
+pub fn unescape_text(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    return unescaped;
+}
+
+// Zerlegt "DTSTART;TZID=Europe/Berlin:20221030T020000" in Name, Parameter und Wert
+// Doppelpunkte in Parametern in Anführungszeichen gehören nicht zum Trenner
+fn parse_property(line: &str) -> Option<(String, Vec<(String, String)>, String)> {
+    let mut quoted = false;
+    let separator = line.char_indices().find(|(_, c)| {
+        if *c == '"' {
+            quoted = !quoted;
+        }
+        return *c == ':' && !quoted;
+    })?.0;
+
+    let mut parts = line[..separator].split(';');
+    let name = parts.next()?.trim().to_uppercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| (key.trim().to_uppercase(), value.trim_matches('"').to_string()))
+        .collect();
+
+    return Some((name, params, line[separator + 1..].to_string()));
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    return params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+}
+
+// Zeiten mit Z sind UTC, mit TZID werden sie aus der angegebenen Zeitzone umgerechnet
+// Zeiten ohne Zeitzone und ganztägige Termine (00:00) gelten in der Zeitzone tz, in der die Sessions angelegt werden
+pub fn parse_ics_date_time(value: &str, params: &[(String, String)], tz: Tz) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    if param(params, "VALUE") == Some("DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d")
+            .map_err(|_| format!("Ungültiges Datum '{}'", value))?;
+        return naive_to_utc(date.and_hms(0, 0, 0), tz);
+    }
+
+    let (local, is_utc) = match value.strip_suffix('Z') {
+        Some(local) => (local, true),
+        None => (value, false)
+    };
+    let naive = NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S")
+        .map_err(|_| format!("Ungültige Zeitangabe '{}'", value))?;
+
+    if is_utc {
+        return Ok(Utc.from_utc_datetime(&naive));
+    }
+    match param(params, "TZID") {
+        Some(tzid) => {
+            let tzid: Tz = tzid.parse().map_err(|_| format!("Unbekannte Zeitzone '{}'", tzid))?;
+            return naive_to_utc(naive, tzid);
+        },
+        None => naive_to_utc(naive, tz)
+    }
+}
+
+// Dauer wie "PT1H30M" oder "P1D", wird genutzt wenn ein Termin kein DTEND hat
+pub fn parse_duration(value: &str) -> Option<chrono::Duration> {
+    let value = value.trim().strip_prefix('P')?;
+    let mut duration = chrono::Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => in_time = true,
+            _ => {
+                let amount: i64 = number.parse().ok()?;
+                number.clear();
+                duration = duration + match (c, in_time) {
+                    ('W', false) => chrono::Duration::weeks(amount),
+                    ('D', false) => chrono::Duration::days(amount),
+                    ('H', true) => chrono::Duration::hours(amount),
+                    ('M', true) => chrono::Duration::minutes(amount),
+                    ('S', true) => chrono::Duration::seconds(amount),
+                    _ => return None
+                };
+            }
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    return Some(duration);
+}
+
+pub fn parse_ics(content: &str, tz: Tz) -> Vec<ImportRecord> {
+    let mut records = Vec::new();
+    let mut current: Option<ImportRecord> = None;
+    let mut duration: Option<chrono::Duration> = None;
+    // Verschachtelte Komponenten (z.B. VALARM) werden übersprungen
+    let mut nested = 0;
+
+    for line in unfold_lines(content) {
+        let (name, params, value) = match parse_property(&line) {
+            Some(property) => property,
+            None => continue
+        };
+
+        match (name.as_str(), value.trim().to_uppercase().as_str()) {
+            ("BEGIN", "VEVENT") => {
+                current = Some(ImportRecord { position: records.len() + 1, ..ImportRecord::default() });
+                duration = None;
+                continue;
+            },
+            ("END", "VEVENT") => {
+                if let Some(mut record) = current.take() {
+                    if let (None, Some(start), Some(duration)) = (record.end, record.start, duration) {
+                        record.end = Some(start + duration);
+                    }
+                    records.push(record);
+                }
+                continue;
+            },
+            ("BEGIN", _) if current.is_some() => { nested += 1; continue; },
+            ("END", _) if nested > 0 => { nested -= 1; continue; },
+            _ => {}
+        }
+
+        let record = match current.as_mut() {
+            Some(record) if nested == 0 => record,
+            _ => continue
+        };
+        match name.as_str() {
+            "UID" => record.uid = Some(value.trim().to_string()),
+            "SUMMARY" => record.name = unescape_text(&value),
+            "DESCRIPTION" => record.description = unescape_text(&value),
+            "DTSTART" | "DTEND" => match parse_ics_date_time(&value, &params, tz) {
+                Ok(date) if name == "DTSTART" => record.start = Some(date),
+                Ok(date) => record.end = Some(date),
+                Err(e) => record.errors.push(e)
+            },
+            "DURATION" => match parse_duration(&value) {
+                Some(parsed) => duration = Some(parsed),
+                None => record.errors.push(format!("Ungültige Dauer '{}'", value))
+            },
+            "X-STREAMIE-PLATTFORM" => record.plattform = value.trim().to_string(),
+            "X-STREAMIE-LINK" => record.link = unescape_text(&value),
+            "X-STREAMIE-FALLBACK" => record.fallback = unescape_text(&value),
+            // Ohne eigene Properties wird der Stream aus der URL des Termins übernommen
+            "URL" if record.link.is_empty() => record.link = value.trim().to_string(),
+            _ => {}
+        }
+    }
+
+    return records;
+}
+
+/**
+ * CSV (RFC 4180), Trenner ist Komma oder Semikolon (z.B. Excel mit deutschen Einstellungen)
+ */
+
+// Liefert die Zeilen mit ihrer Zeilennummer, Felder in Anführungszeichen dürfen Trenner und Zeilenumbrüche enthalten
+pub fn parse_csv_rows(content: &str) -> Vec<(usize, Vec<String>)> {
+    let content = content.trim_start_matches('\u{feff}');
+    let first_line = content.lines().next().unwrap_or("");
+    let delimiter = if first_line.matches(';').count() > first_line.matches(',').count() { ';' } else { ',' };
+
+    let mut rows = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            '\n' | '\r' if !quoted => {
+                if c == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|value| !value.trim().is_empty()) {
+                    rows.push((row_line, std::mem::take(&mut row)));
+                }
+                row.clear();
+                line += 1;
+                row_line = line;
+            },
+            c if c == delimiter && !quoted => row.push(std::mem::take(&mut field)),
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    row.push(field);
+    if row.iter().any(|value| !value.trim().is_empty()) {
+        rows.push((row_line, row));
+    }
+
+    return rows;
+}
+
+// Spaltennamen, die in der Kopfzeile erkannt werden (englisch und deutsch)
+fn csv_column(header: &str) -> Option<&'static str> {
+    return match header.trim().to_lowercase().as_str() {
+        "uid" | "id" => Some("uid"),
+        "name" | "streamname" | "titel" | "title" => Some("name"),
+        "description" | "beschreibung" => Some("description"),
+        "start" | "beginn" | "anfangszeit" => Some("start"),
+        "end" | "ende" => Some("end"),
+        "link" | "url" => Some("link"),
+        "plattform" | "platform" => Some("plattform"),
+        "fallback" | "ausweich-quelle" => Some("fallback"),
+        _ => None
+    };
+}
+
+// Akzeptiert das Format der Formulare (FORMAT_STR) sowie ISO 8601, Zeiten ohne Zeitzone gelten in der Zeitzone tz
+pub fn parse_csv_date_time(value: &str, tz: Tz) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    for format in [FORMAT_STR, "%d.%m.%Y %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return naive_to_utc(naive, tz);
+        }
+    }
+    return Err(format!("Ungültige Zeitangabe '{}', erwartet z.B. 09.07.2022 18:00:00", value));
+}
+
+pub fn parse_csv(content: &str, tz: Tz) -> Result<Vec<ImportRecord>, String> {
+    let mut rows = parse_csv_rows(content).into_iter();
+    let header: Vec<Option<&str>> = match rows.next() {
+        Some((_, header)) => header.iter().map(|column| csv_column(column)).collect(),
+        None => return Err("Die Datei ist leer.".to_string())
+    };
+    for required in ["name", "start", "end"] {
+        if !header.contains(&Some(required)) {
+            return Err(format!("Die Spalte '{}' fehlt in der Kopfzeile.", required));
+        }
+    }
+
+    return Ok(rows.map(|(line, values)| {
+        let mut record = ImportRecord { position: line, ..ImportRecord::default() };
+        for (column, value) in header.iter().zip(values) {
+            let value = value.trim().to_string();
+            match column {
+                Some("uid") if !value.is_empty() => record.uid = Some(value),
+                Some("name") => record.name = value,
+                Some("description") => record.description = value,
+                Some("start") | Some("end") if value.is_empty() => {},
+                Some("start") | Some("end") => match parse_csv_date_time(&value, tz) {
+                    Ok(date) if *column == Some("start") => record.start = Some(date),
+                    Ok(date) => record.end = Some(date),
+                    Err(e) => record.errors.push(e)
+                },
+                Some("link") => record.link = value,
+                Some("plattform") => record.plattform = value,
+                Some("fallback") => record.fallback = value,
+                _ => {}
+            }
+        }
+        return record;
+    }).collect());
+}
+
+// Das Format wird am Inhalt erkannt, nicht an der Dateiendung
+// tz ist die Zeitzone, mit der die Sessions angelegt werden (die des Users)
+pub fn parse_import(content: &str, tz: Tz) -> Result<Vec<ImportRecord>, String> {
+    if content.trim_start_matches('\u{feff}').trim_start().to_uppercase().starts_with("BEGIN:VCALENDAR") {
+        return Ok(parse_ics(content, tz));
+    }
+    return parse_csv(content, tz);
+}
+
+// Prüft alle Einträge wie das Formular zum Erstellen einer Session
+pub fn validate_records(records: Vec<ImportRecord>) -> Vec<ImportRow> {
+    let mut keys: HashSet<String> = HashSet::new();
+
+    return records.into_iter().map(|record| {
+        let mut errors = record.errors;
+        let name = record.name.trim().to_string();
+        if name.is_empty() {
+            errors.push("Der Name fehlt.".to_string());
+        }
+        match (record.start, record.end) {
+            (Some(start), Some(end)) if end <= start => errors.push("Das Ende liegt vor dem Beginn.".to_string()),
+            (None, _) => errors.push("Der Beginn fehlt.".to_string()),
+            (_, None) => errors.push("Das Ende fehlt.".to_string()),
+            _ => {}
+        }
+
+        // Ohne Angabe der Plattform wird sie aus dem Link erkannt
+        let link = record.link.trim().to_string();
+        let stream_type = if record.plattform.trim().is_empty() {
+            detect_stream_type(&link)
+        } else {
+            stream_type_from_plattform(&record.plattform)
+        };
+        if stream_type == StreamType::None && !record.plattform.trim().is_empty()
+            && !record.plattform.trim().eq_ignore_ascii_case("None") {
+            errors.push(format!("Unbekannte Plattform '{}'", record.plattform.trim()));
+        }
+        let channel = match parse_stream_link(&stream_type, &link) {
+            Ok(channel) => channel,
+            Err(e) => {
+                errors.push(e.to_string());
+                String::new()
+            }
+        };
+        let fallback = Some(record.fallback.trim().to_string()).filter(|f| !f.is_empty());
+        if let Some(Err(e)) = fallback.as_deref().map(parse_fallback_link) {
+            errors.push(e.to_string());
+        }
+
+        // Ohne UID (z.B. CSV ohne uid-Spalte) wird der Name zum Wiedererkennen genutzt
+        let key = record.uid.unwrap_or_else(|| format!("name:{}", create_slug(&name)));
+        if !keys.insert(key.clone()) {
+            errors.push(format!("Der Eintrag '{}' kommt in der Datei mehrfach vor.", key));
+        }
+
+        return ImportRow {
+            position: record.position,
+            key,
+            name,
+            description: record.description.trim().to_string(),
+            start: record.start,
+            end: record.end,
+            link,
+            channel,
+            stream_type,
+            fallback,
+            action: ImportAction::Create,
+            existing_id: None,
+            errors,
+        };
+    }).collect();
+}
+
+// Sucht zu einem Eintrag die bereits importierte bzw. exportierte Session
+async fn find_existing(database: &mongodb::Database, key: &str) -> Option<Session> {
+    if let Some(id) = session_id_from_uid(key) {
+        if let Ok(sessions) = get_sessions_by_ids(database, &[id]).await {
+            if let Some(session) = sessions.into_iter().next() {
+                return Some(session);
+            }
+        }
+    }
+    return get_session_by_import_uid(database, key).await.ok().flatten();
+}
+
+async fn preview_rows(database: &mongodb::Database, content: &str, tz: Tz) -> Result<Vec<ImportRow>, String> {
+    let mut rows = validate_records(parse_import(content, tz)?);
+    for row in rows.iter_mut() {
+        if let Some(existing) = find_existing(database, &row.key).await {
+            row.action = ImportAction::Update;
+            row.existing_id = Some(existing.id.to_hex());
+        }
+    }
+    return Ok(rows);
+}
+
+fn row_stream(row: &ImportRow) -> SessionStream {
+    return SessionStream {
+        link: row.link.clone(),
+        channel: row.channel.clone(),
+        stream_type: row.stream_type.clone(),
+        fallback: row.fallback.clone()
+    };
+}
+
+// Legt einen geprüften Eintrag an oder aktualisiert die bestehende Session
+// Die Zeiten sind bereits nach UTC umgerechnet, timezone bestimmt nur die Anzeige im Bearbeiten-Formular
+async fn apply_row(database: &mongodb::Database, row: &ImportRow, timezone: &str) -> Result<(ObjectId, ImportAction), String> {
+    let (start, end) = match (row.start, row.end) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Err("Beginn oder Ende fehlt.".to_string())
+    };
+
+    match find_existing(database, &row.key).await {
+        Some(existing) => {
+            let session = Session {
+                start,
+                end,
+                name: row.name.clone(),
+                description: row.description.clone(),
+                stream: row_stream(row),
+                ..existing
+            };
+            return update_session(database, &session).await
+                .map(|_| (session.id, ImportAction::Update))
+                .map_err(|e| e.to_string());
+        },
+        None => {
+            let mut session = Session {
+                slug: create_slug(&row.name),
+                start,
+                end,
+                name: row.name.clone(),
+                description: row.description.clone(),
+                stream: row_stream(row),
+                import_uid: Some(row.key.clone()),
+                timezone: timezone.to_string(),
+                ..Default::default()
+            };
+            // Gleichnamige Sessions bekommen einen slug mit angehängter Nummer
+            return match add_session_with_unique_slug(database, &mut session).await {
+                Ok(()) => Ok((session.id, ImportAction::Create)),
+                Err(e) => Err(e.to_string())
+            };
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ImportContext<'a> {
+    jwt: &'a str,
+    fullname: &'a str,
+    token: SecurityToken,
+    error: Option<String>,
+    rows: Vec<ImportRow>,
+    valid_rows: usize,
+    // Inhalt der Datei, wird beim Übernehmen erneut geprüft
+    content: Option<String>,
+    summary: Option<ImportSummary>,
+    timezone: String
+}
+
+fn render_import(cookies: &CookieJar<'_>, token: SecurityToken, error: Option<String>, rows: Vec<ImportRow>,
+                 content: Option<String>, summary: Option<ImportSummary>) -> Template {
+    let jwt = cookies.get_private("streamie.live").unwrap();
+    let fullname: Option<Cookie> = cookies.get_private("fullname");
+    let fullname_value = match fullname {
+        Some(fullname) => fullname,
+        None => Cookie::new("fullname", "Unknown User")
+    };
+
+    return Template::render("admin/import", ImportContext {
+        jwt: jwt.value(),
+        fullname: fullname_value.value(),
+        token,
+        error,
+        valid_rows: rows.iter().filter(|row| row.is_valid()).count(),
+        rows,
+        content,
+        summary,
+        timezone: user_timezone(cookies)
+    });
+}
+
+// Formular zum Hochladen einer .ics- oder CSV-Datei
+#[get("/admin/import")]
+pub async fn show_import(cookies: &CookieJar<'_>) -> Template {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            return render_import(cookies, t, None, Vec::new(), None, None);
+        },
+        None => {
+            return Template::render("unauthorized", context!{});
+        }
+    }
+}
+
+#[derive(FromForm)]
+pub struct ImportUpload {
+    file: String
+}
+
+// Vorschau: die Datei wird gelesen und geprüft, aber noch nichts gespeichert
+#[post("/admin/import", data = "<upload>")]
+pub async fn preview_import(upload: Form<ImportUpload>, cookies: &CookieJar<'_>) -> Template {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            let database = get_standard_database().await;
+            return match preview_rows(&database, &upload.file, user_tz(cookies)).await {
+                Ok(rows) => render_import(cookies, t, None, rows, Some(upload.into_inner().file), None),
+                Err(e) => render_import(cookies, t, Some(e), Vec::new(), None, None)
+            };
+        },
+        None => {
+            return Template::render("unauthorized", context!{});
+        }
+    }
+}
+
+#[derive(FromForm)]
+pub struct ImportCommit {
+    content: String
+}
+
+// Übernimmt alle fehlerfreien Einträge, bereits importierte Sessions werden aktualisiert
+#[post("/admin/import/commit", data = "<import>")]
+pub async fn commit_import(import: Form<ImportCommit>, lifecycle: &State<Lifecycle>, cookies: &CookieJar<'_>) -> Template {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            let rows = match parse_import(&import.content, user_tz(cookies)) {
+                Ok(records) => validate_records(records),
+                Err(e) => return render_import(cookies, t, Some(e), Vec::new(), None, None)
+            };
+
+            let database = get_standard_database().await;
+            let mut summary = ImportSummary::default();
+            for row in rows.iter() {
+                if !row.is_valid() {
+                    summary.skipped += 1;
+                    continue;
+                }
+                match apply_row(&database, row, &user_timezone(cookies)).await {
+                    Ok((id, action)) => {
+                        if action == ImportAction::Create { summary.created += 1 } else { summary.updated += 1 }
+                        refresh_session_status(&database, lifecycle, &id).await;
+                    },
+                    Err(e) => {
+                        summary.skipped += 1;
+                        summary.errors.push(format!("{} ({}): {}", row.name, row.position, e));
+                    }
+                }
+            }
+
+            return render_import(cookies, t, None, Vec::new(), None, Some(summary));
+        },
+        None => {
+            return Template::render("unauthorized", context!{});
+        }
+    }
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            show_import,
+            preview_import,
+            commit_import
+    ]).attach(Template::fairing())
+        .manage(Lifecycle::new(DEFAULT_ARCHIVE_AFTER_DAYS))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+    use crate::timezone::parse_timezone;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nUID:meetup-1@example.com\r\n\
+        SUMMARY:Rust Meetup\\, Teil 1\r\nDESCRIPTION:Zeile 1\\nZeile\r\n  2\r\n\
+        DTSTART;TZID=Europe/Berlin:20221030T020000\r\nDURATION:PT1H30M\r\n\
+        URL:https://www.twitch.tv/primeleague\r\nBEGIN:VALARM\r\nDESCRIPTION:Erinnerung\r\nEND:VALARM\r\n\
+        END:VEVENT\r\nBEGIN:VEVENT\r\nUID:62a05c8631a6964f64d829ac@streamie.live\r\nSUMMARY:Export\r\n\
+        DTSTART:20220709T160000Z\r\nDTEND:20220709T150000Z\r\nX-STREAMIE-PLATTFORM:Youtube\r\n\
+        X-STREAMIE-LINK:https://youtu.be/dQw4w9WgXcQ\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+
+    #[test]
+    fn test_parse_ics() {
+        let records = parse_ics(ICS, Tz::UTC);
+        assert_eq!(records.len(), 2);
+
+        let meetup = &records[0];
+        assert_eq!(meetup.uid.as_deref(), Some("meetup-1@example.com"));
+        assert_eq!(meetup.name, "Rust Meetup, Teil 1");
+        assert_eq!(meetup.description, "Zeile 1\nZeile 2");
+        // 02:00 am Tag der Zeitumstellung gibt es zweimal, genommen wird die Sommerzeit
+        assert_eq!(meetup.start, Some(Utc.ymd(2022, 10, 30).and_hms(0, 0, 0)));
+        assert_eq!(meetup.end, Some(Utc.ymd(2022, 10, 30).and_hms(1, 30, 0)));
+        assert_eq!(meetup.link, "https://www.twitch.tv/primeleague");
+
+        let rows = validate_records(records);
+        assert!(rows[0].is_valid());
+        assert_eq!(rows[0].stream_type, StreamType::Twitch);
+        assert_eq!(rows[0].channel, "primeleague");
+        assert_eq!(rows[1].stream_type, StreamType::Youtube);
+        assert_eq!(rows[1].errors, vec!["Das Ende liegt vor dem Beginn.".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_ics_date_time() {
+        let berlin = vec![("TZID".to_string(), "Europe/Berlin".to_string())];
+        let new_york = parse_timezone("America/New_York").unwrap();
+        assert_eq!(parse_ics_date_time("20220709T180000", &berlin, Tz::UTC), Ok(Utc.ymd(2022, 7, 9).and_hms(16, 0, 0)));
+        assert_eq!(parse_ics_date_time("20221209T180000", &berlin, Tz::UTC), Ok(Utc.ymd(2022, 12, 9).and_hms(17, 0, 0)));
+        // TZID und Z haben Vorrang vor der Zeitzone des Users
+        assert_eq!(parse_ics_date_time("20220709T180000", &berlin, new_york), Ok(Utc.ymd(2022, 7, 9).and_hms(16, 0, 0)));
+        assert_eq!(parse_ics_date_time("20220709T180000Z", &[], new_york), Ok(Utc.ymd(2022, 7, 9).and_hms(18, 0, 0)));
+        assert_eq!(parse_ics_date_time("20220709", &[], Tz::UTC), Ok(Utc.ymd(2022, 7, 9).and_hms(0, 0, 0)));
+        assert_eq!(parse_ics_date_time("20220709", &[], new_york), Ok(Utc.ymd(2022, 7, 9).and_hms(4, 0, 0)));
+        // Beim Umstellen auf Sommerzeit fällt 02:30 aus
+        assert!(parse_ics_date_time("20220327T023000", &berlin, Tz::UTC).is_err());
+        assert!(parse_ics_date_time("20220709T180000", &[("TZID".to_string(), "Mars/Olympus".to_string())], Tz::UTC).is_err());
+        assert_eq!(parse_duration("P1DT2H"), Some(chrono::Duration::hours(26)));
+        assert_eq!(parse_duration("PT1H30"), None);
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "\u{feff}Name;Beschreibung;Beginn;Ende;Link;Plattform\r\n\
+            \"Rust; Meetup\";\"Mit \"\"Pizza\"\"\nund Bier\";09.07.2022 18:00:00;2022-07-09T22:00:00+02:00;https://vimeo.com/76979871;\r\n\
+            Ohne Ende;;09.07.2022 18:00;;;\r\n\
+            Rust; Meetup;;09.07.2022 18:00;09.07.2022 19:00;https://example.com;Unbekannt\r\n";
+        let records = parse_csv(csv, Tz::UTC).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].position, 2);
+        assert_eq!(records[0].name, "Rust; Meetup");
+        assert_eq!(records[0].description, "Mit \"Pizza\"\nund Bier");
+        assert_eq!(records[0].end, Some(Utc.ymd(2022, 7, 9).and_hms(20, 0, 0)));
+        assert_eq!(records[1].position, 4);
+
+        let rows = validate_records(records);
+        assert!(rows[0].is_valid());
+        assert_eq!(rows[0].stream_type, StreamType::Vimeo);
+        assert_eq!(rows[0].key, "name:rust-meetup");
+        assert_eq!(rows[1].errors, vec!["Das Ende fehlt.".to_string()]);
+        // Die letzte Zeile hat durch das ungeschützte Semikolon verschobene Spalten
+        assert!(!rows[2].is_valid());
+
+        assert_eq!(parse_csv("Name,Start\n", Tz::UTC), Err("Die Spalte 'end' fehlt in der Kopfzeile.".to_string()));
+    }
+
+    #[test]
+    fn test_import_in_user_timezone() {
+        let berlin = parse_timezone("Europe/Berlin").unwrap();
+        // Zeiten ohne Zeitzone gelten in der Zeitzone des Users, auch über die Zeitumstellung hinweg
+        assert_eq!(parse_csv_date_time("09.07.2022 18:00:00", berlin), Ok(Utc.ymd(2022, 7, 9).and_hms(16, 0, 0)));
+        assert_eq!(parse_csv_date_time("2022-12-09 18:00", berlin), Ok(Utc.ymd(2022, 12, 9).and_hms(17, 0, 0)));
+        assert_eq!(parse_csv_date_time("2022-07-09T22:00:00+02:00", Tz::UTC), Ok(Utc.ymd(2022, 7, 9).and_hms(20, 0, 0)));
+        // 02:30 gibt es am 30.10.2022 zweimal (erste Stunde), am 27.03.2022 gar nicht
+        assert_eq!(parse_csv_date_time("30.10.2022 02:30:00", berlin), Ok(Utc.ymd(2022, 10, 30).and_hms(0, 30, 0)));
+        assert!(parse_csv_date_time("27.03.2022 02:30:00", berlin).unwrap_err().contains("Zeitumstellung"));
+
+        let csv = "Name;Beginn;Ende\nNacht;26.03.2022 23:00:00;27.03.2022 03:30:00\n";
+        let records = parse_csv(csv, berlin).unwrap();
+        assert_eq!(records[0].start, Some(Utc.ymd(2022, 3, 26).and_hms(22, 0, 0)));
+        assert_eq!(records[0].end, Some(Utc.ymd(2022, 3, 27).and_hms(1, 30, 0)));
+
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Nacht\r\nDTSTART:20221029T230000\r\n\
+            DTEND:20221030T030000\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        let records = parse_import(ics, berlin).unwrap();
+        assert_eq!(records[0].start, Some(Utc.ymd(2022, 10, 29).and_hms(21, 0, 0)));
+        assert_eq!(records[0].end, Some(Utc.ymd(2022, 10, 30).and_hms(2, 0, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_import_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get("/admin/import").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.post("/admin/import/commit")
+            .header(ContentType::Form)
+            .body("content=Name%2CStart%2CEnd")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+}
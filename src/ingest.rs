@@ -0,0 +1,391 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rand::{thread_rng, Rng};
+use rand::distributions::Alphanumeric;
+use rocket::fairing::AdHoc;
+use rocket::form::Form;
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_dyn_templates::{Template, context};
+use serde::{Deserialize, Serialize};
+
+use crate::lifecycle::{refresh_session_status, Lifecycle, DEFAULT_ARCHIVE_AFTER_DAYS};
+use crate::database::{get_ingest_sessions, get_session_by_stream_key, get_standard_database, set_session_live, set_stream_key, DatabaseError};
+use crate::security::{admin_token, SecurityToken};
+use crate::sessions::SessionStatus;
+use crate::timezone::user_timezone;
+
+// Länge der generierten Stream-Keys
+const STREAM_KEY_LENGTH: usize = 32;
+
+// Konfiguration des eigenen Ingest-Servers (nginx-rtmp, SRS, MediaMTX) in der Rocket.toml
+// ingest_secret: muss als ?secret= an die Callback-URLs angehängt werden, sonst wird jeder Aufruf abgelehnt
+// ingest_url: wird den Admins zusammen mit dem Stream-Key angezeigt, z.B. rtmp://stream.example.com/live
+#[derive(Debug, Default, Deserialize)]
+pub struct IngestConfig {
+    #[serde(default)]
+    pub ingest_secret: Option<String>,
+    #[serde(default)]
+    pub ingest_url: Option<String>,
+}
+
+pub fn ingest_config() -> AdHoc {
+    return AdHoc::config::<IngestConfig>();
+}
+
+pub fn generate_stream_key() -> String {
+    return thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(STREAM_KEY_LENGTH)
+        .map(char::from)
+        .collect();
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct StreamKeyResult {
+    pub status: u8,
+    pub stream_key: Option<String>
+}
+
+// Antwort an den Ingest-Server, SRS erwartet code 0 für "erlaubt"
+// nginx-rtmp und MediaMTX werten nur den HTTP-Status aus
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub struct IngestResult {
+    pub code: u16
+}
+
+// Session mit Stream-Key für die Admin-Übersicht
+#[derive(Debug, Serialize)]
+pub struct TeraIngestSession {
+    pub id: String,
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub stream_key: Option<String>,
+    pub live: bool,
+    pub live_since: Option<DateTime<Utc>>,
+    pub status: SessionStatus
+}
+
+fn stream_key_result(stream_key: Option<String>, success: bool) -> Json<StreamKeyResult> {
+    return Json(StreamKeyResult {
+        status: if success { 1 } else { 0 },
+        stream_key
+    });
+}
+
+// Übersicht über alle Sessions mit ihren Stream-Keys und dem Live-Status
+#[get("/admin/ingest")]
+pub async fn show_ingest(config: &State<IngestConfig>, cookies: &CookieJar<'_>) -> Template {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            let jwt = cookies.get_private("streamie.live").unwrap();
+            let fullname: Option<Cookie> = cookies.get_private("fullname");
+            let fullname_value = match fullname {
+                Some(fullname) => fullname,
+                None => Cookie::new("fullname", "Unknown User")
+            };
+
+            // Vergangene Sessions brauchen keinen Stream-Key mehr
+            let database = get_standard_database().await;
+            let sessions: Vec<TeraIngestSession> = get_ingest_sessions(&database, Utc::now()).await
+                .expect("Error while find")
+                .into_iter()
+                .map(|session| TeraIngestSession {
+                    id: session.id.to_hex(),
+                    name: session.name,
+                    start: session.start,
+                    stream_key: session.stream_key,
+                    live: session.live,
+                    live_since: session.live_since,
+                    status: session.status
+                })
+                .collect();
+
+            #[derive(Serialize)]
+            struct IngestContext<'a> {
+                jwt: &'a str,
+                fullname: &'a str,
+                sessions: Vec<TeraIngestSession>,
+                ingest_url: Option<&'a str>,
+                timezone: String,
+                token: SecurityToken
+            }
+
+            return Template::render("admin/ingest", IngestContext {
+                jwt: jwt.value(),
+                fullname: fullname_value.value(),
+                sessions,
+                ingest_url: config.ingest_url.as_deref(),
+                timezone: user_timezone(cookies),
+                token: t
+            });
+        },
+        None => {
+            return Template::render("unauthorized", context!{});
+        }
+    }
+}
+
+// Erzeugt einen neuen Stream-Key für die Session, ein vorhandener Key wird damit ungültig
+#[post("/admin/session/<id>/stream_key")]
+pub async fn rotate_stream_key(id: String, cookies: &CookieJar<'_>) -> Json<StreamKeyResult> {
+    if admin_token(cookies).is_none() {
+        return stream_key_result(None, false);
+    }
+
+    let id = match ObjectId::parse_str(&id) {
+        Ok(id) => id,
+        Err(_) => return stream_key_result(None, false)
+    };
+
+    let database = get_standard_database().await;
+    // Bei einer (sehr unwahrscheinlichen) Kollision wird ein weiterer Key erzeugt
+    for _ in 0..3 {
+        let stream_key = generate_stream_key();
+        match set_stream_key(&database, &id, Some(&stream_key)).await {
+            Ok(found) => return stream_key_result(Some(stream_key).filter(|_| found), found),
+            Err(DatabaseError::Conflict(_)) => continue,
+            Err(e) => {
+                error!("Failed to set stream key: {}", e);
+                break;
+            }
+        }
+    }
+
+    return stream_key_result(None, false);
+}
+
+// Entfernt den Stream-Key, danach kann für die Session nicht mehr gestreamt werden
+#[delete("/admin/session/<id>/stream_key")]
+pub async fn revoke_stream_key(id: String, cookies: &CookieJar<'_>) -> Json<StreamKeyResult> {
+    if admin_token(cookies).is_none() {
+        return stream_key_result(None, false);
+    }
+
+    let database = get_standard_database().await;
+    let r = match ObjectId::parse_str(&id) {
+        Ok(id) => set_stream_key(&database, &id, None).await,
+        Err(_) => return stream_key_result(None, false)
+    };
+
+    return stream_key_result(None, matches!(r, Ok(true)));
+}
+
+// Callback von nginx-rtmp (on_publish / on_publish_done), name ist der Stream-Key
+// Alle weiteren Felder (call, addr, app, ...) werden ignoriert
+#[derive(FromForm, Debug)]
+pub struct RtmpCallback<'r> {
+    name: &'r str,
+}
+
+// Callback von SRS (http_hooks, stream ist der Stream-Key)
+// bzw. MediaMTX (externalAuthenticationURL, path ist app/stream-key)
+#[derive(Deserialize, Debug)]
+pub struct JsonCallback {
+    stream: Option<String>,
+    path: Option<String>,
+    action: Option<String>,
+}
+
+// Bei MediaMTX steckt der Key im letzten Teil des Pfads, z.B. live/<key>
+pub fn stream_key_from_path(path: &str) -> Option<&str> {
+    return path.trim_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|key| !key.is_empty());
+}
+
+impl JsonCallback {
+    // MediaMTX fragt auch für Zuschauer (action read/playback) an, diese ändern den Live-Status nicht
+    fn is_read(&self) -> bool {
+        return matches!(self.action.as_deref(), Some("read") | Some("playback"));
+    }
+
+    fn stream_key(&self) -> Option<&str> {
+        return self.stream.as_deref()
+            .filter(|key| !key.is_empty())
+            .or_else(|| self.path.as_deref().and_then(stream_key_from_path));
+    }
+}
+
+fn ingest_result(status: Status) -> status::Custom<Json<IngestResult>> {
+    let code = if status == Status::Ok { 0 } else { status.code };
+    return status::Custom(status, Json(IngestResult { code }));
+}
+
+// Prüft Secret und Stream-Key und setzt die zugehörige Session live bzw. offline
+async fn handle_callback(stream_key: Option<&str>, secret: Option<&str>, config: &IngestConfig, lifecycle: &Lifecycle, live: bool) -> status::Custom<Json<IngestResult>> {
+    if let Some(expected) = config.ingest_secret.as_deref() {
+        if secret != Some(expected) {
+            warn!("Ingest callback with invalid secret rejected");
+            return ingest_result(Status::Forbidden);
+        }
+    }
+
+    let stream_key = match stream_key {
+        Some(stream_key) => stream_key,
+        None => return ingest_result(Status::BadRequest)
+    };
+
+    let database = get_standard_database().await;
+    let session = match get_session_by_stream_key(&database, stream_key).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            info!("Ingest rejected for unknown stream key");
+            return ingest_result(Status::Forbidden);
+        },
+        Err(e) => {
+            error!("Failed to look up stream key: {}", e);
+            return ingest_result(Status::InternalServerError);
+        }
+    };
+
+    match set_session_live(&database, &session.id, live).await {
+        Ok(_) => {
+            info!("Session '{}' is now {}", session.name, if live { "live" } else { "offline" });
+            // Zuschauer sollen nicht auf den nächsten Lauf des Schedulers warten müssen
+            refresh_session_status(&database, lifecycle, &session.id).await;
+            return ingest_result(Status::Ok);
+        },
+        Err(e) => {
+            error!("Failed to update live state: {}", e);
+            return ingest_result(Status::InternalServerError);
+        }
+    }
+}
+
+#[post("/ingest/on_publish?<secret>", format = "form", data = "<callback>", rank = 1)]
+pub async fn on_publish(callback: Form<RtmpCallback<'_>>, secret: Option<&str>, config: &State<IngestConfig>, lifecycle: &State<Lifecycle>) -> status::Custom<Json<IngestResult>> {
+    return handle_callback(Some(callback.name).filter(|key| !key.is_empty()), secret, config, lifecycle, true).await;
+}
+
+#[post("/ingest/on_publish?<secret>", format = "json", data = "<callback>", rank = 2)]
+pub async fn on_publish_json(callback: Json<JsonCallback>, secret: Option<&str>, config: &State<IngestConfig>, lifecycle: &State<Lifecycle>) -> status::Custom<Json<IngestResult>> {
+    if callback.is_read() {
+        return ingest_result(Status::Ok);
+    }
+    return handle_callback(callback.stream_key(), secret, config, lifecycle, true).await;
+}
+
+#[post("/ingest/on_publish_done?<secret>", format = "form", data = "<callback>", rank = 1)]
+pub async fn on_publish_done(callback: Form<RtmpCallback<'_>>, secret: Option<&str>, config: &State<IngestConfig>, lifecycle: &State<Lifecycle>) -> status::Custom<Json<IngestResult>> {
+    return handle_callback(Some(callback.name).filter(|key| !key.is_empty()), secret, config, lifecycle, false).await;
+}
+
+#[post("/ingest/on_publish_done?<secret>", format = "json", data = "<callback>", rank = 2)]
+pub async fn on_publish_done_json(callback: Json<JsonCallback>, secret: Option<&str>, config: &State<IngestConfig>, lifecycle: &State<Lifecycle>) -> status::Custom<Json<IngestResult>> {
+    return handle_callback(callback.stream_key(), secret, config, lifecycle, false).await;
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            show_ingest,
+            rotate_stream_key,
+            revoke_stream_key,
+            on_publish,
+            on_publish_json,
+            on_publish_done,
+            on_publish_done_json
+    ]).attach(Template::fairing())
+        .attach(ingest_config())
+        .manage(Lifecycle::new(DEFAULT_ARCHIVE_AFTER_DAYS))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::Duration;
+    use rocket::http::ContentType;
+    use rocket::local::asynchronous::Client;
+    use crate::database::{add_new_session, get_session_by_id, remove_session_by_id};
+    use crate::sessions::Session;
+
+    #[test]
+    fn test_generate_stream_key() {
+        let key = generate_stream_key();
+        assert_eq!(key.len(), STREAM_KEY_LENGTH);
+        assert!(key.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(key, generate_stream_key());
+    }
+
+    #[test]
+    fn test_stream_key_from_callbacks() {
+        assert_eq!(stream_key_from_path("live/abc123"), Some("abc123"));
+        assert_eq!(stream_key_from_path("/abc123/"), Some("abc123"));
+        assert_eq!(stream_key_from_path(""), None);
+
+        let srs = JsonCallback { stream: Some("abc123".to_string()), path: None, action: Some("on_publish".to_string()) };
+        assert_eq!(srs.stream_key(), Some("abc123"));
+        assert!(!srs.is_read());
+        let mediamtx = JsonCallback { stream: None, path: Some("live/abc123".to_string()), action: Some("read".to_string()) };
+        assert_eq!(mediamtx.stream_key(), Some("abc123"));
+        assert!(mediamtx.is_read());
+    }
+
+    #[tokio::test]
+    async fn test_rotate_stream_key_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post(uri!(super::rotate_stream_key("62a05c8631a6964f64d829ac".to_string()))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "{\"status\":0,\"stream_key\":null}");
+    }
+
+    #[tokio::test]
+    async fn test_on_publish_without_key() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post("/ingest/on_publish")
+            .header(ContentType::Form)
+            .body("call=publish&app=live&name=")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    // Simuliert die Callbacks von nginx-rtmp (Formular) und SRS (JSON) für eine Session mit Stream-Key
+    #[tokio::test]
+    async fn test_simulated_publish_callbacks() {
+        let database = get_standard_database().await;
+        let session = Session {
+            slug: format!("ingest-{}", ObjectId::new().to_hex()),
+            start: Utc::now(),
+            end: Utc::now() + Duration::hours(1),
+            name: "Ingest Test".to_string(),
+            stream_key: Some(generate_stream_key()),
+            ..Default::default()
+        };
+        add_new_session(&database, &session).await.expect("Error while insert");
+        let stream_key = session.stream_key.clone().unwrap();
+
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post("/ingest/on_publish")
+            .header(ContentType::Form)
+            .body(format!("call=publish&addr=127.0.0.1&app=live&name={}", stream_key))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(get_session_by_id(&database, &session.id).await.live);
+
+        let response = client.post("/ingest/on_publish_done")
+            .header(ContentType::JSON)
+            .body(format!("{{\"action\":\"on_unpublish\",\"app\":\"live\",\"stream\":\"{}\"}}", stream_key))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(!get_session_by_id(&database, &session.id).await.live);
+
+        let response = client.post("/ingest/on_publish")
+            .header(ContentType::Form)
+            .body("call=publish&app=live&name=wrongkey")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        remove_session_by_id(&database, &session.id).await.unwrap();
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket::{Shutdown, State};
+use rocket::fairing::AdHoc;
+use rocket::http::{CookieJar, Status};
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Json;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{channel, error::RecvError, Sender};
+use serde::Serialize;
+
+use crate::access::{accessible_session, viewer_from_token};
+use crate::database::{get_active_sessions, get_sessions_by_ids, get_standard_database, set_session_live,
+                      set_session_status, set_session_times};
+use crate::security::{admin_token, user_token};
+use crate::sessions::{Session, SessionStatus};
+
+// Standard: beendete Sessions werden nach 7 Tagen archiviert, kann über archive_after_days in der Rocket.toml geändert werden
+pub const DEFAULT_ARCHIVE_AFTER_DAYS: i64 = 7;
+
+// Intervall in dem der Scheduler alle Sessions prüft
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+// Ereignisse, die per Server-Sent-Events an die Zuschauer der jeweiligen Session gehen
+// Ereignisse, die per Server-Sent-Events an alle Zuschauer gehen
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEvent {
+    Status { session_id: String, status: SessionStatus },
+    // Ein Stream der Session wurde umgeschaltet, die Zuschauer laden dessen Einbettung neu
+    // Der Link selbst wird nicht mitgeschickt, die Ereignisse gehen an alle User, die die Session sehen dürfen
+    Source { session_id: String, track: String },
+}
+
+impl SessionEvent {
+    pub fn session_id(&self) -> &str {
+        return match self {
+            SessionEvent::Status { session_id, .. } | SessionEvent::Source { session_id, .. } => session_id
+        };
+    }
+}
+
+// Gemeinsamer Zustand für Scheduler, Admin-Override und Ingest-Callbacks
+#[derive(Clone)]
+pub struct Lifecycle {
+    pub events: Sender<SessionEvent>,
+    pub archive_after: chrono::Duration,
+}
+
+impl Lifecycle {
+    pub fn new(archive_after_days: i64) -> Lifecycle {
+        return Lifecycle {
+            events: channel::<SessionEvent>(1024).0,
+            archive_after: chrono::Duration::days(archive_after_days),
+        };
+    }
+}
+
+// Berechnet den Status aus Beginn, Ende und dem Live-Signal des Ingest-Servers
+// Solange der Ingest-Server sendet, ist die Session live, auch außerhalb des geplanten Zeitraums
+pub fn compute_status(session: &Session, now: DateTime<Utc>, archive_after: chrono::Duration) -> SessionStatus {
+    if session.live {
+        return SessionStatus::Live;
+    }
+    if now < session.start {
+        return SessionStatus::Scheduled;
+    }
+    if now < session.end {
+        return SessionStatus::Live;
+    }
+    if now >= session.end + archive_after {
+        return SessionStatus::Archived;
+    }
+    return SessionStatus::Ended;
+}
+
+// Speichert einen geänderten Status und benachrichtigt alle verbundenen Zuschauer
+async fn apply_status(database: &mongodb::Database, lifecycle: &Lifecycle, session: &Session, now: DateTime<Utc>) {
+    let status = compute_status(session, now, lifecycle.archive_after);
+    if status == session.status {
+        return;
+    }
+
+    match set_session_status(database, &session.id, status).await {
+        Ok(_) => {
+            info!("Session '{}' changed from {:?} to {:?}", session.name, session.status, status);
+            // Ohne verbundene Zuschauer schlägt send fehl, das ist kein Fehler
+            let _ = lifecycle.events.send(SessionEvent::Status {
+                session_id: session.id.to_hex(),
+                status
+            });
+        },
+        Err(e) => error!("Failed to update status of session '{}': {}", session.name, e)
+    }
+}
+
+// Berechnet den Status einer einzelnen Session sofort neu (nach Admin-Override, Update oder Ingest-Callback)
+pub async fn refresh_session_status(database: &mongodb::Database, lifecycle: &Lifecycle, id: &ObjectId) {
+    match get_sessions_by_ids(database, &[*id]).await {
+        Ok(sessions) => {
+            for session in sessions {
+                apply_status(database, lifecycle, &session, Utc::now()).await;
+            }
+        },
+        Err(e) => error!("Failed to load session for status refresh: {}", e)
+    }
+}
+
+// Legt den Lifecycle-Zustand an und startet den Scheduler nach dem Start der Anwendung
+pub fn lifecycle_fairing() -> AdHoc {
+    AdHoc::on_ignite("Session Lifecycle", |rocket| async {
+        let archive_after_days: i64 = rocket.figment()
+            .extract_inner("archive_after_days")
+            .unwrap_or(DEFAULT_ARCHIVE_AFTER_DAYS);
+
+        rocket.manage(Lifecycle::new(archive_after_days))
+            .attach(AdHoc::on_liftoff("Session Lifecycle Scheduler", |rocket| Box::pin(async move {
+                let lifecycle = rocket.state::<Lifecycle>().unwrap().clone();
+                let mut shutdown = rocket.shutdown();
+
+                rocket::tokio::spawn(async move {
+                    let mut interval = rocket::tokio::time::interval(SCHEDULER_INTERVAL);
+                    loop {
+                        select! {
+                            _ = interval.tick() => {},
+                            _ = &mut shutdown => break,
+                        }
+
+                        let database = get_standard_database().await;
+                        match get_active_sessions(&database).await {
+                            Ok(sessions) => {
+                                let now = Utc::now();
+                                for session in sessions {
+                                    apply_status(&database, &lifecycle, &session, now).await;
+                                }
+                            },
+                            Err(e) => error!("Failed to load sessions for scheduler: {}", e)
+                        }
+                    }
+                });
+            })))
+    })
+}
+
+// Abonniere die Ereignisse der Sessions (Status, umgeschaltete Streams), die der User sehen darf,
+// die Clients filtern selbst nach session_id
+#[get("/session/events")]
+pub async fn session_events(lifecycle: &State<Lifecycle>, mut end: Shutdown, cookies: &CookieJar<'_>) -> Result<EventStream![], Status> {
+    let token = match user_token(cookies) {
+        Some(t) => t,
+        None => return Err(Status::Unauthorized)
+    };
+
+    let database = get_standard_database().await;
+    let viewer = viewer_from_token(&database, &token).await;
+    let mut rx = lifecycle.events.subscribe();
+    return Ok(EventStream! {
+        // Ob eine Session gesehen werden darf, wird je Verbindung nur einmal geprüft
+        let mut sessions: HashMap<String, bool> = HashMap::new();
+        loop {
+            let event = select! {
+                event = rx.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut end => break,
+            };
+
+            let session_id = event.session_id().to_string();
+            if !sessions.contains_key(&session_id) {
+                let allowed = match ObjectId::parse_str(&session_id) {
+                    Ok(id) => matches!(accessible_session(&database, &viewer, &id).await, Ok(Some(_))),
+                    Err(_) => false
+                };
+                sessions.insert(session_id.clone(), allowed);
+            }
+            if sessions[&session_id] {
+                yield Event::json(&event);
+            }
+        }
+    });
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OverrideResult {
+    pub status: u8,
+    pub session_status: Option<SessionStatus>
+}
+
+fn override_result(session_status: Option<SessionStatus>) -> Json<OverrideResult> {
+    return Json(OverrideResult {
+        status: if session_status.is_some() { 1 } else { 0 },
+        session_status
+    });
+}
+
+fn is_admin(cookies: &CookieJar<'_>) -> bool {
+    return admin_token(cookies).is_some();
+}
+
+// Neue Zeiten für einen manuellen Eingriff, die geplante Dauer bleibt beim vorzeitigen Start erhalten
+pub fn override_times(session: &Session, go_live: bool, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    if go_live {
+        let duration = session.end - session.start;
+        let end = if session.end > now { session.end } else { now + duration };
+        return (now, end);
+    }
+    return (session.start.min(now), now);
+}
+
+async fn override_session(id: &str, go_live: bool, lifecycle: &Lifecycle) -> Option<SessionStatus> {
+    let id = ObjectId::parse_str(id).ok()?;
+    let database = get_standard_database().await;
+    let session = get_sessions_by_ids(&database, &[id]).await.ok()?.into_iter().next()?;
+
+    let (start, end) = override_times(&session, go_live, Utc::now());
+    if let Err(e) = set_session_times(&database, &id, start, end).await {
+        error!("Failed to override session times: {}", e);
+        return None;
+    }
+    // Beim vorzeitigen Beenden zählt auch ein noch sendender Encoder nicht mehr als live
+    if !go_live {
+        if let Err(e) = set_session_live(&database, &id, false).await {
+            error!("Failed to reset live state: {}", e);
+        }
+    }
+
+    refresh_session_status(&database, lifecycle, &id).await;
+    return Some(if go_live { SessionStatus::Live } else { SessionStatus::Ended });
+}
+
+// Admin-Override: Session sofort starten
+#[post("/admin/session/<id>/go_live")]
+pub async fn go_live_now(id: &str, lifecycle: &State<Lifecycle>, cookies: &CookieJar<'_>) -> Json<OverrideResult> {
+    if !is_admin(cookies) {
+        return override_result(None);
+    }
+    return override_result(override_session(id, true, lifecycle).await);
+}
+
+// Admin-Override: Session vorzeitig beenden
+#[post("/admin/session/<id>/end")]
+pub async fn end_session_early(id: &str, lifecycle: &State<Lifecycle>, cookies: &CookieJar<'_>) -> Json<OverrideResult> {
+    if !is_admin(cookies) {
+        return override_result(None);
+    }
+    return override_result(override_session(id, false, lifecycle).await);
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            session_events,
+            go_live_now,
+            end_session_early
+    ]).manage(Lifecycle::new(DEFAULT_ARCHIVE_AFTER_DAYS))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::TimeZone;
+    use rocket::local::asynchronous::Client;
+
+    fn session(start: DateTime<Utc>, end: DateTime<Utc>, live: bool) -> Session {
+        return Session {
+            slug: "test".to_string(),
+            start,
+            end,
+            name: "Test".to_string(),
+            live,
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn test_compute_status() {
+        let start = Utc.ymd(2022, 7, 9).and_hms(18, 0, 0);
+        let end = Utc.ymd(2022, 7, 9).and_hms(20, 0, 0);
+        let archive_after = chrono::Duration::days(7);
+        let s = session(start, end, false);
+
+        assert_eq!(compute_status(&s, start - chrono::Duration::minutes(1), archive_after), SessionStatus::Scheduled);
+        assert_eq!(compute_status(&s, start, archive_after), SessionStatus::Live);
+        assert_eq!(compute_status(&s, end, archive_after), SessionStatus::Ended);
+        assert_eq!(compute_status(&s, end + archive_after, archive_after), SessionStatus::Archived);
+
+        // Ein sendender Encoder macht die Session auch vor dem geplanten Beginn live
+        let early = session(start, end, true);
+        assert_eq!(compute_status(&early, start - chrono::Duration::hours(1), archive_after), SessionStatus::Live);
+    }
+
+    #[test]
+    fn test_override_times() {
+        let start = Utc.ymd(2022, 7, 9).and_hms(18, 0, 0);
+        let end = Utc.ymd(2022, 7, 9).and_hms(20, 0, 0);
+        let s = session(start, end, false);
+
+        let now = Utc.ymd(2022, 7, 9).and_hms(17, 30, 0);
+        assert_eq!(override_times(&s, true, now), (now, end));
+
+        // Eine bereits beendete Session behält beim erneuten Start ihre Dauer
+        let later = Utc.ymd(2022, 7, 10).and_hms(10, 0, 0);
+        assert_eq!(override_times(&s, true, later), (later, later + chrono::Duration::hours(2)));
+
+        let during = Utc.ymd(2022, 7, 9).and_hms(19, 0, 0);
+        assert_eq!(override_times(&s, false, during), (start, during));
+    }
+
+    #[test]
+    fn test_status_event_json() {
+        let event = SessionEvent::Status { session_id: "abc".to_string(), status: SessionStatus::Live };
+        assert_eq!(serde_json::to_string(&event).unwrap(), "{\"type\":\"status\",\"session_id\":\"abc\",\"status\":\"live\"}");
+    }
+
+    #[test]
+    fn test_source_event_json() {
+        let event = SessionEvent::Source { session_id: "abc".to_string(), track: "main".to_string() };
+        assert_eq!(serde_json::to_string(&event).unwrap(), "{\"type\":\"source\",\"session_id\":\"abc\",\"track\":\"main\"}");
+    }
+
+    #[test]
+    fn test_event_session_id() {
+        assert_eq!(SessionEvent::Status { session_id: "abc".to_string(), status: SessionStatus::Live }.session_id(), "abc");
+        assert_eq!(SessionEvent::Source { session_id: "def".to_string(), track: "main".to_string() }.session_id(), "def");
+    }
+
+    #[tokio::test]
+    async fn test_go_live_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post(uri!(super::go_live_now("62a05c8631a6964f64d829ac"))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "{\"status\":0,\"session_status\":null}");
+    }
+
+    #[tokio::test]
+    async fn test_session_events_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get(uri!(super::session_events)).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
@@ -0,0 +1,262 @@
+use chrono::{DateTime, Timelike, Utc};
+use mongodb::bson::{doc, Document, Regex, to_bson};
+use mongodb::options::FindOptions;
+use serde::Serialize;
+
+use crate::database::not_deleted;
+
+// Standardmäßig werden 20 Einträge pro Seite angezeigt, maximal 100
+pub const DEFAULT_PER_PAGE: u64 = 20;
+pub const MAX_PER_PAGE: u64 = 100;
+
+// Query-Parameter für die Session-Listen (/sessions und APIs)
+// status: upcoming, live oder past (nach Zeitraum) bzw. scheduled, ended oder archived (gespeicherter Status)
+// plattform: Wert eines StreamType (siehe providers.rs)
+// q: Freitextsuche in Name und Beschreibung
+// order: asc (Standard) oder desc, sortiert wird nach der Startzeit
+#[derive(Debug, Default, Clone, FromForm, Serialize)]
+#[cfg_attr(test, derive(UriDisplayQuery))]
+pub struct SessionFilter {
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+    pub status: Option<String>,
+    pub plattform: Option<String>,
+    pub q: Option<String>,
+    pub order: Option<String>,
+}
+
+// Query-Parameter für die Benutzerliste (/usermanagement und APIs)
+// role: ADMIN, MODERATOR oder USER
+// q: Freitextsuche in Username und Anzeigename
+#[derive(Debug, Default, Clone, FromForm, Serialize)]
+#[cfg_attr(test, derive(UriDisplayQuery))]
+pub struct UserFilter {
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+    pub role: Option<String>,
+    pub q: Option<String>,
+}
+
+// Eine Seite einer Liste inklusive der Informationen für die Navigation
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: u64,
+    pub per_page: u64,
+    pub total: u64,
+    pub pages: u64,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, page: u64, per_page: u64, total: u64) -> Page<T> {
+        let pages = if total == 0 { 1 } else { total.div_ceil(per_page) };
+        return Page { items, page, per_page, total, pages };
+    }
+
+    // Überführt die Einträge, z.B. in die Tera-Structs, und behält die Seiteninformationen bei
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> Page<U> {
+        return Page {
+            items: self.items.into_iter().map(f).collect(),
+            page: self.page,
+            per_page: self.per_page,
+            total: self.total,
+            pages: self.pages,
+        };
+    }
+}
+
+// Seite ist 1-basiert, ungültige Werte werden auf die Standardwerte zurückgesetzt
+fn page_and_size(page: Option<u64>, per_page: Option<u64>) -> (u64, u64) {
+    let page = page.filter(|p| *p > 0).unwrap_or(1);
+    let per_page = per_page.filter(|p| *p > 0).unwrap_or(DEFAULT_PER_PAGE).min(MAX_PER_PAGE);
+    return (page, per_page);
+}
+
+fn find_options(page: u64, per_page: u64, sort: Document) -> FindOptions {
+    return FindOptions::builder()
+        .sort(sort)
+        .skip((page - 1) * per_page)
+        .limit(per_page as i64)
+        .build();
+}
+
+// Maskiert alle Regex-Sonderzeichen, damit die Freitextsuche wörtlich sucht
+pub fn escape_regex(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    return escaped;
+}
+
+fn contains_ignore_case(value: &str) -> Regex {
+    return Regex {
+        pattern: escape_regex(value.trim()),
+        options: "i".to_string(),
+    };
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    return value.as_deref().map(str::trim).filter(|v| !v.is_empty());
+}
+
+impl SessionFilter {
+    pub fn page_and_size(&self) -> (u64, u64) {
+        return page_and_size(self.page, self.per_page);
+    }
+
+    // Baut den MongoDB Filter, now wird übergeben damit "live" etc. testbar bleibt
+    pub fn to_document(&self, now: DateTime<Utc>) -> Document {
+        // Sessions im Papierkorb werden nie gelistet
+        let mut filter = not_deleted();
+        // start und end liegen als RFC3339 Strings vor und sind damit lexikografisch vergleichbar
+        let now = to_bson(&now.with_nanosecond(0).unwrap()).unwrap();
+
+        match non_empty(&self.status) {
+            Some("upcoming") => {
+                filter.insert("start", doc! {"$gt": &now});
+            },
+            Some("live") => {
+                filter.insert("start", doc! {"$lte": &now});
+                filter.insert("end", doc! {"$gt": &now});
+            },
+            Some("past") => {
+                filter.insert("end", doc! {"$lte": &now});
+            },
+            Some(status @ ("scheduled" | "ended" | "archived")) => {
+                filter.insert("status", status);
+            },
+            _ => {}
+        }
+
+        if let Some(plattform) = non_empty(&self.plattform) {
+            filter.insert("stream.stream_type", plattform);
+        }
+
+        if let Some(q) = non_empty(&self.q) {
+            filter.insert("$or", vec![
+                doc! {"name": contains_ignore_case(q)},
+                doc! {"description": contains_ignore_case(q)},
+            ]);
+        }
+
+        return filter;
+    }
+
+    pub fn find_options(&self) -> FindOptions {
+        let (page, per_page) = self.page_and_size();
+        let direction = if non_empty(&self.order) == Some("desc") { -1 } else { 1 };
+        return find_options(page, per_page, doc! {"start": direction, "_id": direction});
+    }
+
+    // Query-String für eine andere Seite mit den gleichen Filtern (für die Blätter-Links)
+    pub fn query_for_page(&self, page: u64) -> String {
+        let mut params: Vec<(&str, String)> = vec![("page", page.to_string())];
+        if let Some(per_page) = self.per_page {
+            params.push(("per_page", per_page.to_string()));
+        }
+        for (key, value) in [("status", &self.status), ("plattform", &self.plattform),
+                             ("q", &self.q), ("order", &self.order)] {
+            if let Some(value) = non_empty(value) {
+                params.push((key, value.to_string()));
+            }
+        }
+        return encode_query(&params);
+    }
+}
+
+impl UserFilter {
+    pub fn page_and_size(&self) -> (u64, u64) {
+        return page_and_size(self.page, self.per_page);
+    }
+
+    pub fn to_document(&self) -> Document {
+        // User im Papierkorb werden nie gelistet
+        let mut filter = not_deleted();
+
+        if let Some(role) = non_empty(&self.role) {
+            filter.insert("role", role);
+        }
+
+        if let Some(q) = non_empty(&self.q) {
+            filter.insert("$or", vec![
+                doc! {"username": contains_ignore_case(q)},
+                doc! {"fullname": contains_ignore_case(q)},
+            ]);
+        }
+
+        return filter;
+    }
+
+    pub fn find_options(&self) -> FindOptions {
+        let (page, per_page) = self.page_and_size();
+        return find_options(page, per_page, doc! {"username": 1});
+    }
+
+    pub fn query_for_page(&self, page: u64) -> String {
+        let mut params: Vec<(&str, String)> = vec![("page", page.to_string())];
+        if let Some(per_page) = self.per_page {
+            params.push(("per_page", per_page.to_string()));
+        }
+        for (key, value) in [("role", &self.role), ("q", &self.q)] {
+            if let Some(value) = non_empty(value) {
+                params.push((key, value.to_string()));
+            }
+        }
+        return encode_query(&params);
+    }
+}
+
+fn encode_query(params: &[(&str, String)]) -> String {
+    return params.iter()
+        .map(|(key, value)| format!("{}={}", key, rocket::http::RawStr::new(value).percent_encode()))
+        .collect::<Vec<String>>()
+        .join("&");
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    #[test]
+    fn test_page_and_size_defaults() {
+        let filter = SessionFilter { page: Some(0), per_page: Some(1000), ..Default::default() };
+        assert_eq!(filter.page_and_size(), (1, MAX_PER_PAGE));
+        assert_eq!(SessionFilter::default().page_and_size(), (1, DEFAULT_PER_PAGE));
+
+        let page: Page<u8> = Page::new(vec![], 1, 20, 41);
+        assert_eq!(page.pages, 3);
+    }
+
+    #[test]
+    fn test_session_filter_document() {
+        let now = Utc.ymd(2022, 7, 9).and_hms(8, 0, 0);
+        let filter = SessionFilter {
+            status: Some("live".to_string()),
+            plattform: Some("Twitch".to_string()),
+            q: Some("rust".to_string()),
+            ..Default::default()
+        };
+
+        let document = filter.to_document(now);
+        assert_eq!(document.get_document("start").unwrap(), &doc! {"$lte": "2022-07-09T08:00:00Z"});
+        assert_eq!(document.get_document("end").unwrap(), &doc! {"$gt": "2022-07-09T08:00:00Z"});
+        assert_eq!(document.get_str("stream.stream_type").unwrap(), "Twitch");
+        assert!(document.get_array("$or").is_ok());
+
+        let archived = SessionFilter { status: Some("archived".to_string()), ..Default::default() };
+        assert_eq!(archived.to_document(now).get_str("status").unwrap(), "archived");
+    }
+
+    #[test]
+    fn test_escape_regex_and_query() {
+        assert_eq!(escape_regex("a.b(c)"), "a\\.b\\(c\\)");
+
+        let filter = UserFilter { role: Some("ADMIN".to_string()), q: Some("max m".to_string()), ..Default::default() };
+        assert_eq!(filter.query_for_page(2), "page=2&role=ADMIN&q=max%20m");
+    }
+}
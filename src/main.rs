@@ -6,10 +6,10 @@ use rocket::http::{Header, ContentType};
 use rocket_dyn_templates::{Template};
 use rocket::http::{Cookie, CookieJar};
 use rocket::fs::{relative, FileServer, Options};
+use rocket::fairing::AdHoc;
 use std::collections::HashMap;
 use chrono::{DateTime, Utc};
 use mongodb::bson::doc;
-use mongodb::bson::oid::ObjectId;
 use mongodb::Client;
 use mongodb::options::ClientOptions;
 use serde::Serialize;
@@ -35,7 +35,9 @@ use crate::administration::show_overview;
 use crate::administration::add_session;
 use crate::administration::ask_session_detail;
 use crate::administration::ask_session_detail_update;
+use crate::administration::edit_session;
 use crate::administration::admin_update_session;
+use crate::administration::session_state;
 use crate::administration::ask_session_detail_delete;
 use crate::administration::delete_session;
 
@@ -53,14 +55,187 @@ use crate::security::logout;
 use crate::usermanagement::{
     list_all_user,
     create_new_user,
-    delete_existing_user
+    delete_existing_user,
+    update_user_groups
 };
 
+/**
+ * Imports for all Search-related stuff
+ */
+use crate::search::show_search;
+
+/**
+ * Imports for all Trash-related stuff
+ */
+use crate::trash::{
+    show_trash,
+    restore_session,
+    purge_session,
+    restore_user,
+    purge_user,
+    retention_fairing
+};
+
+/**
+ * Imports for self-hosted streams
+ */
+use crate::stream_proxy::{proxy_stream, stream_proxy_config};
+
+/**
+ * Imports for the Ingest-Server callbacks and stream keys
+ */
+use crate::ingest::{
+    show_ingest,
+    rotate_stream_key,
+    revoke_stream_key,
+    on_publish,
+    on_publish_json,
+    on_publish_done,
+    on_publish_done_json,
+    ingest_config
+};
+
+/**
+ * Imports for the Session lifecycle
+ */
+use crate::lifecycle::{
+    session_events,
+    go_live_now,
+    end_session_early,
+    lifecycle_fairing
+};
+
+/**
+ * Imports for the iCalendar feeds
+ */
+use crate::calendar::{
+    session_calendar,
+    calendar_feed,
+    regenerate_calendar_token
+};
+
+/**
+ * Imports for the Session import
+ */
+use crate::import::{
+    show_import,
+    preview_import,
+    commit_import
+};
+
+/**
+ * Imports for recurring Sessions
+ */
+use crate::series::{
+    show_series,
+    add_new_series,
+    show_series_detail,
+    edit_occurrence,
+    update_occurrence,
+    cancel_occurrence,
+    series_fairing
+};
+
+/**
+ * Imports for Session Attachments
+ */
+use crate::attachments::{
+    upload_attachments,
+    list_attachments,
+    download_attachment,
+    delete_attachment,
+    attachment_config
+};
+
+/**
+ * Imports for Session Registration
+ */
+use crate::registration::{
+    register,
+    unregister,
+    update_registration,
+    export_attendees,
+    registration_fairing
+};
+
+/**
+ * Imports for Multi-Track Sessions
+ */
+use crate::tracks::{
+    select_session_track,
+    session_track_embed,
+    switch_source,
+    update_tracks
+};
+
+/**
+ * Imports for Agenda and Speakers
+ */
+use crate::speakers::{
+    show_speakers,
+    edit_speaker,
+    add_new_speaker,
+    update_existing_speaker,
+    delete_speaker,
+    speaker_photo
+};
+use crate::agenda::update_agenda;
+
+/**
+ * Imports for Event Hubs
+ */
+use crate::events::{
+    show_events,
+    edit_event,
+    add_new_event,
+    update_existing_event,
+    delete_event,
+    assign_session,
+    unassign_session,
+    list_events,
+    event_hub
+};
+
+/**
+ * Imports for User Settings
+ */
+use crate::timezone::{show_settings, update_timezone};
+use crate::markdown::preview_markdown;
+use crate::posters::{delete_poster, poster_file, upload_poster};
+use crate::waiting_room::{update_waiting_room, waiting_room_state};
+
+/**
+ * Imports for all Database-related stuff
+ */
+use crate::database::{ensure_indexes, get_standard_database};
+
 mod security;
 mod database;
 mod sessions;
 mod administration;
 mod usermanagement;
+mod listing;
+mod search;
+mod trash;
+mod providers;
+mod stream_proxy;
+mod ingest;
+mod lifecycle;
+mod calendar;
+mod import;
+mod recurrence;
+mod series;
+mod attachments;
+mod access;
+mod registration;
+mod tracks;
+mod speakers;
+mod agenda;
+mod events;
+mod timezone;
+mod markdown;
+mod posters;
+mod waiting_room;
 
 // Index Page
 #[get("/")]
@@ -119,14 +294,101 @@ fn rocket() -> _ {
         add_session,
         ask_session_detail,
         ask_session_detail_update,
+        edit_session,
+        session_state,
         admin_update_session,
         ask_session_detail_delete,
         delete_session,
         list_all_user,
         create_new_user,
-        delete_existing_user
+        delete_existing_user,
+        update_user_groups,
+        show_search,
+        show_trash,
+        restore_session,
+        purge_session,
+        restore_user,
+        purge_user,
+        proxy_stream,
+        show_ingest,
+        rotate_stream_key,
+        revoke_stream_key,
+        on_publish,
+        on_publish_json,
+        on_publish_done,
+        on_publish_done_json,
+        session_events,
+        go_live_now,
+        end_session_early,
+        session_calendar,
+        calendar_feed,
+        regenerate_calendar_token,
+        show_import,
+        preview_import,
+        commit_import,
+        show_series,
+        add_new_series,
+        show_series_detail,
+        edit_occurrence,
+        update_occurrence,
+        cancel_occurrence,
+        upload_attachments,
+        list_attachments,
+        download_attachment,
+        delete_attachment,
+        register,
+        unregister,
+        update_registration,
+        export_attendees,
+        select_session_track,
+        session_track_embed,
+        switch_source,
+        update_tracks,
+        show_speakers,
+        edit_speaker,
+        add_new_speaker,
+        update_existing_speaker,
+        delete_speaker,
+        speaker_photo,
+        update_agenda,
+        show_events,
+        edit_event,
+        add_new_event,
+        update_existing_event,
+        delete_event,
+        assign_session,
+        unassign_session,
+        list_events,
+        event_hub,
+        show_settings,
+        update_timezone,
+        preview_markdown,
+        upload_poster,
+        delete_poster,
+        poster_file,
+        waiting_room_state,
+        update_waiting_room
     ])
     .mount("/", FileServer::new("./static", options))
     .register("/", catchers![internal_error, not_found])
     .attach(Template::fairing())
+    // Indizes (u.a. unique username und slug) werden beim Start angelegt bzw. überprüft
+    .attach(AdHoc::try_on_ignite("Database Indexes", |rocket| async {
+        match ensure_indexes(&get_standard_database().await).await {
+            Ok(()) => Ok(rocket),
+            Err(e) => {
+                error!("Failed to create or verify database indexes: {}", e);
+                Err(rocket)
+            }
+        }
+    }))
+    .attach(retention_fairing())
+    .attach(stream_proxy_config())
+    .attach(ingest_config())
+    // Status der Sessions (geplant, live, beendet, archiviert) wird im Hintergrund fortgeschrieben
+    .attach(lifecycle_fairing())
+    // Termine von Serien werden im Voraus als Sessions angelegt
+    .attach(series_fairing())
+    .attach(attachment_config())
+    .attach(registration_fairing())
 }
\ No newline at end of file
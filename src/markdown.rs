@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+
+use ammonia::Builder;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use rocket::form::Form;
+use rocket::http::{CookieJar, Status};
+use rocket::serde::json::Json;
+use rocket_dyn_templates::tera::escape_html;
+use serde::{Deserialize, Serialize};
+
+use crate::security::admin_token;
+
+// Obergrenze für Beschreibungen, gilt für das Formular und die Vorschau
+pub const MAX_DESCRIPTION_LENGTH: usize = 5000;
+
+// Erlaubte Tags nach dem Rendern, alles andere wird entfernt (der Text bleibt erhalten)
+// Bilder sind bewusst nicht erlaubt, damit Beschreibungen keine externen Inhalte nachladen
+const ALLOWED_TAGS: [&str; 19] = ["p", "br", "hr", "em", "strong", "del", "a", "ul", "ol", "li", "code", "pre",
+    "blockquote", "h1", "h2", "h3", "h4", "h5", "h6"];
+
+const ALLOWED_URL_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+// Format der Beschreibung einer Session
+// Sessions, die vor Einführung von Markdown angelegt wurden, sowie importierte Sessions sind Klartext
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DescriptionFormat {
+    Plain,
+    Markdown
+}
+
+impl Default for DescriptionFormat {
+    fn default() -> Self {
+        return DescriptionFormat::Plain;
+    }
+}
+
+fn sanitiser() -> Builder<'static> {
+    let mut builder = Builder::empty();
+    builder.tags(ALLOWED_TAGS.iter().copied().collect())
+        .add_tag_attributes("a", ["href", "title"].iter())
+        .add_tag_attributes("ol", ["start"].iter())
+        .url_schemes(ALLOWED_URL_SCHEMES.iter().copied().collect())
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean_content_tags(["script", "style"].iter().copied().collect::<HashSet<&str>>());
+    return builder;
+}
+
+// Rendert CommonMark nach HTML und lässt das Ergebnis durch die Allow-List laufen
+// Rohes HTML im Markdown wird nicht interpretiert, sondern als Text angezeigt
+pub fn render_markdown(source: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    // Von Bildern bleibt nur der Alt-Text
+    let parser = Parser::new_ext(source, options).filter_map(|event| match event {
+        Event::Html(raw) => Some(Event::Text(raw)),
+        Event::Start(Tag::Image(..)) | Event::End(Tag::Image(..)) => None,
+        event => Some(event)
+    });
+
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, parser);
+    return sanitiser().clean(&rendered).to_string();
+}
+
+// HTML der Beschreibung für die Session-Seite
+// Klartext wird wie bisher nur escaped, Zeilenumbrüche bleiben wie zuvor ohne Wirkung
+pub fn description_html(description: &str, format: DescriptionFormat) -> String {
+    if description.trim().is_empty() {
+        return String::new();
+    }
+    return match format {
+        DescriptionFormat::Plain => format!("<p>{}</p>", escape_html(description)),
+        DescriptionFormat::Markdown => render_markdown(description)
+    };
+}
+
+// Beschreibung ohne Formatierung für Listen und Kacheln
+pub fn description_text(description: &str, format: DescriptionFormat) -> String {
+    if format == DescriptionFormat::Plain {
+        return description.to_string();
+    }
+
+    let mut text = String::new();
+    for event in Parser::new_ext(description, Options::ENABLE_STRIKETHROUGH) {
+        match event {
+            Event::Text(t) | Event::Code(t) | Event::Html(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak | Event::Rule => text.push(' '),
+            Event::End(Tag::Paragraph) | Event::End(Tag::Heading(..)) | Event::End(Tag::Item)
+            | Event::End(Tag::CodeBlock(_)) => text.push(' '),
+            _ => {}
+        }
+    }
+    return text.split_whitespace().collect::<Vec<&str>>().join(" ");
+}
+
+#[derive(FromForm)]
+pub struct PreviewForm<'r> {
+    #[field(validate = len(..=MAX_DESCRIPTION_LENGTH))]
+    description: &'r str
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MarkdownPreview {
+    pub html: String
+}
+
+// Live-Vorschau für die Session-Formulare, nutzt denselben Sanitiser wie die Session-Seite
+#[post("/admin/markdown/preview", data = "<form>")]
+pub async fn preview_markdown(form: Form<PreviewForm<'_>>, cookies: &CookieJar<'_>) -> Result<Json<MarkdownPreview>, Status> {
+    if admin_token(cookies).is_none() {
+        return Err(Status::Unauthorized);
+    }
+    return Ok(Json(MarkdownPreview { html: render_markdown(form.description) }));
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            preview_markdown
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::http::ContentType;
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn test_render_markdown() {
+        assert_eq!(render_markdown("Hallo **Welt**"), "<p>Hallo <strong>Welt</strong></p>\n");
+        assert_eq!(render_markdown("- eins\n- zwei"), "<ul>\n<li>eins</li>\n<li>zwei</li>\n</ul>\n");
+        assert_eq!(render_markdown("`code` und ~~alt~~"), "<p><code>code</code> und <del>alt</del></p>\n");
+        assert_eq!(render_markdown("[Programm](https://example.org/programm)"),
+                   "<p><a href=\"https://example.org/programm\" rel=\"noopener noreferrer nofollow\">Programm</a></p>\n");
+    }
+
+    #[test]
+    fn test_render_markdown_sanitises() {
+        // Rohes HTML wird escaped statt ausgeführt
+        let script = render_markdown("<script>alert(1)</script>");
+        assert!(!script.contains("<script"));
+        assert!(script.contains("&lt;script&gt;"));
+        let inline = render_markdown("Text <img src=x onerror=alert(1)> mehr");
+        assert!(!inline.contains("<img"));
+
+        // Gefährliche Link-Ziele werden entfernt, der Text bleibt
+        let link = render_markdown("[klick](javascript:alert(1))");
+        assert!(!link.contains("javascript"));
+        assert!(link.contains("klick"));
+
+        // Bilder sind nicht erlaubt, der Alt-Text bleibt sichtbar
+        let image = render_markdown("![Logo](https://example.org/logo.png)");
+        assert!(!image.contains("<img"));
+        assert!(image.contains("Logo"));
+    }
+
+    #[test]
+    fn test_plain_descriptions_render_as_before() {
+        assert_eq!(description_html("ein Test zum Streamen", DescriptionFormat::Plain), "<p>ein Test zum Streamen</p>");
+        // Markdown-Zeichen und HTML im Altbestand bleiben unverändert sichtbar
+        assert_eq!(description_html("*Sternchen* & <b>", DescriptionFormat::Plain), "<p>*Sternchen* &amp; &lt;b&gt;</p>");
+        assert_eq!(description_html("  ", DescriptionFormat::Markdown), "");
+        assert_eq!(DescriptionFormat::default(), DescriptionFormat::Plain);
+    }
+
+    #[test]
+    fn test_description_text() {
+        assert_eq!(description_text("# Ablauf\n\nErst **Talk**, dann\n- Fragen\n- Apero", DescriptionFormat::Markdown),
+                   "Ablauf Erst Talk, dann Fragen Apero");
+        assert_eq!(description_text("**bleibt**", DescriptionFormat::Plain), "**bleibt**");
+    }
+
+    #[tokio::test]
+    async fn test_preview_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post("/admin/markdown/preview")
+            .header(ContentType::Form)
+            .body("description=**fett**")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
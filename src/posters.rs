@@ -0,0 +1,493 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::io::Reader;
+use image::{ColorType, DynamicImage, GenericImageView, ImageFormat, RgbImage};
+use mongodb::bson::oid::ObjectId;
+use multer::{Constraints, Multipart, SizeLimit};
+use rocket::data::{ByteUnit, Data, ToByteUnit};
+use rocket::http::{ContentType, CookieJar, Header, Status};
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+
+use crate::access::{publicly_visible, viewable_session, viewer_from_token};
+use crate::attachments::{attachment_config, AttachmentConfig};
+use crate::database::{get_sessions_by_ids, get_standard_database, set_session_poster};
+use crate::security::{admin_token, user_token};
+use crate::sessions::Session;
+
+// Kleinere Bilder werden abgelehnt, damit die Kachel in der Liste nicht hochskaliert werden muss
+const MIN_WIDTH: u32 = 480;
+const MIN_HEIGHT: u32 = 270;
+// Schutz vor Bildern, die erst beim Dekodieren riesig werden
+const MAX_PIXELS: u64 = 40_000_000;
+const JPEG_QUALITY: u8 = 85;
+
+// Größe einer Variante, das Bild wird mittig auf das Seitenverhältnis zugeschnitten und nie vergrößert
+pub struct PosterSize {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    // Zusätzlich als WebP, für og:image reicht JPEG, das alle Crawler lesen können
+    pub webp: bool
+}
+
+pub const POSTER_SIZES: [PosterSize; 3] = [
+    PosterSize { name: "card", width: MIN_WIDTH, height: MIN_HEIGHT, webp: true },
+    PosterSize { name: "page", width: 1280, height: 720, webp: true },
+    PosterSize { name: "og", width: 1200, height: 630, webp: false },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PosterFormat {
+    Jpeg,
+    Webp
+}
+
+impl PosterFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PosterFormat::Jpeg => "jpg",
+            PosterFormat::Webp => "webp"
+        }
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            PosterFormat::Jpeg => ContentType::JPEG,
+            PosterFormat::Webp => ContentType::WEBP
+        }
+    }
+}
+
+// Eine erzeugte Datei, sie liegt neben den Anhängen unter <attachments_dir>/<session id>/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PosterVariant {
+    pub name: String,
+    pub format: PosterFormat,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+    pub path: String
+}
+
+impl PosterVariant {
+    // Dateiname ohne Ordner, Teil der URL
+    pub fn file_name(&self) -> String {
+        return Path::new(&self.path).file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+    }
+}
+
+// Poster einer Session, das hochgeladene Original wird nicht aufbewahrt (u.a. wegen der EXIF-Daten)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Poster {
+    pub id: ObjectId,
+    pub variants: Vec<PosterVariant>,
+    pub uploaded_at: DateTime<Utc>,
+    pub uploaded_by: String
+}
+
+impl Poster {
+    fn variant(&self, name: &str, format: PosterFormat) -> Option<&PosterVariant> {
+        return self.variants.iter().find(|v| v.name == name && v.format == format);
+    }
+}
+
+// Bild für die Templates, WebP wird nur angeboten, wenn es kleiner als das JPEG ist
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TeraPosterImage {
+    pub url: String,
+    pub webp_url: Option<String>,
+    pub width: u32,
+    pub height: u32
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TeraPoster {
+    pub card: Option<TeraPosterImage>,
+    pub page: Option<TeraPosterImage>,
+    pub og: Option<TeraPosterImage>
+}
+
+impl TeraPoster {
+    pub fn from_session(session: &Session) -> Option<TeraPoster> {
+        let poster = session.poster.as_ref()?;
+        let image = |name: &str| -> Option<TeraPosterImage> {
+            let jpeg = poster.variant(name, PosterFormat::Jpeg)?;
+            return Some(TeraPosterImage {
+                url: poster_url(&session.id, jpeg),
+                webp_url: poster.variant(name, PosterFormat::Webp)
+                    .filter(|webp| webp.size < jpeg.size)
+                    .map(|webp| poster_url(&session.id, webp)),
+                width: jpeg.width,
+                height: jpeg.height
+            });
+        };
+        return Some(TeraPoster { card: image("card"), page: image("page"), og: image("og") });
+    }
+}
+
+pub fn poster_url(session_id: &ObjectId, variant: &PosterVariant) -> String {
+    return format!("/session/{}/poster/{}", session_id.to_hex(), variant.file_name());
+}
+
+// Erzeugte Variante vor dem Speichern
+#[derive(Debug)]
+pub struct EncodedPoster {
+    pub name: &'static str,
+    pub format: PosterFormat,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>
+}
+
+// Schneidet mittig auf das Seitenverhältnis von size zu und verkleinert auf höchstens dessen Größe
+fn fill(image: &DynamicImage, size: &PosterSize) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let (crop_width, crop_height) = if width as u64 * size.height as u64 > height as u64 * size.width as u64 {
+        ((height as u64 * size.width as u64 / size.height as u64) as u32, height)
+    } else {
+        (width, (width as u64 * size.height as u64 / size.width as u64) as u32)
+    };
+    let cropped = image.crop_imm((width - crop_width) / 2, (height - crop_height) / 2, crop_width, crop_height);
+    if crop_width <= size.width {
+        return cropped;
+    }
+    return cropped.resize_exact(size.width, size.height, FilterType::CatmullRom);
+}
+
+// JPEG hat keinen Alphakanal, transparente Bereiche werden weiß
+fn flatten(image: &DynamicImage) -> RgbImage {
+    let rgba = image.to_rgba8();
+    return RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    });
+}
+
+// Prüft das hochgeladene Bild und erzeugt alle Varianten
+// Das Format wird an den ersten Bytes erkannt, die Dateiendung spielt keine Rolle
+pub fn process_poster(bytes: &[u8]) -> Result<Vec<EncodedPoster>, String> {
+    let format = match image::guess_format(bytes) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP)) => format,
+        _ => return Err("Das Poster muss ein JPEG-, PNG-, GIF- oder WebP-Bild sein".to_string())
+    };
+
+    // Die Größe steht im Header, so werden zu große Bilder abgelehnt, bevor sie dekodiert werden
+    let (width, height) = Reader::with_format(Cursor::new(bytes), format).into_dimensions()
+        .map_err(|_| "Das Bild konnte nicht gelesen werden".to_string())?;
+    if width < MIN_WIDTH || height < MIN_HEIGHT {
+        return Err(format!("Das Poster muss mindestens {}x{} Pixel groß sein", MIN_WIDTH, MIN_HEIGHT));
+    }
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(format!("Das Poster darf höchstens {} Megapixel haben", MAX_PIXELS / 1_000_000));
+    }
+    let image = image::load_from_memory_with_format(bytes, format)
+        .map_err(|_| "Das Bild konnte nicht gelesen werden".to_string())?;
+
+    let mut encoded = Vec::new();
+    for size in &POSTER_SIZES {
+        let variant = fill(&image, size);
+        let (width, height) = variant.dimensions();
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+            .encode(&flatten(&variant), width, height, ColorType::Rgb8)
+            .map_err(|_| "Das Poster konnte nicht umgewandelt werden".to_string())?;
+        encoded.push(EncodedPoster { name: size.name, format: PosterFormat::Jpeg, width, height, data: jpeg });
+
+        if size.webp {
+            let mut webp = Vec::new();
+            WebPEncoder::new_lossless(&mut webp)
+                .encode(variant.to_rgba8().as_raw(), width, height, ColorType::Rgba8)
+                .map_err(|_| "Das Poster konnte nicht umgewandelt werden".to_string())?;
+            encoded.push(EncodedPoster { name: size.name, format: PosterFormat::Webp, width, height, data: webp });
+        }
+    }
+    return Ok(encoded);
+}
+
+// Dateien eines Posters löschen, Fehler werden ignoriert (die Datei ist dann schon weg)
+pub async fn remove_poster_files(poster: &Poster) {
+    for variant in &poster.variants {
+        let _ = tokio::fs::remove_file(&variant.path).await;
+    }
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PosterResult {
+    pub status: u8,
+    pub poster: Option<TeraPoster>,
+    pub errors: Vec<String>
+}
+
+impl PosterResult {
+    fn failed(error: &str) -> Json<PosterResult> {
+        return Json(PosterResult { status: 0, poster: None, errors: vec![error.to_string()] });
+    }
+}
+
+// Die Dateinamen ändern sich mit jedem Upload, daher dürfen Browser die Bilder dauerhaft cachen
+#[derive(Responder)]
+pub struct PosterFile {
+    file: (ContentType, File),
+    cache: Header<'static>,
+    nosniff: Header<'static>,
+}
+
+async fn find_session(database: &mongodb::Database, id: &ObjectId) -> Result<Option<Session>, Status> {
+    let sessions = get_sessions_by_ids(database, &[*id]).await
+        .map_err(|_| Status::InternalServerError)?;
+    return Ok(sessions.into_iter().next());
+}
+
+// Liest die Datei aus dem Feld "file", größere Dateien als attachment_max_size werden abgelehnt
+async fn read_upload(data: Data<'_>, content_type: &ContentType, max_size: ByteUnit) -> Result<Vec<u8>, String> {
+    let boundary = match content_type.params().find(|(key, _)| key == "boundary") {
+        Some((_, boundary)) if content_type.is_form_data() => boundary.to_string(),
+        _ => return Err("Erwartet wird multipart/form-data".to_string())
+    };
+
+    let stream_limit = max_size.as_u64() + 64 * 1024;
+    let constraints = Constraints::new()
+        .allowed_fields(vec!["file"])
+        .size_limit(SizeLimit::new().per_field(max_size.as_u64()).whole_stream(stream_limit));
+    let mut multipart = Multipart::with_reader_with_constraints(data.open(stream_limit.bytes()), boundary, constraints);
+
+    let field = match multipart.next_field().await {
+        Ok(Some(field)) => field,
+        Ok(None) => return Err("Es wurde keine Datei ausgewählt".to_string()),
+        Err(e) => return Err(format!("Der Upload ist fehlgeschlagen: {}", e))
+    };
+    return match field.bytes().await {
+        Ok(bytes) if bytes.is_empty() => Err("Es wurde keine Datei ausgewählt".to_string()),
+        Ok(bytes) => Ok(bytes.to_vec()),
+        Err(multer::Error::FieldSizeExceeded { .. }) => Err(format!("Die Datei ist größer als {}", max_size)),
+        Err(e) => Err(format!("Der Upload ist fehlgeschlagen: {}", e))
+    };
+}
+
+// Upload eines Posters (multipart/form-data, Feldname "file"), ein vorhandenes Poster wird ersetzt
+#[post("/admin/session/<id>/poster", data = "<data>")]
+pub async fn upload_poster(id: &str, data: Data<'_>, content_type: &ContentType, config: &State<AttachmentConfig>,
+                           cookies: &CookieJar<'_>) -> Json<PosterResult> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return PosterResult::failed("Nur Admins dürfen Poster hochladen")
+    };
+    let session_id = match ObjectId::parse_str(id) {
+        Ok(session_id) => session_id,
+        Err(_) => return PosterResult::failed("Die Session wurde nicht gefunden")
+    };
+    let database = get_standard_database().await;
+    let session = match find_session(&database, &session_id).await {
+        Ok(Some(session)) => session,
+        _ => return PosterResult::failed("Die Session wurde nicht gefunden")
+    };
+
+    let bytes = match read_upload(data, content_type, config.attachment_max_size).await {
+        Ok(bytes) => bytes,
+        Err(e) => return PosterResult::failed(&e)
+    };
+    // Dekodieren und Kodieren blockiert, damit es die anderen Requests nicht aufhält
+    let encoded = match rocket::tokio::task::spawn_blocking(move || process_poster(&bytes)).await {
+        Ok(Ok(encoded)) => encoded,
+        Ok(Err(e)) => return PosterResult::failed(&e),
+        Err(_) => return PosterResult::failed("Das Poster konnte nicht umgewandelt werden")
+    };
+
+    let directory = PathBuf::from(&config.attachments_dir).join(session_id.to_hex());
+    if tokio::fs::create_dir_all(&directory).await.is_err() {
+        return PosterResult::failed("Der Ordner für das Poster konnte nicht angelegt werden");
+    }
+    let mut poster = Poster { id: ObjectId::new(), variants: Vec::new(), uploaded_at: Utc::now(), uploaded_by: token.username.clone() };
+    for variant in encoded {
+        let path = directory.join(format!("poster-{}-{}.{}", poster.id.to_hex(), variant.name, variant.format.extension()));
+        poster.variants.push(PosterVariant {
+            name: variant.name.to_string(),
+            format: variant.format,
+            width: variant.width,
+            height: variant.height,
+            size: variant.data.len() as u64,
+            path: path.to_string_lossy().to_string()
+        });
+        if tokio::fs::write(&path, &variant.data).await.is_err() {
+            remove_poster_files(&poster).await;
+            return PosterResult::failed("Das Poster konnte nicht gespeichert werden");
+        }
+    }
+
+    match set_session_poster(&database, &session_id, Some(&poster)).await {
+        Ok(true) => {
+            if let Some(old) = &session.poster {
+                remove_poster_files(old).await;
+            }
+            let session = Session { poster: Some(poster), ..session };
+            return Json(PosterResult { status: 1, poster: TeraPoster::from_session(&session), errors: vec![] });
+        },
+        _ => {
+            remove_poster_files(&poster).await;
+            return PosterResult::failed("Das Poster konnte nicht gespeichert werden");
+        }
+    }
+}
+
+// Entfernt das Poster samt Dateien
+#[delete("/admin/session/<id>/poster")]
+pub async fn delete_poster(id: &str, cookies: &CookieJar<'_>) -> Json<PosterResult> {
+    if admin_token(cookies).is_none() {
+        return PosterResult::failed("Nur Admins dürfen Poster löschen");
+    }
+    let session_id = match ObjectId::parse_str(id) {
+        Ok(session_id) => session_id,
+        Err(_) => return PosterResult::failed("Die Session wurde nicht gefunden")
+    };
+
+    let database = get_standard_database().await;
+    let poster = match find_session(&database, &session_id).await {
+        Ok(Some(session)) => session.poster,
+        _ => return PosterResult::failed("Die Session wurde nicht gefunden")
+    };
+    let poster = match poster {
+        Some(poster) => poster,
+        None => return PosterResult::failed("Die Session hat kein Poster")
+    };
+    if !matches!(set_session_poster(&database, &session_id, None).await, Ok(true)) {
+        return PosterResult::failed("Das Poster konnte nicht gelöscht werden");
+    }
+    remove_poster_files(&poster).await;
+    return Json(PosterResult { status: 1, poster: None, errors: vec![] });
+}
+
+// Auslieferung einer Variante
+// Poster öffentlicher Sessions gibt es ohne Anmeldung, damit Vorschauen beim Teilen (og:image) funktionieren
+#[get("/session/<id>/poster/<file_name>")]
+pub async fn poster_file(id: &str, file_name: &str, cookies: &CookieJar<'_>) -> Result<PosterFile, Status> {
+    let session_id = ObjectId::parse_str(id).map_err(|_| Status::NotFound)?;
+    let database = get_standard_database().await;
+    let session = find_session(&database, &session_id).await?.ok_or(Status::NotFound)?;
+    let variant = session.poster.as_ref()
+        .and_then(|poster| poster.variants.iter().find(|v| v.file_name() == file_name))
+        .ok_or(Status::NotFound)?;
+
+    let public = publicly_visible(&database, &session).await.map_err(|_| Status::InternalServerError)?;
+    if !public {
+        let token = user_token(cookies).ok_or(Status::Unauthorized)?;
+        let viewer = viewer_from_token(&database, &token).await;
+        if viewable_session(&database, &viewer, &session_id).await.map_err(|_| Status::InternalServerError)?.is_none() {
+            return Err(Status::NotFound);
+        }
+    }
+
+    let file = File::open(&variant.path).await.map_err(|_| Status::NotFound)?;
+    let cache = if public { "public, max-age=31536000, immutable" } else { "private, max-age=31536000, immutable" };
+    return Ok(PosterFile {
+        file: (variant.format.content_type(), file),
+        cache: Header::new("Cache-Control", cache),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+    });
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .attach(attachment_config())
+        .mount("/", routes![
+            upload_poster,
+            delete_poster,
+            poster_file
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use image::{ImageOutputFormat, Rgba, RgbaImage};
+    use rocket::local::asynchronous::Client;
+
+    fn encode_test_image(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, y| Rgba([(x % 256) as u8, (y % 256) as u8, 128, if x < 10 { 0 } else { 255 }]));
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(image).write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+        return bytes;
+    }
+
+    #[test]
+    fn test_process_poster() {
+        let encoded = process_poster(&encode_test_image(1600, 1200, ImageOutputFormat::Png)).unwrap();
+        let sizes: Vec<(&str, PosterFormat, u32, u32)> = encoded.iter().map(|e| (e.name, e.format, e.width, e.height)).collect();
+        assert_eq!(sizes, vec![
+            ("card", PosterFormat::Jpeg, 480, 270), ("card", PosterFormat::Webp, 480, 270),
+            ("page", PosterFormat::Jpeg, 1280, 720), ("page", PosterFormat::Webp, 1280, 720),
+            ("og", PosterFormat::Jpeg, 1200, 630)
+        ]);
+        for variant in &encoded {
+            let format = if variant.format == PosterFormat::Jpeg { ImageFormat::Jpeg } else { ImageFormat::WebP };
+            assert_eq!(image::guess_format(&variant.data).unwrap(), format);
+        }
+        let card = image::load_from_memory_with_format(&encoded[0].data, ImageFormat::Jpeg).unwrap();
+        assert_eq!(card.dimensions(), (480, 270));
+        // WebP ist verlustfrei, das Bild kommt beim Dekodieren unverändert zurück
+        let webp = image::load_from_memory_with_format(&encoded[1].data, ImageFormat::WebP).unwrap();
+        let source = image::load_from_memory(&encode_test_image(1600, 1200, ImageOutputFormat::Png)).unwrap();
+        assert_eq!(webp.to_rgba8(), fill(&source, &POSTER_SIZES[0]).to_rgba8());
+    }
+
+    #[test]
+    fn test_process_poster_small_source() {
+        // Kleine Bilder werden nur zugeschnitten, nicht vergrößert
+        let encoded = process_poster(&encode_test_image(800, 600, ImageOutputFormat::Jpeg(90))).unwrap();
+        let page = encoded.iter().find(|e| e.name == "page").unwrap();
+        assert_eq!((page.width, page.height), (800, 450));
+        let og = encoded.iter().find(|e| e.name == "og").unwrap();
+        assert_eq!((og.width, og.height), (800, 420));
+    }
+
+    #[test]
+    fn test_process_poster_rejects() {
+        assert!(process_poster(b"%PDF-1.4 kein Bild").unwrap_err().contains("JPEG"));
+        assert!(process_poster(&encode_test_image(320, 240, ImageOutputFormat::Png)).unwrap_err().contains("480x270"));
+        // Abgeschnittene Dateien
+        let png = encode_test_image(600, 400, ImageOutputFormat::Png);
+        assert!(process_poster(&png[..png.len() / 2]).unwrap_err().contains("gelesen"));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let transparent = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 0])));
+        assert_eq!(flatten(&transparent).get_pixel(0, 0).0, [255, 255, 255]);
+        let opaque = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 1, Rgba([10, 20, 30, 255])));
+        assert_eq!(flatten(&opaque).get_pixel(1, 0).0, [10, 20, 30]);
+    }
+
+    #[tokio::test]
+    async fn test_poster_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post("/admin/session/62a05c8631a6964f64d829ac/poster")
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", "X")))
+            .body("--X--\r\n")
+            .dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+
+        let response = client.delete("/admin/session/62a05c8631a6964f64d829ac/poster").dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+
+        let response = client.get("/session/kein-objectid/poster/poster-card.jpg").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
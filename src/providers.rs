@@ -0,0 +1,655 @@
+use std::fmt;
+use serde::Serialize;
+use url::Url;
+
+use crate::sessions::{SessionStream, StreamType};
+
+// Art des Players auf der Session-Seite
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerKind {
+    // Fremder Player im iframe (Twitch, Youtube, ...)
+    Iframe,
+    // Eigener <video>-Player für HLS-Manifeste (.m3u8)
+    Hls,
+    // Eigener <video>-Player für DASH-Manifeste (.mpd)
+    Dash,
+}
+
+// Beschreibt wie ein Stream auf der Session-Seite eingebettet wird
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct EmbedDescriptor {
+    pub player: PlayerKind,
+    // Bei iframes die Player-URL, bei HLS/DASH die URL des Manifests
+    pub player_url: String,
+    // Ausweich-Quelle falls das Manifest nicht abgespielt werden kann
+    pub fallback_url: Option<String>,
+    // Domains unter denen der Player eingebettet werden darf (z.B. für Twitch parent=)
+    pub parent_domains: Vec<String>,
+    // Seitenverhältnis als "16 / 9", kann direkt als CSS aspect-ratio genutzt werden
+    pub aspect_ratio: String,
+}
+
+// Fehler beim Erzeugen der Einbettung, enthält eine für Admins lesbare Beschreibung
+#[derive(Debug, PartialEq)]
+pub enum StreamError {
+    Invalid(String),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// Eine Streaming-Plattform, die aus Link und Channel einer Session einen einbettbaren Player erzeugt
+pub trait StreamProvider: Sync {
+    // Typ unter dem die Session gespeichert wird
+    fn stream_type(&self) -> StreamType;
+
+    // Anzeigename in Formularen und Listen
+    fn label(&self) -> &'static str;
+
+    // Liest aus einem vom Admin eingegebenen Link die kanonische Video- bzw. Channel-ID
+    // Daraus wird der channel der Session abgeleitet
+    fn parse_link(&self, link: &str) -> Result<String, StreamError>;
+
+    // Prüft Link und Channel und liefert die Einbettung für den Player
+    fn embed(&self, stream: &SessionStream, parent_domains: &[String]) -> Result<EmbedDescriptor, StreamError>;
+}
+
+const DEFAULT_ASPECT_RATIO: &str = "16 / 9";
+
+fn iframe_embed(player_url: String, parent_domains: &[String]) -> EmbedDescriptor {
+    return EmbedDescriptor {
+        player: PlayerKind::Iframe,
+        player_url,
+        fallback_url: None,
+        parent_domains: parent_domains.to_vec(),
+        aspect_ratio: DEFAULT_ASPECT_RATIO.to_string(),
+    };
+}
+
+fn invalid<T>(message: &str) -> Result<T, StreamError> {
+    return Err(StreamError::Invalid(message.to_string()));
+}
+
+// Prüft ob eine ID nur aus erlaubten Zeichen besteht
+fn is_valid_id(id: &str, max_len: usize, allowed: fn(char) -> bool) -> bool {
+    return !id.is_empty() && id.len() <= max_len && id.chars().all(allowed);
+}
+
+// Nur https-Links mit Host sind als Player-Quelle erlaubt
+fn parse_https_link(link: &str) -> Result<Url, StreamError> {
+    let url = match Url::parse(link.trim()) {
+        Ok(url) => url,
+        Err(_) => return invalid("Der Link ist keine gültige URL.")
+    };
+
+    if url.scheme() != "https" || url.host_str().is_none() {
+        return invalid("Der Link muss mit https:// beginnen.");
+    }
+
+    return Ok(url);
+}
+
+// Parst einen vom Admin eingegebenen Link, fehlt das Schema wird https angenommen
+fn parse_any_link(link: &str) -> Result<Url, StreamError> {
+    let link = link.trim();
+    if link.is_empty() {
+        return invalid("Bitte einen Link angeben.");
+    }
+
+    let url = if link.contains("://") {
+        Url::parse(link)
+    } else {
+        Url::parse(&format!("https://{}", link))
+    };
+
+    match url {
+        Ok(url) if (url.scheme() == "https" || url.scheme() == "http") && url.host_str().is_some() => Ok(url),
+        _ => invalid("Der Link ist keine gültige URL.")
+    }
+}
+
+// Host ohne www. bzw. m. Präfix, damit alle Varianten einer Plattform gleich behandelt werden
+fn base_host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or("").to_lowercase();
+    for prefix in ["www.", "m.", "go."] {
+        if let Some(stripped) = host.strip_prefix(prefix) {
+            return stripped.to_string();
+        }
+    }
+    return host;
+}
+
+fn path_segments(url: &Url) -> Vec<String> {
+    return url.path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default();
+}
+
+fn query_value(url: &Url, key: &str) -> Option<String> {
+    return url.query_pairs()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.to_string());
+}
+
+fn is_twitch_channel(channel: &str) -> bool {
+    return channel.len() >= 4 && is_valid_id(channel, 25, |c| c.is_ascii_alphanumeric() || c == '_');
+}
+
+fn is_youtube_video_id(id: &str) -> bool {
+    return id.len() == 11 && is_valid_id(id, 11, |c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+}
+
+// Pfade auf twitch.tv die keine Channels sind
+const TWITCH_RESERVED_PATHS: [&str; 7] = ["videos", "directory", "p", "settings", "downloads", "jobs", "search"];
+
+pub struct TwitchProvider;
+
+impl StreamProvider for TwitchProvider {
+    fn stream_type(&self) -> StreamType {
+        return StreamType::Twitch;
+    }
+
+    fn label(&self) -> &'static str {
+        return "Twitch";
+    }
+
+    // twitch.tv/<channel> oder player.twitch.tv/?channel=<channel>
+    fn parse_link(&self, link: &str) -> Result<String, StreamError> {
+        let url = parse_any_link(link)?;
+
+        let channel = match base_host(&url).as_str() {
+            "twitch.tv" => path_segments(&url).into_iter().next(),
+            "player.twitch.tv" => query_value(&url, "channel"),
+            _ => return invalid("Der Link muss auf twitch.tv zeigen.")
+        };
+
+        match channel {
+            Some(channel) if is_twitch_channel(&channel)
+                && !TWITCH_RESERVED_PATHS.contains(&channel.to_lowercase().as_str()) => Ok(channel.to_lowercase()),
+            _ => invalid("Der Link enthält keinen gültigen Twitch-Channel (twitch.tv/<channel>).")
+        }
+    }
+
+    fn embed(&self, stream: &SessionStream, parent_domains: &[String]) -> Result<EmbedDescriptor, StreamError> {
+        let channel = stream.channel.trim();
+        if !is_valid_id(channel, 25, |c| c.is_ascii_alphanumeric() || c == '_') {
+            return invalid("Der Twitch-Channel darf nur Buchstaben, Ziffern und _ enthalten.");
+        }
+
+        // Twitch verlangt jede einbettende Domain als parent-Parameter
+        let mut player_url = format!("https://player.twitch.tv/?channel={}", channel.to_lowercase());
+        for domain in parent_domains {
+            player_url.push_str(&format!("&parent={}", domain));
+        }
+
+        return Ok(iframe_embed(player_url, parent_domains));
+    }
+}
+
+pub struct YoutubeProvider;
+
+impl StreamProvider for YoutubeProvider {
+    fn stream_type(&self) -> StreamType {
+        return StreamType::Youtube;
+    }
+
+    fn label(&self) -> &'static str {
+        return "Youtube";
+    }
+
+    // youtube.com/watch?v=<id>, youtu.be/<id>, youtube.com/live|embed|shorts|v/<id>
+    fn parse_link(&self, link: &str) -> Result<String, StreamError> {
+        let url = parse_any_link(link)?;
+        let segments = path_segments(&url);
+
+        let video_id = match base_host(&url).as_str() {
+            "youtu.be" => segments.first().cloned(),
+            "youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+                match segments.first().map(String::as_str) {
+                    Some("watch") => query_value(&url, "v"),
+                    Some("live") | Some("embed") | Some("shorts") | Some("v") => segments.get(1).cloned(),
+                    _ => None
+                }
+            },
+            _ => return invalid("Der Link muss auf youtube.com oder youtu.be zeigen.")
+        };
+
+        match video_id {
+            Some(id) if is_youtube_video_id(&id) => Ok(id),
+            _ => invalid("Der Link enthält keine gültige Youtube Video-ID.")
+        }
+    }
+
+    fn embed(&self, stream: &SessionStream, parent_domains: &[String]) -> Result<EmbedDescriptor, StreamError> {
+        let video_id = stream.channel.trim();
+        if !is_valid_id(video_id, 64, |c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return invalid("Die Youtube Video-ID darf nur Buchstaben, Ziffern, _ und - enthalten.");
+        }
+
+        return Ok(iframe_embed(format!("https://www.youtube-nocookie.com/embed/{}?autoplay=1", video_id), parent_domains));
+    }
+}
+
+pub struct VimeoProvider;
+
+impl StreamProvider for VimeoProvider {
+    fn stream_type(&self) -> StreamType {
+        return StreamType::Vimeo;
+    }
+
+    fn label(&self) -> &'static str {
+        return "Vimeo";
+    }
+
+    // vimeo.com/<id>, vimeo.com/channels/<name>/<id>, vimeo.com/event/<id>, player.vimeo.com/video/<id>
+    fn parse_link(&self, link: &str) -> Result<String, StreamError> {
+        let url = parse_any_link(link)?;
+
+        match base_host(&url).as_str() {
+            "vimeo.com" | "player.vimeo.com" => {},
+            _ => return invalid("Der Link muss auf vimeo.com zeigen.")
+        }
+
+        // Die ID ist das letzte rein numerische Pfadsegment
+        let video_id = path_segments(&url).into_iter()
+            .filter(|segment| is_valid_id(segment, 20, |c| c.is_ascii_digit()))
+            .last();
+
+        match video_id {
+            Some(id) => Ok(id),
+            None => invalid("Der Link enthält keine gültige Vimeo-ID.")
+        }
+    }
+
+    fn embed(&self, stream: &SessionStream, parent_domains: &[String]) -> Result<EmbedDescriptor, StreamError> {
+        let video_id = stream.channel.trim();
+        if !is_valid_id(video_id, 20, |c| c.is_ascii_digit()) {
+            return invalid("Die Vimeo-ID muss aus Ziffern bestehen.");
+        }
+
+        // Live-Events haben eine eigene Einbettung
+        let player_url = if stream.link.contains("/event/") {
+            format!("https://vimeo.com/event/{}/embed", video_id)
+        } else {
+            format!("https://player.vimeo.com/video/{}?autoplay=1", video_id)
+        };
+
+        return Ok(iframe_embed(player_url, parent_domains));
+    }
+}
+
+pub struct PeerTubeProvider;
+
+impl StreamProvider for PeerTubeProvider {
+    fn stream_type(&self) -> StreamType {
+        return StreamType::PeerTube;
+    }
+
+    fn label(&self) -> &'static str {
+        return "PeerTube";
+    }
+
+    // https://<instanz>/w/<id>, /videos/watch/<id> oder /videos/embed/<id>
+    fn parse_link(&self, link: &str) -> Result<String, StreamError> {
+        let url = parse_https_link(link)?;
+        let segments = path_segments(&url);
+
+        let video_id = match segments.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+            ["w", id, ..] => Some(id.to_string()),
+            ["videos", "watch", id, ..] | ["videos", "embed", id, ..] => Some(id.to_string()),
+            _ => None
+        };
+
+        match video_id {
+            Some(id) if is_valid_id(&id, 64, |c| c.is_ascii_alphanumeric() || c == '-') => Ok(id),
+            _ => invalid("Der Link enthält keine gültige PeerTube Video-ID (/w/<id>).")
+        }
+    }
+
+    // PeerTube ist dezentral, die Instanz wird aus dem Link gelesen
+    fn embed(&self, stream: &SessionStream, parent_domains: &[String]) -> Result<EmbedDescriptor, StreamError> {
+        let url = parse_https_link(&stream.link)?;
+        let video_id = stream.channel.trim();
+        if !is_valid_id(video_id, 64, |c| c.is_ascii_alphanumeric() || c == '-') {
+            return invalid("Die PeerTube Video-ID darf nur Buchstaben, Ziffern und - enthalten.");
+        }
+
+        let mut instance = url.host_str().unwrap().to_string();
+        if let Some(port) = url.port() {
+            instance = format!("{}:{}", instance, port);
+        }
+
+        return Ok(iframe_embed(format!("https://{}/videos/embed/{}", instance, video_id), parent_domains));
+    }
+}
+
+// Beliebiger Player, der als iframe eingebettet werden kann
+pub struct IframeProvider;
+
+impl StreamProvider for IframeProvider {
+    fn stream_type(&self) -> StreamType {
+        return StreamType::Iframe;
+    }
+
+    fn label(&self) -> &'static str {
+        return "Iframe";
+    }
+
+    // Beim iframe ist die kanonische Form die vollständige https-URL
+    fn parse_link(&self, link: &str) -> Result<String, StreamError> {
+        return Ok(parse_https_link(link)?.to_string());
+    }
+
+    fn embed(&self, stream: &SessionStream, parent_domains: &[String]) -> Result<EmbedDescriptor, StreamError> {
+        let url = parse_https_link(&stream.link)?;
+
+        return Ok(iframe_embed(url.to_string(), parent_domains));
+    }
+}
+
+// Selbst gehosteter Stream, abgespielt wird direkt das HLS- bzw. DASH-Manifest des eigenen Encoders
+pub struct ManifestProvider;
+
+// Ermittelt anhand der Dateiendung, ob es sich um ein HLS- oder DASH-Manifest handelt
+pub fn manifest_kind(url: &Url) -> Option<PlayerKind> {
+    let path = url.path().to_lowercase();
+    if path.ends_with(".m3u8") {
+        return Some(PlayerKind::Hls);
+    }
+    if path.ends_with(".mpd") {
+        return Some(PlayerKind::Dash);
+    }
+    return None;
+}
+
+fn parse_manifest_link(link: &str) -> Result<(Url, PlayerKind), StreamError> {
+    let url = parse_https_link(link)?;
+    match manifest_kind(&url) {
+        Some(kind) => Ok((url, kind)),
+        None => invalid("Der Link muss auf ein HLS- (.m3u8) oder DASH-Manifest (.mpd) zeigen.")
+    }
+}
+
+// Die Ausweich-Quelle darf ein weiteres Manifest oder eine direkt abspielbare Datei (z.B. .mp4) sein
+pub fn parse_fallback_link(link: &str) -> Result<String, StreamError> {
+    return Ok(parse_https_link(link)?.to_string());
+}
+
+impl StreamProvider for ManifestProvider {
+    fn stream_type(&self) -> StreamType {
+        return StreamType::Manifest;
+    }
+
+    fn label(&self) -> &'static str {
+        return "HLS/DASH";
+    }
+
+    // Die kanonische Form ist die vollständige https-URL des Manifests
+    fn parse_link(&self, link: &str) -> Result<String, StreamError> {
+        return Ok(parse_manifest_link(link)?.0.to_string());
+    }
+
+    fn embed(&self, stream: &SessionStream, parent_domains: &[String]) -> Result<EmbedDescriptor, StreamError> {
+        let (url, player) = parse_manifest_link(&stream.link)?;
+        let fallback_url = match stream.fallback.as_deref().map(str::trim).filter(|f| !f.is_empty()) {
+            Some(fallback) => Some(parse_fallback_link(fallback)?),
+            None => None
+        };
+
+        return Ok(EmbedDescriptor {
+            player,
+            player_url: url.to_string(),
+            fallback_url,
+            parent_domains: parent_domains.to_vec(),
+            aspect_ratio: DEFAULT_ASPECT_RATIO.to_string(),
+        });
+    }
+}
+
+// Alle unterstützten Plattformen, neue Provider werden nur hier registriert
+static PROVIDERS: [&dyn StreamProvider; 6] = [
+    &TwitchProvider,
+    &YoutubeProvider,
+    &VimeoProvider,
+    &PeerTubeProvider,
+    &IframeProvider,
+    &ManifestProvider,
+];
+
+pub fn providers() -> &'static [&'static dyn StreamProvider] {
+    return &PROVIDERS;
+}
+
+pub fn provider_for(stream_type: &StreamType) -> Option<&'static dyn StreamProvider> {
+    return providers().iter()
+        .find(|provider| &provider.stream_type() == stream_type)
+        .copied();
+}
+
+// Wandelt den Formularwert (z.B. "Youtube") in den StreamType, unbekannte Werte ergeben None
+// Akzeptiert wird der Name des StreamType und der Anzeigename
+pub fn stream_type_from_plattform(plattform: &str) -> StreamType {
+    let plattform = plattform.trim();
+    return providers().iter()
+        .find(|provider| provider.label().eq_ignore_ascii_case(plattform)
+            || format!("{:?}", provider.stream_type()).eq_ignore_ascii_case(plattform))
+        .map(|provider| provider.stream_type())
+        .unwrap_or(StreamType::None);
+}
+
+// Auswahlmöglichkeit für die Plattform in den Formularen
+#[derive(Debug, Serialize)]
+pub struct PlattformOption {
+    pub value: String,
+    pub label: &'static str,
+}
+
+pub fn plattform_options() -> Vec<PlattformOption> {
+    let mut options: Vec<PlattformOption> = providers().iter()
+        .map(|provider| PlattformOption {
+            value: format!("{:?}", provider.stream_type()),
+            label: provider.label(),
+        })
+        .collect();
+
+    options.push(PlattformOption {
+        value: "None".to_string(),
+        label: "Kein Stream",
+    });
+
+    return options;
+}
+
+// Liefert die kanonische ID zu einem Link, für Sessions ohne Stream wird nichts geprüft
+pub fn parse_stream_link(stream_type: &StreamType, link: &str) -> Result<String, StreamError> {
+    match provider_for(stream_type) {
+        Some(provider) => provider.parse_link(link),
+        None => Ok(String::new())
+    }
+}
+
+// Ermittelt die Plattform aus dem Link, z.B. beim Import ohne Angabe der Plattform
+// Der generische iframe-Player wird nur gewählt, wenn keine andere Plattform den Link erkennt
+pub fn detect_stream_type(link: &str) -> StreamType {
+    if link.trim().is_empty() {
+        return StreamType::None;
+    }
+    let detected = providers().iter()
+        .filter(|provider| provider.stream_type() != StreamType::Iframe)
+        .find(|provider| provider.parse_link(link).is_ok())
+        .map(|provider| provider.stream_type());
+
+    return match detected {
+        Some(stream_type) => stream_type,
+        None if IframeProvider.parse_link(link).is_ok() => StreamType::Iframe,
+        None => StreamType::None
+    };
+}
+
+// Feld-Validierung für Formulare, der Link wird passend zur gewählten Plattform geprüft
+pub fn valid_stream_link<'v>(link: &str, plattform: &str) -> rocket::form::Result<'v, ()> {
+    match parse_stream_link(&stream_type_from_plattform(plattform), link) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(rocket::form::Error::validation(e.to_string()).into())
+    }
+}
+
+// Feld-Validierung für die optionale Ausweich-Quelle
+pub fn valid_fallback_link<'v>(fallback: &Option<&str>) -> rocket::form::Result<'v, ()> {
+    match fallback.map(str::trim).filter(|f| !f.is_empty()) {
+        Some(link) => match parse_fallback_link(link) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(rocket::form::Error::validation(e.to_string()).into())
+        },
+        None => Ok(())
+    }
+}
+
+// Erzeugt die Einbettung für einen Stream, None wenn kein Stream hinterlegt ist
+pub fn embed_stream(stream: &SessionStream, parent_domains: &[String]) -> Option<Result<EmbedDescriptor, StreamError>> {
+    return provider_for(&stream.stream_type)
+        .map(|provider| provider.embed(stream, parent_domains));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(stream_type: StreamType, link: &str, channel: &str) -> SessionStream {
+        return SessionStream {
+            link: link.to_string(),
+            channel: channel.to_string(),
+            stream_type,
+            fallback: None
+        };
+    }
+
+    #[test]
+    fn test_detect_stream_type() {
+        assert_eq!(detect_stream_type("https://www.twitch.tv/primeleague"), StreamType::Twitch);
+        assert_eq!(detect_stream_type("https://youtu.be/dQw4w9WgXcQ"), StreamType::Youtube);
+        assert_eq!(detect_stream_type("https://vimeo.com/76979871"), StreamType::Vimeo);
+        assert_eq!(detect_stream_type("https://live.example.com/hls/stream.m3u8"), StreamType::Manifest);
+        assert_eq!(detect_stream_type("https://player.example.com/embed/1"), StreamType::Iframe);
+        assert_eq!(detect_stream_type(""), StreamType::None);
+    }
+
+    #[test]
+    fn test_twitch_embed_with_parents() {
+        let parents = vec!["streamie.live".to_string(), "localhost".to_string()];
+        let embed = embed_stream(&stream(StreamType::Twitch, "https://www.twitch.tv/primeleague", "PrimeLeague"), &parents)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(embed.player_url, "https://player.twitch.tv/?channel=primeleague&parent=streamie.live&parent=localhost");
+        assert_eq!(embed.parent_domains, parents);
+    }
+
+    #[test]
+    fn test_vimeo_and_peertube_embed() {
+        let vimeo = embed_stream(&stream(StreamType::Vimeo, "https://vimeo.com/76979871", "76979871"), &[]).unwrap();
+        assert_eq!(vimeo.unwrap().player_url, "https://player.vimeo.com/video/76979871?autoplay=1");
+
+        let event = embed_stream(&stream(StreamType::Vimeo, "https://vimeo.com/event/12345", "12345"), &[]).unwrap();
+        assert_eq!(event.unwrap().player_url, "https://vimeo.com/event/12345/embed");
+
+        let peertube = embed_stream(&stream(StreamType::PeerTube, "https://framatube.org/w/9c9de5e8-0a1e-484a", "9c9de5e8-0a1e-484a"), &[]).unwrap();
+        assert_eq!(peertube.unwrap().player_url, "https://framatube.org/videos/embed/9c9de5e8-0a1e-484a");
+    }
+
+    #[test]
+    fn test_invalid_streams_are_rejected() {
+        assert!(embed_stream(&stream(StreamType::Vimeo, "", "abc"), &[]).unwrap().is_err());
+        assert!(embed_stream(&stream(StreamType::Twitch, "", "no spaces"), &[]).unwrap().is_err());
+        assert!(embed_stream(&stream(StreamType::Iframe, "http://example.com/player", ""), &[]).unwrap().is_err());
+        assert!(embed_stream(&stream(StreamType::None, "", ""), &[]).is_none());
+    }
+
+    #[test]
+    fn test_parse_stream_links() {
+        // (Plattform, Link, erwartete ID oder None wenn der Link abgelehnt werden soll)
+        let cases: Vec<(StreamType, &str, Option<&str>)> = vec![
+            (StreamType::Youtube, "https://www.youtube.com/watch?v=dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://www.youtube.com/watch?feature=share&v=dQw4w9WgXcQ&t=42", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://m.youtube.com/watch?v=dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "youtube.com/watch?v=dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://youtu.be/dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://youtu.be/dQw4w9WgXcQ?si=abc", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://www.youtube.com/live/dQw4w9WgXcQ?feature=share", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://www.youtube.com/embed/dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://www.youtube.com/shorts/dQw4w9WgXcQ", Some("dQw4w9WgXcQ")),
+            (StreamType::Youtube, "https://www.youtube.com/watch?v=tooshort", None),
+            (StreamType::Youtube, "https://www.youtube.com/watch", None),
+            (StreamType::Youtube, "https://www.youtube.com/@channel", None),
+            (StreamType::Youtube, "https://vimeo.com/76979871", None),
+            (StreamType::Youtube, "", None),
+            (StreamType::Twitch, "https://www.twitch.tv/PrimeLeague", Some("primeleague")),
+            (StreamType::Twitch, "twitch.tv/primeleague/videos", Some("primeleague")),
+            (StreamType::Twitch, "https://m.twitch.tv/primeleague", Some("primeleague")),
+            (StreamType::Twitch, "https://player.twitch.tv/?channel=primeleague&parent=streamie.live", Some("primeleague")),
+            (StreamType::Twitch, "https://www.twitch.tv/directory", None),
+            (StreamType::Twitch, "https://www.twitch.tv/", None),
+            (StreamType::Twitch, "https://www.twitch.tv/abc", None),
+            (StreamType::Twitch, "https://www.youtube.com/primeleague", None),
+            (StreamType::Vimeo, "https://vimeo.com/76979871", Some("76979871")),
+            (StreamType::Vimeo, "https://vimeo.com/channels/staffpicks/76979871", Some("76979871")),
+            (StreamType::Vimeo, "https://player.vimeo.com/video/76979871?h=abc", Some("76979871")),
+            (StreamType::Vimeo, "https://vimeo.com/event/12345/embed", Some("12345")),
+            (StreamType::Vimeo, "https://vimeo.com/staffpicks", None),
+            (StreamType::PeerTube, "https://framatube.org/w/kkGMgK9ZtnKfYAgnEtQxbv", Some("kkGMgK9ZtnKfYAgnEtQxbv")),
+            (StreamType::PeerTube, "https://framatube.org/videos/watch/9c9de5e8-0a1e-484a-b099-e80766180a6d", Some("9c9de5e8-0a1e-484a-b099-e80766180a6d")),
+            (StreamType::PeerTube, "http://framatube.org/w/kkGMgK9ZtnKfYAgnEtQxbv", None),
+            (StreamType::PeerTube, "https://framatube.org/about", None),
+            (StreamType::Iframe, "https://player.example.com/live?id=1", Some("https://player.example.com/live?id=1")),
+            (StreamType::Iframe, "http://player.example.com/live", None),
+            (StreamType::Manifest, "https://live.example.com/hls/stream.m3u8", Some("https://live.example.com/hls/stream.m3u8")),
+            (StreamType::Manifest, "https://live.example.com/dash/stream.mpd?token=1", Some("https://live.example.com/dash/stream.mpd?token=1")),
+            (StreamType::Manifest, "https://live.example.com/hls/stream.mp4", None),
+            (StreamType::Manifest, "http://live.example.com/hls/stream.m3u8", None),
+            (StreamType::None, "", Some("")),
+        ];
+
+        for (stream_type, link, expected) in cases {
+            let result = parse_stream_link(&stream_type, link);
+            match expected {
+                Some(id) => assert_eq!(result, Ok(id.to_string()), "{:?} {}", stream_type, link),
+                None => assert!(result.is_err(), "{:?} {} should be rejected", stream_type, link)
+            }
+        }
+    }
+
+    #[test]
+    fn test_manifest_embed_with_fallback() {
+        let mut hls = stream(StreamType::Manifest, "https://live.example.com/hls/stream.m3u8", "");
+        hls.fallback = Some("https://cdn.example.com/pause.mp4".to_string());
+        let embed = embed_stream(&hls, &[]).unwrap().unwrap();
+        assert_eq!(embed.player, PlayerKind::Hls);
+        assert_eq!(embed.player_url, "https://live.example.com/hls/stream.m3u8");
+        assert_eq!(embed.fallback_url, Some("https://cdn.example.com/pause.mp4".to_string()));
+
+        let dash = stream(StreamType::Manifest, "https://live.example.com/dash/stream.mpd", "");
+        let embed = embed_stream(&dash, &[]).unwrap().unwrap();
+        assert_eq!(embed.player, PlayerKind::Dash);
+        assert_eq!(embed.fallback_url, None);
+
+        hls.fallback = Some("javascript:alert(1)".to_string());
+        assert!(embed_stream(&hls, &[]).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_stream_type_from_plattform() {
+        assert_eq!(stream_type_from_plattform("youtube"), StreamType::Youtube);
+        assert_eq!(stream_type_from_plattform("Vimeo"), StreamType::Vimeo);
+        assert_eq!(stream_type_from_plattform("Manifest"), StreamType::Manifest);
+        assert_eq!(stream_type_from_plattform("HLS/DASH"), StreamType::Manifest);
+        assert_eq!(stream_type_from_plattform("None"), StreamType::None);
+        assert_eq!(stream_type_from_plattform("Unbekannt"), StreamType::None);
+    }
+}
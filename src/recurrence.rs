@@ -0,0 +1,410 @@
+use std::fmt;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+// Obergrenze an Perioden, die beim Berechnen durchlaufen werden
+// Verhindert Endlosschleifen bei Regeln, die nie zutreffen (z.B. jährlich am 30. Februar)
+const MAX_PERIODS: u32 = 10_000;
+
+// Termine nach dem Jahr 9999 werden nicht berechnet, ein großes INTERVAL würde sonst den Datumsbereich von chrono sprengen
+const MAX_YEAR: i64 = 9999;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+// Unterstützte Teilmenge einer RRULE nach RFC 5545 (3.3.10)
+// FREQ, INTERVAL, COUNT, UNTIL, BYDAY (auch mit Ordnungszahl wie 1MO oder -1FR), BYMONTHDAY und BYMONTH
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<DateTime<Utc>>,
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+}
+
+fn parse_weekday(value: &str) -> Option<Weekday> {
+    return match value {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None
+    };
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    return match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    };
+}
+
+// UNTIL als UTC-Zeit (20221231T235959Z) oder als Datum (20221231, gilt bis Tagesende)
+fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms(23, 59, 59)));
+    }
+    return NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")
+        .map(|date| Utc.from_utc_datetime(&date))
+        .map_err(|_| format!("Ungültiges UNTIL '{}'", value));
+}
+
+fn parse_list<T>(value: &str, part: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Vec<T>, String> {
+    return value.split(',')
+        .map(|item| parse(item.trim()).ok_or_else(|| format!("Ungültiger Wert '{}' in {}", item, part)))
+        .collect();
+}
+
+impl RecurrenceRule {
+    // Liest eine Regel wie "FREQ=WEEKLY;BYDAY=MO,WE;COUNT=10", das Präfix "RRULE:" ist optional
+    pub fn parse(value: &str) -> Result<RecurrenceRule, String> {
+        let value = value.trim();
+        let value = value.strip_prefix("RRULE:").unwrap_or(value);
+
+        let mut frequency = None;
+        let mut rule = RecurrenceRule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+        };
+
+        for part in value.split(';').filter(|part| !part.trim().is_empty()) {
+            let (key, value) = part.split_once('=')
+                .ok_or_else(|| format!("Ungültiger Teil '{}'", part))?;
+            let value = value.trim().to_uppercase();
+            match key.trim().to_uppercase().as_str() {
+                "FREQ" => frequency = Some(match value.as_str() {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return Err(format!("FREQ={} wird nicht unterstützt", value))
+                }),
+                "INTERVAL" => rule.interval = value.parse().ok().filter(|i| *i > 0)
+                    .ok_or_else(|| format!("Ungültiges INTERVAL '{}'", value))?,
+                "COUNT" => rule.count = Some(value.parse().ok().filter(|c| *c > 0)
+                    .ok_or_else(|| format!("Ungültiges COUNT '{}'", value))?),
+                "UNTIL" => rule.until = Some(parse_until(&value)?),
+                "BYDAY" => rule.by_day = parse_list(&value, "BYDAY", |item| {
+                    // Bei Umlauten o.ä. liegt split mitten in einem Zeichen, dann ist der Eintrag ungültig
+                    let split = item.len().checked_sub(2)?;
+                    let weekday = parse_weekday(item.get(split..)?)?;
+                    let ordinal = match item.get(..split)? {
+                        "" => None,
+                        ordinal => Some(ordinal.trim_start_matches('+').parse::<i32>().ok()
+                            .filter(|o| *o != 0 && o.abs() <= 5)?)
+                    };
+                    return Some((ordinal, weekday));
+                })?,
+                "BYMONTHDAY" => rule.by_month_day = parse_list(&value, "BYMONTHDAY", |item| {
+                    item.parse::<i32>().ok().filter(|d| *d != 0 && d.abs() <= 31)
+                })?,
+                "BYMONTH" => rule.by_month = parse_list(&value, "BYMONTH", |item| {
+                    item.parse::<u32>().ok().filter(|m| (1..=12).contains(m))
+                })?,
+                // Wochenbeginn ist immer Montag
+                "WKST" => {},
+                other => return Err(format!("{} wird nicht unterstützt", other))
+            }
+        }
+
+        rule.frequency = frequency.ok_or_else(|| "FREQ fehlt".to_string())?;
+        if rule.count.is_some() && rule.until.is_some() {
+            return Err("COUNT und UNTIL dürfen nicht zusammen angegeben werden".to_string());
+        }
+        if rule.frequency != Frequency::Monthly && rule.frequency != Frequency::Yearly
+            && rule.by_day.iter().any(|(ordinal, _)| ordinal.is_some()) {
+            return Err("BYDAY mit Ordnungszahl ist nur monatlich oder jährlich möglich".to_string());
+        }
+        return Ok(rule);
+    }
+
+    // Alle Tage eines Monats, die zu BYMONTHDAY bzw. BYDAY passen, ohne Angabe der Tag des ersten Termins
+    fn days_in_month(&self, year: i32, month: u32, start: NaiveDate) -> Vec<NaiveDate> {
+        let first = match NaiveDate::from_ymd_opt(year, month, 1) {
+            Some(first) => first,
+            None => return Vec::new()
+        };
+        let last = (1..=31).rev()
+            .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+            .unwrap_or(first);
+        let all_days: Vec<NaiveDate> = first.iter_days().take_while(|day| *day <= last).collect();
+
+        if self.by_month_day.is_empty() && self.by_day.is_empty() {
+            return NaiveDate::from_ymd_opt(year, month, start.day()).into_iter().collect();
+        }
+
+        return all_days.iter().copied().filter(|day| {
+            let month_day_matches = self.by_month_day.is_empty() || self.by_month_day.iter().any(|d| {
+                if *d > 0 { day.day() as i32 == *d } else { last.day() as i32 + d + 1 == day.day() as i32 }
+            });
+            let weekday_matches = self.by_day.is_empty() || self.by_day.iter().any(|(ordinal, weekday)| {
+                if day.weekday() != *weekday {
+                    return false;
+                }
+                match ordinal {
+                    None => true,
+                    Some(n) if *n > 0 => (day.day() as i32 - 1) / 7 + 1 == *n,
+                    Some(n) => (last.day() as i32 - day.day() as i32) / 7 + 1 == -n
+                }
+            });
+            return month_day_matches && weekday_matches;
+        }).collect();
+    }
+
+    // Kandidaten einer Periode (Tag, Woche, Monat oder Jahr), aufsteigend sortiert
+    // None, wenn die Periode hinter MAX_YEAR liegt, danach gibt es keine weiteren Termine
+    fn period_days(&self, start: NaiveDate, period: u32) -> Option<Vec<NaiveDate>> {
+        let step = (period as i64).checked_mul(self.interval as i64)?;
+        let mut days = match self.frequency {
+            Frequency::Daily => vec![add_days(start, step)?],
+            Frequency::Weekly => {
+                let monday = add_days(start, step.checked_mul(7)? - start.weekday().num_days_from_monday() as i64)?;
+                let week: Vec<NaiveDate> = (0..7).map(|offset| monday + Duration::days(offset)).collect();
+                if self.by_day.is_empty() {
+                    week.into_iter().filter(|day| day.weekday() == start.weekday()).collect()
+                } else {
+                    week.into_iter().filter(|day| self.by_day.iter().any(|(_, weekday)| *weekday == day.weekday())).collect()
+                }
+            },
+            Frequency::Monthly => {
+                let months = (start.year() as i64 * 12 + start.month0() as i64).checked_add(step)?;
+                if months / 12 > MAX_YEAR {
+                    return None;
+                }
+                self.days_in_month((months / 12) as i32, (months % 12) as u32 + 1, start)
+            },
+            Frequency::Yearly => {
+                let year = (start.year() as i64).checked_add(step)?;
+                if year > MAX_YEAR {
+                    return None;
+                }
+                let year = year as i32;
+                let months = if self.by_month.is_empty() { vec![start.month()] } else { self.by_month.clone() };
+                months.iter().flat_map(|month| self.days_in_month(year, *month, start)).collect()
+            }
+        };
+
+        // Einschränkungen, die bei täglichen Regeln filtern
+        if self.frequency == Frequency::Daily {
+            days.retain(|day| self.by_day.is_empty() || self.by_day.iter().any(|(_, weekday)| *weekday == day.weekday()));
+            days.retain(|day| self.by_month_day.is_empty() || self.by_month_day.contains(&(day.day() as i32)));
+        }
+        if self.frequency != Frequency::Yearly {
+            days.retain(|day| self.by_month.is_empty() || self.by_month.contains(&day.month()));
+        }
+        days.sort();
+        return Some(days);
+    }
+
+    // Berechnet die Startzeiten aller Termine bis einschließlich limit
+    // Gerechnet wird in der Zeitzone der Serie, damit z.B. 18:00 Uhr auch nach der Zeitumstellung 18:00 Uhr bleibt
+    // Der erste Termin ist immer start (RFC 5545), EXDATEs zählen bei COUNT mit, werden aber nicht ausgegeben
+    pub fn occurrences(&self, start: DateTime<Utc>, timezone: Tz, exdates: &[DateTime<Utc>], limit: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let local_start = start.with_timezone(&timezone).naive_local();
+        let mut occurrences = Vec::new();
+        let mut count = 0;
+
+        'periods: for period in 0..MAX_PERIODS {
+            let days = match self.period_days(local_start.date(), period) {
+                Some(days) => days,
+                None => break
+            };
+            let mut candidates: Vec<DateTime<Utc>> = days.into_iter()
+                .map(|day| to_utc(timezone, day.and_time(local_start.time())))
+                .filter(|candidate| *candidate > start)
+                .collect();
+            if period == 0 {
+                candidates.insert(0, start);
+            }
+
+            for candidate in candidates {
+                if candidate > limit || self.until.map_or(false, |until| candidate > until)
+                    || self.count.map_or(false, |max| count >= max) {
+                    break 'periods;
+                }
+                count += 1;
+                if !exdates.contains(&candidate) {
+                    occurrences.push(candidate);
+                }
+            }
+        }
+
+        return occurrences;
+    }
+}
+
+// Lokale Zeit in UTC, in der Lücke der Zeitumstellung wird die Zeit um eine Stunde verschoben
+// Wie date + days, aber ohne Panik, wenn das Ergebnis hinter MAX_YEAR liegt
+fn add_days(date: NaiveDate, days: i64) -> Option<NaiveDate> {
+    if days > (MAX_YEAR + 1) * 366 {
+        return None;
+    }
+    return date.checked_add_signed(Duration::days(days)).filter(|day| day.year() as i64 <= MAX_YEAR);
+}
+
+fn to_utc(timezone: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    return timezone.from_local_datetime(&local).earliest()
+        .or_else(|| timezone.from_local_datetime(&(local + Duration::hours(1))).earliest())
+        .map(|date| date.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local));
+}
+
+impl fmt::Display for RecurrenceRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", frequency)?;
+        if self.interval > 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"))?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self.by_day.iter()
+                .map(|(ordinal, weekday)| format!("{}{}", ordinal.map(|o| o.to_string()).unwrap_or_default(), weekday_code(*weekday)))
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(|d| d.to_string()).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if !self.by_month.is_empty() {
+            let months: Vec<String> = self.by_month.iter().map(|m| m.to_string()).collect();
+            write!(f, ";BYMONTH={}", months.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+// Feld-Validierung für Formulare
+pub fn valid_rrule<'v>(value: &str) -> rocket::form::Result<'v, ()> {
+    match RecurrenceRule::parse(value) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(rocket::form::Error::validation(e).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn berlin() -> Tz {
+        return "Europe/Berlin".parse().unwrap();
+    }
+
+    fn far_future() -> DateTime<Utc> {
+        return Utc.ymd(2030, 1, 1).and_hms(0, 0, 0);
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let rule = RecurrenceRule::parse("RRULE:FREQ=MONTHLY;INTERVAL=2;BYDAY=-1FR,1MO;COUNT=5").unwrap();
+        assert_eq!(rule.frequency, Frequency::Monthly);
+        assert_eq!(rule.by_day, vec![(Some(-1), Weekday::Fri), (Some(1), Weekday::Mon)]);
+        assert_eq!(rule.to_string(), "FREQ=MONTHLY;INTERVAL=2;COUNT=5;BYDAY=-1FR,1MO");
+
+        assert!(RecurrenceRule::parse("INTERVAL=2").is_err());
+        assert!(RecurrenceRule::parse("FREQ=HOURLY").is_err());
+        assert!(RecurrenceRule::parse("FREQ=WEEKLY;BYSETPOS=1").is_err());
+        assert!(RecurrenceRule::parse("FREQ=WEEKLY;BYDAY=1MO").is_err());
+        assert!(RecurrenceRule::parse("FREQ=WEEKLY;BYDAY=ÖX").is_err());
+        assert!(RecurrenceRule::parse("FREQ=MONTHLY;BYDAY=1MÖ,2€").is_err());
+        assert!(RecurrenceRule::parse("FREQ=DAILY;COUNT=2;UNTIL=20221231").is_err());
+    }
+
+    #[test]
+    fn test_weekly_keeps_local_time_across_dst() {
+        // Donnerstags 18:00 Uhr in Berlin, vor und nach dem Ende der Sommerzeit
+        let rule = RecurrenceRule::parse("FREQ=WEEKLY;COUNT=3").unwrap();
+        let occurrences = rule.occurrences(Utc.ymd(2022, 10, 20).and_hms(16, 0, 0), berlin(), &[], far_future());
+        assert_eq!(occurrences, vec![
+            Utc.ymd(2022, 10, 20).and_hms(16, 0, 0),
+            Utc.ymd(2022, 10, 27).and_hms(16, 0, 0),
+            Utc.ymd(2022, 11, 3).and_hms(17, 0, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_byday_exdates_and_until() {
+        // Montag bis Freitag, ein Feiertag fällt aus
+        let rule = RecurrenceRule::parse("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR;UNTIL=20220715").unwrap();
+        let holiday = Utc.ymd(2022, 7, 12).and_hms(7, 0, 0);
+        let occurrences = rule.occurrences(Utc.ymd(2022, 7, 8).and_hms(7, 0, 0), berlin(), &[holiday], far_future());
+        let days: Vec<u32> = occurrences.iter().map(|o| o.day()).collect();
+        assert_eq!(days, vec![8, 11, 13, 14, 15]);
+
+        // Mehrere Tage pro Woche
+        let rule = RecurrenceRule::parse("FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH;COUNT=4").unwrap();
+        let occurrences = rule.occurrences(Utc.ymd(2022, 7, 5).and_hms(7, 0, 0), berlin(), &[], far_future());
+        let days: Vec<u32> = occurrences.iter().map(|o| o.day()).collect();
+        assert_eq!(days, vec![5, 7, 19, 21]);
+    }
+
+    #[test]
+    fn test_monthly_and_yearly() {
+        // Letzter Freitag im Monat
+        let rule = RecurrenceRule::parse("FREQ=MONTHLY;BYDAY=-1FR;COUNT=3").unwrap();
+        let occurrences = rule.occurrences(Utc.ymd(2022, 7, 29).and_hms(16, 0, 0), berlin(), &[], far_future());
+        let days: Vec<(u32, u32)> = occurrences.iter().map(|o| (o.month(), o.day())).collect();
+        assert_eq!(days, vec![(7, 29), (8, 26), (9, 30)]);
+
+        // Den 31. gibt es nicht in jedem Monat, diese Monate entfallen
+        let rule = RecurrenceRule::parse("FREQ=MONTHLY;COUNT=3").unwrap();
+        let occurrences = rule.occurrences(Utc.ymd(2022, 7, 31).and_hms(16, 0, 0), berlin(), &[], far_future());
+        let days: Vec<(u32, u32)> = occurrences.iter().map(|o| (o.month(), o.day())).collect();
+        assert_eq!(days, vec![(7, 31), (8, 31), (10, 31)]);
+
+        // Regel ohne Treffer endet über die Obergrenze der Perioden
+        let rule = RecurrenceRule::parse("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30").unwrap();
+        let occurrences = rule.occurrences(Utc.ymd(2022, 1, 1).and_hms(16, 0, 0), berlin(), &[], far_future());
+        assert_eq!(occurrences.len(), 1);
+
+        // Ohne COUNT/UNTIL wird nur bis zum Limit gerechnet
+        let rule = RecurrenceRule::parse("FREQ=DAILY").unwrap();
+        let occurrences = rule.occurrences(Utc.ymd(2022, 7, 1).and_hms(7, 0, 0), berlin(), &[], Utc.ymd(2022, 7, 10).and_hms(0, 0, 0));
+        assert_eq!(occurrences.len(), 9);
+    }
+
+    #[test]
+    fn test_large_interval() {
+        // Liegt die nächste Periode außerhalb des Datumsbereichs, bleibt nur der Starttermin
+        for frequency in ["DAILY", "WEEKLY", "MONTHLY", "YEARLY"] {
+            for interval in ["100000000", "4294967295"] {
+                let rule = RecurrenceRule::parse(&format!("FREQ={};INTERVAL={}", frequency, interval)).unwrap();
+                let occurrences = rule.occurrences(Utc.ymd(2022, 7, 1).and_hms(7, 0, 0), berlin(), &[], far_future());
+                assert_eq!(occurrences.len(), 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket::fairing::AdHoc;
+use rocket::form::Form;
+use rocket::http::{ContentType, CookieJar, Header, Status};
+use rocket::serde::json::Json;
+use rocket::tokio::sync::Mutex;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::access::{accessible_session, viewer_from_token, Viewer};
+use crate::database::{add_registration, get_registration, get_session_registrations, get_standard_database,
+                      get_user_by_username, remove_registration, set_registration_settings, set_registration_status,
+                      DatabaseError, DatabaseResult};
+use crate::security::{user_token, SecurityRole, SecurityToken};
+use crate::sessions::Session;
+
+// Anmeldung an einer Session, ohne capacity ist die Zahl der Plätze unbegrenzt
+// registered_only: Seite, Chat, Stream und Anhänge nur für bestätigte Teilnehmer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RegistrationSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub capacity: Option<u32>,
+    #[serde(default)]
+    pub registered_only: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationStatus {
+    Confirmed,
+    Waitlisted
+}
+
+impl RegistrationStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RegistrationStatus::Confirmed => "Bestätigt",
+            RegistrationStatus::Waitlisted => "Warteliste",
+        }
+    }
+}
+
+// Eine Anmeldung je User und Session (unique Index), die Reihenfolge ergibt sich aus registered_at
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub session_id: ObjectId,
+    pub username: String,
+    pub fullname: String,
+    pub status: RegistrationStatus,
+    pub registered_at: DateTime<Utc>,
+    // Zeitpunkt, an dem der User von der Warteliste nachgerückt ist
+    #[serde(default)]
+    pub promoted_at: Option<DateTime<Utc>>
+}
+
+// Anmeldungen und Nachrücken laufen nacheinander, damit die Plätze nicht doppelt vergeben werden
+// Die Anwendung läuft als ein Prozess (siehe auch der Chat über den broadcast Channel)
+#[derive(Default)]
+pub struct RegistrationLock(Mutex<()>);
+
+pub fn registration_fairing() -> AdHoc {
+    return AdHoc::on_ignite("Session Registration", |rocket| async {
+        rocket.manage(RegistrationLock::default())
+    });
+}
+
+// Stand der Anmeldung für die Session-Seite, locked verbirgt Stream, Chat und Anhänge
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RegistrationInfo {
+    pub enabled: bool,
+    pub capacity: Option<u32>,
+    pub registered_only: bool,
+    pub confirmed: usize,
+    pub waitlisted: usize,
+    pub own_status: Option<RegistrationStatus>,
+    // Platz auf der Warteliste, beginnend bei 1
+    pub position: Option<usize>,
+    pub locked: bool
+}
+
+impl RegistrationInfo {
+    pub fn new(settings: &RegistrationSettings, registrations: &[Registration], viewer: &Viewer) -> RegistrationInfo {
+        let waitlist: Vec<&Registration> = registrations.iter()
+            .filter(|r| r.status == RegistrationStatus::Waitlisted)
+            .collect();
+        let own = registrations.iter().find(|r| r.username == viewer.username);
+        let confirmed = own.map_or(false, |r| r.status == RegistrationStatus::Confirmed);
+
+        return RegistrationInfo {
+            enabled: settings.enabled,
+            capacity: settings.capacity,
+            registered_only: settings.registered_only,
+            confirmed: registrations.len() - waitlist.len(),
+            waitlisted: waitlist.len(),
+            own_status: own.map(|r| r.status),
+            position: waitlist.iter().position(|r| r.username == viewer.username).map(|p| p + 1),
+            locked: requires_registration(settings, viewer) && !confirmed
+        };
+    }
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RegistrationResult {
+    pub status: u8,
+    pub info: Option<RegistrationInfo>
+}
+
+impl RegistrationResult {
+    fn failed() -> Json<RegistrationResult> {
+        return Json(RegistrationResult { status: 0, info: None });
+    }
+}
+
+// Admins werden nie ausgesperrt, sonst könnten sie die Session nicht betreuen
+pub fn requires_registration(settings: &RegistrationSettings, viewer: &Viewer) -> bool {
+    return settings.enabled && settings.registered_only && !viewer.is_admin();
+}
+
+// Status einer neuen Anmeldung, solange Plätze frei sind wird sie bestätigt
+pub fn initial_status(capacity: Option<u32>, confirmed: usize) -> RegistrationStatus {
+    match capacity {
+        Some(capacity) if confirmed >= capacity as usize => RegistrationStatus::Waitlisted,
+        _ => RegistrationStatus::Confirmed
+    }
+}
+
+// Wer von der Warteliste nachrückt, in der Reihenfolge der Anmeldung
+// registrations muss nach registered_at sortiert sein
+pub fn promotions(capacity: Option<u32>, registrations: &[Registration]) -> Vec<ObjectId> {
+    let confirmed = registrations.iter().filter(|r| r.status == RegistrationStatus::Confirmed).count();
+    let free = match capacity {
+        Some(capacity) => (capacity as usize).saturating_sub(confirmed),
+        None => usize::MAX
+    };
+
+    return registrations.iter()
+        .filter(|r| r.status == RegistrationStatus::Waitlisted)
+        .take(free)
+        .map(|r| r.id)
+        .collect();
+}
+
+// Füllt freie Plätze mit der Warteliste auf, muss unter dem RegistrationLock laufen
+async fn promote_waitlist(database: &mongodb::Database, session: &Session) -> DatabaseResult<()> {
+    let registrations = get_session_registrations(database, &session.id).await?;
+    for id in promotions(session.registration.capacity, &registrations) {
+        set_registration_status(database, &id, RegistrationStatus::Confirmed, Some(Utc::now())).await?;
+    }
+    Ok(())
+}
+
+// Darf der User in eine Session mit registered_only hinein (Chat, Stream, Anhänge)
+pub async fn is_admitted(database: &mongodb::Database, session: &Session, viewer: &Viewer) -> bool {
+    if !requires_registration(&session.registration, viewer) {
+        return true;
+    }
+    return matches!(get_registration(database, &session.id, &viewer.username).await,
+                    Ok(Some(registration)) if registration.status == RegistrationStatus::Confirmed);
+}
+
+pub async fn registration_info(database: &mongodb::Database, session: &Session, viewer: &Viewer) -> RegistrationInfo {
+    let registrations = get_session_registrations(database, &session.id).await.unwrap_or_default();
+    return RegistrationInfo::new(&session.registration, &registrations, viewer);
+}
+
+// Lädt die Session für den angemeldeten User, sofern er sie sehen darf
+async fn load_session(database: &mongodb::Database, id: &str, token: &SecurityToken) -> Option<(Session, Viewer)> {
+    let session_id = ObjectId::parse_str(id).ok()?;
+    let viewer = viewer_from_token(database, token).await;
+    let session = accessible_session(database, &viewer, &session_id).await.ok()??;
+    return Some((session, viewer));
+}
+
+// Anmeldung des aktuellen Users, bei vollen Plätzen landet er auf der Warteliste
+#[post("/session/<id>/register")]
+pub async fn register(id: &str, lock: &State<RegistrationLock>, cookies: &CookieJar<'_>) -> Json<RegistrationResult> {
+    let token = match user_token(cookies) {
+        Some(t) => t,
+        None => return RegistrationResult::failed()
+    };
+
+    let database = get_standard_database().await;
+    let (session, viewer) = match load_session(&database, id, &token).await {
+        Some(loaded) if loaded.0.registration.enabled => loaded,
+        _ => return RegistrationResult::failed()
+    };
+
+    let _guard = lock.0.lock().await;
+    let registrations = match get_session_registrations(&database, &session.id).await {
+        Ok(registrations) => registrations,
+        Err(_) => return RegistrationResult::failed()
+    };
+
+    if !registrations.iter().any(|r| r.username == viewer.username) {
+        let confirmed = registrations.iter().filter(|r| r.status == RegistrationStatus::Confirmed).count();
+        let fullname = match get_user_by_username(&database, &viewer.username).await {
+            Ok(Some(user)) => user.fullname,
+            _ => viewer.username.clone()
+        };
+        let registration = Registration {
+            id: ObjectId::new(),
+            session_id: session.id,
+            username: viewer.username.clone(),
+            fullname,
+            status: initial_status(session.registration.capacity, confirmed),
+            registered_at: Utc::now(),
+            promoted_at: None
+        };
+        match add_registration(&database, &registration).await {
+            // Eine doppelte Anmeldung (z.B. zweiter Tab) ändert nichts am bestehenden Platz
+            Ok(()) | Err(DatabaseError::Conflict(_)) => {},
+            Err(_) => return RegistrationResult::failed()
+        }
+    }
+
+    return Json(RegistrationResult { status: 1, info: Some(registration_info(&database, &session, &viewer).await) });
+}
+
+// Abmeldung des aktuellen Users, ein frei gewordener Platz geht an die Warteliste
+#[post("/session/<id>/unregister")]
+pub async fn unregister(id: &str, lock: &State<RegistrationLock>, cookies: &CookieJar<'_>) -> Json<RegistrationResult> {
+    let token = match user_token(cookies) {
+        Some(t) => t,
+        None => return RegistrationResult::failed()
+    };
+
+    let database = get_standard_database().await;
+    let (session, viewer) = match load_session(&database, id, &token).await {
+        Some(loaded) => loaded,
+        None => return RegistrationResult::failed()
+    };
+
+    let _guard = lock.0.lock().await;
+    let result = match remove_registration(&database, &session.id, &viewer.username).await {
+        Ok(Some(registration)) if registration.status == RegistrationStatus::Confirmed => promote_waitlist(&database, &session).await,
+        Ok(_) => Ok(()),
+        Err(e) => Err(e)
+    };
+    if result.is_err() {
+        return RegistrationResult::failed();
+    }
+
+    return Json(RegistrationResult { status: 1, info: Some(registration_info(&database, &session, &viewer).await) });
+}
+
+// Ein leeres Feld bedeutet unbegrenzte Plätze, 0 Plätze ergibt keinen Sinn
+fn valid_capacity<'v>(capacity: &Option<u32>) -> rocket::form::Result<'v, ()> {
+    if *capacity == Some(0) {
+        Err(rocket::form::Error::validation("Es muss mindestens einen Platz geben"))?;
+    }
+    Ok(())
+}
+
+#[derive(FromForm)]
+pub struct RegistrationForm {
+    pub enabled: bool,
+    #[field(validate = valid_capacity())]
+    pub capacity: Option<u32>,
+    pub registered_only: bool
+}
+
+// Einstellungen der Anmeldung, eine höhere capacity lässt die Warteliste sofort nachrücken
+#[post("/admin/session/<id>/registration", data = "<form>")]
+pub async fn update_registration(id: &str, form: Form<RegistrationForm>, lock: &State<RegistrationLock>,
+                                 cookies: &CookieJar<'_>) -> Json<RegistrationResult> {
+    let token = match user_token(cookies) {
+        Some(t) if t.role == SecurityRole::ADMIN => t,
+        _ => return RegistrationResult::failed()
+    };
+
+    let database = get_standard_database().await;
+    let (mut session, viewer) = match load_session(&database, id, &token).await {
+        Some(loaded) => loaded,
+        None => return RegistrationResult::failed()
+    };
+
+    session.registration = RegistrationSettings {
+        enabled: form.enabled,
+        capacity: form.capacity,
+        registered_only: form.registered_only
+    };
+
+    let _guard = lock.0.lock().await;
+    let result = match set_registration_settings(&database, &session.id, &session.registration).await {
+        Ok(true) => promote_waitlist(&database, &session).await.map(|_| true),
+        Ok(false) => Ok(false),
+        Err(e) => Err(e)
+    };
+    // Ohne Treffer wurde die Session inzwischen gelöscht
+    if !matches!(result, Ok(true)) {
+        return RegistrationResult::failed();
+    }
+
+    return Json(RegistrationResult { status: 1, info: Some(registration_info(&database, &session, &viewer).await) });
+}
+
+// Teilnehmerliste als Datei
+#[derive(Responder)]
+pub struct AttendeeExport {
+    body: (ContentType, String),
+    disposition: Header<'static>,
+}
+
+// Felder mit Trennzeichen, Anführungszeichen oder Zeilenumbruch werden nach RFC 4180 in Anführungszeichen gesetzt
+// Ein führendes =, +, - oder @ wird entschärft, damit Tabellenprogramme keine Formel ausführen
+pub fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) { format!("'{}", value) } else { value.to_string() };
+    if value.contains([';', '"', '\n', '\r']) {
+        return format!("\"{}\"", value.replace('"', "\"\""));
+    }
+    return value;
+}
+
+pub fn attendees_csv(registrations: &[Registration]) -> String {
+    let mut csv = String::from("name;username;status;position;registered_at;promoted_at\r\n");
+    let mut position = 0;
+    for registration in registrations {
+        let waitlist_position = match registration.status {
+            RegistrationStatus::Waitlisted => {
+                position += 1;
+                position.to_string()
+            },
+            RegistrationStatus::Confirmed => String::new()
+        };
+        let fields = [
+            csv_field(&registration.fullname),
+            csv_field(&registration.username),
+            registration.status.label().to_string(),
+            waitlist_position,
+            registration.registered_at.to_rfc3339(),
+            registration.promoted_at.map(|p| p.to_rfc3339()).unwrap_or_default()
+        ];
+        csv.push_str(&fields.join(";"));
+        csv.push_str("\r\n");
+    }
+    return csv;
+}
+
+// Export aller Anmeldungen einer Session, bestätigte und Warteliste in der Reihenfolge der Anmeldung
+#[get("/admin/session/<id>/attendees.csv")]
+pub async fn export_attendees(id: &str, cookies: &CookieJar<'_>) -> Result<AttendeeExport, Status> {
+    let token = match user_token(cookies) {
+        Some(t) if t.role == SecurityRole::ADMIN => t,
+        _ => return Err(Status::Unauthorized)
+    };
+
+    let database = get_standard_database().await;
+    let (session, _) = load_session(&database, id, &token).await.ok_or(Status::NotFound)?;
+    let registrations = get_session_registrations(&database, &session.id).await
+        .map_err(|_| Status::InternalServerError)?;
+
+    return Ok(AttendeeExport {
+        body: (ContentType::CSV, attendees_csv(&registrations)),
+        disposition: Header::new("Content-Disposition", format!("attachment; filename=\"{}-teilnehmer.csv\"", session.slug)),
+    });
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .attach(registration_fairing())
+        .mount("/", routes![
+            register,
+            unregister,
+            update_registration,
+            export_attendees
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::local::asynchronous::Client;
+
+    fn registration(username: &str, status: RegistrationStatus, minute: u32) -> Registration {
+        return Registration {
+            id: ObjectId::new(),
+            session_id: ObjectId::parse_str("62a05c8631a6964f64d829ac").unwrap(),
+            username: username.to_string(),
+            fullname: username.to_uppercase(),
+            status,
+            registered_at: DateTime::parse_from_rfc3339(&format!("2022-10-01T10:{:02}:00Z", minute)).unwrap().with_timezone(&Utc),
+            promoted_at: None
+        };
+    }
+
+    fn viewer(username: &str, role: &str) -> Viewer {
+        return Viewer {
+            username: username.to_string(),
+            role: role.to_string(),
+            groups: Vec::new(),
+            hidden_events: Vec::new(),
+            unlisted_events: Vec::new(),
+            events_locked: false
+        };
+    }
+
+    #[test]
+    fn test_initial_status() {
+        assert_eq!(initial_status(None, 500), RegistrationStatus::Confirmed);
+        assert_eq!(initial_status(Some(2), 1), RegistrationStatus::Confirmed);
+        assert_eq!(initial_status(Some(2), 2), RegistrationStatus::Waitlisted);
+    }
+
+    #[test]
+    fn test_promotions() {
+        let registrations = vec![
+            registration("anna", RegistrationStatus::Confirmed, 0),
+            registration("ben", RegistrationStatus::Waitlisted, 1),
+            registration("cem", RegistrationStatus::Waitlisted, 2),
+            registration("dana", RegistrationStatus::Waitlisted, 3),
+        ];
+        assert!(promotions(Some(1), &registrations).is_empty());
+        // Nachgerückt wird in der Reihenfolge der Anmeldung
+        assert_eq!(promotions(Some(3), &registrations), vec![registrations[1].id, registrations[2].id]);
+        assert_eq!(promotions(None, &registrations).len(), 3);
+    }
+
+    #[test]
+    fn test_registration_info() {
+        let settings = RegistrationSettings { enabled: true, capacity: Some(1), registered_only: true };
+        let registrations = vec![
+            registration("anna", RegistrationStatus::Confirmed, 0),
+            registration("ben", RegistrationStatus::Waitlisted, 1),
+            registration("cem", RegistrationStatus::Waitlisted, 2),
+        ];
+
+        let info = RegistrationInfo::new(&settings, &registrations, &viewer("cem", "USER"));
+        assert_eq!((info.confirmed, info.waitlisted), (1, 2));
+        assert_eq!(info.position, Some(2));
+        assert!(info.locked);
+
+        assert!(!RegistrationInfo::new(&settings, &registrations, &viewer("anna", "USER")).locked);
+        assert!(!RegistrationInfo::new(&settings, &registrations, &viewer("admin", "ADMIN")).locked);
+    }
+
+    #[test]
+    fn test_attendees_csv() {
+        let mut registrations = vec![
+            registration("anna", RegistrationStatus::Confirmed, 0),
+            registration("ben", RegistrationStatus::Waitlisted, 1),
+        ];
+        registrations[0].fullname = "Müller; Anna".to_string();
+        registrations[1].fullname = "=HYPERLINK(\"x\")".to_string();
+
+        let csv = attendees_csv(&registrations);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], "name;username;status;position;registered_at;promoted_at");
+        assert_eq!(lines[1], "\"Müller; Anna\";anna;Bestätigt;;2022-10-01T10:00:00+00:00;");
+        assert_eq!(lines[2], "\"'=HYPERLINK(\"\"x\"\")\";ben;Warteliste;1;2022-10-01T10:01:00+00:00;");
+    }
+
+    #[tokio::test]
+    async fn test_registration_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post("/session/62a05c8631a6964f64d829ac/register").dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+
+        let response = client.get("/admin/session/62a05c8631a6964f64d829ac/attendees.csv").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use regex::{Regex, RegexBuilder};
+use rocket::http::{Cookie, CookieJar};
+use rocket_dyn_templates::{Template, context};
+use serde::Serialize;
+
+use crate::database::{get_listed_sessions, DatabaseResult, get_standard_database, search_chat_messages, search_sessions};
+use crate::access::{viewer_from_token, Viewer};
+use crate::markdown::description_text;
+use crate::registration::is_admitted;
+use crate::security::{SecurityToken, validate_token};
+use crate::sessions::Session;
+
+// Maximale Anzahl an Treffern pro Kategorie
+const SEARCH_LIMIT: i64 = 20;
+
+// Länge der Textausschnitte in den Suchergebnissen (in Zeichen)
+const SNIPPET_LENGTH: usize = 160;
+
+// Suchtreffer einer Session, name und description (ohne Markdown) sind bereits escaped und markiert
+#[derive(Debug, Serialize)]
+pub struct SessionHit {
+    pub id: String,
+    pub slug: String,
+    pub name: String,
+    pub description: String,
+    pub score: f64
+}
+
+// Suchtreffer im Chatverlauf, message ist bereits escaped und markiert
+#[derive(Debug, Serialize)]
+pub struct ChatHit {
+    pub session_id: String,
+    pub session_name: String,
+    pub username: String,
+    pub message: String,
+    pub score: f64
+}
+
+// Zerlegt die Suchanfrage in einzelne Begriffe, Ausschlüsse (-wort) werden nicht hervorgehoben
+pub fn search_terms(query: &str) -> Vec<String> {
+    return query.split_whitespace()
+        .filter(|term| !term.starts_with('-'))
+        .map(|term| term.trim_matches('"').to_string())
+        .filter(|term| !term.is_empty())
+        .collect();
+}
+
+fn terms_regex(terms: &[String]) -> Option<Regex> {
+    if terms.is_empty() {
+        return None;
+    }
+
+    let pattern = terms.iter()
+        .map(|term| regex::escape(term))
+        .collect::<Vec<String>>()
+        .join("|");
+
+    return RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .build()
+        .ok();
+}
+
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c)
+        }
+    }
+    return escaped;
+}
+
+// Schneidet einen Ausschnitt von max_len Zeichen um den ersten Treffer herum aus
+fn snippet(text: &str, pattern: Option<&Regex>, max_len: usize) -> String {
+    let char_count = text.chars().count();
+    if char_count <= max_len {
+        return text.to_string();
+    }
+
+    let match_start = pattern
+        .and_then(|re| re.find(text))
+        .map(|m| text[..m.start()].chars().count())
+        .unwrap_or(0);
+
+    let start = match_start.saturating_sub(max_len / 3).min(char_count - max_len);
+    let end = start + max_len;
+
+    let mut result: String = text.chars().skip(start).take(max_len).collect();
+    if start > 0 {
+        result = format!("…{}", result);
+    }
+    if end < char_count {
+        result.push('…');
+    }
+    return result;
+}
+
+// Liefert einen HTML-sicheren Ausschnitt, in dem alle Suchbegriffe mit <mark> hervorgehoben sind
+pub fn highlight(text: &str, terms: &[String], max_len: usize) -> String {
+    let pattern = terms_regex(terms);
+    let snippet = snippet(text, pattern.as_ref(), max_len);
+
+    let mut result = String::new();
+    let mut last = 0;
+    if let Some(re) = &pattern {
+        for m in re.find_iter(&snippet) {
+            result.push_str(&escape_html(&snippet[last..m.start()]));
+            result.push_str("<mark>");
+            result.push_str(&escape_html(m.as_str()));
+            result.push_str("</mark>");
+            last = m.end();
+        }
+    }
+    result.push_str(&escape_html(&snippet[last..]));
+
+    return result;
+}
+
+// Sucht in Sessions und Chatverlauf, liefert die Treffer bereits escaped und markiert
+async fn search(database: &mongodb::Database, query: &str, viewer: &Viewer) -> DatabaseResult<(Vec<SessionHit>, Vec<ChatHit>)> {
+    let terms = search_terms(query);
+
+    let session_hits = search_sessions(database, query, viewer, SEARCH_LIMIT).await?
+        .into_iter()
+        .map(|(session, score)| SessionHit {
+            id: session.id.to_hex(),
+            slug: session.slug.clone(),
+            name: highlight(&session.name, &terms, SNIPPET_LENGTH),
+            description: highlight(&description_text(&session.description, session.description_format), &terms, SNIPPET_LENGTH),
+            score
+        })
+        .collect();
+
+    // Gesucht wird nur in den Räumen der Sessions, die der User gelistet sehen darf, sonst würde das Limit
+    // mit Treffern aus fremden Sessions aufgebraucht. Wie beim Mitlesen im Chat zählen Sessions mit
+    // registered_only nur, wenn der User bestätigt angemeldet ist
+    let mut rooms: HashMap<String, Session> = HashMap::new();
+    for session in get_listed_sessions(database, viewer).await? {
+        if is_admitted(database, &session, viewer).await {
+            rooms.insert(session.id.to_hex(), session);
+        }
+    }
+    let room_names: Vec<String> = rooms.keys().cloned().collect();
+
+    let mut chat_hits = Vec::new();
+    for (message, score) in search_chat_messages(database, query, Some(&room_names), SEARCH_LIMIT).await? {
+        if let Some(session) = rooms.get(&message.room) {
+            chat_hits.push(ChatHit {
+                session_id: message.room.clone(),
+                session_name: session.name.clone(),
+                username: message.username,
+                message: highlight(&message.message, &terms, SNIPPET_LENGTH),
+                score
+            });
+        }
+    }
+
+    return Ok((session_hits, chat_hits));
+}
+
+// Volltextsuche über Sessions und Chatverlauf
+#[get("/search?<q>")]
+pub async fn show_search(q: Option<String>, cookies: &CookieJar<'_>) -> Template {
+
+    // streamie.live ist der Standardcookie für den Auth-Token
+    let token: Option<Cookie> = cookies.get_private("streamie.live");
+    let token_value = match token {
+        Some(jwt) => jwt,
+        None => Cookie::new("streamie.live", "None")
+    };
+
+    let valuated_token = validate_token(token_value.value().to_string());
+
+    // Falls Token abgelaufen oder ungültig -> render unauthorized template
+    match valuated_token {
+        Some(t) => {
+            let fullname: Option<Cookie> = cookies.get_private("fullname");
+            let fullname_value = match fullname {
+                Some(fullname) => fullname,
+                None => Cookie::new("fullname", "Unknown User")
+            };
+
+            #[derive(Serialize)]
+            struct SearchContext<'a> {
+                jwt: &'a str,
+                fullname: &'a str,
+                query: &'a str,
+                sessions: Vec<SessionHit>,
+                messages: Vec<ChatHit>,
+                error: bool,
+                token: SecurityToken
+            }
+
+            let query = q.as_deref().unwrap_or("").trim();
+            let mut session_hits: Vec<SessionHit> = Vec::new();
+            let mut chat_hits: Vec<ChatHit> = Vec::new();
+
+            let mut error = false;
+
+            if !query.is_empty() {
+                let database = get_standard_database().await;
+                // Gefunden wird nur, was der User auch in der Übersicht sehen würde
+                let viewer = viewer_from_token(&database, &t).await;
+
+                match search(&database, query, &viewer).await {
+                    Ok((sessions, messages)) => {
+                        session_hits = sessions;
+                        chat_hits = messages;
+                    },
+                    Err(e) => {
+                        error!("Search for '{}' failed: {}", query, e);
+                        error = true;
+                    }
+                }
+            }
+
+            return Template::render("search/results", SearchContext {
+                jwt: token_value.value(),
+                fullname: fullname_value.value(),
+                query,
+                sessions: session_hits,
+                messages: chat_hits,
+                error,
+                token: t
+            });
+        },
+        None => {
+            return Template::render("unauthorized", context!{});
+        }
+    }
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            show_search
+    ]).attach(Template::fairing())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+
+    #[tokio::test]
+    async fn test_search_page() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get(uri!(super::show_search(Some("rust".to_string())))).dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_search_terms() {
+        assert_eq!(search_terms("rust \"meetup\" -java"), vec!["rust".to_string(), "meetup".to_string()]);
+    }
+
+    #[test]
+    fn test_highlight_escapes_and_marks() {
+        let terms = vec!["rust".to_string()];
+        assert_eq!(highlight("<b>Rust</b> & rust", &terms, 160),
+                   "&lt;b&gt;<mark>Rust</mark>&lt;/b&gt; &amp; <mark>rust</mark>");
+    }
+
+    #[test]
+    fn test_highlight_snippet() {
+        let text = format!("{} Rust {}", "a".repeat(200), "b".repeat(200));
+        let result = highlight(&text, &vec!["rust".to_string()], 60);
+        assert!(result.starts_with('…'));
+        assert!(result.ends_with('…'));
+        assert!(result.contains("<mark>Rust</mark>"));
+    }
+}
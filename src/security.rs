@@ -11,7 +11,13 @@ use captcha_rs::CaptchaBuilder;
 use rocket::form::Form;
 use crate::database::{get_client, get_standard_database, get_user_by_username_and_password};
 use crate::sessions::{User};
+use crate::timezone::TIMEZONE_COOKIE;
 use rocket::serde::Serialize;
+use rand::{thread_rng, Rng};
+use rand::distributions::Alphanumeric;
+
+// Länge der Tokens in den Kalender-Abo-Links
+pub const CALENDAR_TOKEN_LENGTH: usize = 32;
 
 // Aktuell unterstützt die Anwendung 3 Rollen -> Admin sieht Admin-Panel, 
 // Moderator wird nur im Chat speziell markiert
@@ -112,6 +118,40 @@ pub fn validate_token(token: String) -> Option<SecurityToken> {
     }
 }
 
+// Liest den Auth-Token aus dem Cookie streamie.live und prüft ihn
+// Ohne Cookie oder mit abgelaufenem Token gibt es keinen Token
+pub fn user_token(cookies: &CookieJar<'_>) -> Option<SecurityToken> {
+    // streamie.live ist der Standardcookie für den Auth-Token
+    let token: Option<Cookie> = cookies.get_private("streamie.live");
+    let token_value = match token {
+        Some(jwt) => jwt,
+        None => Cookie::new("streamie.live", "None")
+    };
+
+    return validate_token(token_value.value().to_string());
+}
+
+// Wie user_token, liefert den Token aber nur für Admins zurück
+pub fn admin_token(cookies: &CookieJar<'_>) -> Option<SecurityToken> {
+    return user_token(cookies).filter(|t| t.role == SecurityRole::ADMIN);
+}
+
+// Token für Kalender-Abos
+// Kalender-Programme schicken keine Cookies mit, daher steht der Token in der Feed-URL
+// Er wird zufällig erzeugt und beim User gespeichert, ein neuer Token macht den alten Link ungültig
+pub fn create_calendar_token() -> String {
+    return thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(CALENDAR_TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+}
+
+// Tokens in einem anderen Format müssen nicht erst in der Datenbank gesucht werden
+pub fn is_calendar_token(token: &str) -> bool {
+    return token.len() == CALENDAR_TOKEN_LENGTH && token.chars().all(|c| c.is_ascii_alphanumeric());
+}
+
 // Standard Login-Page
 #[get("/login")]
 pub async fn login(cookies: &CookieJar<'_>) -> Template {
@@ -179,6 +219,11 @@ pub async fn login_proceed(loginuser: Form<LoginUser<'_>>, cookies: &CookieJar<'
         Some(v) => {
             // Erzeuge neue Cookies mit dem JWT
             cookies.add_private(Cookie::new("fullname", v.fullname));
+            // Ohne eigene Einstellung gilt die Standard-Zeitzone
+            match v.timezone {
+                Some(timezone) => cookies.add_private(Cookie::new(TIMEZONE_COOKIE, timezone)),
+                None => cookies.remove_private(Cookie::named(TIMEZONE_COOKIE))
+            }
 
             let security_token = SecurityToken {
                 username: v.username,
@@ -253,6 +298,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_calendar_token() {
+        let token = super::create_calendar_token();
+        assert!(super::is_calendar_token(&token));
+        assert_ne!(super::create_calendar_token(), token);
+
+        assert!(!super::is_calendar_token("None"));
+        assert!(!super::is_calendar_token(&format!("{}.", &token[1..])));
+        assert!(!super::is_calendar_token(&format!("{}0", token)));
+    }
+
     #[test]
     fn test_token_is_invalid_time() {
         let st = super::SecurityToken {
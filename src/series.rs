@@ -0,0 +1,856 @@
+use std::time::Duration;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use mongodb::bson::oid::ObjectId;
+use rocket::fairing::AdHoc;
+use rocket::form::{self, Contextual, Form};
+use rocket::http::{Cookie, CookieJar};
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_dyn_templates::{Template, context};
+use serde::{Deserialize, Serialize};
+
+use crate::administration::{fallback_value, valid_date_time, FormFeedback};
+use crate::database::{add_new_session, add_series, get_all_series, get_series_by_id, get_series_sessions,
+                      get_sessions_by_ids, get_standard_database, set_session_series, trash_session_by_id,
+                      update_series, update_session, DatabaseError, DatabaseResult};
+use crate::providers::{parse_stream_link, plattform_options, stream_type_from_plattform, valid_fallback_link,
+                       valid_stream_link, PlattformOption};
+use crate::recurrence::{valid_rrule, RecurrenceRule};
+use crate::security::{admin_token, SecurityToken};
+use crate::sessions::{create_slug, Session, SessionStatus, SessionStream, FORMAT_STR};
+use crate::timezone::{local_to_utc, parse_timezone, timezone_names, user_timezone, utc_to_local, valid_timezone};
+
+// Standard: Termine werden 90 Tage im Voraus als Sessions angelegt, kann über series_horizon_days in der Rocket.toml geändert werden
+pub const DEFAULT_SERIES_HORIZON_DAYS: i64 = 90;
+
+// Intervall in dem neue Termine aller Serien angelegt werden
+const MATERIALISE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+// Eine Serie von Sessions, die Termine werden über die RRULE berechnet und als eigene Sessions gespeichert
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSeries {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub description: String,
+    pub stream: SessionStream,
+    // Beginn des ersten Termins
+    pub start: DateTime<Utc>,
+    pub duration_minutes: i64,
+    pub rrule: String,
+    // Zeitzone, in der die Regel gerechnet wird (z.B. wöchentlich 18:00 Uhr Europe/Berlin)
+    pub timezone: String,
+    // Ausgefallene Termine (EXDATE)
+    #[serde(default)]
+    pub exdates: Vec<DateTime<Utc>>,
+    #[serde(default)]
+    pub version: i64,
+}
+
+impl SessionSeries {
+    pub fn rule(&self) -> Option<RecurrenceRule> {
+        return RecurrenceRule::parse(&self.rrule).ok();
+    }
+
+    pub fn tz(&self) -> Tz {
+        return self.timezone.parse().unwrap_or(Tz::UTC);
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        return chrono::Duration::minutes(self.duration_minutes);
+    }
+
+    // Startzeiten aller Termine bis einschließlich limit
+    pub fn occurrences(&self, limit: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        return match self.rule() {
+            Some(rule) => rule.occurrences(self.start, self.tz(), &self.exdates, limit),
+            None => vec![self.start]
+        };
+    }
+}
+
+// Wie weit im Voraus Termine angelegt werden
+pub struct SeriesConfig {
+    pub horizon: chrono::Duration,
+}
+
+impl SeriesConfig {
+    pub fn new(horizon_days: i64) -> SeriesConfig {
+        return SeriesConfig { horizon: chrono::Duration::days(horizon_days) };
+    }
+
+    pub fn limit(&self) -> DateTime<Utc> {
+        return Utc::now() + self.horizon;
+    }
+}
+
+// Der slug enthält den Termin, damit die Sessions einer Serie unterscheidbar bleiben
+pub fn occurrence_slug(name: &str, occurrence: &DateTime<Utc>) -> String {
+    return format!("{}-{}", create_slug(name), occurrence.format("%Y-%m-%d-%H%M"));
+}
+
+fn occurrence_session(series: &SessionSeries, occurrence: DateTime<Utc>) -> Session {
+    return Session {
+        slug: occurrence_slug(&series.name, &occurrence),
+        start: occurrence,
+        end: occurrence + series.duration(),
+        name: series.name.clone(),
+        description: series.description.clone(),
+        stream: series.stream.clone(),
+        series_id: Some(series.id),
+        occurrence: Some(occurrence),
+        timezone: series.timezone.clone(),
+        ..Default::default()
+    };
+}
+
+// Legt alle noch fehlenden Termine bis limit als Sessions an
+// Bereits angelegte (auch einzeln bearbeitete oder gelöschte) Termine bleiben unverändert
+pub async fn materialise_series(database: &mongodb::Database, series: &SessionSeries, limit: DateTime<Utc>) -> DatabaseResult<usize> {
+    let existing: Vec<DateTime<Utc>> = get_series_sessions(database, &series.id, true).await?
+        .into_iter()
+        .filter_map(|session| session.occurrence)
+        .collect();
+
+    let mut created = 0;
+    for occurrence in series.occurrences(limit) {
+        if existing.contains(&occurrence) {
+            continue;
+        }
+        match add_new_session(database, &occurrence_session(series, occurrence)).await {
+            Ok(()) => created += 1,
+            // Wurde parallel schon angelegt
+            Err(DatabaseError::Conflict(_)) => {},
+            Err(e) => return Err(e)
+        }
+    }
+    return Ok(created);
+}
+
+// Legt beim Start die Konfiguration an und ergänzt regelmäßig die Termine aller Serien
+pub fn series_fairing() -> AdHoc {
+    AdHoc::on_ignite("Session Series", |rocket| async {
+        let horizon_days: i64 = rocket.figment()
+            .extract_inner("series_horizon_days")
+            .unwrap_or(DEFAULT_SERIES_HORIZON_DAYS);
+
+        rocket.manage(SeriesConfig::new(horizon_days))
+            .attach(AdHoc::on_liftoff("Session Series Materialisation", |rocket| Box::pin(async move {
+                let horizon = rocket.state::<SeriesConfig>().unwrap().horizon;
+                let mut shutdown = rocket.shutdown();
+
+                rocket::tokio::spawn(async move {
+                    let mut interval = rocket::tokio::time::interval(MATERIALISE_INTERVAL);
+                    loop {
+                        rocket::tokio::select! {
+                            _ = interval.tick() => {},
+                            _ = &mut shutdown => break,
+                        };
+
+                        let database = get_standard_database().await;
+                        let all_series = match get_all_series(&database).await {
+                            Ok(all_series) => all_series,
+                            Err(e) => {
+                                error!("Failed to load session series: {}", e);
+                                continue;
+                            }
+                        };
+                        for series in all_series {
+                            if let Err(e) = materialise_series(&database, &series, Utc::now() + horizon).await {
+                                error!("Failed to create sessions of series {}: {}", series.id, e);
+                            }
+                        }
+                    }
+                });
+            })))
+    })
+}
+
+// Bearbeiten "dieser und aller folgenden Termine": die Serie wird am Termin geteilt
+// Die alte Serie endet vor dem Termin, die neue beginnt mit ihm. Beim ersten Termin wird die Serie direkt geändert
+// Liefert die alte (gekürzte) und die neue Serie
+pub fn split_series(series: &SessionSeries, pivot: DateTime<Utc>, mut changed: SessionSeries) -> (Option<SessionSeries>, SessionSeries) {
+    if pivot <= series.start {
+        if changed.rrule.trim().is_empty() {
+            changed.rrule = series.rrule.clone();
+        }
+        changed.id = series.id;
+        changed.version = series.version;
+        changed.exdates = series.exdates.clone();
+        return (None, changed);
+    }
+
+    let mut old = series.clone();
+    if let Some(mut rule) = series.rule() {
+        // Ohne neue Regel übernimmt die neue Serie die restlichen Termine der alten
+        if changed.rrule.trim().is_empty() {
+            let mut remaining = rule.clone();
+            if let Some(count) = rule.count {
+                let before = rule.occurrences(series.start, series.tz(), &[], pivot - chrono::Duration::seconds(1)).len() as u32;
+                remaining.count = Some(count.saturating_sub(before).max(1));
+            }
+            changed.rrule = remaining.to_string();
+        }
+        rule.count = None;
+        rule.until = Some(pivot - chrono::Duration::seconds(1));
+        old.rrule = rule.to_string();
+    }
+    old.exdates.retain(|exdate| *exdate < pivot);
+
+    changed.id = ObjectId::new();
+    changed.version = 0;
+    changed.exdates = series.exdates.iter().copied().filter(|exdate| *exdate >= pivot).collect();
+    return (Some(old), changed);
+}
+
+// Überträgt die geänderte Serie auf die Sessions ab dem Termin
+// Bestehende Sessions werden der Reihe nach weiterverwendet (ids und damit Kalender-UIDs bleiben gleich),
+// überzählige kommen in den Papierkorb, fehlende werden neu angelegt
+async fn apply_future_edit(database: &mongodb::Database, series: &SessionSeries, pivot: DateTime<Utc>,
+                           changed: SessionSeries, limit: DateTime<Utc>) -> DatabaseResult<SessionSeries> {
+    let future: Vec<Session> = get_series_sessions(database, &series.id, false).await?
+        .into_iter()
+        .filter(|session| session.occurrence.map_or(false, |occurrence| occurrence >= pivot))
+        .collect();
+
+    let (old, new) = split_series(series, pivot, changed);
+    match old {
+        Some(old) => {
+            update_series(database, &old).await?;
+            add_series(database, &new).await?;
+        },
+        None => update_series(database, &new).await?
+    }
+
+    // Zuerst werden alle Termine gelöst, damit sich alte und neue Termine im unique Index nicht in die Quere kommen
+    for session in future.iter() {
+        set_session_series(database, &session.id, &new.id, None).await?;
+    }
+
+    let occurrences: Vec<DateTime<Utc>> = new.occurrences(limit).into_iter()
+        .filter(|occurrence| *occurrence >= pivot)
+        .collect();
+    for (index, session) in future.into_iter().enumerate() {
+        match occurrences.get(index) {
+            Some(occurrence) => {
+                set_session_series(database, &session.id, &new.id, Some(*occurrence)).await?;
+                update_session(database, &Session {
+                    start: *occurrence,
+                    end: *occurrence + new.duration(),
+                    name: new.name.clone(),
+                    description: new.description.clone(),
+                    stream: new.stream.clone(),
+                    ..session
+                }).await?;
+            },
+            None => {
+                trash_session_by_id(database, &session.id).await?;
+            }
+        }
+    }
+
+    materialise_series(database, &new, limit).await?;
+    return Ok(new);
+}
+
+// Ausfall-Termine durch Komma oder Zeilenumbruch getrennt im Format FORMAT_STR, angegeben in der Zeitzone der Serie
+fn parse_exdates(value: &str, tz: Tz) -> Result<Vec<DateTime<Utc>>, String> {
+    return value.split(|c| c == ',' || c == '\n')
+        .map(str::trim)
+        .filter(|exdate| !exdate.is_empty())
+        .map(|exdate| local_to_utc(exdate, tz)
+            .map_err(|e| format!("Ungültiger Ausfall-Termin '{}': {}", exdate, e)))
+        .collect();
+}
+
+// Eine ungültige Zeitzone meldet schon das Feld timezone, geprüft wird dann nur das Format
+fn valid_exdates<'v>(value: &str, timezone: &str) -> form::Result<'v, ()> {
+    match parse_exdates(value, parse_timezone(timezone).unwrap_or(Tz::UTC)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(form::Error::validation(e).into())
+    }
+}
+
+fn valid_end<'v>(end: &str, start: &str) -> form::Result<'v, ()> {
+    valid_date_time(end)?;
+    if let (Ok(start), Ok(end)) = (NaiveDateTime::parse_from_str(start, FORMAT_STR), NaiveDateTime::parse_from_str(end, FORMAT_STR)) {
+        if end <= start {
+            return Err(form::Error::validation("Das Ende muss nach dem Beginn liegen.").into());
+        }
+    }
+    Ok(())
+}
+
+//Inputs für eine neue Serie, Start und Ende beschreiben den ersten Termin
+#[derive(FromForm, Debug)]
+pub struct NewSeries<'r> {
+    #[field(validate = valid_date_time())]
+    start: &'r str,
+    #[field(validate = valid_end(self.start))]
+    end: &'r str,
+    #[field(validate = len(1..))]
+    name: &'r str,
+    #[field(validate = len(1..))]
+    description: &'r str,
+    #[field(validate = valid_stream_link(self.plattform))]
+    link: &'r str,
+    #[field(validate = len(1..))]
+    plattform: &'r str,
+    #[field(validate = valid_fallback_link())]
+    fallback: Option<&'r str>,
+    #[field(validate = valid_rrule())]
+    rrule: &'r str,
+    #[field(validate = valid_timezone())]
+    timezone: &'r str,
+    #[field(validate = valid_exdates(self.timezone))]
+    exdates: &'r str,
+}
+
+const NEW_SERIES_FIELDS: [&str; 10] = ["start", "end", "name", "description", "link", "plattform", "fallback", "rrule", "timezone", "exdates"];
+
+// Start und Ende werden in der Zeitzone der Serie eingegeben, in der übersprungenen Stunde der Zeitumstellung gibt es sie nicht
+fn form_series(start: &str, end: &str, name: &str, description: &str, link: &str, plattform: &str,
+               fallback: Option<&str>, rrule: &str, timezone: &str) -> Result<SessionSeries, String> {
+    let tz = parse_timezone(timezone).unwrap_or(Tz::UTC);
+    let start = local_to_utc(start, tz).map_err(|e| format!("Beginn: {}", e))?;
+    let end = local_to_utc(end, tz).map_err(|e| format!("Ende: {}", e))?;
+    let stream_type = stream_type_from_plattform(plattform);
+    return Ok(SessionSeries {
+        id: ObjectId::new(),
+        name: name.to_string(),
+        description: description.to_string(),
+        stream: SessionStream {
+            link: link.trim().to_string(),
+            channel: parse_stream_link(&stream_type, link).unwrap_or_default(),
+            stream_type,
+            fallback: fallback_value(fallback)
+        },
+        start,
+        duration_minutes: (end - start).num_minutes(),
+        rrule: RecurrenceRule::parse(rrule).map(|rule| rule.to_string()).unwrap_or_default(),
+        timezone: timezone.trim().to_string(),
+        exdates: Vec::new(),
+        version: 0,
+    });
+}
+
+// Serie für die Übersicht, die ObjectId kann von Tera nicht verarbeitet werden
+#[derive(Debug, Serialize)]
+pub struct TeraSeries {
+    pub id: String,
+    pub name: String,
+    pub rrule: String,
+    pub timezone: String,
+    pub start: DateTime<Utc>,
+    pub duration_minutes: i64,
+    pub exdates: Vec<DateTime<Utc>>,
+}
+
+impl TeraSeries {
+    fn from_series(series: &SessionSeries) -> TeraSeries {
+        return TeraSeries {
+            id: series.id.to_hex(),
+            name: series.name.clone(),
+            rrule: series.rrule.clone(),
+            timezone: series.timezone.clone(),
+            start: series.start,
+            duration_minutes: series.duration_minutes,
+            exdates: series.exdates.clone(),
+        };
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TeraOccurrence {
+    pub id: String,
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub occurrence: Option<DateTime<Utc>>,
+    // Einzeln verschobener Termin
+    pub moved: bool,
+    pub status: SessionStatus,
+}
+
+#[derive(Serialize)]
+struct SeriesContext<'a> {
+    jwt: &'a str,
+    fullname: &'a str,
+    token: SecurityToken,
+    error: Option<String>,
+    plattforms: Vec<PlattformOption>,
+    timezone: String,
+    timezones: Vec<&'static str>,
+    form: FormFeedback,
+    series: Vec<TeraSeries>,
+    current: Option<TeraSeries>,
+    occurrences: Vec<TeraOccurrence>,
+    session: Option<TeraOccurrence>
+}
+
+fn fullname(cookies: &CookieJar<'_>) -> String {
+    let fullname: Option<Cookie> = cookies.get_private("fullname");
+    return fullname.map(|f| f.value().to_string()).unwrap_or_else(|| "Unknown User".to_string());
+}
+
+// Übersicht aller Serien mit Formular für eine neue Serie
+async fn render_overview(cookies: &CookieJar<'_>, token: SecurityToken, error: Option<String>, form: FormFeedback) -> Template {
+    let jwt = cookies.get_private("streamie.live").unwrap();
+    let database = get_standard_database().await;
+    let series = get_all_series(&database).await
+        .expect("Error while find")
+        .iter()
+        .map(TeraSeries::from_series)
+        .collect();
+
+    return Template::render("admin/series", SeriesContext {
+        jwt: jwt.value(),
+        fullname: &fullname(cookies),
+        token,
+        error,
+        plattforms: plattform_options(),
+        timezone: user_timezone(cookies),
+        timezones: timezone_names(),
+        form,
+        series,
+        current: None,
+        occurrences: Vec::new(),
+        session: None
+    });
+}
+
+// Eine Serie mit allen Terminen
+async fn render_detail(cookies: &CookieJar<'_>, token: SecurityToken, series: &SessionSeries, error: Option<String>) -> Template {
+    let jwt = cookies.get_private("streamie.live").unwrap();
+    let database = get_standard_database().await;
+    let occurrences = get_series_sessions(&database, &series.id, false).await
+        .expect("Error while find")
+        .into_iter()
+        .map(|session| TeraOccurrence {
+            id: session.id.to_hex(),
+            name: session.name,
+            moved: session.occurrence.map_or(false, |occurrence| occurrence != session.start),
+            start: session.start,
+            end: session.end,
+            occurrence: session.occurrence,
+            status: session.status
+        })
+        .collect();
+
+    return Template::render("admin/series_detail", SeriesContext {
+        jwt: jwt.value(),
+        fullname: &fullname(cookies),
+        token,
+        error,
+        plattforms: plattform_options(),
+        timezone: user_timezone(cookies),
+        timezones: timezone_names(),
+        form: FormFeedback::default(),
+        series: Vec::new(),
+        current: Some(TeraSeries::from_series(series)),
+        occurrences,
+        session: None
+    });
+}
+
+#[get("/admin/series")]
+pub async fn show_series(cookies: &CookieJar<'_>) -> Template {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            return render_overview(cookies, t, None, FormFeedback::default()).await;
+        },
+        None => {
+            return Template::render("unauthorized", context!{});
+        }
+    }
+}
+
+// Legt eine Serie an und erzeugt die Termine bis zum konfigurierten Horizont
+#[post("/admin/series", data = "<new_series>")]
+pub async fn add_new_series(new_series: Form<Contextual<'_, NewSeries<'_>>>, config: &State<SeriesConfig>,
+                            cookies: &CookieJar<'_>) -> Template {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            let input = match new_series.value {
+                Some(ref input) => input,
+                None => {
+                    let feedback = FormFeedback::from_context(&new_series.context, &NEW_SERIES_FIELDS);
+                    return render_overview(cookies, t, None, feedback).await;
+                }
+            };
+
+            let mut series = match form_series(input.start, input.end, input.name, input.description, input.link,
+                                               input.plattform, input.fallback, input.rrule, input.timezone) {
+                Ok(series) => series,
+                Err(e) => {
+                    let feedback = FormFeedback::from_context(&new_series.context, &NEW_SERIES_FIELDS);
+                    return render_overview(cookies, t, Some(e), feedback).await;
+                }
+            };
+            series.exdates = parse_exdates(input.exdates, series.tz()).unwrap_or_default();
+
+            let database = get_standard_database().await;
+            let result = match add_series(&database, &series).await {
+                Ok(()) => materialise_series(&database, &series, config.limit()).await,
+                Err(e) => Err(e)
+            };
+            if let Err(e) = result {
+                let feedback = FormFeedback::from_context(&new_series.context, &NEW_SERIES_FIELDS);
+                return render_overview(cookies, t, Some(e.to_string()), feedback).await;
+            }
+            return render_detail(cookies, t, &series, None).await;
+        },
+        None => {
+            return Template::render("unauthorized", context!{});
+        }
+    }
+}
+
+#[get("/admin/series/<id>")]
+pub async fn show_series_detail(id: &str, cookies: &CookieJar<'_>) -> Option<Template> {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            let database = get_standard_database().await;
+            let series = get_series_by_id(&database, &ObjectId::parse_str(id).ok()?).await.ok()??;
+            return Some(render_detail(cookies, t, &series, None).await);
+        },
+        None => {
+            return Some(Template::render("unauthorized", context!{}));
+        }
+    }
+}
+
+async fn load_occurrence(database: &mongodb::Database, id: &str) -> Option<(Session, SessionSeries)> {
+    let session = get_sessions_by_ids(database, &[ObjectId::parse_str(id).ok()?]).await.ok()?
+        .into_iter()
+        .next()?;
+    let series = get_series_by_id(database, &session.series_id?).await.ok()??;
+    return Some((session, series));
+}
+
+// Formular zum Bearbeiten eines Termins
+#[get("/admin/series/occurrence/<id>")]
+pub async fn edit_occurrence(id: &str, cookies: &CookieJar<'_>) -> Option<Template> {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            let database = get_standard_database().await;
+            let (session, series) = load_occurrence(&database, id).await?;
+            let jwt = cookies.get_private("streamie.live").unwrap();
+
+            let mut form = FormFeedback::default();
+            form.values.insert("start".to_string(), utc_to_local(session.start, series.tz()));
+            form.values.insert("end".to_string(), utc_to_local(session.end, series.tz()));
+            form.values.insert("name".to_string(), session.name.clone());
+            form.values.insert("description".to_string(), session.description.clone());
+            form.values.insert("link".to_string(), session.stream.link.clone());
+            form.values.insert("plattform".to_string(), format!("{:?}", session.stream.stream_type));
+            form.values.insert("fallback".to_string(), session.stream.fallback.clone().unwrap_or_default());
+            form.values.insert("rrule".to_string(), series.rrule.clone());
+
+            return Some(Template::render("admin/series_occurrence", SeriesContext {
+                jwt: jwt.value(),
+                fullname: &fullname(cookies),
+                token: t,
+                error: None,
+                plattforms: plattform_options(),
+                timezone: user_timezone(cookies),
+                timezones: timezone_names(),
+                form,
+                series: Vec::new(),
+                current: Some(TeraSeries::from_series(&series)),
+                occurrences: Vec::new(),
+                session: Some(TeraOccurrence {
+                    id: session.id.to_hex(),
+                    name: session.name,
+                    moved: session.occurrence.map_or(false, |occurrence| occurrence != session.start),
+                    start: session.start,
+                    end: session.end,
+                    occurrence: session.occurrence,
+                    status: session.status
+                })
+            }));
+        },
+        None => {
+            return Some(Template::render("unauthorized", context!{}));
+        }
+    }
+}
+
+fn valid_scope<'v>(scope: &str) -> form::Result<'v, ()> {
+    match scope {
+        "this" | "future" => Ok(()),
+        _ => Err(form::Error::validation("Bitte 'nur diesen Termin' oder 'alle folgenden Termine' wählen.").into())
+    }
+}
+
+fn valid_optional_rrule<'v>(rrule: &Option<&str>) -> form::Result<'v, ()> {
+    match rrule.map(str::trim).filter(|r| !r.is_empty()) {
+        Some(rrule) => valid_rrule(rrule),
+        None => Ok(())
+    }
+}
+
+//Änderungen an einem Termin, scope ist "this" (nur dieser Termin) oder "future" (dieser und alle folgenden)
+#[derive(FromForm, Debug)]
+pub struct OccurrenceEdit<'r> {
+    #[field(validate = valid_scope())]
+    scope: &'r str,
+    #[field(validate = valid_date_time())]
+    start: &'r str,
+    #[field(validate = valid_end(self.start))]
+    end: &'r str,
+    #[field(validate = len(1..))]
+    name: &'r str,
+    #[field(validate = len(1..))]
+    description: &'r str,
+    #[field(validate = valid_stream_link(self.plattform))]
+    link: &'r str,
+    #[field(validate = len(1..))]
+    plattform: &'r str,
+    #[field(validate = valid_fallback_link())]
+    fallback: Option<&'r str>,
+    // Nur bei "future", leer übernimmt die bisherige Regel
+    #[field(validate = valid_optional_rrule())]
+    rrule: Option<&'r str>,
+}
+
+const OCCURRENCE_FIELDS: [&str; 9] = ["scope", "start", "end", "name", "description", "link", "plattform", "fallback", "rrule"];
+
+#[post("/admin/series/occurrence/<id>", data = "<edit>")]
+pub async fn update_occurrence(id: &str, edit: Form<Contextual<'_, OccurrenceEdit<'_>>>, config: &State<SeriesConfig>,
+                               cookies: &CookieJar<'_>) -> Option<Template> {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            let database = get_standard_database().await;
+            let (session, series) = load_occurrence(&database, id).await?;
+
+            let input = match edit.value {
+                Some(ref input) => input,
+                None => {
+                    let jwt = cookies.get_private("streamie.live").unwrap();
+                    return Some(Template::render("admin/series_occurrence", SeriesContext {
+                        jwt: jwt.value(),
+                        fullname: &fullname(cookies),
+                        token: t,
+                        error: None,
+                        plattforms: plattform_options(),
+                        timezone: user_timezone(cookies),
+                        timezones: timezone_names(),
+                        form: FormFeedback::from_context(&edit.context, &OCCURRENCE_FIELDS),
+                        series: Vec::new(),
+                        current: Some(TeraSeries::from_series(&series)),
+                        occurrences: Vec::new(),
+                        session: Some(TeraOccurrence {
+                            id: session.id.to_hex(),
+                            name: session.name,
+                            moved: false,
+                            start: session.start,
+                            end: session.end,
+                            occurrence: session.occurrence,
+                            status: session.status
+                        })
+                    }));
+                }
+            };
+
+            let changed = match form_series(input.start, input.end, input.name, input.description, input.link,
+                                            input.plattform, input.fallback, input.rrule.unwrap_or(""), &series.timezone) {
+                Ok(changed) => changed,
+                Err(e) => return Some(render_detail(cookies, t, &series, Some(e)).await)
+            };
+
+            let result = if input.scope == "this" {
+                // Nur dieser Termin: die Session wird geändert, die Serie bleibt wie sie ist
+                update_session(&database, &Session {
+                    start: changed.start,
+                    end: changed.start + changed.duration(),
+                    name: changed.name,
+                    description: changed.description,
+                    stream: changed.stream,
+                    ..session
+                }).await.map(|_| series.clone())
+            } else {
+                let pivot = session.occurrence.unwrap_or(session.start);
+                apply_future_edit(&database, &series, pivot, changed, config.limit()).await
+            };
+
+            return Some(match result {
+                Ok(series) => render_detail(cookies, t, &series, None).await,
+                Err(e) => render_detail(cookies, t, &series, Some(e.to_string())).await
+            });
+        },
+        None => {
+            return Some(Template::render("unauthorized", context!{}));
+        }
+    }
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SeriesResult {
+    pub status: u8
+}
+
+// Sagt einen einzelnen Termin ab: er wird als EXDATE in der Serie vermerkt und kommt in den Papierkorb
+#[post("/admin/series/occurrence/<id>/cancel")]
+pub async fn cancel_occurrence(id: &str, cookies: &CookieJar<'_>) -> Json<SeriesResult> {
+    if admin_token(cookies).is_none() {
+        return Json(SeriesResult { status: 0 });
+    }
+
+    let database = get_standard_database().await;
+    let (session, mut series) = match load_occurrence(&database, id).await {
+        Some(loaded) => loaded,
+        None => return Json(SeriesResult { status: 0 })
+    };
+
+    if let Some(occurrence) = session.occurrence {
+        if !series.exdates.contains(&occurrence) {
+            series.exdates.push(occurrence);
+            series.exdates.sort();
+        }
+    }
+    let result = match update_series(&database, &series).await {
+        Ok(()) => trash_session_by_id(&database, &session.id).await,
+        Err(e) => Err(e)
+    };
+
+    return Json(SeriesResult {
+        status: if matches!(result, Ok(true)) { 1 } else { 0 }
+    });
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            show_series,
+            add_new_series,
+            show_series_detail,
+            edit_occurrence,
+            update_occurrence,
+            cancel_occurrence
+    ]).attach(Template::fairing())
+        .manage(SeriesConfig::new(DEFAULT_SERIES_HORIZON_DAYS))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::TimeZone;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+    use crate::sessions::StreamType;
+
+    fn series(rrule: &str) -> SessionSeries {
+        return SessionSeries {
+            id: ObjectId::new(),
+            name: "Town Hall".to_string(),
+            description: "Wöchentlich".to_string(),
+            stream: SessionStream {
+                link: "".to_string(),
+                channel: "".to_string(),
+                stream_type: StreamType::None,
+                fallback: None
+            },
+            start: Utc.ymd(2022, 7, 7).and_hms(16, 0, 0),
+            duration_minutes: 60,
+            rrule: rrule.to_string(),
+            timezone: "Europe/Berlin".to_string(),
+            exdates: vec![Utc.ymd(2022, 7, 14).and_hms(16, 0, 0), Utc.ymd(2022, 7, 28).and_hms(16, 0, 0)],
+            version: 2,
+        };
+    }
+
+    #[test]
+    fn test_occurrence_session() {
+        let series = series("FREQ=WEEKLY;COUNT=3");
+        let session = occurrence_session(&series, Utc.ymd(2022, 7, 21).and_hms(16, 0, 0));
+        assert_eq!(session.slug, "town-hall-2022-07-21-1600");
+        assert_eq!(session.end, Utc.ymd(2022, 7, 21).and_hms(17, 0, 0));
+        assert_eq!(session.series_id, Some(series.id));
+        assert_eq!(session.occurrence, Some(session.start));
+
+        let limit = Utc.ymd(2023, 1, 1).and_hms(0, 0, 0);
+        assert_eq!(series.occurrences(limit), vec![Utc.ymd(2022, 7, 7).and_hms(16, 0, 0), Utc.ymd(2022, 7, 21).and_hms(16, 0, 0)]);
+    }
+
+    #[test]
+    fn test_split_series() {
+        let original = series("FREQ=WEEKLY;COUNT=6");
+        let pivot = Utc.ymd(2022, 7, 21).and_hms(16, 0, 0);
+        let mut changed = series("");
+        changed.start = Utc.ymd(2022, 7, 21).and_hms(17, 0, 0);
+
+        let (old, new) = split_series(&original, pivot, changed);
+        let old = old.unwrap();
+        assert_eq!(old.id, original.id);
+        assert_eq!(old.rrule, "FREQ=WEEKLY;UNTIL=20220721T155959Z");
+        assert_eq!(old.exdates, vec![Utc.ymd(2022, 7, 14).and_hms(16, 0, 0)]);
+        // Zwei der sechs Termine liegen vor dem Termin (einer davon ausgefallen)
+        assert_eq!(new.rrule, "FREQ=WEEKLY;COUNT=4");
+        assert_ne!(new.id, original.id);
+        assert_eq!(new.exdates, vec![Utc.ymd(2022, 7, 28).and_hms(16, 0, 0)]);
+
+        let limit = Utc.ymd(2023, 1, 1).and_hms(0, 0, 0);
+        assert_eq!(old.occurrences(limit), vec![Utc.ymd(2022, 7, 7).and_hms(16, 0, 0)]);
+        assert_eq!(new.occurrences(limit).len(), 4);
+
+        // Ab dem ersten Termin wird die Serie direkt geändert
+        let (old, new) = split_series(&original, original.start, series("FREQ=DAILY;COUNT=2"));
+        assert!(old.is_none());
+        assert_eq!(new.id, original.id);
+        assert_eq!(new.version, original.version);
+    }
+
+    #[test]
+    fn test_parse_exdates() {
+        let berlin = parse_timezone("Europe/Berlin").unwrap();
+        assert_eq!(parse_exdates("14.07.2022 18:00:00, 28.07.2022 18:00:00\n", berlin),
+                   Ok(vec![Utc.ymd(2022, 7, 14).and_hms(16, 0, 0), Utc.ymd(2022, 7, 28).and_hms(16, 0, 0)]));
+        assert_eq!(parse_exdates("14.07.2022 16:00:00", Tz::UTC), Ok(vec![Utc.ymd(2022, 7, 14).and_hms(16, 0, 0)]));
+        // Nach dem Ende der Sommerzeit liegt 18:00 eine Stunde später in UTC
+        assert_eq!(parse_exdates("03.11.2022 18:00:00", berlin), Ok(vec![Utc.ymd(2022, 11, 3).and_hms(17, 0, 0)]));
+        assert!(parse_exdates("27.03.2022 02:30:00", berlin).unwrap_err().contains("Zeitumstellung"));
+        assert_eq!(parse_exdates("", berlin), Ok(vec![]));
+        assert!(parse_exdates("14.07.2022", berlin).is_err());
+    }
+
+    #[test]
+    fn test_form_series_timezone() {
+        let form = |start: &str, end: &str, timezone: &str| form_series(start, end, "Town Hall", "Wöchentlich",
+            "", "None", None, "FREQ=WEEKLY;COUNT=3", timezone);
+        // Der erste Termin liegt vor, die weiteren nach dem Ende der Sommerzeit und bleiben um 18:00 Ortszeit
+        let series = form("20.10.2022 18:00:00", "20.10.2022 19:00:00", "Europe/Berlin").unwrap();
+        assert_eq!(series.start, Utc.ymd(2022, 10, 20).and_hms(16, 0, 0));
+        assert_eq!(series.duration_minutes, 60);
+        assert_eq!(series.occurrences(Utc.ymd(2023, 1, 1).and_hms(0, 0, 0)), vec![
+            Utc.ymd(2022, 10, 20).and_hms(16, 0, 0), Utc.ymd(2022, 10, 27).and_hms(16, 0, 0), Utc.ymd(2022, 11, 3).and_hms(17, 0, 0)
+        ]);
+        // Über die Zeitumstellung hinweg zählt die tatsächliche Dauer
+        assert_eq!(form("30.10.2022 01:30:00", "30.10.2022 03:30:00", "Europe/Berlin").unwrap().duration_minutes, 180);
+        assert_eq!(form("20.10.2022 18:00:00", "20.10.2022 19:00:00", "UTC").unwrap().start, Utc.ymd(2022, 10, 20).and_hms(18, 0, 0));
+        assert!(form("27.03.2022 02:30:00", "27.03.2022 04:00:00", "Europe/Berlin").unwrap_err().starts_with("Beginn"));
+    }
+
+    #[tokio::test]
+    async fn test_series_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get(uri!(super::show_series)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.post("/admin/series/occurrence/62a05c8631a6964f64d829ac/cancel")
+            .header(ContentType::Form)
+            .dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "{\"status\":0}");
+    }
+}
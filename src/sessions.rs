@@ -1,28 +1,83 @@
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use mongodb::bson::oid::ObjectId;
 use serde::Serialize;
 use serde::Deserialize;
 use rocket_dyn_templates::{Template, context};
 use rocket::http::{Cookie, CookieJar};
-use crate::security::{SecurityToken, validate_token};
+use rocket::http::uri::Host;
+use rocket::State;
+use crate::providers::{plattform_options, EmbedDescriptor, PlattformOption};
+use crate::security::{SecurityRole, SecurityToken, validate_token};
 use crate::database::{get_client, get_standard_database};
-use crate::database::get_all_sessions;
-use crate::database::get_session_by_id;
-
-// Aktuell nur Twitch und Youtube implementiert
-#[derive(Debug, Serialize, Deserialize)]
+use crate::database::find_sessions;
+use crate::database::get_session_by_key;
+use crate::database::get_session_attachments;
+use crate::attachments::TeraAttachment;
+use crate::access::{publicly_visible, viewer_from_token, SessionAccess, SessionVisibility};
+use crate::registration::{registration_info, RegistrationInfo, RegistrationSettings};
+use crate::agenda::{agenda_speakers, tera_agenda, AgendaSegment, SegmentState, TeraSegment};
+use crate::speakers::TeraSpeaker;
+use crate::events::{EventPlacement, TeraEvent};
+use crate::timezone::{legacy_timezone, user_timezone};
+use crate::markdown::{description_html, description_text, DescriptionFormat};
+use crate::posters::{Poster, TeraPoster};
+use crate::waiting_room::{TeraWaitingRoom, WaitingRoom};
+use crate::database::get_event_by_id;
+use crate::database::get_speakers;
+use crate::tracks::{chosen_track, parent_domains, session_tracks, track_embed, track_options, SessionTrack, TrackOption};
+use crate::listing::{Page, SessionFilter};
+use crate::stream_proxy::{stream_proxy_config, StreamProxyConfig};
+use crate::calendar::{base_url, calendar_token};
+
+// Die Einbettung der einzelnen Plattformen ist in providers.rs implementiert
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 pub enum StreamType {
     Twitch,
     Youtube,
+    Vimeo,
+    PeerTube,
+    Iframe,
+    // Selbst gehostetes HLS- oder DASH-Manifest
+    Manifest,
+    #[default]
     None
 }
 
+// Lebenszyklus einer Session, wird vom Scheduler in lifecycle.rs fortgeschrieben
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStatus {
+    #[default]
+    Scheduled,
+    Live,
+    Ended,
+    Archived
+}
+
+impl SessionStatus {
+    // Reihenfolge und Überschriften der Gruppen in /sessions
+    pub const ALL: [SessionStatus; 4] = [SessionStatus::Live, SessionStatus::Scheduled, SessionStatus::Ended, SessionStatus::Archived];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SessionStatus::Scheduled => "Geplant",
+            SessionStatus::Live => "Live",
+            SessionStatus::Ended => "Beendet",
+            SessionStatus::Archived => "Archiviert",
+        }
+    }
+}
+
 // Basis-Daten für einen Stream
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SessionStream {
     pub link: String,
     pub channel: String,
-    pub stream_type: StreamType
+    pub stream_type: StreamType,
+    // Optionale Ausweich-Quelle, wird aktuell nur bei selbst gehosteten Manifesten genutzt
+    #[serde(default)]
+    pub fallback: Option<String>
 }
 
 // Dieser Session-struct bildet das MongoDB deserialisierte Objekt ab
@@ -30,11 +85,109 @@ pub struct SessionStream {
 pub struct Session {
     #[serde(rename = "_id")]
     pub id: ObjectId,
+    // Eindeutiger, lesbarer Bezeichner der Session (unique Index)
+    #[serde(default)]
+    pub slug: String,
     pub start: DateTime<Utc>,
     pub end: DateTime<Utc>,
     pub name: String,
     pub description: String,
-    pub stream: SessionStream
+    pub stream: SessionStream,
+    // Wird bei jeder Änderung erhöht, verhindert dass sich gleichzeitige Änderungen überschreiben
+    #[serde(default)]
+    pub version: i64,
+    // Zeitpunkt an dem die Session in den Papierkorb verschoben wurde
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Schlüssel mit dem ein Encoder (z.B. OBS) auf den eigenen Ingest-Server streamen darf
+    #[serde(default)]
+    pub stream_key: Option<String>,
+    // Wird vom Ingest-Server über on_publish / on_publish_done gesetzt
+    #[serde(default)]
+    pub live: bool,
+    #[serde(default)]
+    pub live_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub status: SessionStatus,
+    // UID aus einer importierten Kalender- bzw. CSV-Datei, ein erneuter Import aktualisiert darüber die Session
+    #[serde(default)]
+    pub import_uid: Option<String>,
+    // Serie, aus der die Session als Termin erzeugt wurde
+    #[serde(default)]
+    pub series_id: Option<ObjectId>,
+    // Ursprünglicher Beginn laut Serien-Regel, bleibt auch beim Verschieben des einzelnen Termins gleich
+    #[serde(default)]
+    pub occurrence: Option<DateTime<Utc>>,
+    // Wer die Session sehen darf, ohne Angabe ist sie für alle angemeldeten User sichtbar
+    #[serde(default)]
+    pub access: SessionAccess,
+    // Anmeldung mit optionaler Platzzahl und Warteliste
+    #[serde(default)]
+    pub registration: RegistrationSettings,
+    // Alle Streams der Session (z.B. Hauptbühne, Gebärdensprache, Übersetzung), siehe tracks.rs
+    // Ohne Angabe gibt es nur den Stream aus stream
+    #[serde(default)]
+    pub tracks: Vec<SessionTrack>,
+    // Zeitlicher Ablauf mit Sprechern, nach Beginn sortiert (siehe agenda.rs)
+    #[serde(default)]
+    pub agenda: Vec<AgendaSegment>,
+    // Event (Konferenz), zu dem die Session gehört, samt Track innerhalb des Events
+    #[serde(default)]
+    pub event: Option<EventPlacement>,
+    // IANA-Zeitzone, in der Beginn und Ende eingegeben wurden und im Formular angezeigt werden
+    #[serde(default = "legacy_timezone")]
+    pub timezone: String,
+    // Klartext (Altbestand, Importe) oder Markdown aus dem Formular, siehe markdown.rs
+    #[serde(default)]
+    pub description_format: DescriptionFormat,
+    // Poster mit den erzeugten Varianten (siehe posters.rs)
+    #[serde(default)]
+    pub poster: Option<Poster>,
+    // Halte-Medium und Hinweis für den Warteraum vor dem Beginn (siehe waiting_room.rs)
+    #[serde(default)]
+    pub waiting_room: WaitingRoom
+}
+
+// Neue Session ohne Stream und ohne Einstellungen, Anlegen, Import und Serien ergänzen nur ihre eigenen Felder
+// Die übrigen Werte entsprechen den serde-Defaults für Sessions aus älteren Versionen
+impl Default for Session {
+    fn default() -> Self {
+        let now = Utc::now();
+        return Session {
+            id: ObjectId::new(),
+            slug: String::new(),
+            start: now,
+            end: now,
+            name: String::new(),
+            description: String::new(),
+            stream: SessionStream::default(),
+            version: 0,
+            deleted_at: None,
+            stream_key: None,
+            live: false,
+            live_since: None,
+            status: SessionStatus::Scheduled,
+            import_uid: None,
+            series_id: None,
+            occurrence: None,
+            access: SessionAccess::default(),
+            registration: RegistrationSettings::default(),
+            tracks: Vec::new(),
+            agenda: Vec::new(),
+            event: None,
+            timezone: legacy_timezone(),
+            description_format: DescriptionFormat::Plain,
+            poster: None,
+            waiting_room: WaitingRoom::default()
+        };
+    }
+}
+
+impl Session {
+    // Zeitzone der Eingaben, unbekannte Angaben werden wie Altbestand als UTC behandelt
+    pub fn tz(&self) -> Tz {
+        return self.timezone.parse().unwrap_or(Tz::UTC);
+    }
 }
 
 // Aufgrund des Problems, dass die MongoDB ObjectId von Tera nicht sauber verarbeitet werden kann
@@ -43,11 +196,77 @@ pub struct Session {
 pub struct TeraSession {
     #[serde(rename = "_id")]
     pub id: String,
+    pub slug: String,
     pub start: DateTime<Utc>,
     pub end: DateTime<Utc>,
     pub name: String,
     pub description: String,
-    pub stream: SessionStream
+    // Gerenderte und bereinigte Beschreibung für die Session-Seite bzw. ohne Formatierung für Listen
+    pub description_html: String,
+    pub description_text: String,
+    pub stream: SessionStream,
+    pub live: bool,
+    pub status: SessionStatus,
+    pub visibility: SessionVisibility,
+    #[serde(skip_deserializing)]
+    pub poster: Option<TeraPoster>
+}
+
+// Vorschau beim Teilen des Links (Open Graph), Crawler brauchen absolute URLs
+#[derive(Debug, Serialize)]
+pub struct OpenGraph {
+    pub title: String,
+    pub description: String,
+    pub url: String,
+    pub image: Option<String>,
+    pub image_width: u32,
+    pub image_height: u32
+}
+
+// Länge der Beschreibung in der Vorschau
+const OPEN_GRAPH_DESCRIPTION_LENGTH: usize = 200;
+
+impl OpenGraph {
+    pub fn from_session(session: &Session, host: Option<&Host<'_>>) -> OpenGraph {
+        let base = base_url(host);
+        let image = TeraPoster::from_session(session).and_then(|poster| poster.og);
+        let mut description = description_text(&session.description, session.description_format);
+        if description.chars().count() > OPEN_GRAPH_DESCRIPTION_LENGTH {
+            description = description.chars().take(OPEN_GRAPH_DESCRIPTION_LENGTH - 1).collect::<String>() + "…";
+        }
+        return OpenGraph {
+            title: session.name.clone(),
+            description,
+            url: format!("{}/session/{}", base, session.slug),
+            image_width: image.as_ref().map_or(0, |i| i.width),
+            image_height: image.as_ref().map_or(0, |i| i.height),
+            image: image.map(|i| format!("{}{}", base, i.url))
+        };
+    }
+}
+
+// Sessions einer Seite, gruppiert nach ihrem Status für /sessions
+#[derive(Debug, Serialize)]
+pub struct SessionGroup {
+    pub status: SessionStatus,
+    pub label: &'static str,
+    pub sessions: Vec<TeraSession>
+}
+
+// Teilt die Sessions in Gruppen in der Reihenfolge von SessionStatus::ALL, leere Gruppen entfallen
+pub fn group_by_status(sessions: Vec<TeraSession>) -> Vec<SessionGroup> {
+    let mut groups: Vec<SessionGroup> = SessionStatus::ALL.iter()
+        .map(|status| SessionGroup { status: *status, label: status.label(), sessions: Vec::new() })
+        .collect();
+
+    for session in sessions {
+        if let Some(group) = groups.iter_mut().find(|group| group.status == session.status) {
+            group.sessions.push(session);
+        }
+    }
+
+    groups.retain(|group| !group.sessions.is_empty());
+    return groups;
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -59,6 +278,18 @@ pub struct User {
     pub salt: String,
     pub role: String,
     pub fullname: String,
+    // Zeitpunkt an dem der User in den Papierkorb verschoben wurde
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Gruppen für die Freigabe eingeschränkter Sessions
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // IANA-Zeitzone für die Anzeige, ohne Angabe gilt timezone::DEFAULT_TIMEZONE
+    #[serde(default)]
+    pub timezone: Option<String>,
+    // Token für den Link zum Kalender-Abo, wird beim ersten Aufruf der Übersicht erzeugt
+    #[serde(default)]
+    pub calendar_token: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -70,14 +301,42 @@ pub struct TeraUser {
     pub salt: String,
     pub role: String,
     pub fullname: String,
+    pub groups: Vec<String>,
 }
 
 // Basis Zeit Formatierung (Europa)
 pub const FORMAT_STR: &str = "%d.%m.%Y %H:%M:%S";
 
+// Erzeugt aus einem Sessionnamen einen slug (nur Kleinbuchstaben, Ziffern und Bindestriche)
+// Umlaute werden umgeschrieben, alle anderen Zeichen werden zu einem Bindestrich zusammengefasst
+pub fn create_slug(name: &str) -> String {
+    let mut slug = String::new();
+
+    for c in name.to_lowercase().chars() {
+        match c {
+            'ä' => slug.push_str("ae"),
+            'ö' => slug.push_str("oe"),
+            'ü' => slug.push_str("ue"),
+            'ß' => slug.push_str("ss"),
+            c if c.is_ascii_alphanumeric() => slug.push(c),
+            _ => if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+    }
+
+    let slug = slug.trim_end_matches('-').to_string();
+    if slug.is_empty() {
+        return "session".to_string();
+    }
+
+    return slug;
+}
+
 // Übersichts-Liste aller Sessions
-#[get("/sessions")]
-pub async fn list_sessions(cookies: &CookieJar<'_>) -> Template {
+// Blättern, Filtern und Sortieren erfolgt über die Query-Parameter aus SessionFilter
+#[get("/sessions?<filter..>")]
+pub async fn list_sessions(filter: SessionFilter, cookies: &CookieJar<'_>) -> Template {
     
     // streamie.live ist der Standardcookie für den Auth-Token
     let token: Option<Cookie> = cookies.get_private("streamie.live");
@@ -102,30 +361,58 @@ pub async fn list_sessions(cookies: &CookieJar<'_>) -> Template {
             struct EventsContext<'a> {
                 jwt: &'a str,
                 fullname: &'a str,
-                sessions: Vec<TeraSession>,
+                sessions: Page<TeraSession>,
+                groups: Vec<SessionGroup>,
+                filter: &'a SessionFilter,
+                plattforms: Vec<PlattformOption>,
+                prev_query: Option<String>,
+                next_query: Option<String>,
+                // Token für das Kalender-Abo, wird in die Feed-URL eingesetzt
+                calendar_token: Option<String>,
+                // Zeitzone des Users für die Anzeige der Zeiten
+                timezone: String,
                 token: SecurityToken
             }
 
             let database = get_standard_database().await;
-            let streams: Vec<Session> = get_all_sessions(&database).await;
+            // Eingeschränkte und nicht gelistete Sessions werden bereits in der Abfrage herausgefiltert
+            let viewer = viewer_from_token(&database, &t).await;
+            let streams: Page<Session> = find_sessions(&database, &filter, &viewer).await
+                .expect("Error while find");
 
             // Aufgrund der MongoDB ObjectId müssen alle Sessions in eine eigene Tera-Session überführt werden
-            let mut tera_streams: Vec<TeraSession> = Vec::new();
-            for stream in streams {
-                tera_streams.push(TeraSession {
-                    id: stream.id.to_hex(),
-                    start: stream.start,
-                    end: stream.end,
-                    stream: stream.stream,
-                    description: stream.description,
-                    name: stream.name
-                });
-            }
+            let mut tera_streams: Page<TeraSession> = streams.map(|stream| TeraSession {
+                poster: TeraPoster::from_session(&stream),
+                id: stream.id.to_hex(),
+                slug: stream.slug,
+                start: stream.start,
+                end: stream.end,
+                stream: stream.stream,
+                description_html: description_html(&stream.description, stream.description_format),
+                description_text: description_text(&stream.description, stream.description_format),
+                description: stream.description,
+                name: stream.name,
+                live: stream.live,
+                status: stream.status,
+                visibility: stream.access.visibility
+            });
+
+            let groups = group_by_status(std::mem::take(&mut tera_streams.items));
+
+            let prev_query = if tera_streams.page > 1 { Some(filter.query_for_page(tera_streams.page - 1)) } else { None };
+            let next_query = if tera_streams.page < tera_streams.pages { Some(filter.query_for_page(tera_streams.page + 1)) } else { None };
 
             return Template::render("sessions/events", EventsContext {
                 jwt: token_value.value(),
                 fullname: fullname_value.value(),
                 sessions: tera_streams,
+                groups,
+                filter: &filter,
+                plattforms: plattform_options(),
+                prev_query,
+                next_query,
+                calendar_token: calendar_token(&database, &t.username).await.ok().flatten(),
+                timezone: user_timezone(cookies),
                 token: t
             });
         },
@@ -138,9 +425,9 @@ pub async fn list_sessions(cookies: &CookieJar<'_>) -> Template {
 }
 
 // Anzeige einer einzelnen Session
-// id ist hierbei eine MongoDB ObjectId als String
-#[get("/session/<id>")]
-pub async fn single_session(id: String, cookies: &CookieJar<'_>) -> Template {
+// key ist die MongoDB ObjectId als String oder der slug, /session/<slug> ist der Link zum Teilen
+#[get("/session/<key>")]
+pub async fn single_session(key: &str, host: Option<&Host<'_>>, proxy: &State<StreamProxyConfig>, cookies: &CookieJar<'_>) -> Option<Template> {
     
     // streamie.live ist der Standard-Cookie für den Auth Token
     let token: Option<Cookie> = cookies.get_private("streamie.live");
@@ -155,21 +442,86 @@ pub async fn single_session(id: String, cookies: &CookieJar<'_>) -> Template {
     match valuated_token {
         Some(t) => {
 
-            let current_session: Session;
-
-            // Suche nach der Session, auf welche navigiert wurde
-            // Hier fliegt ein panic wenn nicht gefunden -> könnte verschönert werden
+            // Suche nach der Session, auf welche navigiert wurde, unbekannte Sessions ergeben ein 404
             let database = get_standard_database().await;
-            current_session = get_session_by_id(&database, &ObjectId::parse_str(&id).unwrap()).await;
+            let current_session: Session = get_session_by_key(&database, key).await
+                .expect("Error while find")?;
+            let id = current_session.id.to_hex();
+
+            // Nicht gelistete Sessions sind über den Link erreichbar, eingeschränkte nur mit Freigabe
+            let viewer = viewer_from_token(&database, &t).await;
+            if !viewer.can_view_session(&current_session) {
+                return Some(Template::render("unauthorized", context!{}));
+            }
+
+            // Verlangt die Session eine Anmeldung, sehen nicht bestätigte User nur die Anmeldung
+            let registration = registration_info(&database, &current_session, &viewer).await;
+
+            // Bei mehreren Streams wird der zuletzt vom User gewählte eingebettet
+            let tracks = session_tracks(&current_session);
+            let selected = chosen_track(&database, &current_session, &tracks, &t.username).await;
+            let (embed, embed_error) = if registration.locked {
+                (None, None)
+            } else {
+                track_embed(&tracks[selected].stream, &id, selected, &parent_domains(host), proxy.stream_proxy)
+            };
+
+            // Anhänge zum Herunterladen, bei einem Datenbankfehler wird die Seite ohne Anhänge angezeigt
+            let attachments: Vec<TeraAttachment> = get_session_attachments(&database, &current_session.id).await
+                .unwrap_or_default()
+                .iter()
+                .filter(|_| !registration.locked)
+                .map(TeraAttachment::from_attachment)
+                .collect();
+
+            // Links und Plattformen der Streams bekommen nur Admins für das Bearbeiten zu sehen
+            let track_editor: Vec<SessionTrack> = if t.role == SecurityRole::ADMIN { tracks.clone() } else { Vec::new() };
+
+            // Ablauf mit Sprechern, der laufende Abschnitt wird anhand der Serverzeit markiert
+            let server_time = Utc::now();
+            let speakers = agenda_speakers(&database, &current_session.agenda).await;
+            let agenda = tera_agenda(&current_session.agenda, &speakers, server_time);
+            let session_speakers: Vec<TeraSpeaker> = speakers.iter().map(TeraSpeaker::from_speaker).collect();
+
+            // Für das Bearbeiten der Agenda braucht es alle Sprecher-Profile zur Auswahl
+            let (agenda_editor, all_speakers) = if t.role == SecurityRole::ADMIN {
+                (current_session.agenda.iter().map(|segment| SegmentState::from_segment(segment, current_session.tz())).collect(),
+                 get_speakers(&database).await.unwrap_or_default().iter().map(TeraSpeaker::from_speaker).collect())
+            } else {
+                (Vec::new(), Vec::new())
+            };
+
+            // Gehört die Session zu einem Event, verlinkt die Seite zurück auf dessen Startseite
+            let event = match &current_session.event {
+                Some(placement) => get_event_by_id(&database, &placement.event_id).await.ok().flatten()
+                    .map(|event| TeraEvent::from_event(&event)),
+                None => None
+            };
+
+            let session_timezone = current_session.timezone.clone();
+
+            let og = OpenGraph::from_session(&current_session, host);
+
+            // Vor dem Beginn sehen Zuschauer statt des noch leeren Streams den Warteraum mit Countdown
+            // Der Player wird erst beim Wechsel auf live per Server-Sent-Events nachgeladen
+            let waiting_room = if registration.locked { None } else { TeraWaitingRoom::from_session(&current_session, server_time) };
+            let waiting_room_editor = if t.role == SecurityRole::ADMIN { Some(current_session.waiting_room.clone()) } else { None };
 
             // Überführe die Session, falls gefunden in eine Tera Session
             let current_tera_session = TeraSession {
+                poster: TeraPoster::from_session(&current_session),
                 id: current_session.id.to_hex(),
+                slug: current_session.slug,
                 start: current_session.start,
                 end: current_session.end,
                 stream: current_session.stream,
+                description_html: description_html(&current_session.description, current_session.description_format),
+                description_text: description_text(&current_session.description, current_session.description_format),
                 description: current_session.description,
-                name: current_session.name
+                name: current_session.name,
+                live: current_session.live,
+                status: current_session.status,
+                visibility: current_session.access.visibility
             };
 
             #[derive(Serialize)]
@@ -177,6 +529,25 @@ pub async fn single_session(id: String, cookies: &CookieJar<'_>) -> Template {
                 jwt: &'a str,
                 fullname: &'a str,
                 session: TeraSession,
+                embed: Option<EmbedDescriptor>,
+                embed_error: Option<String>,
+                attachments: Vec<TeraAttachment>,
+                registration: RegistrationInfo,
+                tracks: Vec<TrackOption>,
+                track_editor: Vec<SessionTrack>,
+                plattforms: Vec<PlattformOption>,
+                agenda: Vec<TeraSegment>,
+                session_speakers: Vec<TeraSpeaker>,
+                agenda_editor: Vec<SegmentState>,
+                all_speakers: Vec<TeraSpeaker>,
+                server_time: DateTime<Utc>,
+                event: Option<TeraEvent>,
+                og: OpenGraph,
+                waiting_room: Option<TeraWaitingRoom>,
+                waiting_room_editor: Option<WaitingRoom>,
+                timezone: String,
+                // Zeitzone, in der Beginn und Ende der Session und ihrer Agenda eingegeben werden
+                session_timezone: String,
                 token: SecurityToken
             }
 
@@ -192,15 +563,40 @@ pub async fn single_session(id: String, cookies: &CookieJar<'_>) -> Template {
                 None => Cookie::new("fullname", "Unknown User")
             };
 
-            return Template::render("sessions/session", SessionContext {
+            return Some(Template::render("sessions/session", SessionContext {
                 jwt: token_value.value(),
                 fullname: fullname_value.value(),
                 session: current_tera_session,
+                embed,
+                embed_error,
+                attachments,
+                registration,
+                tracks: track_options(&tracks, selected),
+                track_editor,
+                plattforms: plattform_options(),
+                agenda,
+                session_speakers,
+                agenda_editor,
+                all_speakers,
+                server_time,
+                event,
+                og,
+                waiting_room,
+                waiting_room_editor,
+                timezone: user_timezone(cookies),
+                session_timezone,
                 token: t
-            });
+            }));
         },
         None => {
-            return  Template::render("unauthorized", context!{});
+            // Öffentliche Sessions liefern die Vorschau fürs Teilen auch ohne Anmeldung mit
+            let database = get_standard_database().await;
+            // Sessions in nicht öffentlichen Events bekommen keine Vorschau, auch wenn sie selbst öffentlich sind
+            let og = match get_session_by_key(&database, key).await.ok().flatten() {
+                Some(session) if matches!(publicly_visible(&database, &session).await, Ok(true)) => Some(OpenGraph::from_session(&session, host)),
+                _ => None
+            };
+            return Some(Template::render("unauthorized", context!{ og }));
         }
     }
 
@@ -216,19 +612,22 @@ fn rocket() -> _ {
             single_session,
             list_sessions,
     ]).attach(Template::fairing())
+        .attach(stream_proxy_config())
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::rocket;
+    use super::create_slug;
+    use crate::listing::SessionFilter;
     use rocket::http::Status;
     use rocket::local::asynchronous::Client;
 
     #[tokio::test]
     async fn test_session_list() {
         let client = Client::tracked(rocket()).await.expect("valid rocket instance");
-        let mut response = client.get(uri!(super::list_sessions)).dispatch();
+        let mut response = client.get(uri!(super::list_sessions(SessionFilter::default()))).dispatch();
         assert_eq!(response.await.status(), Status::Ok);
     }
 
@@ -238,4 +637,11 @@ mod tests {
         let mut response = client.get(uri!(super::single_session("62a05c8631a6964f64d829ac'".to_string()))).dispatch();
         assert_eq!(response.await.status(), Status::Ok);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_create_slug() {
+        assert_eq!(create_slug("Rust Meetup 2022"), "rust-meetup-2022");
+        assert_eq!(create_slug("  Größe & Übung!! "), "groesse-uebung");
+        assert_eq!(create_slug("---"), "session");
+    }
+}
@@ -0,0 +1,346 @@
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket::form::{Contextual, Form};
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Cookie, CookieJar, Header, Status};
+use rocket::State;
+use rocket_dyn_templates::{context, Template};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use crate::administration::FormFeedback;
+use crate::attachments::{attachment_config, AttachmentConfig};
+use crate::database::{add_speaker, get_speaker, get_speakers, get_standard_database, remove_speaker, update_speaker};
+use crate::security::{admin_token, user_token, SecurityToken};
+
+// Wiederverwendbares Profil eines Sprechers, wird in den Agenda-Abschnitten der Sessions referenziert
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Speaker {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    #[serde(default)]
+    pub bio: String,
+    #[serde(default)]
+    pub photo: Option<SpeakerPhoto>,
+    pub created_at: DateTime<Utc>
+}
+
+// Das Foto liegt unter <attachments_dir>/speakers/<id>.<Endung>
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeakerPhoto {
+    pub path: String,
+    pub content_type: String,
+    // Teil der URL, damit Browser nach einem neuen Foto nicht das alte aus dem Cache zeigen
+    pub updated_at: DateTime<Utc>
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TeraSpeaker {
+    pub id: String,
+    pub name: String,
+    pub bio: String,
+    pub photo_url: Option<String>
+}
+
+impl TeraSpeaker {
+    pub fn from_speaker(speaker: &Speaker) -> TeraSpeaker {
+        return TeraSpeaker {
+            id: speaker.id.to_hex(),
+            name: speaker.name.clone(),
+            bio: speaker.bio.clone(),
+            photo_url: speaker.photo.as_ref()
+                .map(|photo| format!("/speakers/{}/photo?v={}", speaker.id.to_hex(), photo.updated_at.timestamp()))
+        };
+    }
+}
+
+// Erkennt das Bildformat an den ersten Bytes, die Dateiendung des Uploads wird nicht beachtet
+// Liefert Endung und Content-Type
+pub fn detect_image(header: &[u8]) -> Option<(&'static str, &'static str)> {
+    if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some(("jpg", "image/jpeg"));
+    }
+    if header.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        return Some(("png", "image/png"));
+    }
+    if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WEBP" {
+        return Some(("webp", "image/webp"));
+    }
+    return None;
+}
+
+fn photo_dir(config: &AttachmentConfig) -> PathBuf {
+    return Path::new(&config.attachments_dir).join("speakers");
+}
+
+// Speichert das hochgeladene Foto, eine Datei in einem anderen Format als JPEG, PNG oder WebP wird verworfen
+async fn store_photo(file: &mut TempFile<'_>, id: &ObjectId, config: &AttachmentConfig) -> Result<SpeakerPhoto, String> {
+    let directory = photo_dir(config);
+    tokio::fs::create_dir_all(&directory).await
+        .map_err(|_| "Das Foto konnte nicht gespeichert werden".to_string())?;
+
+    let upload = directory.join(format!("{}.upload", id.to_hex()));
+    file.copy_to(&upload).await
+        .map_err(|_| "Das Foto konnte nicht gespeichert werden".to_string())?;
+
+    let mut header = [0u8; 12];
+    let read = match File::open(&upload).await {
+        Ok(mut f) => f.read(&mut header).await.unwrap_or(0),
+        Err(_) => 0
+    };
+    let (extension, content_type) = match detect_image(&header[..read]) {
+        Some(image) => image,
+        None => {
+            let _ = tokio::fs::remove_file(&upload).await;
+            return Err("Das Foto muss ein JPEG-, PNG- oder WebP-Bild sein".to_string());
+        }
+    };
+
+    let path = directory.join(format!("{}.{}", id.to_hex(), extension));
+    tokio::fs::rename(&upload, &path).await
+        .map_err(|_| "Das Foto konnte nicht gespeichert werden".to_string())?;
+
+    return Ok(SpeakerPhoto {
+        path: path.to_string_lossy().to_string(),
+        content_type: content_type.to_string(),
+        updated_at: Utc::now()
+    });
+}
+
+fn fullname(cookies: &CookieJar<'_>) -> String {
+    let fullname: Option<Cookie> = cookies.get_private("fullname");
+    return fullname.map(|f| f.value().to_string()).unwrap_or_else(|| "Unknown User".to_string());
+}
+
+// Felder des Formulars, die bei einem Fehler wieder angezeigt werden
+const SPEAKER_FIELDS: [&str; 2] = ["name", "bio"];
+
+#[derive(FromForm)]
+pub struct SpeakerForm<'r> {
+    #[field(validate = len(1..=80))]
+    pub name: &'r str,
+    #[field(validate = len(..=2000))]
+    pub bio: &'r str,
+    // Ein leeres Datei-Feld wird vom Browser trotzdem übertragen, es zählt dann als kein Foto
+    pub photo: Option<TempFile<'r>>,
+    pub remove_photo: bool
+}
+
+#[derive(Serialize)]
+struct SpeakersContext<'a> {
+    jwt: &'a str,
+    fullname: &'a str,
+    token: SecurityToken,
+    speakers: Vec<TeraSpeaker>,
+    // Sprecher, der gerade bearbeitet wird
+    current: Option<TeraSpeaker>,
+    error: Option<String>,
+    form: FormFeedback
+}
+
+async fn render_speakers(cookies: &CookieJar<'_>, token: SecurityToken, current: Option<&Speaker>,
+                         error: Option<String>, form: FormFeedback) -> Template {
+    let jwt = cookies.get_private("streamie.live").map(|c| c.value().to_string()).unwrap_or_default();
+    let database = get_standard_database().await;
+    let speakers = get_speakers(&database).await
+        .expect("Error while find")
+        .iter()
+        .map(TeraSpeaker::from_speaker)
+        .collect();
+
+    return Template::render("admin/speakers", SpeakersContext {
+        jwt: &jwt,
+        fullname: &fullname(cookies),
+        token,
+        speakers,
+        current: current.map(TeraSpeaker::from_speaker),
+        error,
+        form
+    });
+}
+
+// Übersicht aller Sprecher mit Formular für einen neuen Sprecher
+#[get("/admin/speakers")]
+pub async fn show_speakers(cookies: &CookieJar<'_>) -> Template {
+    match admin_token(cookies) {
+        Some(t) => return render_speakers(cookies, t, None, None, FormFeedback::default()).await,
+        None => return Template::render("unauthorized", context!{})
+    }
+}
+
+#[get("/admin/speakers/<id>")]
+pub async fn edit_speaker(id: &str, cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let database = get_standard_database().await;
+    let speaker = get_speaker(&database, &ObjectId::parse_str(id).ok()?).await.ok()??;
+    return Some(render_speakers(cookies, token, Some(&speaker), None, FormFeedback::default()).await);
+}
+
+// Legt einen Sprecher an (ohne id) oder ändert einen bestehenden
+async fn save_speaker(id: Option<&str>, mut form: Form<Contextual<'_, SpeakerForm<'_>>>, config: &AttachmentConfig,
+                      cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let database = get_standard_database().await;
+    let existing = match id {
+        Some(id) => Some(get_speaker(&database, &ObjectId::parse_str(id).ok()?).await.ok()??),
+        None => None
+    };
+
+    let feedback = FormFeedback::from_context(&form.context, &SPEAKER_FIELDS);
+    let input = match form.value.as_mut() {
+        Some(input) => input,
+        None => return Some(render_speakers(cookies, token, existing.as_ref(), None, feedback).await)
+    };
+
+    let mut speaker = existing.clone().unwrap_or_else(|| Speaker {
+        id: ObjectId::new(),
+        name: String::new(),
+        bio: String::new(),
+        photo: None,
+        created_at: Utc::now()
+    });
+    speaker.name = input.name.trim().to_string();
+    speaker.bio = input.bio.trim().to_string();
+
+    let old_photo = speaker.photo.clone();
+    if input.remove_photo {
+        speaker.photo = None;
+    }
+    if let Some(photo) = input.photo.as_mut().filter(|photo| photo.len() > 0) {
+        match store_photo(photo, &speaker.id, config).await {
+            Ok(photo) => speaker.photo = Some(photo),
+            Err(e) => return Some(render_speakers(cookies, token, existing.as_ref(), Some(e), feedback).await)
+        }
+    }
+
+    let result = match existing {
+        Some(_) => update_speaker(&database, &speaker).await,
+        None => add_speaker(&database, &speaker).await
+    };
+    if let Err(e) = result {
+        return Some(render_speakers(cookies, token, existing.as_ref(), Some(e.to_string()), feedback).await);
+    }
+
+    // Ein entferntes oder durch ein anderes Format ersetztes Foto wird gelöscht
+    if let Some(old) = old_photo {
+        if speaker.photo.as_ref().map_or(true, |photo| photo.path != old.path) {
+            let _ = tokio::fs::remove_file(&old.path).await;
+        }
+    }
+
+    return Some(render_speakers(cookies, token, None, None, FormFeedback::default()).await);
+}
+
+#[post("/admin/speakers", data = "<form>")]
+pub async fn add_new_speaker(form: Form<Contextual<'_, SpeakerForm<'_>>>, config: &State<AttachmentConfig>,
+                             cookies: &CookieJar<'_>) -> Option<Template> {
+    return save_speaker(None, form, config, cookies).await;
+}
+
+#[post("/admin/speakers/<id>", data = "<form>")]
+pub async fn update_existing_speaker(id: &str, form: Form<Contextual<'_, SpeakerForm<'_>>>, config: &State<AttachmentConfig>,
+                                     cookies: &CookieJar<'_>) -> Option<Template> {
+    return save_speaker(Some(id), form, config, cookies).await;
+}
+
+// Löscht den Sprecher samt Foto, aus den Agenden der Sessions wird er entfernt
+#[post("/admin/speakers/<id>/delete")]
+pub async fn delete_speaker(id: &str, cookies: &CookieJar<'_>) -> Option<Template> {
+    let token = match admin_token(cookies) {
+        Some(t) => t,
+        None => return Some(Template::render("unauthorized", context!{}))
+    };
+
+    let database = get_standard_database().await;
+    match remove_speaker(&database, &ObjectId::parse_str(id).ok()?).await {
+        Ok(Some(speaker)) => {
+            if let Some(photo) = speaker.photo {
+                let _ = tokio::fs::remove_file(&photo.path).await;
+            }
+            return Some(render_speakers(cookies, token, None, None, FormFeedback::default()).await);
+        },
+        Ok(None) => return None,
+        Err(e) => return Some(render_speakers(cookies, token, None, Some(e.to_string()), FormFeedback::default()).await)
+    }
+}
+
+#[derive(Responder)]
+pub struct SpeakerPhotoFile {
+    file: (ContentType, File),
+    nosniff: Header<'static>,
+    cache: Header<'static>,
+}
+
+// Foto eines Sprechers für angemeldete User, die URL ändert sich mit jedem neuen Foto
+#[get("/speakers/<id>/photo")]
+pub async fn speaker_photo(id: &str, cookies: &CookieJar<'_>) -> Result<SpeakerPhotoFile, Status> {
+    user_token(cookies).ok_or(Status::Unauthorized)?;
+
+    let id = ObjectId::parse_str(id).map_err(|_| Status::NotFound)?;
+    let database = get_standard_database().await;
+    let photo = get_speaker(&database, &id).await
+        .map_err(|_| Status::InternalServerError)?
+        .and_then(|speaker| speaker.photo)
+        .ok_or(Status::NotFound)?;
+    let file = File::open(&photo.path).await.map_err(|_| Status::NotFound)?;
+
+    return Ok(SpeakerPhotoFile {
+        file: (ContentType::parse_flexible(&photo.content_type).unwrap_or(ContentType::Binary), file),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+        cache: Header::new("Cache-Control", "private, max-age=86400"),
+    });
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            show_speakers,
+            edit_speaker,
+            add_new_speaker,
+            update_existing_speaker,
+            delete_speaker,
+            speaker_photo
+    ]).attach(Template::fairing())
+        .attach(attachment_config())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn test_detect_image() {
+        assert_eq!(detect_image(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00]), Some(("jpg", "image/jpeg")));
+        assert_eq!(detect_image(b"\x89PNG\r\n\x1a\n\x00\x00"), Some(("png", "image/png")));
+        assert_eq!(detect_image(b"RIFF\x10\x00\x00\x00WEBPVP8 "), Some(("webp", "image/webp")));
+        assert_eq!(detect_image(b"<svg xmlns="), None);
+        assert_eq!(detect_image(b""), None);
+    }
+
+    #[tokio::test]
+    async fn test_speakers_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get("/admin/speakers").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("Keine Berechtigung"));
+
+        let response = client.get("/speakers/62a05c8631a6964f64d829ac/photo").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use mongodb::bson::oid::ObjectId;
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, CookieJar, Status};
+use rocket::State;
+use serde::Deserialize;
+use url::Url;
+
+use crate::database::get_standard_database;
+use crate::access::{viewable_session, viewer_from_token};
+use crate::providers::{EmbedDescriptor, PlayerKind};
+use crate::security::user_token;
+use crate::sessions::StreamType;
+use crate::tracks::session_tracks;
+
+// Über stream_proxy = true in der Rocket.toml werden Manifeste und Segmente selbst gehosteter Streams
+// über den Server ausgeliefert, die URL des Encoders bekommen die Zuschauer dann nicht zu sehen
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamProxyConfig {
+    #[serde(default)]
+    pub stream_proxy: bool,
+}
+
+pub fn stream_proxy_config() -> AdHoc {
+    return AdHoc::config::<StreamProxyConfig>();
+}
+
+// Ein Client für alle Anfragen, damit Verbindungen zum Encoder wiederverwendet werden
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    return CLIENT.get_or_init(reqwest::Client::new);
+}
+
+// Verzeichnis des Manifests, relativ dazu werden Playlists und Segmente aufgelöst
+fn base_url(origin: &Url) -> Url {
+    return origin.join("./").unwrap_or_else(|_| origin.clone());
+}
+
+fn file_name(url: &Url) -> &str {
+    return url.path_segments()
+        .and_then(|segments| segments.last())
+        .unwrap_or("");
+}
+
+// Liefert die URL beim Encoder für einen über den Proxy angefragten Pfad
+// Es können nur Dateien unterhalb des Manifest-Verzeichnisses abgerufen werden
+pub fn resolve_upstream(origin: &Url, path: &str) -> Option<Url> {
+    // Das Manifest selbst wird inklusive Query (z.B. Token des Encoders) abgerufen
+    if path == file_name(origin) {
+        return Some(origin.clone());
+    }
+
+    let base = base_url(origin);
+    let target = base.join(path).ok()?;
+    if target.as_str().starts_with(base.as_str()) {
+        return Some(target);
+    }
+    return None;
+}
+
+// Absolute URLs unterhalb des Manifest-Verzeichnisses werden relativ, damit der Player sie über den Proxy lädt
+pub fn rewrite_manifest(manifest: &str, origin: &Url) -> String {
+    return manifest.replace(base_url(origin).as_str(), "");
+}
+
+// Name der Quelle im Proxy-Pfad: "main" und "fallback" für den ersten Stream der Session,
+// "track2", "track2-fallback" usw. für die weiteren Streams
+pub fn source_name(track: usize, fallback: bool) -> String {
+    return match (track, fallback) {
+        (0, false) => "main".to_string(),
+        (0, true) => "fallback".to_string(),
+        (track, false) => format!("track{}", track + 1),
+        (track, true) => format!("track{}-fallback", track + 1)
+    };
+}
+
+// Umkehrung von source_name, liefert Index des Streams und ob die Ausweich-Quelle gemeint ist
+pub fn parse_source(source: &str) -> Option<(usize, bool)> {
+    match source {
+        "main" => return Some((0, false)),
+        "fallback" => return Some((0, true)),
+        _ => {}
+    }
+    let rest = source.strip_prefix("track")?;
+    let (number, fallback) = match rest.strip_suffix("-fallback") {
+        Some(number) => (number, true),
+        None => (rest, false)
+    };
+    let number: usize = number.parse().ok()?;
+    if number < 2 {
+        return None;
+    }
+    return Some((number - 1, fallback));
+}
+
+// Ersetzt die Quellen der Einbettung durch die Proxy-Pfade des Streams der Session
+pub fn proxied_embed(embed: EmbedDescriptor, session_id: &str, track: usize) -> EmbedDescriptor {
+    if embed.player == PlayerKind::Iframe {
+        return embed;
+    }
+
+    let proxied = |source: String, url: &str| -> String {
+        let name = Url::parse(url).map(|url| file_name(&url).to_string()).unwrap_or_default();
+        return format!("/session/{}/stream/{}/{}", session_id, source, name);
+    };
+
+    return EmbedDescriptor {
+        player_url: proxied(source_name(track, false), &embed.player_url),
+        fallback_url: embed.fallback_url.as_deref().map(|fallback| proxied(source_name(track, true), fallback)),
+        ..embed
+    };
+}
+
+fn content_type_for(url: &Url, upstream: Option<&str>) -> ContentType {
+    let path = url.path().to_lowercase();
+    if path.ends_with(".m3u8") {
+        return ContentType::new("application", "vnd.apple.mpegurl");
+    }
+    if path.ends_with(".mpd") {
+        return ContentType::new("application", "dash+xml");
+    }
+    return upstream
+        .and_then(ContentType::parse_flexible)
+        .unwrap_or(ContentType::Binary);
+}
+
+// Liefert Manifest oder Segment eines selbst gehosteten Streams über den Server aus
+// source benennt Stream und Quelle (Manifest oder Ausweich-Quelle), siehe source_name
+#[get("/session/<id>/stream/<source>/<path..>")]
+pub async fn proxy_stream(id: &str, source: &str, path: PathBuf, config: &State<StreamProxyConfig>,
+                          cookies: &CookieJar<'_>) -> Result<(ContentType, Vec<u8>), Status> {
+    if !config.stream_proxy {
+        return Err(Status::NotFound);
+    }
+
+    let token = user_token(cookies).ok_or(Status::Unauthorized)?;
+
+    let session_id = ObjectId::parse_str(id).map_err(|_| Status::NotFound)?;
+    let database = get_standard_database().await;
+    // Der Stream einer eingeschränkten Session ist genauso geschützt wie ihre Seite
+    let viewer = viewer_from_token(&database, &token).await;
+    let session = viewable_session(&database, &viewer, &session_id).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let (track, fallback) = parse_source(source).ok_or(Status::NotFound)?;
+    let stream = session_tracks(&session).into_iter()
+        .nth(track)
+        .map(|track| track.stream)
+        .filter(|stream| stream.stream_type == StreamType::Manifest)
+        .ok_or(Status::NotFound)?;
+
+    let origin = if fallback { stream.fallback } else { Some(stream.link) };
+    let origin = origin
+        .and_then(|origin| Url::parse(&origin).ok())
+        .ok_or(Status::NotFound)?;
+
+    let path: Vec<String> = path.iter().map(|segment| segment.to_string_lossy().to_string()).collect();
+    let target = resolve_upstream(&origin, &path.join("/")).ok_or(Status::NotFound)?;
+
+    let response = http_client().get(target.clone()).send().await.map_err(|e| {
+        warn!("Stream proxy request to {} failed: {}", target, e);
+        Status::BadGateway
+    })?;
+    if !response.status().is_success() {
+        return Err(Status::BadGateway);
+    }
+
+    let upstream_type = response.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let content_type = content_type_for(&target, upstream_type.as_deref());
+    let body = response.bytes().await.map_err(|_| Status::BadGateway)?;
+
+    // Manifeste werden umgeschrieben, Segmente unverändert durchgereicht
+    if content_type == ContentType::new("application", "vnd.apple.mpegurl")
+        || content_type == ContentType::new("application", "dash+xml") {
+        let manifest = String::from_utf8_lossy(&body);
+        return Ok((content_type, rewrite_manifest(&manifest, &origin).into_bytes()));
+    }
+
+    return Ok((content_type, body.to_vec()));
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            proxy_stream
+    ]).attach(stream_proxy_config())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn test_resolve_upstream() {
+        let origin = Url::parse("https://live.example.com/hls/stream.m3u8?token=abc").unwrap();
+        assert_eq!(resolve_upstream(&origin, "stream.m3u8").unwrap().as_str(), "https://live.example.com/hls/stream.m3u8?token=abc");
+        assert_eq!(resolve_upstream(&origin, "720p/segment1.ts").unwrap().as_str(), "https://live.example.com/hls/720p/segment1.ts");
+        assert_eq!(resolve_upstream(&origin, "//evil.example.com/x.ts"), None);
+        assert_eq!(resolve_upstream(&origin, "https://evil.example.com/x.ts"), None);
+    }
+
+    #[test]
+    fn test_rewrite_manifest_and_embed() {
+        let origin = Url::parse("https://live.example.com/hls/stream.m3u8").unwrap();
+        let manifest = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=800000\nhttps://live.example.com/hls/720p.m3u8\nlow.m3u8\n";
+        assert_eq!(rewrite_manifest(manifest, &origin), "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=800000\n720p.m3u8\nlow.m3u8\n");
+
+        let embed = EmbedDescriptor {
+            player: PlayerKind::Hls,
+            player_url: origin.to_string(),
+            fallback_url: Some("https://cdn.example.com/pause.mp4".to_string()),
+            parent_domains: vec![],
+            aspect_ratio: "16 / 9".to_string(),
+        };
+        let proxied = proxied_embed(embed.clone(), "62a05c8631a6964f64d829ac", 0);
+        assert_eq!(proxied.player_url, "/session/62a05c8631a6964f64d829ac/stream/main/stream.m3u8");
+        assert_eq!(proxied.fallback_url.unwrap(), "/session/62a05c8631a6964f64d829ac/stream/fallback/pause.mp4");
+
+        let proxied = proxied_embed(embed, "62a05c8631a6964f64d829ac", 2);
+        assert_eq!(proxied.player_url, "/session/62a05c8631a6964f64d829ac/stream/track3/stream.m3u8");
+        assert_eq!(proxied.fallback_url.unwrap(), "/session/62a05c8631a6964f64d829ac/stream/track3-fallback/pause.mp4");
+    }
+
+    #[test]
+    fn test_parse_source() {
+        for track in 0..4 {
+            for fallback in [false, true] {
+                assert_eq!(parse_source(&source_name(track, fallback)), Some((track, fallback)));
+            }
+        }
+        assert_eq!(parse_source("track1"), None);
+        assert_eq!(parse_source("track"), None);
+        assert_eq!(parse_source("other"), None);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_disabled_by_default() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get("/session/62a05c8631a6964f64d829ac/stream/main/stream.m3u8").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,222 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::{Tz, TZ_VARIANTS};
+use rocket::form::{self, Form};
+use rocket::http::{Cookie, CookieJar};
+use rocket::serde::json::Json;
+use rocket_dyn_templates::{context, Template};
+use serde::Serialize;
+
+use crate::database::{get_standard_database, get_user_by_username, set_user_timezone};
+use crate::security::user_token;
+use crate::sessions::FORMAT_STR;
+
+// Zeitzone für User ohne eigene Einstellung, entspricht der bisherigen Anzeige
+pub const DEFAULT_TIMEZONE: &str = "Europe/Berlin";
+
+// Private Cookie mit der Zeitzone des Users, wird beim Login und beim Ändern der Einstellung gesetzt
+pub const TIMEZONE_COOKIE: &str = "timezone";
+
+// Sessions, die vor Einführung der Zeitzonen angelegt wurden, sind als UTC eingegeben worden
+pub fn legacy_timezone() -> String {
+    return "UTC".to_string();
+}
+
+pub fn parse_timezone(value: &str) -> Option<Tz> {
+    return value.trim().parse::<Tz>().ok();
+}
+
+pub fn valid_timezone<'v>(value: &str) -> form::Result<'v, ()> {
+    match parse_timezone(value) {
+        Some(_) => Ok(()),
+        None => Err(form::Error::validation(format!("Unbekannte Zeitzone '{}', z.B. Europe/Berlin", value)).into())
+    }
+}
+
+// Wandelt eine Eingabe im Format FORMAT_STR aus der Zeitzone tz nach UTC
+// Bei der Zeitumstellung doppelt vorkommende Zeiten werden wie beim Import der ersten Stunde zugeordnet,
+// in der übersprungenen Stunde gibt es die Zeit nicht
+pub fn local_to_utc(value: &str, tz: Tz) -> Result<DateTime<Utc>, String> {
+    let naive = NaiveDateTime::parse_from_str(value.trim(), FORMAT_STR)
+        .map_err(|_| "Bitte im Format D.M.Y H:M:SEC angeben, z.B. 09.07.2022 18:00:00".to_string())?;
+    return naive_to_utc(naive, tz);
+}
+
+// Wie local_to_utc für bereits gelesene Zeiten, z.B. aus Import-Dateien mit anderen Formaten
+pub fn naive_to_utc(naive: NaiveDateTime, tz: Tz) -> Result<DateTime<Utc>, String> {
+    return tz.from_local_datetime(&naive)
+        .earliest()
+        .map(|date| date.with_timezone(&Utc))
+        .ok_or_else(|| format!("Die Zeit {} existiert in {} wegen der Zeitumstellung nicht", naive.format(FORMAT_STR), tz.name()));
+}
+
+// Gegenstück zu local_to_utc für das Befüllen der Formulare
+pub fn utc_to_local(value: DateTime<Utc>, tz: Tz) -> String {
+    return value.with_timezone(&tz).format(FORMAT_STR).to_string();
+}
+
+// Zeitzone des angemeldeten Users für die Anzeige und als Vorgabe in den Formularen
+pub fn user_timezone(cookies: &CookieJar<'_>) -> String {
+    return cookies.get_private(TIMEZONE_COOKIE)
+        .map(|c| c.value().to_string())
+        .filter(|tz| parse_timezone(tz).is_some())
+        .unwrap_or_else(|| DEFAULT_TIMEZONE.to_string());
+}
+
+pub fn user_tz(cookies: &CookieJar<'_>) -> Tz {
+    return parse_timezone(&user_timezone(cookies)).unwrap_or(chrono_tz::Europe::Berlin);
+}
+
+// Alle bekannten IANA-Zeitzonen für die Auswahl in den Formularen
+pub fn timezone_names() -> Vec<&'static str> {
+    return TZ_VARIANTS.iter().map(|tz| tz.name()).collect();
+}
+
+// Einstellungen des Users, aktuell die Zeitzone
+#[get("/settings")]
+pub async fn show_settings(cookies: &CookieJar<'_>) -> Template {
+    let token = match user_token(cookies) {
+        Some(t) => t,
+        None => return Template::render("unauthorized", context!{})
+    };
+
+    let jwt = cookies.get_private("streamie.live").map(|c| c.value().to_string()).unwrap_or_default();
+    let fullname = cookies.get_private("fullname").map(|c| c.value().to_string()).unwrap_or_else(|| "Unknown User".to_string());
+    return Template::render("user/settings", context! {
+        jwt,
+        fullname,
+        token,
+        timezone: user_timezone(cookies),
+        timezones: timezone_names()
+    });
+}
+
+#[derive(FromForm)]
+pub struct TimezoneSetting<'r> {
+    timezone: &'r str
+}
+
+// status: 0 = fehlgeschlagen, 1 = gespeichert
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SettingsResult {
+    pub status: u8,
+    pub errors: Vec<String>
+}
+
+// Speichert die Zeitzone am User und im Cookie, damit sie ohne erneuten Login gilt
+#[post("/settings/timezone", data = "<form>")]
+pub async fn update_timezone(form: Form<TimezoneSetting<'_>>, cookies: &CookieJar<'_>) -> Json<SettingsResult> {
+    let token = match user_token(cookies) {
+        Some(t) => t,
+        None => return Json(SettingsResult { status: 0, errors: vec!["Keine Berechtigung".to_string()] })
+    };
+    let tz = match parse_timezone(form.timezone) {
+        Some(tz) => tz,
+        None => return Json(SettingsResult { status: 0, errors: vec![format!("Unbekannte Zeitzone '{}'", form.timezone)] })
+    };
+
+    let database = get_standard_database().await;
+    let user = match get_user_by_username(&database, &token.username).await {
+        Ok(Some(user)) => user,
+        _ => return Json(SettingsResult { status: 0, errors: vec!["Unbekannter User".to_string()] })
+    };
+    if let Err(e) = set_user_timezone(&database, &user.id, tz.name()).await {
+        error!("Failed to store timezone: {}", e);
+        return Json(SettingsResult { status: 0, errors: vec!["Die Zeitzone konnte nicht gespeichert werden".to_string()] });
+    }
+
+    cookies.add_private(Cookie::new(TIMEZONE_COOKIE, tz.name().to_string()));
+    return Json(SettingsResult { status: 1, errors: Vec::new() });
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            show_settings,
+            update_timezone
+    ]).attach(Template::fairing())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket_dyn_templates::tera::{Context, Tera};
+
+    fn berlin() -> Tz {
+        return parse_timezone("Europe/Berlin").unwrap();
+    }
+
+    #[test]
+    fn test_local_to_utc() {
+        // Sommerzeit (UTC+2) und Winterzeit (UTC+1)
+        assert_eq!(local_to_utc("09.07.2022 18:00:00", berlin()), Ok(Utc.ymd(2022, 7, 9).and_hms(16, 0, 0)));
+        assert_eq!(local_to_utc("09.12.2022 18:00:00", berlin()), Ok(Utc.ymd(2022, 12, 9).and_hms(17, 0, 0)));
+        assert_eq!(local_to_utc("09.07.2022 18:00:00", Tz::UTC), Ok(Utc.ymd(2022, 7, 9).and_hms(18, 0, 0)));
+        assert_eq!(local_to_utc("09.07.2022 18:00:00", parse_timezone("America/New_York").unwrap()),
+                   Ok(Utc.ymd(2022, 7, 9).and_hms(22, 0, 0)));
+        assert!(local_to_utc("2022-07-09 18:00", berlin()).is_err());
+    }
+
+    #[test]
+    fn test_dst_transitions() {
+        // Am 27.03.2022 springt die Uhr in Berlin von 02:00 auf 03:00, 02:30 gibt es nicht
+        assert!(local_to_utc("27.03.2022 02:30:00", berlin()).unwrap_err().contains("Zeitumstellung"));
+        assert_eq!(local_to_utc("27.03.2022 01:59:59", berlin()), Ok(Utc.ymd(2022, 3, 27).and_hms(0, 59, 59)));
+        assert_eq!(local_to_utc("27.03.2022 03:00:00", berlin()), Ok(Utc.ymd(2022, 3, 27).and_hms(1, 0, 0)));
+
+        // Am 30.10.2022 gibt es 02:30 zweimal, gewählt wird die erste (noch Sommerzeit)
+        assert_eq!(local_to_utc("30.10.2022 02:30:00", berlin()), Ok(Utc.ymd(2022, 10, 30).and_hms(0, 30, 0)));
+        assert_eq!(local_to_utc("30.10.2022 03:30:00", berlin()), Ok(Utc.ymd(2022, 10, 30).and_hms(2, 30, 0)));
+
+        // Zurück ins Formular ergibt sich wieder die Eingabe
+        assert_eq!(utc_to_local(Utc.ymd(2022, 10, 30).and_hms(0, 30, 0), berlin()), "30.10.2022 02:30:00");
+        assert_eq!(utc_to_local(Utc.ymd(2022, 10, 30).and_hms(1, 30, 0), berlin()), "30.10.2022 02:30:00");
+        assert_eq!(utc_to_local(Utc.ymd(2022, 3, 27).and_hms(1, 0, 0), berlin()), "27.03.2022 03:00:00");
+    }
+
+    // Die Templates rechnen mit dem date-Filter von Tera um, dieser muss dieselben Zeiten liefern
+    #[test]
+    fn test_template_rendering() {
+        let render = |time: DateTime<Utc>, timezone: &str| {
+            let mut context = Context::new();
+            context.insert("time", &time);
+            context.insert("timezone", timezone);
+            return Tera::one_off("{{ time | date(format=\"%d.%m.%Y %H:%M\", timezone=timezone) }}", &context, false).unwrap();
+        };
+        assert_eq!(render(Utc.ymd(2022, 7, 9).and_hms(16, 0, 0), "Europe/Berlin"), "09.07.2022 18:00");
+        assert_eq!(render(Utc.ymd(2022, 10, 30).and_hms(0, 30, 0), "Europe/Berlin"), "30.10.2022 02:30");
+        assert_eq!(render(Utc.ymd(2022, 10, 30).and_hms(1, 30, 0), "Europe/Berlin"), "30.10.2022 02:30");
+        assert_eq!(render(Utc.ymd(2022, 3, 27).and_hms(1, 0, 0), "Europe/Berlin"), "27.03.2022 03:00");
+        assert_eq!(render(Utc.ymd(2022, 7, 9).and_hms(16, 0, 0), "Asia/Kolkata"), "09.07.2022 21:30");
+        for name in timezone_names() {
+            render(Utc.ymd(2022, 7, 9).and_hms(16, 0, 0), name);
+        }
+    }
+
+    #[test]
+    fn test_valid_timezone() {
+        assert!(valid_timezone("Europe/Berlin").is_ok());
+        assert!(valid_timezone(" UTC ").is_ok());
+        assert!(valid_timezone("Mitteleuropa").is_err());
+        assert!(timezone_names().contains(&DEFAULT_TIMEZONE));
+    }
+
+    #[tokio::test]
+    async fn test_settings_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get("/settings").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("Keine Berechtigung"));
+
+        let response = client.post("/settings/timezone")
+            .header(ContentType::Form)
+            .body("timezone=Europe/Berlin")
+            .dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+    }
+}
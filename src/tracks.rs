@@ -0,0 +1,550 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket::form::Form;
+use rocket::http::{CookieJar, Status};
+use rocket::http::uri::Host;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::access::{viewable_session, viewer_from_token};
+use crate::database::{get_latest_track_choice, get_sessions_by_ids, get_standard_database, get_track_choice,
+                      set_session_tracks, set_track_choice, set_track_stream};
+use crate::lifecycle::{Lifecycle, SessionEvent, DEFAULT_ARCHIVE_AFTER_DAYS};
+use crate::providers::{embed_stream, parse_fallback_link, parse_stream_link, stream_type_from_plattform, EmbedDescriptor};
+use crate::security::{user_token, SecurityRole};
+use crate::sessions::{create_slug, Session, SessionStream, StreamType};
+use crate::stream_proxy::{proxied_embed, stream_proxy_config, StreamProxyConfig};
+
+// Mehr Streams sind auf einer Seite nicht sinnvoll auswählbar
+pub const MAX_TRACKS: usize = 8;
+
+// Ein Stream einer Session (z.B. Hauptbühne, Gebärdensprache, englische Übersetzung)
+// key ist innerhalb der Session eindeutig und wird für die gemerkte Auswahl der User genutzt
+// language ist ein Sprach-Tag nach BCP 47 (z.B. de, en, gsg für Deutsche Gebärdensprache)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionTrack {
+    pub key: String,
+    pub name: String,
+    #[serde(default)]
+    pub language: Option<String>,
+    pub stream: SessionStream
+}
+
+// Gemerkte Auswahl eines Users, eine je User und Session (unique Index)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackChoice {
+    pub username: String,
+    pub session_id: ObjectId,
+    pub track: String,
+    // Sprache des gewählten Streams, wird für Sessions ohne eigene Auswahl vorgeschlagen
+    #[serde(default)]
+    pub language: Option<String>,
+    pub chosen_at: DateTime<Utc>
+}
+
+// Alle Streams der Session in ihrer Reihenfolge
+// Der erste Stream spielt immer session.stream, damit Import, Serien und Ingest weiterhin nur diesen pflegen
+// Sessions ohne eigene Liste haben genau einen Stream
+pub fn session_tracks(session: &Session) -> Vec<SessionTrack> {
+    let mut tracks = session.tracks.clone();
+    match tracks.first_mut() {
+        Some(first) => first.stream = session.stream.clone(),
+        None => tracks.push(SessionTrack {
+            key: "main".to_string(),
+            name: "Stream".to_string(),
+            language: None,
+            stream: session.stream.clone()
+        })
+    }
+    return tracks;
+}
+
+// Vereinfachte Prüfung nach BCP 47: Sprache aus 2-3 Buchstaben, danach Subtags aus 1-8 Buchstaben oder Ziffern
+pub fn valid_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or("");
+    if !(2..=3).contains(&primary.len()) || !primary.chars().all(|c| c.is_ascii_alphabetic()) {
+        return false;
+    }
+    return subtags.all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()));
+}
+
+// Gewählt wird der gemerkte Stream, sonst einer in der zuletzt gewählten Sprache, sonst der erste
+// Bei der Sprache reicht auch die Übereinstimmung der Hauptsprache (de-AT passt zu de)
+pub fn select_track(tracks: &[SessionTrack], choice: Option<&str>, language: Option<&str>) -> usize {
+    if let Some(index) = choice.and_then(|key| tracks.iter().position(|t| t.key == key)) {
+        return index;
+    }
+    let language = match language {
+        Some(language) => language.to_lowercase(),
+        None => return 0
+    };
+    let primary = |tag: &str| tag.split('-').next().unwrap_or("").to_string();
+    let languages: Vec<String> = tracks.iter()
+        .map(|t| t.language.as_deref().unwrap_or("").to_lowercase())
+        .collect();
+    return languages.iter().position(|l| *l == language)
+        .or_else(|| languages.iter().position(|l| !l.is_empty() && primary(l) == primary(&language)))
+        .unwrap_or(0);
+}
+
+// Die Auswahl auf der Session-Seite, der Link des Streams bleibt den Zuschauern verborgen
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TrackOption {
+    pub key: String,
+    pub name: String,
+    pub language: Option<String>,
+    pub selected: bool
+}
+
+pub fn track_options(tracks: &[SessionTrack], selected: usize) -> Vec<TrackOption> {
+    return tracks.iter().enumerate()
+        .map(|(index, track)| TrackOption {
+            key: track.key.clone(),
+            name: track.name.clone(),
+            language: track.language.clone(),
+            selected: index == selected
+        })
+        .collect();
+}
+
+// Der Player darf unter der aufgerufenen Domain eingebettet werden (z.B. Twitch parent=)
+pub fn parent_domains(host: Option<&Host<'_>>) -> Vec<String> {
+    return vec![host.map(|h| h.domain().to_string()).unwrap_or_else(|| "localhost".to_string())];
+}
+
+// Einbettung eines Streams der Session und ggf. die Fehlermeldung, warum er nicht eingebettet werden kann
+// Selbst gehostete Streams werden bei aktiviertem Proxy über den Server ausgeliefert
+pub fn track_embed(stream: &SessionStream, session_id: &str, track: usize, parent_domains: &[String],
+                   proxy: bool) -> (Option<EmbedDescriptor>, Option<String>) {
+    match embed_stream(stream, parent_domains) {
+        Some(Ok(embed)) if proxy => (Some(proxied_embed(embed, session_id, track)), None),
+        Some(Ok(embed)) => (Some(embed), None),
+        Some(Err(e)) => (None, Some(e.to_string())),
+        None => (None, None)
+    }
+}
+
+// Liefert den Index des Streams, den der User zuletzt gewählt hat
+// Fehler beim Laden der Auswahl führen zum ersten Stream
+pub async fn chosen_track(database: &mongodb::Database, session: &Session, tracks: &[SessionTrack], username: &str) -> usize {
+    if tracks.len() < 2 {
+        return 0;
+    }
+    let choice = get_track_choice(database, username, &session.id).await.ok().flatten();
+    let latest = match choice {
+        Some(_) => None,
+        None => get_latest_track_choice(database, username).await.ok().flatten()
+    };
+    return select_track(tracks,
+                        choice.as_ref().map(|c| c.track.as_str()),
+                        latest.as_ref().and_then(|c| c.language.as_deref()));
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TrackResult {
+    pub status: u8,
+    pub errors: Vec<String>
+}
+
+impl TrackResult {
+    fn ok() -> Json<TrackResult> {
+        return Json(TrackResult { status: 1, errors: Vec::new() });
+    }
+
+    fn failed(errors: Vec<String>) -> Json<TrackResult> {
+        return Json(TrackResult { status: 0, errors });
+    }
+}
+
+#[derive(FromForm)]
+pub struct TrackSelection<'r> {
+    pub track: &'r str
+}
+
+// Merkt sich den gewählten Stream des Users, die Seite lädt danach mit diesem Stream neu
+#[post("/session/<id>/track", data = "<selection>")]
+pub async fn select_session_track(id: &str, selection: Form<TrackSelection<'_>>, cookies: &CookieJar<'_>) -> Json<TrackResult> {
+    let token = match user_token(cookies) {
+        Some(t) => t,
+        None => return TrackResult::failed(vec!["Nicht angemeldet".to_string()])
+    };
+
+    let session_id = match ObjectId::parse_str(id) {
+        Ok(session_id) => session_id,
+        Err(_) => return TrackResult::failed(vec!["Unbekannte Session".to_string()])
+    };
+
+    let database = get_standard_database().await;
+    let viewer = viewer_from_token(&database, &token).await;
+    let session = match viewable_session(&database, &viewer, &session_id).await {
+        Ok(Some(session)) => session,
+        _ => return TrackResult::failed(vec!["Unbekannte Session".to_string()])
+    };
+
+    let track = match session_tracks(&session).into_iter().find(|t| t.key == selection.track) {
+        Some(track) => track,
+        None => return TrackResult::failed(vec!["Unbekannter Stream".to_string()])
+    };
+
+    let choice = TrackChoice {
+        username: token.username,
+        session_id,
+        track: track.key,
+        language: track.language,
+        chosen_at: Utc::now()
+    };
+    match set_track_choice(&database, &choice).await {
+        Ok(()) => return TrackResult::ok(),
+        Err(e) => {
+            error!("Failed to store track choice: {}", e);
+            return TrackResult::failed(vec!["Die Auswahl konnte nicht gespeichert werden".to_string()]);
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TrackEmbed {
+    pub embed: Option<EmbedDescriptor>,
+    pub embed_error: Option<String>
+}
+
+// Aktuelle Einbettung eines Streams, wird nach dem Umschalten der Quelle vom Player abgefragt
+#[get("/session/<id>/embed/<track>")]
+pub async fn session_track_embed(id: &str, track: &str, host: Option<&Host<'_>>, proxy: &State<StreamProxyConfig>,
+                                 cookies: &CookieJar<'_>) -> Result<Json<TrackEmbed>, Status> {
+    let token = user_token(cookies).ok_or(Status::Unauthorized)?;
+    let session_id = ObjectId::parse_str(id).map_err(|_| Status::NotFound)?;
+
+    let database = get_standard_database().await;
+    let viewer = viewer_from_token(&database, &token).await;
+    let session = viewable_session(&database, &viewer, &session_id).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let tracks = session_tracks(&session);
+    let index = tracks.iter().position(|t| t.key == track).ok_or(Status::NotFound)?;
+    let (embed, embed_error) = track_embed(&tracks[index].stream, id, index, &parent_domains(host), proxy.stream_proxy);
+    return Ok(Json(TrackEmbed { embed, embed_error }));
+}
+
+#[derive(FromForm)]
+pub struct SourceSwitch<'r> {
+    // Ohne Angabe wird der Haupt-Stream umgeschaltet
+    pub track: Option<&'r str>,
+    pub plattform: &'r str,
+    pub link: &'r str,
+    pub fallback: Option<&'r str>
+}
+
+// Schaltet einen Stream einer laufenden Session auf eine andere Quelle (z.B. Backup-Link oder andere Plattform)
+// Alle Zuschauer dieses Streams bekommen die Änderung per Server-Sent-Events und tauschen den Player ohne Neuladen
+#[post("/admin/session/<id>/source", data = "<form>")]
+pub async fn switch_source(id: &str, form: Form<SourceSwitch<'_>>, lifecycle: &State<Lifecycle>,
+                           cookies: &CookieJar<'_>) -> Json<TrackResult> {
+    match user_token(cookies) {
+        Some(t) if t.role == SecurityRole::ADMIN => {},
+        _ => return TrackResult::failed(vec!["Keine Berechtigung".to_string()])
+    };
+
+    let session_id = match ObjectId::parse_str(id) {
+        Ok(session_id) => session_id,
+        Err(_) => return TrackResult::failed(vec!["Unbekannte Session".to_string()])
+    };
+
+    let stream = match parse_stream(form.plattform, form.link, form.fallback) {
+        Ok(stream) => stream,
+        Err(errors) => return TrackResult::failed(errors)
+    };
+
+    let database = get_standard_database().await;
+    let session = match get_sessions_by_ids(&database, &[session_id]).await {
+        Ok(sessions) => match sessions.into_iter().next() {
+            Some(session) => session,
+            None => return TrackResult::failed(vec!["Unbekannte Session".to_string()])
+        },
+        Err(e) => {
+            error!("Failed to load session for source switch: {}", e);
+            return TrackResult::failed(vec!["Die Session konnte nicht geladen werden".to_string()]);
+        }
+    };
+
+    let tracks = session_tracks(&session);
+    let index = match form.track.map(str::trim).filter(|t| !t.is_empty()) {
+        Some(key) => match tracks.iter().position(|t| t.key == key) {
+            Some(index) => index,
+            None => return TrackResult::failed(vec!["Unbekannter Stream".to_string()])
+        },
+        None => 0
+    };
+
+    match set_track_stream(&database, &session_id, index, &stream).await {
+        Ok(true) => {
+            info!("Source of track '{}' in session '{}' switched to {:?}", tracks[index].key, session.name, stream.stream_type);
+            // Ohne verbundene Zuschauer schlägt send fehl, das ist kein Fehler
+            let _ = lifecycle.events.send(SessionEvent::Source {
+                session_id: session_id.to_hex(),
+                track: tracks[index].key.clone()
+            });
+            return TrackResult::ok();
+        },
+        Ok(false) => return TrackResult::failed(vec!["Unbekannte Session".to_string()]),
+        Err(e) => {
+            error!("Failed to switch stream source: {}", e);
+            return TrackResult::failed(vec!["Die Quelle konnte nicht umgeschaltet werden".to_string()]);
+        }
+    }
+}
+
+// Eine Zeile des Formulars, die Felder werden als tracks[0].name usw. übertragen
+#[derive(FromForm)]
+pub struct TrackInput<'r> {
+    pub name: &'r str,
+    pub language: Option<&'r str>,
+    pub plattform: &'r str,
+    pub link: &'r str,
+    pub fallback: Option<&'r str>
+}
+
+#[derive(FromForm)]
+pub struct TracksForm<'r> {
+    pub tracks: Vec<TrackInput<'r>>
+}
+
+// Prüft Plattform, Link und Ausweich-Quelle eines Streams, der channel wird aus dem Link abgeleitet
+pub fn parse_stream(plattform: &str, link: &str, fallback: Option<&str>) -> Result<SessionStream, Vec<String>> {
+    let mut errors: Vec<String> = Vec::new();
+
+    let stream_type = stream_type_from_plattform(plattform);
+    let link = link.trim();
+    let channel = match parse_stream_link(&stream_type, link) {
+        Ok(channel) => channel,
+        Err(e) => {
+            errors.push(e.to_string());
+            String::new()
+        }
+    };
+
+    let fallback = match fallback.map(str::trim).filter(|f| !f.is_empty()) {
+        Some(fallback) => match parse_fallback_link(fallback) {
+            Ok(fallback) => Some(fallback),
+            Err(e) => {
+                errors.push(e.to_string());
+                None
+            }
+        },
+        None => None
+    };
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(SessionStream {
+        link: if stream_type == StreamType::None { String::new() } else { link.to_string() },
+        channel,
+        stream_type,
+        fallback
+    });
+}
+
+// Prüft alle Zeilen und liefert sämtliche Fehler auf einmal, damit der Admin sie in einem Durchgang korrigieren kann
+// Die keys werden aus den Namen erzeugt und bei gleichen Namen durchnummeriert
+pub fn parse_tracks(inputs: &[TrackInput<'_>]) -> Result<Vec<SessionTrack>, Vec<String>> {
+    let mut errors: Vec<String> = Vec::new();
+    let mut tracks: Vec<SessionTrack> = Vec::new();
+
+    if inputs.is_empty() {
+        errors.push("Die Session braucht mindestens einen Stream".to_string());
+    }
+    if inputs.len() > MAX_TRACKS {
+        errors.push(format!("Es sind höchstens {} Streams möglich", MAX_TRACKS));
+    }
+
+    for (index, input) in inputs.iter().enumerate() {
+        let row = index + 1;
+        let name = input.name.trim();
+        if name.is_empty() || name.chars().count() > 60 {
+            errors.push(format!("Stream {}: Der Name muss 1 bis 60 Zeichen lang sein", row));
+        }
+
+        let language = input.language.map(str::trim).filter(|l| !l.is_empty());
+        if let Some(language) = language {
+            if !valid_language_tag(language) {
+                errors.push(format!("Stream {}: '{}' ist kein gültiges Sprach-Tag (z.B. de, en-GB, gsg)", row, language));
+            }
+        }
+
+        let stream = match parse_stream(input.plattform, input.link, input.fallback) {
+            Ok(stream) => stream,
+            Err(stream_errors) => {
+                errors.extend(stream_errors.into_iter().map(|e| format!("Stream {}: {}", row, e)));
+                continue;
+            }
+        };
+
+        let base = create_slug(name);
+        let mut key = base.clone();
+        let mut counter = 2;
+        while tracks.iter().any(|t| t.key == key) {
+            key = format!("{}-{}", base, counter);
+            counter += 1;
+        }
+
+        tracks.push(SessionTrack {
+            key,
+            name: name.to_string(),
+            language: language.map(str::to_string),
+            stream
+        });
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(tracks);
+}
+
+// Speichert die Streams einer Session, der erste wird zum Haupt-Stream der Session
+#[post("/admin/session/<id>/tracks", data = "<form>")]
+pub async fn update_tracks(id: &str, form: Form<TracksForm<'_>>, cookies: &CookieJar<'_>) -> Json<TrackResult> {
+    match user_token(cookies) {
+        Some(t) if t.role == SecurityRole::ADMIN => {},
+        _ => return TrackResult::failed(vec!["Keine Berechtigung".to_string()])
+    };
+
+    let session_id = match ObjectId::parse_str(id) {
+        Ok(session_id) => session_id,
+        Err(_) => return TrackResult::failed(vec!["Unbekannte Session".to_string()])
+    };
+
+    let tracks = match parse_tracks(&form.tracks) {
+        Ok(tracks) => tracks,
+        Err(errors) => return TrackResult::failed(errors)
+    };
+
+    let database = get_standard_database().await;
+    match set_session_tracks(&database, &session_id, &tracks).await {
+        Ok(true) => return TrackResult::ok(),
+        Ok(false) => return TrackResult::failed(vec!["Unbekannte Session".to_string()]),
+        Err(e) => {
+            error!("Failed to update session tracks: {}", e);
+            return TrackResult::failed(vec!["Die Streams konnten nicht gespeichert werden".to_string()]);
+        }
+    }
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            select_session_track,
+            session_track_embed,
+            switch_source,
+            update_tracks
+    ]).attach(stream_proxy_config())
+        .manage(Lifecycle::new(DEFAULT_ARCHIVE_AFTER_DAYS))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+
+    fn track(key: &str, language: Option<&str>) -> SessionTrack {
+        return SessionTrack {
+            key: key.to_string(),
+            name: key.to_string(),
+            language: language.map(str::to_string),
+            stream: SessionStream { link: String::new(), channel: String::new(), stream_type: StreamType::None, fallback: None }
+        };
+    }
+
+    fn input<'r>(name: &'r str, language: Option<&'r str>, plattform: &'r str, link: &'r str) -> TrackInput<'r> {
+        return TrackInput { name, language, plattform, link, fallback: None };
+    }
+
+    #[test]
+    fn test_valid_language_tag() {
+        assert!(valid_language_tag("de"));
+        assert!(valid_language_tag("en-GB"));
+        assert!(valid_language_tag("gsg"));
+        assert!(valid_language_tag("sgn-DE"));
+        assert!(!valid_language_tag("deutsch"));
+        assert!(!valid_language_tag("de-"));
+        assert!(!valid_language_tag("d3"));
+    }
+
+    #[test]
+    fn test_select_track() {
+        let tracks = vec![track("main", Some("de")), track("dgs", Some("gsg")), track("english", Some("en-GB"))];
+        assert_eq!(select_track(&tracks, Some("english"), Some("gsg")), 2);
+        // Ein gelöschter Stream fällt auf die zuletzt gewählte Sprache zurück
+        assert_eq!(select_track(&tracks, Some("removed"), Some("gsg")), 1);
+        assert_eq!(select_track(&tracks, None, Some("en")), 2);
+        assert_eq!(select_track(&tracks, None, Some("fr")), 0);
+        assert_eq!(select_track(&tracks, None, None), 0);
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let stream = parse_stream("Youtube", " https://www.youtube.com/watch?v=dQw4w9WgXcQ ", None).unwrap();
+        assert_eq!(stream.stream_type, StreamType::Youtube);
+        assert_eq!(stream.link, "https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+
+        // Ohne Plattform wird der Link verworfen
+        assert_eq!(parse_stream("None", "https://example.com", None).unwrap().link, "");
+        assert_eq!(parse_stream("Twitch", "https://example.com/x", Some("ftp://backup")).unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_tracks() {
+        let tracks = parse_tracks(&[
+            input("Hauptbühne", Some("de"), "Twitch", "https://www.twitch.tv/primeleague"),
+            input("Hauptbühne", Some("gsg"), "Youtube", "https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            input("English", None, "None", "")
+        ]).unwrap();
+        assert_eq!(tracks.iter().map(|t| t.key.as_str()).collect::<Vec<&str>>(), vec!["hauptbuehne", "hauptbuehne-2", "english"]);
+        assert_eq!(tracks[0].stream.channel, "primeleague");
+        assert_eq!(tracks[1].language.as_deref(), Some("gsg"));
+
+        let errors = parse_tracks(&[
+            input("", Some("deutsch"), "Twitch", "https://example.com/stream")
+        ]).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(parse_tracks(&[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tracks_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post("/admin/session/62a05c8631a6964f64d829ac/tracks")
+            .header(ContentType::Form)
+            .body("tracks[0].name=Main&tracks[0].plattform=None&tracks[0].link=")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+
+        let response = client.post("/session/62a05c8631a6964f64d829ac/track")
+            .header(ContentType::Form)
+            .body("track=main")
+            .dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+
+        let response = client.post("/admin/session/62a05c8631a6964f64d829ac/source")
+            .header(ContentType::Form)
+            .body("plattform=Twitch&link=https://www.twitch.tv/backup")
+            .dispatch().await;
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+
+        let response = client.get("/session/62a05c8631a6964f64d829ac/embed/main").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
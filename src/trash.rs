@@ -0,0 +1,226 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket::fairing::AdHoc;
+use rocket::http::{Cookie, CookieJar};
+use rocket::serde::json::Json;
+use rocket_dyn_templates::{Template, context};
+use serde::Serialize;
+
+use crate::database::{get_standard_database, get_trashed_sessions, get_trashed_users, purge_trash,
+                      purge_trashed_session, purge_trashed_user, restore_session_by_id, restore_user_by_id};
+use crate::security::{admin_token, SecurityToken};
+use crate::timezone::user_timezone;
+
+// Standard-Aufbewahrungsdauer im Papierkorb, kann über trash_retention_days in der Rocket.toml geändert werden
+pub const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+// Intervall in dem der Papierkorb auf abgelaufene Einträge geprüft wird
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TrashResult {
+    pub status: u8
+}
+
+// Eintrag im Papierkorb für das Template (Session oder User)
+#[derive(Debug, Serialize)]
+pub struct TeraTrashEntry {
+    pub id: String,
+    pub name: String,
+    pub detail: String,
+    pub deleted_at: Option<DateTime<Utc>>
+}
+
+fn trash_result(success: bool) -> Json<TrashResult> {
+    return Json(TrashResult {
+        status: if success { 1 } else { 0 }
+    });
+}
+
+// Übersicht über alle gelöschten Sessions und User
+#[get("/admin/trash")]
+pub async fn show_trash(cookies: &CookieJar<'_>) -> Template {
+
+    match admin_token(cookies) {
+        Some(t) => {
+            let jwt = cookies.get_private("streamie.live").unwrap();
+            let fullname: Option<Cookie> = cookies.get_private("fullname");
+            let fullname_value = match fullname {
+                Some(fullname) => fullname,
+                None => Cookie::new("fullname", "Unknown User")
+            };
+
+            let database = get_standard_database().await;
+            let sessions: Vec<TeraTrashEntry> = get_trashed_sessions(&database).await
+                .expect("Error while find")
+                .into_iter()
+                .map(|session| TeraTrashEntry {
+                    id: session.id.to_hex(),
+                    name: session.name,
+                    detail: session.slug,
+                    deleted_at: session.deleted_at
+                })
+                .collect();
+            let users: Vec<TeraTrashEntry> = get_trashed_users(&database).await
+                .expect("Error while find")
+                .into_iter()
+                .map(|user| TeraTrashEntry {
+                    id: user.id.to_hex(),
+                    name: user.fullname,
+                    detail: user.username,
+                    deleted_at: user.deleted_at
+                })
+                .collect();
+
+            #[derive(Serialize)]
+            struct TrashContext<'a> {
+                jwt: &'a str,
+                fullname: &'a str,
+                sessions: Vec<TeraTrashEntry>,
+                users: Vec<TeraTrashEntry>,
+                timezone: String,
+                token: SecurityToken
+            }
+
+            return Template::render("admin/trash", TrashContext {
+                jwt: jwt.value(),
+                fullname: fullname_value.value(),
+                sessions,
+                users,
+                timezone: user_timezone(cookies),
+                token: t
+            });
+        },
+        None => {
+            return Template::render("unauthorized", context!{});
+        }
+    }
+}
+
+#[post("/admin/trash/session/<id>/restore")]
+pub async fn restore_session(id: String, cookies: &CookieJar<'_>) -> Json<TrashResult> {
+    if admin_token(cookies).is_none() {
+        return trash_result(false);
+    }
+
+    let database = get_standard_database().await;
+    let r = match ObjectId::parse_str(&id) {
+        Ok(id) => restore_session_by_id(&database, &id).await,
+        Err(_) => return trash_result(false)
+    };
+
+    return trash_result(matches!(r, Ok(true)));
+}
+
+#[delete("/admin/trash/session/<id>")]
+pub async fn purge_session(id: String, cookies: &CookieJar<'_>) -> Json<TrashResult> {
+    if admin_token(cookies).is_none() {
+        return trash_result(false);
+    }
+
+    let database = get_standard_database().await;
+    let r = match ObjectId::parse_str(&id) {
+        Ok(id) => purge_trashed_session(&database, &id).await,
+        Err(_) => return trash_result(false)
+    };
+
+    return trash_result(matches!(r, Ok(true)));
+}
+
+#[post("/admin/trash/user/<id>/restore")]
+pub async fn restore_user(id: String, cookies: &CookieJar<'_>) -> Json<TrashResult> {
+    if admin_token(cookies).is_none() {
+        return trash_result(false);
+    }
+
+    let database = get_standard_database().await;
+    let r = match ObjectId::parse_str(&id) {
+        Ok(id) => restore_user_by_id(&database, &id).await,
+        Err(_) => return trash_result(false)
+    };
+
+    return trash_result(matches!(r, Ok(true)));
+}
+
+#[delete("/admin/trash/user/<id>")]
+pub async fn purge_user(id: String, cookies: &CookieJar<'_>) -> Json<TrashResult> {
+    if admin_token(cookies).is_none() {
+        return trash_result(false);
+    }
+
+    let database = get_standard_database().await;
+    let r = match ObjectId::parse_str(&id) {
+        Ok(id) => purge_trashed_user(&database, &id).await,
+        Err(_) => return trash_result(false)
+    };
+
+    return trash_result(matches!(r, Ok(true)));
+}
+
+// Hintergrund-Job, der abgelaufene Einträge aus dem Papierkorb endgültig löscht
+// Die Aufbewahrungsdauer wird aus trash_retention_days in der Rocket.toml gelesen
+pub fn retention_fairing() -> AdHoc {
+    AdHoc::on_liftoff("Trash Retention", |rocket| Box::pin(async move {
+        let retention_days: i64 = rocket.figment()
+            .extract_inner("trash_retention_days")
+            .unwrap_or(DEFAULT_RETENTION_DAYS);
+        let mut shutdown = rocket.shutdown();
+
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(PURGE_INTERVAL);
+            loop {
+                rocket::tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = &mut shutdown => break,
+                }
+
+                let before = Utc::now() - chrono::Duration::days(retention_days);
+                let database = get_standard_database().await;
+                match purge_trash(&database, before).await {
+                    Ok((0, 0)) => {},
+                    Ok((sessions, users)) => info!("Purged {} sessions and {} users from trash", sessions, users),
+                    Err(e) => error!("Failed to purge trash: {}", e)
+                }
+            }
+        });
+    }))
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            show_trash,
+            restore_session,
+            purge_session,
+            restore_user,
+            purge_user
+    ]).attach(Template::fairing())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::rocket;
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+
+    #[tokio::test]
+    async fn test_show_trash() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get(uri!(super::show_trash)).dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_restore_session_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.post(uri!(super::restore_session("62a05c8631a6964f64d829ac".to_string()))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "{\"status\":0}");
+    }
+}
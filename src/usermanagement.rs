@@ -12,16 +12,20 @@ use mongodb::bson::oid::ObjectId;
 
 use crate::security::{validate_token, SecurityRole, SecurityToken};
 use crate::sessions::{User, TeraUser};
-use crate::database::{get_client, get_all_users, create_hash, add_new_user, remove_user_by_id, get_standard_database};
+use crate::listing::{Page, UserFilter};
+use crate::database::{get_client, find_users, create_hash, add_new_user, trash_user_by_id, set_user_groups, get_standard_database, DatabaseError};
+use crate::access::parse_list;
 
+// status: 0 = fehlgeschlagen, 1 = erfolgreich, 2 = Konflikt (z.B. username bereits vergeben)
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct UserResult { 
     pub status: u8
 }
 
-#[get("/usermanagement")]
-pub async fn list_all_user(cookies: &CookieJar<'_>) -> Template {
+// Blättern, Filtern nach Rolle und Suche erfolgt über die Query-Parameter aus UserFilter
+#[get("/usermanagement?<filter..>")]
+pub async fn list_all_user(filter: UserFilter, cookies: &CookieJar<'_>) -> Template {
     
     // streamie.live ist der Standardcookie für den Auth-Token
     let token: Option<Cookie> = cookies.get_private("streamie.live");
@@ -46,25 +50,30 @@ pub async fn list_all_user(cookies: &CookieJar<'_>) -> Template {
                 None => Cookie::new("fullname", "Unknown User")
             };
             let database = get_standard_database().await;
-            let user_list: Vec<User> = get_all_users(&database).await;
-            let mut user_list_tera: Vec<TeraUser> = Vec::new();
-
-            for user in user_list {
-                user_list_tera.push(TeraUser{
-                    id: user.id.to_hex(),
-                    fullname: user.fullname,
-                    role: user.role,
-                    hash: user.hash,
-                    salt: user.salt,
-                    username: user.username
-                });
-            }
+            let user_list: Page<User> = find_users(&database, &filter).await
+                .expect("Error while find");
+
+            let user_list_tera: Page<TeraUser> = user_list.map(|user| TeraUser{
+                id: user.id.to_hex(),
+                fullname: user.fullname,
+                role: user.role,
+                hash: user.hash,
+                salt: user.salt,
+                username: user.username,
+                groups: user.groups
+            });
+
+            let prev_query = if user_list_tera.page > 1 { Some(filter.query_for_page(user_list_tera.page - 1)) } else { None };
+            let next_query = if user_list_tera.page < user_list_tera.pages { Some(filter.query_for_page(user_list_tera.page + 1)) } else { None };
 
             #[derive(Serialize)]
             struct UsermanagementContext<'a> {
                 jwt: &'a str,
                 fullname: &'a str,
-                user: Vec<TeraUser>,
+                user: Page<TeraUser>,
+                filter: &'a UserFilter,
+                prev_query: Option<String>,
+                next_query: Option<String>,
                 token: SecurityToken
             }
 
@@ -72,6 +81,9 @@ pub async fn list_all_user(cookies: &CookieJar<'_>) -> Template {
                 jwt: token_value.value(),
                 fullname: fullname_value.value(),
                 user: user_list_tera,
+                filter: &filter,
+                prev_query,
+                next_query,
                 token: t
             });
         },
@@ -90,7 +102,9 @@ pub struct NewUser<'r> {
     #[field(validate = len(1..))]
     pub password:  &'r str,
     #[field(validate = len(1..))]
-    pub role:  &'r str
+    pub role:  &'r str,
+    // Komma-getrennte Gruppen, z.B. "team-a, speaker"
+    pub groups: Option<&'r str>
 }
 
 #[post("/usermanagement/add", data="<new_user>")]
@@ -124,6 +138,10 @@ pub async fn create_new_user(cookies: &CookieJar<'_>, new_user: Form<NewUser<'_>
                 username: new_user.username.to_string(),
                 role: new_user.role.to_string(),
                 fullname: new_user.fullname.to_string(),
+                deleted_at: None,
+                groups: new_user.groups.map(parse_list).unwrap_or_default(),
+                timezone: None,
+                calendar_token: None,
                 salt: salt,
                 hash: hash
             };
@@ -137,6 +155,11 @@ pub async fn create_new_user(cookies: &CookieJar<'_>, new_user: Form<NewUser<'_>
                         status: 1
                     });
                 },
+                Err(DatabaseError::Conflict(_)) => {
+                    return Json(UserResult{
+                        status: 2
+                    });
+                },
                 Err(e) => {
                     return Json(UserResult{
                         status: 0
@@ -176,7 +199,8 @@ pub async fn delete_existing_user(cookies: &CookieJar<'_>, id: String) -> Json<U
             }
 
             let database = get_standard_database().await;
-            let r = remove_user_by_id(&database, &ObjectId::parse_str(&id).unwrap()).await;
+            // Der User wird in den Papierkorb verschoben und kann dort wiederhergestellt werden
+            let r = trash_user_by_id(&database, &ObjectId::parse_str(&id).unwrap()).await;
 
             match r {
                 Ok(o) => {
@@ -200,6 +224,43 @@ pub async fn delete_existing_user(cookies: &CookieJar<'_>, id: String) -> Json<U
     }
 }
 
+#[derive(FromForm)]
+pub struct UserGroups<'r> {
+    pub groups: &'r str
+}
+
+// Setzt die Gruppen eines Users, eine leere Eingabe entfernt alle Gruppen
+#[post("/usermanagement/groups/<id>", data="<user_groups>")]
+pub async fn update_user_groups(cookies: &CookieJar<'_>, id: String, user_groups: Form<UserGroups<'_>>) -> Json<UserResult> {
+
+    // streamie.live ist der Standardcookie für den Auth-Token
+    let token: Option<Cookie> = cookies.get_private("streamie.live");
+    let token_value = match token {
+        Some(jwt) => jwt,
+        None => Cookie::new("streamie.live", "None")
+    };
+
+    match validate_token(token_value.value().to_string()) {
+        Some(t) if t.role == SecurityRole::ADMIN => {
+            let user_id = match ObjectId::parse_str(&id) {
+                Ok(user_id) => user_id,
+                Err(_) => return Json(UserResult{ status: 0 })
+            };
+
+            let database = get_standard_database().await;
+            return match set_user_groups(&database, &user_id, &parse_list(user_groups.groups)).await {
+                Ok(true) => Json(UserResult{ status: 1 }),
+                _ => Json(UserResult{ status: 0 })
+            };
+        },
+        _ => {
+            return Json(UserResult{
+                status: 0
+            });
+        }
+    }
+}
+
 pub fn create_salt() -> String {
     let rand_string: String = thread_rng()
     .sample_iter(&Alphanumeric)
@@ -224,13 +285,14 @@ fn rocket() -> _ {
 mod tests {
 
     use super::rocket;
+    use crate::listing::UserFilter;
     use rocket::http::Status;
     use rocket::local::asynchronous::Client;
 
     #[tokio::test]
     async fn test_user_list() {
         let client = Client::tracked(rocket()).await.expect("valid rocket instance");
-        let mut response = client.get(uri!(super::list_all_user)).dispatch();
+        let mut response = client.get(uri!(super::list_all_user(UserFilter::default()))).dispatch();
         assert_eq!(response.await.status(), Status::Ok);
     }
 
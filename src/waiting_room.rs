@@ -0,0 +1,287 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use rocket::form::Form;
+use rocket::http::{CookieJar, Status};
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::access::{viewable_session, viewer_from_token};
+use crate::database::{get_standard_database, set_session_waiting_room};
+use crate::security::{admin_token, user_token};
+use crate::sessions::Session;
+
+// Obergrenze für den Hinweis im Warteraum (z.B. "Wir starten pünktlich, der Chat ist schon offen")
+pub const MAX_MESSAGE_LENGTH: usize = 500;
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "webp", "avif"];
+const VIDEO_EXTENSIONS: [&str; 3] = ["mp4", "webm", "m4v"];
+
+// Art des Halte-Mediums, Videos laufen stumm in einer Schleife
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HoldingKind {
+    Image,
+    Video
+}
+
+// Warteraum vor dem Beginn einer Session
+// Ohne Halte-Medium zeigt der Warteraum nur den Countdown, ein Poster steht ohnehin über der Session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WaitingRoom {
+    #[serde(default)]
+    pub media_url: Option<String>,
+    #[serde(default)]
+    pub media_kind: Option<HoldingKind>,
+    #[serde(default)]
+    pub message: String
+}
+
+// Die Art wird an der Dateiendung erkannt, Query und Fragment des Links werden dabei ignoriert
+pub fn holding_kind(url: &str) -> Option<HoldingKind> {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or_default();
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    let extension = match file_name.rsplit_once('.') {
+        Some((_, extension)) => extension.to_lowercase(),
+        None => return None
+    };
+    if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        return Some(HoldingKind::Image);
+    }
+    if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+        return Some(HoldingKind::Video);
+    }
+    return None;
+}
+
+// Prüft die Eingaben aus dem Formular, ein leerer Link entfernt das Halte-Medium
+// Erlaubt sind http(s)-Links und Pfade auf diesem Server (z.B. ein Anhang der Session)
+pub fn parse_waiting_room(media_url: &str, message: &str) -> Result<WaitingRoom, Vec<String>> {
+    let mut errors = Vec::new();
+    let media_url = media_url.trim();
+    let message = message.trim();
+
+    let media_kind = if media_url.is_empty() {
+        None
+    } else {
+        let allowed = media_url.starts_with("https://") || media_url.starts_with("http://")
+            || (media_url.starts_with('/') && !media_url.starts_with("//"));
+        if !allowed {
+            errors.push("Der Link muss mit https:// oder / beginnen".to_string());
+        }
+        let kind = holding_kind(media_url);
+        if kind.is_none() {
+            errors.push("Bitte ein Bild (JPEG, PNG, GIF, WebP, AVIF) oder Video (MP4, WebM) verlinken".to_string());
+        }
+        kind
+    };
+    if message.chars().count() > MAX_MESSAGE_LENGTH {
+        errors.push(format!("Der Hinweis darf höchstens {} Zeichen lang sein", MAX_MESSAGE_LENGTH));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(WaitingRoom {
+        media_url: media_kind.map(|_| media_url.to_string()),
+        media_kind,
+        message: message.to_string()
+    });
+}
+
+// Vor dem Beginn ist die Session im Warteraum, außer der Ingest-Server sendet schon (dann ist sie live)
+// Gerechnet wird mit start statt mit dem gespeicherten Status, der Scheduler läuft nur alle 30 Sekunden
+pub fn in_waiting_room(session: &Session, now: DateTime<Utc>) -> bool {
+    return !session.live && now < session.start;
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TeraWaitingRoom {
+    pub start: DateTime<Utc>,
+    pub image_url: Option<String>,
+    pub video_url: Option<String>,
+    pub message: String
+}
+
+impl TeraWaitingRoom {
+    pub fn from_session(session: &Session, now: DateTime<Utc>) -> Option<TeraWaitingRoom> {
+        if !in_waiting_room(session, now) {
+            return None;
+        }
+        let room = &session.waiting_room;
+        let media = |kind: HoldingKind| room.media_url.clone().filter(|_| room.media_kind == Some(kind));
+        return Some(TeraWaitingRoom {
+            start: session.start,
+            image_url: media(HoldingKind::Image),
+            video_url: media(HoldingKind::Video),
+            message: room.message.clone()
+        });
+    }
+}
+
+// Serverzeit und Beginn für den Countdown, der Browser gleicht damit seine Uhr ab
+// waiting = false heißt, dass der Player geladen werden kann
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WaitingRoomState {
+    pub server_time: DateTime<Utc>,
+    pub start: DateTime<Utc>,
+    pub waiting: bool
+}
+
+#[get("/session/<id>/waiting-room")]
+pub async fn waiting_room_state(id: &str, cookies: &CookieJar<'_>) -> Result<Json<WaitingRoomState>, Status> {
+    let token = user_token(cookies).ok_or(Status::Unauthorized)?;
+    let session_id = ObjectId::parse_str(id).map_err(|_| Status::NotFound)?;
+
+    let database = get_standard_database().await;
+    let viewer = viewer_from_token(&database, &token).await;
+    let session = viewable_session(&database, &viewer, &session_id).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let server_time = Utc::now();
+    return Ok(Json(WaitingRoomState {
+        server_time,
+        start: session.start,
+        waiting: in_waiting_room(&session, server_time)
+    }));
+}
+
+#[derive(FromForm)]
+pub struct WaitingRoomForm<'r> {
+    pub media_url: &'r str,
+    pub message: &'r str
+}
+
+// status: 0 = fehlgeschlagen, 1 = erfolgreich
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WaitingRoomResult {
+    pub status: u8,
+    pub errors: Vec<String>
+}
+
+impl WaitingRoomResult {
+    fn failed(errors: Vec<String>) -> Json<WaitingRoomResult> {
+        return Json(WaitingRoomResult { status: 0, errors });
+    }
+}
+
+// Speichert Halte-Medium und Hinweis, Zuschauer sehen die Änderung beim nächsten Laden der Seite
+#[post("/admin/session/<id>/waiting-room", data = "<form>")]
+pub async fn update_waiting_room(id: &str, form: Form<WaitingRoomForm<'_>>, cookies: &CookieJar<'_>) -> Json<WaitingRoomResult> {
+    if admin_token(cookies).is_none() {
+        return WaitingRoomResult::failed(vec!["Keine Berechtigung".to_string()]);
+    }
+    let session_id = match ObjectId::parse_str(id) {
+        Ok(session_id) => session_id,
+        Err(_) => return WaitingRoomResult::failed(vec!["Unbekannte Session".to_string()])
+    };
+    let waiting_room = match parse_waiting_room(form.media_url, form.message) {
+        Ok(waiting_room) => waiting_room,
+        Err(errors) => return WaitingRoomResult::failed(errors)
+    };
+
+    let database = get_standard_database().await;
+    match set_session_waiting_room(&database, &session_id, &waiting_room).await {
+        Ok(true) => return Json(WaitingRoomResult { status: 1, errors: Vec::new() }),
+        Ok(false) => return WaitingRoomResult::failed(vec!["Unbekannte Session".to_string()]),
+        Err(e) => {
+            error!("Failed to store waiting room: {}", e);
+            return WaitingRoomResult::failed(vec!["Der Warteraum konnte nicht gespeichert werden".to_string()]);
+        }
+    }
+}
+
+#[launch]
+fn rocket() -> _ {
+
+    rocket::build()
+        .mount("/", routes![
+            waiting_room_state,
+            update_waiting_room
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use rocket::http::ContentType;
+    use rocket::local::asynchronous::Client;
+
+    #[test]
+    fn test_holding_kind() {
+        assert_eq!(holding_kind("https://cdn.example.org/loop.MP4"), Some(HoldingKind::Video));
+        assert_eq!(holding_kind("https://cdn.example.org/loop.webm?v=2#t=5"), Some(HoldingKind::Video));
+        assert_eq!(holding_kind("/session/62a05c8631a6964f64d829ac/poster/poster-page.jpg"), Some(HoldingKind::Image));
+        assert_eq!(holding_kind("https://example.org/bild.png"), Some(HoldingKind::Image));
+        assert_eq!(holding_kind("https://example.org/stream.m3u8"), None);
+        assert_eq!(holding_kind("https://example.org/"), None);
+        assert_eq!(holding_kind("https://example.org.mp4/video"), None);
+    }
+
+    #[test]
+    fn test_parse_waiting_room() {
+        let room = parse_waiting_room(" https://cdn.example.org/loop.mp4 ", " Gleich geht's los ").unwrap();
+        assert_eq!(room.media_url.as_deref(), Some("https://cdn.example.org/loop.mp4"));
+        assert_eq!(room.media_kind, Some(HoldingKind::Video));
+        assert_eq!(room.message, "Gleich geht's los");
+
+        // Ein leerer Link entfernt das Halte-Medium
+        assert_eq!(parse_waiting_room("", "").unwrap(), WaitingRoom::default());
+
+        assert_eq!(parse_waiting_room("javascript:alert(1).png", "").unwrap_err().len(), 1);
+        assert_eq!(parse_waiting_room("//evil.example.org/x.gif", "").unwrap_err().len(), 1);
+        assert_eq!(parse_waiting_room("ftp://example.org/stream", &"x".repeat(MAX_MESSAGE_LENGTH + 1)).unwrap_err().len(), 3);
+    }
+
+    fn session(start: DateTime<Utc>, live: bool) -> Session {
+        return Session {
+            slug: "warteraum".to_string(),
+            start,
+            end: start + Duration::hours(1),
+            name: "Warteraum".to_string(),
+            live,
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn test_in_waiting_room() {
+        let start = Utc.ymd(2022, 7, 9).and_hms(16, 0, 0);
+        assert!(in_waiting_room(&session(start, false), start - Duration::seconds(1)));
+        assert!(!in_waiting_room(&session(start, false), start));
+
+        // Sendet der Ingest-Server vor dem Beginn, geht die Session sofort live
+        assert!(!in_waiting_room(&session(start, true), start - Duration::minutes(5)));
+
+        // Ohne eigenes Medium bleibt nur der Countdown
+        let room = TeraWaitingRoom::from_session(&session(start, false), start - Duration::minutes(5)).unwrap();
+        assert_eq!(room.start, start);
+        assert!(room.image_url.is_none() && room.video_url.is_none());
+
+        let mut with_image = session(start, false);
+        with_image.waiting_room = parse_waiting_room("https://example.org/bild.png", "").unwrap();
+        let room = TeraWaitingRoom::from_session(&with_image, start - Duration::minutes(5)).unwrap();
+        assert_eq!(room.image_url.as_deref(), Some("https://example.org/bild.png"));
+        assert!(room.video_url.is_none());
+        assert!(TeraWaitingRoom::from_session(&with_image, start + Duration::minutes(5)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_waiting_room_unauthorized() {
+        let client = Client::tracked(rocket()).await.expect("valid rocket instance");
+        let response = client.get("/session/62a05c8631a6964f64d829ac/waiting-room").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.post("/admin/session/62a05c8631a6964f64d829ac/waiting-room")
+            .header(ContentType::Form)
+            .body("media_url=https://example.org/loop.mp4&message=Gleich")
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("\"status\":0"));
+    }
+}